@@ -5,31 +5,58 @@ out vec4 fragColor;
 in vec2 uv;
 in float oddBarFac;
 in float bLength;
+// beats in the bar, fractional when a time signature change cuts it short
+in float bBeats;
+// grid steps in the bar, 0 to not draw the grid
+in float bDivisions;
 
 uniform float width;
 uniform float height;
+// the pitch classes in the highlighted scale as bits from C, 0 when no scale is highlighted
+uniform float scaleMask;
+// 1.0 to fade the lines' edges over a pixel instead of cutting them off
+uniform float antialias;
+// the theme's colors, the black keys' rows and alternate beats and bars are shaded from keyRowColor
+uniform vec3 keyRowColor;
+uniform vec3 barLineColor;
+uniform vec3 beatLineColor;
+uniform vec3 gridLineColor;
+
+// how much of the pixel is covered by a line starting `dist` pixels back and `lineWidth` pixels wide
+float lineCoverage(float dist, float lineWidth) {
+    if (antialias > 0.5) {
+        return clamp(lineWidth - dist + 0.5, 0.0, 1.0);
+    }
+    return dist <= lineWidth ? 1.0 : 0.0;
+}
 
 void main() {
     float key_pos = uv.y * 128.0;
     int key_int = int(key_pos) % 12;
     float key_sharp_fac = (key_int == 1 || key_int == 3 || key_int == 6 || key_int == 8 || key_int == 10) ? 0.7 : 1.0;
 
-    float beat_pos = uv.x * 4.0;
+    float beat_pos = uv.x * bBeats;
     int beat_int = int(beat_pos) % 2;
     float beat_odds_fac = (beat_int == 0) ? 0.9 : 1.0;
 
-    vec3 color = vec3(0.2, 0.2, 0.25);
+    vec3 color = keyRowColor;
     color *= key_sharp_fac;
+    int scale_mask = int(scaleMask);
+    if (scale_mask != 0 && ((scale_mask >> key_int) & 1) == 0) {
+        color *= 0.7;
+    }
     color *= beat_odds_fac;
     color *= oddBarFac;
-    if (uv.x * bLength <= 1.5 / width) {
-        color *= 0.1;
-    }
-    if (fract(beat_pos) * (bLength / 4.0) <= 0.5 / width) {
-        color *= 0.1;
-    }
-    if (fract(key_pos) <= 0.07) {
-        color *= 0.3;
+    color = mix(color, beatLineColor, lineCoverage(fract(beat_pos) * (bLength / bBeats) * width, 0.5));
+    // only draw the grid while its lines are a few pixels apart
+    float grid_pos = uv.x * bDivisions;
+    if (bDivisions > 0.0 && bLength / bDivisions * width >= 4.0) {
+        color = mix(color, gridLineColor, lineCoverage(fract(grid_pos) * (bLength / bDivisions) * width, 0.5));
     }
+    // the bar line goes over the beat and grid lines it starts with
+    color = mix(color, barLineColor, lineCoverage(uv.x * bLength * width, 1.5));
+    // the key lines are a fraction of a key tall, measured in pixels through how much a pixel moves the key position
+    float key_px = max(fwidth(key_pos), 1e-6);
+    color *= mix(1.0, 0.3, lineCoverage(fract(key_pos) / key_px, 0.07 / key_px));
     fragColor = vec4(color, 1.0);
 }
\ No newline at end of file
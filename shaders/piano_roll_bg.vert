@@ -4,10 +4,14 @@ layout (location = 0) in vec2 vPos;
 layout (location = 1) in float barStart;
 layout (location = 2) in float barLength;
 layout (location = 3) in uint barNumber;
+layout (location = 4) in float barBeats;
+layout (location = 5) in float barDivisions;
 
 out vec2 uv;
 out float oddBarFac;
 out float bLength;
+out float bBeats;
+out float bDivisions;
 
 uniform float prBarBottom;
 uniform float prBarTop;
@@ -33,6 +37,8 @@ void main() {
     uv = vPos;
     oddBarFac = (int(barNumber) % 2 == 1) ? 0.8 : 1.0;
     bLength = barLength;
+    bBeats = barBeats;
+    bDivisions = barDivisions;
 
     gl_Position = vec4(vec2(x_pos, y_pos) * 2.0 - 1.0, 0.0, 1.0);
 }
\ No newline at end of file
@@ -10,17 +10,39 @@ in float noteHeight;
 
 uniform float width;
 uniform float height;
+// 1.0 to darken a one pixel edge around each note
+uniform float noteBorders;
+// in pixels, 0.0 for square corners
+uniform float cornerRadius;
+// 1.0 to fade the note's outline over a pixel instead of cutting it off
+uniform float antialias;
 
 void main() {
-    float borders = 1.0;
-    
-    if (uv.x * noteWidth <= 1.0 / width || (1.0 - uv.x) * noteWidth <= 0.5 / width) {
-        borders = 0.1;
+    vec2 size = vec2(noteWidth * width, noteHeight * height);
+    vec2 halfSize = size * 0.5;
+
+    // signed distance to the edge of the rounded note, negative inside
+    float radius = min(cornerRadius, min(halfSize.x, halfSize.y));
+    vec2 q = abs(uv * size - halfSize) - (halfSize - vec2(radius));
+    float dist = length(max(q, 0.0)) + min(max(q.x, q.y), 0.0) - radius;
+    // egui blends with premultiplied alpha
+    float coverage = 1.0;
+    float border = dist > -1.0 ? 1.0 : 0.0;
+    if (antialias > 0.5) {
+        coverage = clamp(0.5 - dist, 0.0, 1.0);
+        border = clamp(dist + 1.5, 0.0, 1.0);
+    } else if (dist > 0.0) {
+        coverage = 0.0;
+    }
+    if (coverage <= 0.0) {
+        discard;
     }
 
-    if (uv.y * noteHeight <= 0.5 / height || (1.0 - uv.y) * noteHeight <= 0.5 / height) {
-        borders = 0.1;
+    // notes only a couple of pixels across would be all border, keep them their color
+    float borders = 1.0;
+    if (noteBorders > 0.5 && min(size.x, size.y) >= 3.0) {
+        borders = mix(1.0, 0.1, border);
     }
 
-    fragColor = vec4(color * borders, 1.0);
-}
\ No newline at end of file
+    fragColor = vec4(color * borders * coverage, coverage);
+}
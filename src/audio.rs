@@ -1,2 +1,11 @@
 pub mod prerenderer;
 pub mod playback;
+pub mod audio_error;
+pub mod peak_meter;
+pub mod output_capture;
+pub mod spectrum;
+pub mod soundfont_info;
+pub mod wav_writer;
+pub mod audio_render;
+pub mod channel_pitch;
+pub mod high_pass;
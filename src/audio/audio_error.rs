@@ -0,0 +1,47 @@
+use std::fmt;
+
+use xsynth_core::soundfont::LoadSfError;
+
+/// Everything that can go wrong while opening the audio output.
+#[derive(Debug)]
+pub enum AudioError {
+    /// the host has no output device, like on a machine without a sound card
+    NoOutputDevice,
+    DefaultConfig(cpal::DefaultStreamConfigError),
+    BuildStream(cpal::BuildStreamError),
+    PlayStream(cpal::PlayStreamError),
+    /// the soundfont at `path` is missing or couldn't be parsed
+    Soundfont { path: String, err: LoadSfError },
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioError::NoOutputDevice => write!(f, "No audio output device was found"),
+            AudioError::DefaultConfig(err) => write!(f, "Could not get the output device's configuration: {}", err),
+            AudioError::BuildStream(err) => write!(f, "Could not open the audio stream: {}", err),
+            AudioError::PlayStream(err) => write!(f, "Could not start the audio stream: {}", err),
+            AudioError::Soundfont { path, err } => write!(f, "Could not load the soundfont {}: {}", path, err)
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+impl From<cpal::DefaultStreamConfigError> for AudioError {
+    fn from(err: cpal::DefaultStreamConfigError) -> Self {
+        AudioError::DefaultConfig(err)
+    }
+}
+
+impl From<cpal::BuildStreamError> for AudioError {
+    fn from(err: cpal::BuildStreamError) -> Self {
+        AudioError::BuildStream(err)
+    }
+}
+
+impl From<cpal::PlayStreamError> for AudioError {
+    fn from(err: cpal::PlayStreamError) -> Self {
+        AudioError::PlayStream(err)
+    }
+}
@@ -0,0 +1,216 @@
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+
+use xsynth_core::channel_group::ChannelGroup;
+
+use crate::audio::channel_pitch::ChannelPitch;
+use crate::audio::high_pass::HighPass;
+use crate::audio::prerenderer::{mono_sum, ChannelMode, SynthSetup};
+use crate::audio::wav_writer::WavWriter;
+use crate::midi::events::MIDIEvent;
+
+/// How long rendering carries on after the last event at most, so releases and reverb tails aren't cut off.
+const RENDER_TAIL_SECS: f32 = 5.0;
+/// How many frames are rendered at a time between events.
+const RENDER_CHUNK_FRAMES: usize = 4096;
+
+/// One file to render, the events heard in it with their times in seconds.
+pub struct RenderPass {
+    pub path: PathBuf,
+    pub events: Vec<MIDIEvent>,
+}
+
+/// Renders passes to WAV files one after another on a worker thread, as fast as the synth goes.
+/// Each pass gets a fresh synth so nothing still ringing carries over into the next file.
+pub struct AudioRender {
+    pub pass_count: usize,
+    /// the pass being rendered, from 0
+    current_pass: Arc<AtomicUsize>,
+    /// how much of the current pass is done from 0.0 to 1.0, as f32 bits
+    pass_progress: Arc<AtomicU32>,
+    cancel: Arc<AtomicBool>,
+    receiver: Receiver<Result<(), (PathBuf, io::Error)>>,
+}
+
+impl AudioRender {
+    pub fn start(setup: SynthSetup, passes: Vec<RenderPass>) -> Self {
+        let pass_count = passes.len();
+        let current_pass = Arc::new(AtomicUsize::new(0));
+        let pass_progress = Arc::new(AtomicU32::new(0.0f32.to_bits()));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_pass = current_pass.clone();
+        let thread_progress = pass_progress.clone();
+        let thread_cancel = cancel.clone();
+        std::thread::spawn(move || {
+            for (i, pass) in passes.into_iter().enumerate() {
+                thread_pass.store(i, Ordering::Relaxed);
+                thread_progress.store(0.0f32.to_bits(), Ordering::Relaxed);
+                if let Err(err) = render_pass(&setup, &pass.events, &pass.path, &thread_progress, &thread_cancel) {
+                    // the receiver is gone if the render was abandoned
+                    let _ = sender.send(Err((pass.path, err)));
+                    return;
+                }
+                if thread_cancel.load(Ordering::Relaxed) { return; }
+            }
+            let _ = sender.send(Ok(()));
+        });
+
+        Self {
+            pass_count,
+            current_pass,
+            pass_progress,
+            cancel,
+            receiver
+        }
+    }
+
+    /// The pass being rendered from 0, and how much of all the passes is done from 0.0 to 1.0.
+    pub fn progress(&self) -> (usize, f32) {
+        let pass = self.current_pass.load(Ordering::Relaxed);
+        let pass_progress = f32::from_bits(self.pass_progress.load(Ordering::Relaxed));
+        (pass, (pass as f32 + pass_progress) / self.pass_count.max(1) as f32)
+    }
+
+    /// Stops after the chunk being rendered. The file being written is left unfinished.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns once every pass is written, or the file that couldn't be and why.
+    pub fn poll(&self) -> Option<Result<(), (PathBuf, io::Error)>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err((PathBuf::new(), io::Error::other("the render stopped unexpectedly"))))
+        }
+    }
+}
+
+/// Renders a track to memory on a worker thread so it can be played back instead of synthesized, see `PrerenderedAudio::set_clips`.
+/// The master gain isn't applied, the live output applies it to the clip along with everything else.
+pub struct FreezeRender {
+    progress: Arc<AtomicU32>,
+    cancel: Arc<AtomicBool>,
+    receiver: Receiver<Vec<f32>>,
+}
+
+impl FreezeRender {
+    /// `[events]` - the track's events with their times in seconds from the start of the song
+    pub fn start(setup: SynthSetup, events: Vec<MIDIEvent>) -> Self {
+        let progress = Arc::new(AtomicU32::new(0.0f32.to_bits()));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_progress = progress.clone();
+        let thread_cancel = cancel.clone();
+        std::thread::spawn(move || {
+            let mut clip = Vec::new();
+            let rendered = render_events(&setup, &events, 1.0, &thread_progress, &thread_cancel, |samples| {
+                clip.extend_from_slice(samples);
+                Ok(())
+            });
+            if let Ok(true) = rendered {
+                let _ = sender.send(clip);
+            }
+        });
+
+        Self { progress, cancel, receiver }
+    }
+
+    /// How much is rendered, from 0.0 to 1.0.
+    pub fn progress(&self) -> f32 {
+        f32::from_bits(self.progress.load(Ordering::Relaxed))
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// The interleaved stereo samples of the track from the start of the song, once they're all rendered.
+    pub fn poll(&self) -> Option<Vec<f32>> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Renders `[events]` to a WAV at `[path]`, scaled by the master gain and high-passed like the live output.
+/// The WAV is mono if the output is, otherwise stereo.
+/// The limiter isn't applied, the float samples can go past full scale without clipping.
+fn render_pass(setup: &SynthSetup, events: &[MIDIEvent], path: &PathBuf, progress: &AtomicU32, cancel: &AtomicBool) -> io::Result<()> {
+    let mono = setup.channel_mode == ChannelMode::Mono;
+    let mut wav = WavWriter::create(path, setup.stream_params.sample_rate, if mono { 1 } else { 2 })?;
+    let mut mixed = Vec::new();
+    let mut high_pass = setup.high_pass_cutoff.map(|cutoff| HighPass::new(cutoff, setup.stream_params.sample_rate));
+    let mut filtered = Vec::new();
+    let finished = render_events(setup, events, setup.master_gain, progress, cancel, |samples| {
+        let samples = match high_pass.as_mut() {
+            Some(high_pass) => {
+                filtered.clear();
+                filtered.extend_from_slice(samples);
+                high_pass.process(&mut filtered);
+                filtered.as_slice()
+            },
+            None => samples
+        };
+        if !mono { return wav.write_samples(samples); }
+        mixed.clear();
+        mixed.extend(samples.chunks_exact(2).map(mono_sum));
+        wav.write_samples(&mixed)
+    })?;
+    if finished { wav.finish() } else { Ok(()) }
+}
+
+/// Plays `[events]` through a fresh synth as fast as it goes, handing every chunk of interleaved stereo samples
+/// scaled by `[gain]` to `[sink]`. The samples start at 0 seconds. Returns false if it was cancelled partway.
+fn render_events(
+    setup: &SynthSetup,
+    events: &[MIDIEvent],
+    gain: f32,
+    progress: &AtomicU32,
+    cancel: &AtomicBool,
+    mut sink: impl FnMut(&[f32]) -> io::Result<()>
+) -> io::Result<bool> {
+    let mut synth = setup.build();
+    let sample_rate = setup.stream_params.sample_rate;
+    let mut scratch = Vec::new();
+    let mut written_frames = 0usize;
+    let mut pitch = ChannelPitch::new(setup.vibrato_depth, sample_rate);
+
+    let mut render = |synth: &mut ChannelGroup, pitch: &mut ChannelPitch, frames: usize| -> io::Result<()> {
+        scratch.resize(frames * 2, 0.0);
+        pitch.read_samples(synth, &mut scratch);
+        if gain != 1.0 {
+            scratch.iter_mut().for_each(|sample| *sample *= gain);
+        }
+        sink(&scratch)
+    };
+
+    for (i, e) in events.iter().enumerate() {
+        let event_frame = (e.time.max(0.0) * sample_rate as f32) as usize;
+        while written_frames < event_frame {
+            if cancel.load(Ordering::Relaxed) { return Ok(false); }
+            let frames = (event_frame - written_frames).min(RENDER_CHUNK_FRAMES);
+            render(&mut synth, &mut pitch, frames)?;
+            written_frames += frames;
+        }
+        pitch.send(&mut synth, e);
+        if i % 1024 == 0 {
+            progress.store((i as f32 / events.len() as f32).to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    // let the last notes ring out, stopping early once everything has gone quiet
+    let tail_frames = (RENDER_TAIL_SECS * sample_rate as f32) as usize;
+    let mut rendered_tail = 0;
+    while rendered_tail < tail_frames && synth.voice_count() > 0 {
+        if cancel.load(Ordering::Relaxed) { return Ok(false); }
+        render(&mut synth, &mut pitch, RENDER_CHUNK_FRAMES)?;
+        rendered_tail += RENDER_CHUNK_FRAMES;
+    }
+    progress.store(1.0f32.to_bits(), Ordering::Relaxed);
+    Ok(true)
+}
@@ -0,0 +1,207 @@
+use xsynth_core::channel::{ChannelAudioEvent, ChannelEvent, ControlEvent};
+use xsynth_core::channel_group::{ChannelGroup, SynthEvent};
+use xsynth_core::AudioPipe;
+
+use crate::midi::events::{
+    MIDIEvent, MIDIEventType, CC_DATA_ENTRY, CC_DATA_ENTRY_LSB, CC_MODULATION, CC_RESET_ALL_CONTROLLERS, CC_RPN_LSB, CC_RPN_MSB,
+    RPN_BEND_RANGE, RPN_NULL
+};
+
+use super::prerenderer::{send_midi_event, MAX_SYNTH_PORTS};
+
+/// How many times a second the vibrato swings up and down.
+const VIBRATO_RATE: f32 = 5.5;
+/// How often the pitch of a channel with vibrato is updated, in seconds.
+const UPDATE_INTERVAL: f32 = 0.004;
+/// How far a full bend goes either way until a channel sets its own range, in semitones.
+const DEFAULT_BEND_RANGE: u8 = 2;
+
+/// What sets the pitch of one channel.
+#[derive(Clone, Copy)]
+struct PitchState {
+    /// from -1.0 to 1.0
+    bend: f32,
+    /// the semitones and cents a full bend goes either way
+    bend_range: (u8, u8),
+    /// the RPN data entries go to, as its MSB and LSB
+    rpn: (u8, u8),
+    mod_wheel: u8,
+}
+
+impl Default for PitchState {
+    fn default() -> Self {
+        Self {
+            bend: 0.0,
+            bend_range: (DEFAULT_BEND_RANGE, 0),
+            rpn: RPN_NULL,
+            mod_wheel: 0
+        }
+    }
+}
+
+impl PitchState {
+    fn bend_semitones(&self) -> f32 {
+        self.bend * (self.bend_range.0 as f32 + self.bend_range.1 as f32 / 100.0)
+    }
+}
+
+/// The pitch bend and the mod wheel's vibrato of each channel.
+/// xsynth has no modulation input, so the pitch of every channel with the wheel up is swung by an LFO
+/// between the blocks the synth is read in. The bend range is kept here too, since xsynth scales bends
+/// by it only once and then loses the unscaled bend, so the synth is always sent the channel's whole pitch offset.
+pub struct ChannelPitch {
+    /// how far the pitch swings either way at full modulation, in semitones
+    vibrato_depth: f32,
+    sample_rate: u32,
+    /// by synth channel, as many as the most the synth can have
+    channels: [PitchState; MAX_SYNTH_PORTS as usize * 16],
+    /// where the LFO is in its cycle, from 0.0 to 1.0. Every channel shares it
+    phase: f32,
+}
+
+impl ChannelPitch {
+    pub fn new(vibrato_depth: f32, sample_rate: u32) -> Self {
+        Self {
+            vibrato_depth,
+            sample_rate,
+            channels: [PitchState::default(); MAX_SYNTH_PORTS as usize * 16],
+            phase: 0.0
+        }
+    }
+
+    /// Sends `[e]` to the synth, keeping what it says about the channel's pitch.
+    pub fn send(&mut self, xsynth: &mut ChannelGroup, e: &MIDIEvent) {
+        let channel = e.data[0] as usize % self.channels.len();
+        let state = &mut self.channels[channel];
+        match e.event_type {
+            MIDIEventType::PitchBend => {
+                let value = ((e.data[2] as u16) << 7) | e.data[1] as u16;
+                state.bend = ((value as f32 - 8192.0) / 8192.0).clamp(-1.0, 1.0);
+                self.update_channel(xsynth, channel);
+            }
+            MIDIEventType::ControlChange => match e.data[1] {
+                CC_MODULATION => {
+                    state.mod_wheel = e.data[2];
+                    self.update_channel(xsynth, channel);
+                }
+                CC_RPN_MSB => {
+                    state.rpn.0 = e.data[2];
+                    send_midi_event(xsynth, e);
+                }
+                CC_RPN_LSB => {
+                    state.rpn.1 = e.data[2];
+                    send_midi_event(xsynth, e);
+                }
+                CC_DATA_ENTRY | CC_DATA_ENTRY_LSB if state.rpn == RPN_BEND_RANGE => {
+                    if e.data[1] == CC_DATA_ENTRY {
+                        state.bend_range.0 = e.data[2];
+                    } else {
+                        state.bend_range.1 = e.data[2];
+                    }
+                    self.update_channel(xsynth, channel);
+                }
+                // the bend range isn't a controller, it stays as it was
+                CC_RESET_ALL_CONTROLLERS if e.data[2] == 0 => {
+                    *state = PitchState { bend_range: state.bend_range, ..Default::default() };
+                    send_midi_event(xsynth, e);
+                    self.update_channel(xsynth, channel);
+                }
+                _ => send_midi_event(xsynth, e)
+            },
+            _ => send_midi_event(xsynth, e)
+        }
+    }
+
+    /// The most frames to read from the synth before calling `advance`, `None` while no channel has vibrato.
+    pub fn block_frames(&self) -> Option<usize> {
+        let modulated = self.vibrato_depth > 0.0 && self.channels.iter().any(|state| state.mod_wheel > 0);
+        modulated.then(|| ((UPDATE_INTERVAL * self.sample_rate as f32) as usize).max(1))
+    }
+
+    /// Moves the LFO on by `[frames]` and sends the channels with vibrato their new pitch.
+    pub fn advance(&mut self, xsynth: &mut ChannelGroup, frames: usize) {
+        if self.block_frames().is_none() { return; }
+        self.phase = (self.phase + frames as f32 * VIBRATO_RATE / self.sample_rate as f32).fract();
+        for channel in 0..self.channels.len() {
+            if self.channels[channel].mod_wheel > 0 {
+                self.update_channel(xsynth, channel);
+            }
+        }
+    }
+
+    /// Reads `[out]` full of interleaved stereo samples, keeping the vibrato moving while it does.
+    pub fn read_samples(&mut self, xsynth: &mut ChannelGroup, out: &mut [f32]) {
+        let Some(block_frames) = self.block_frames() else {
+            xsynth.read_samples(out);
+            return;
+        };
+        for block in out.chunks_mut(block_frames * 2) {
+            xsynth.read_samples(block);
+            self.advance(xsynth, block.len() / 2);
+        }
+    }
+
+    /// The channel's pitch offset in semitones.
+    fn pitch(&self, channel: usize) -> f32 {
+        let state = &self.channels[channel];
+        let modulation = state.mod_wheel as f32 / 127.0;
+        state.bend_semitones() + modulation * self.vibrato_depth * (self.phase * std::f32::consts::TAU).sin()
+    }
+
+    fn update_channel(&self, xsynth: &mut ChannelGroup, channel: usize) {
+        xsynth.send_event(SynthEvent::Channel(channel as u32,
+            ChannelEvent::Audio(ChannelAudioEvent::Control(ControlEvent::PitchBend(self.pitch(channel))))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xsynth_core::channel::ChannelInitOptions;
+    use xsynth_core::channel_group::{ChannelGroup, ChannelGroupConfig, ParallelismOptions, SynthFormat, ThreadCount};
+    use xsynth_core::{AudioStreamParams, ChannelCount};
+
+    use super::ChannelPitch;
+    use crate::midi::events::{MIDIEvent, MIDIEventType, CC_DATA_ENTRY, CC_RPN_LSB, CC_RPN_MSB};
+
+    fn synth() -> ChannelGroup {
+        ChannelGroup::new(ChannelGroupConfig {
+            channel_init_options: ChannelInitOptions { fade_out_killing: false },
+            format: SynthFormat::Midi,
+            audio_params: AudioStreamParams::new(48000, ChannelCount::Stereo),
+            parallelism: ParallelismOptions { channel: ThreadCount::None, key: ThreadCount::None }
+        })
+    }
+
+    fn control(number: u8, value: u8) -> MIDIEvent {
+        MIDIEvent { time: 0.0, event_type: MIDIEventType::ControlChange, data: vec![0, number, value] }
+    }
+
+    fn full_bend_up() -> MIDIEvent {
+        MIDIEvent { time: 0.0, event_type: MIDIEventType::PitchBend, data: vec![0, 0x7F, 0x7F] }
+    }
+
+    fn set_bend_range(pitch: &mut ChannelPitch, xsynth: &mut ChannelGroup, semitones: u8) {
+        pitch.send(xsynth, &control(CC_RPN_MSB, 0));
+        pitch.send(xsynth, &control(CC_RPN_LSB, 0));
+        pitch.send(xsynth, &control(CC_DATA_ENTRY, semitones));
+    }
+
+    #[test]
+    fn full_bend_goes_the_whole_range() {
+        let mut xsynth = synth();
+        let mut pitch = ChannelPitch::new(0.0, 48000);
+        set_bend_range(&mut pitch, &mut xsynth, 12);
+        pitch.send(&mut xsynth, &full_bend_up());
+        assert!((pitch.pitch(0) - 12.0).abs() < 0.01, "{}", pitch.pitch(0));
+    }
+
+    #[test]
+    fn range_set_after_the_bend_rescales_it() {
+        let mut xsynth = synth();
+        let mut pitch = ChannelPitch::new(0.0, 48000);
+        pitch.send(&mut xsynth, &full_bend_up());
+        assert!((pitch.pitch(0) - 2.0).abs() < 0.01, "{}", pitch.pitch(0));
+        set_bend_range(&mut pitch, &mut xsynth, 12);
+        assert!((pitch.pitch(0) - 12.0).abs() < 0.01, "{}", pitch.pitch(0));
+    }
+}
@@ -0,0 +1,40 @@
+use std::f32::consts::PI;
+
+/// The cutoff the high-pass starts at, low enough to leave bass alone while taking out DC and rumble.
+pub const DEFAULT_HIGH_PASS_CUTOFF: f32 = 20.0;
+
+/// A one-pole high-pass on interleaved stereo samples, for taking DC offset and subsonic content out of the output.
+/// Keeps each channel's last sample between blocks, so it can be fed a stream a callback at a time.
+pub struct HighPass {
+    coefficient: f32,
+    last_in: [f32; 2],
+    last_out: [f32; 2],
+}
+
+impl HighPass {
+    pub fn new(cutoff: f32, sample_rate: u32) -> Self {
+        let mut high_pass = Self {
+            coefficient: 1.0,
+            last_in: [0.0; 2],
+            last_out: [0.0; 2],
+        };
+        high_pass.set_cutoff(cutoff, sample_rate);
+        high_pass
+    }
+
+    /// Moves the cutoff to `[cutoff]` Hz, keeping what the filter holds so it doesn't click.
+    pub fn set_cutoff(&mut self, cutoff: f32, sample_rate: u32) {
+        self.coefficient = 1.0 / (1.0 + 2.0 * PI * cutoff.max(0.0) / sample_rate as f32);
+    }
+
+    pub fn process(&mut self, data: &mut [f32]) {
+        for frame in data.chunks_exact_mut(2) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let out = self.coefficient * (self.last_out[channel] + *sample - self.last_in[channel]);
+                self.last_in[channel] = *sample;
+                self.last_out[channel] = out;
+                *sample = out;
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// How many of the most recent frames are kept, about 170ms at 48kHz.
+pub const CAPTURE_FRAMES: usize = 8192;
+
+/// A ring of the most recent output frames, written by the audio callback and read by the UI to draw the waveform.
+/// Taken at the same point as the `PeakMeter`, before the limiter, so clipping shows as the wave going past ±1.0.
+pub struct OutputCapture {
+    /// interleaved left and right samples as f32 bits, `CAPTURE_FRAMES` frames long
+    samples: Box<[AtomicU32]>,
+    /// how many frames were written in total, the next one goes at this modulo `CAPTURE_FRAMES`
+    written: AtomicUsize,
+}
+
+impl Default for OutputCapture {
+    fn default() -> Self {
+        Self {
+            samples: (0..CAPTURE_FRAMES * 2).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl OutputCapture {
+    /// Copies an interleaved stereo block into the ring. Relaxed stores only, nothing the callback can wait on.
+    pub fn record(&self, data: &[f32]) {
+        let start = self.written.load(Ordering::Relaxed);
+        let frames = data.len() / 2;
+        // a block longer than the ring only leaves its end
+        let skip = frames.saturating_sub(CAPTURE_FRAMES);
+        for (i, frame) in data.chunks_exact(2).enumerate().skip(skip) {
+            let index = (start + i) % CAPTURE_FRAMES * 2;
+            self.samples[index].store(frame[0].to_bits(), Ordering::Relaxed);
+            self.samples[index + 1].store(frame[1].to_bits(), Ordering::Relaxed);
+        }
+        self.written.store(start + frames, Ordering::Relaxed);
+    }
+
+    /// How many frames were recorded so far. Stays the same while the stream is stopped.
+    pub fn frames_written(&self) -> usize {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// The last `[frames]` left and right frames, oldest first, at most `CAPTURE_FRAMES`.
+    /// The callback may be writing while they're read, which only tears the oldest few of them.
+    pub fn latest(&self, frames: usize) -> Vec<[f32; 2]> {
+        let end = self.written.load(Ordering::Relaxed);
+        let frames = frames.min(CAPTURE_FRAMES).min(end);
+        (end - frames..end)
+            .map(|frame| {
+                let index = frame % CAPTURE_FRAMES * 2;
+                [
+                    f32::from_bits(self.samples[index].load(Ordering::Relaxed)),
+                    f32::from_bits(self.samples[index + 1].load(Ordering::Relaxed))
+                ]
+            })
+            .collect()
+    }
+}
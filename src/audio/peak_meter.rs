@@ -0,0 +1,39 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// The peak levels of the audio output, written by the audio callback and read by the UI.
+/// Measured after the master gain and before the limiter, so it shows what the limiter has to hold back.
+#[derive(Default)]
+pub struct PeakMeter {
+    /// the loudest left and right sample since the UI last read them, as f32 bits
+    peaks: [AtomicU32; 2],
+    /// if a sample went past ±1.0 since the UI last read it
+    clipped: AtomicBool,
+}
+
+impl PeakMeter {
+    /// Records the peaks of an interleaved stereo block. Only a couple of atomic operations per block.
+    pub fn record(&self, data: &[f32]) {
+        let mut peaks = [0.0f32; 2];
+        for frame in data.chunks_exact(2) {
+            peaks[0] = peaks[0].max(frame[0].abs());
+            peaks[1] = peaks[1].max(frame[1].abs());
+        }
+        // the bits of non-negative floats sort the same way as the floats
+        for (peak, recorded) in peaks.iter().zip(self.peaks.iter()) {
+            recorded.fetch_max(peak.to_bits(), Ordering::Relaxed);
+        }
+        if peaks[0] > 1.0 || peaks[1] > 1.0 {
+            self.clipped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// The left and right peaks since the last call, resetting them.
+    pub fn take_peaks(&self) -> [f32; 2] {
+        self.peaks.each_ref().map(|peak| f32::from_bits(peak.swap(0, Ordering::Relaxed)))
+    }
+
+    /// If the output clipped since the last call, resetting it.
+    pub fn take_clipped(&self) -> bool {
+        self.clipped.swap(false, Ordering::Relaxed)
+    }
+}
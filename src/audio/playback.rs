@@ -28,16 +28,27 @@ impl Playback {
         }
     }
 
-    pub fn play_or_stop(&mut self) {
-        if self.is_playing {
-            self.playback_secs = self.last_pos;
-            println!("Stopping");
-        } else {
-            self.last_pos = self.playback_secs;
-            self.time_delta = Instant::now();
-            println!("Playing");
-        }
-        self.is_playing = !self.is_playing;
+    /// Starts playing from `playback_secs`.
+    pub fn play(&mut self) {
+        if self.is_playing { return; }
+        self.last_pos = self.playback_secs;
+        self.time_delta = Instant::now();
+        self.is_playing = true;
+    }
+
+    /// Stops playing, keeping the position it stopped at so playing again carries on from there.
+    pub fn pause(&mut self) {
+        if !self.is_playing { return; }
+        self.playback_secs = self.time_delta.elapsed().as_secs_f32() + self.last_pos;
+        self.last_pos = self.playback_secs;
+        self.is_playing = false;
+    }
+
+    /// Stops playing and goes back to the start of the song.
+    pub fn stop_to_start(&mut self) {
+        self.is_playing = false;
+        self.playback_secs = 0.0;
+        self.last_pos = 0.0;
     }
 
     pub fn navigate_to(&mut self, ppq: u16, tick: f32) {
@@ -47,26 +58,108 @@ impl Playback {
 
     pub fn get_playback_time(&mut self, ppq: u16) -> f32 {
         let time = self.time_delta.elapsed().as_secs_f32() + self.last_pos;
+        self.secs_to_tick(ppq, time)
+    }
+
+    /// The playback position at `[instant]` in ticks, for timing events that arrive while playing.
+    pub fn tick_at(&self, ppq: u16, instant: Instant) -> f32 {
+        let time = instant.saturating_duration_since(self.time_delta).as_secs_f32() + self.last_pos;
+        self.secs_to_tick(ppq, time)
+    }
+
+    /// Adds a tempo change to `[tempo]` BPM at `[tick]`, or changes the one already there, keeping the list sorted.
+    pub fn insert_tempo(&mut self, ppq: u16, tick: u64, tempo: f32) {
+        match self.tempo_events.binary_search_by_key(&tick, |ev| ev.time) {
+            Ok(i) => self.tempo_events[i].tempo = tempo,
+            Err(i) => self.tempo_events.insert(i, TempoEvent { time: tick, time_norm: 0.0, tempo })
+        }
+        self.update_tempo_times(ppq);
+    }
+
+    /// Removes the tempo change at `[tick]`. The one at tick 0 stays, there always has to be a tempo to start at.
+    pub fn remove_tempo(&mut self, ppq: u16, tick: u64) {
+        if tick == 0 { return; }
+        self.tempo_events.retain(|ev| ev.time != tick);
+        self.update_tempo_times(ppq);
+    }
+
+    /// The tempo in BPM at `[tick]`, 120 if there are no tempo changes like when converting times without any.
+    pub fn tempo_at(&self, tick: u64) -> f32 {
+        let i = self.tempo_events.partition_point(|ev| ev.time <= tick);
+        i.checked_sub(1).or((!self.tempo_events.is_empty()).then_some(0))
+            .map_or(120.0, |i| self.tempo_events[i].tempo)
+    }
+
+    /// Sorts the tempo changes, drops all but the last of any on the same tick
+    /// and sets every `time_norm` to the seconds the change happens at.
+    pub fn update_tempo_times(&mut self, ppq: u16) {
+        self.tempo_events.sort_by_key(|ev| ev.time);
+        let mut deduped: Vec<TempoEvent> = Vec::with_capacity(self.tempo_events.len());
+        for ev in self.tempo_events.drain(..) {
+            match deduped.last_mut() {
+                Some(last) if last.time == ev.time => *last = ev,
+                _ => deduped.push(ev)
+            }
+        }
+        self.tempo_events = deduped;
+
+        let mut times = self.tempo_events.iter().map(|ev| ev.time as f32).collect::<Vec<_>>();
+        self.sorted_ticks_to_secs(ppq, times.iter_mut());
+        for (ev, secs) in self.tempo_events.iter_mut().zip(times) {
+            ev.time_norm = secs;
+        }
+    }
+
+    /// Converts a song position in seconds to ticks by walking every tempo segment up to `[secs]`,
+    /// the inverse of `tick_to_secs`.
+    pub fn secs_to_tick(&self, ppq: u16, secs: f32) -> f32 {
         if self.tempo_events.len() == 0 {
-            return time * (ppq as f32 * 120.0 / 60.0);
+            return secs * (ppq as f32 * 120.0 / 60.0);
         }
 
-        let mut bpm = self.tempo_events[0].tempo;
-        let mut last_time = self.tempo_events[0].time_norm;
-        let mut last_tick = self.tempo_events[0].time;
+        let mut last_tick = 0;
+        let mut last_tempo = self.tempo_events[0].tempo;
+        let mut seconds = 0.0;
 
-        for tempo in self.tempo_events.iter() {
-            if tempo.time_norm > time { break; }
-            last_time = tempo.time_norm;
-            last_tick = tempo.time;
-            bpm = tempo.tempo;
+        for ev in self.tempo_events.iter().skip(1) {
+            let delta_ticks = ev.time - last_tick;
+            let sec_per_tick = 60.0 / (last_tempo * ppq as f32);
+            let segment_secs = delta_ticks as f32 * sec_per_tick;
+            if seconds + segment_secs > secs {
+                break;
+            }
+
+            seconds += segment_secs;
+            last_tick = ev.time;
+            last_tempo = ev.tempo;
         }
 
-        let tick_pos = (time - last_time) * (ppq as f32 * bpm / 60.0);
-        return tick_pos + last_tick as f32;
+        let ticks_per_sec = ppq as f32 * last_tempo / 60.0;
+        last_tick as f32 + (secs - seconds) * ticks_per_sec
     }
 
-    fn tick_to_secs(&self, ppq: u16, tick: f32) -> f32 {
+    /// Converts ticks that are in ascending order to seconds in place. Walks the tempo map once
+    /// instead of once per tick like `tick_to_secs`, so use this for long lists.
+    pub fn sorted_ticks_to_secs<'a>(&self, ppq: u16, ticks: impl Iterator<Item = &'a mut f32>) {
+        let mut tempo_id = 0;
+        let mut last_tick = 0.0;
+        let mut last_tempo = self.tempo_events.first().map_or(120.0, |ev| ev.tempo);
+        let mut seconds = 0.0;
+
+        for tick in ticks {
+            while tempo_id + 1 < self.tempo_events.len() && self.tempo_events[tempo_id + 1].time as f32 <= *tick {
+                tempo_id += 1;
+                let ev = &self.tempo_events[tempo_id];
+                seconds += (ev.time as f32 - last_tick) * 60.0 / (last_tempo * ppq as f32);
+                last_tick = ev.time as f32;
+                last_tempo = ev.tempo;
+            }
+
+            *tick = seconds + (*tick - last_tick) * 60.0 / (last_tempo * ppq as f32);
+        }
+    }
+
+    pub fn tick_to_secs(&self, ppq: u16, tick: f32) -> f32 {
         if self.tempo_events.len() == 0 {
             return tick / (ppq as f32 * 120.0 / 60.0);
         }
@@ -99,3 +192,65 @@ impl Playback {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::Playback;
+
+    const PPQ: u16 = 480;
+
+    /// A playback with a tempo change to each `[tempos]` (tick, BPM).
+    fn playback(tempos: &[(u64, f32)]) -> Playback {
+        let mut playback = Playback::new();
+        for &(tick, tempo) in tempos {
+            playback.insert_tempo(PPQ, tick, tempo);
+        }
+        playback
+    }
+
+    fn two_tempos() -> Playback {
+        playback(&[(0, 120.0), (960, 60.0)])
+    }
+
+    fn three_tempos() -> Playback {
+        playback(&[(0, 120.0), (960, 60.0), (1920, 240.0)])
+    }
+
+    /// Ticks on both sides of and right at the tempo changes.
+    const TICKS: [f32; 10] = [0.0, 100.0, 959.0, 960.0, 961.0, 1500.0, 1919.5, 1920.0, 2500.0, 10000.0];
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 0.01, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn ticks_to_secs_across_tempo_changes() {
+        let playback = three_tempos();
+        // 2 quarter notes at 120, 2 at 60 then 4 at 240
+        assert_close(playback.tick_to_secs(PPQ, 960.0), 1.0);
+        assert_close(playback.tick_to_secs(PPQ, 1920.0), 3.0);
+        assert_close(playback.tick_to_secs(PPQ, 3840.0), 4.0);
+        assert_close(playback.secs_to_tick(PPQ, 2.0), 1440.0);
+        assert_close(playback.secs_to_tick(PPQ, 3.5), 2880.0);
+    }
+
+    #[test]
+    fn secs_to_tick_inverts_tick_to_secs() {
+        for playback in [two_tempos(), three_tempos()] {
+            for tick in TICKS {
+                assert_close(playback.secs_to_tick(PPQ, playback.tick_to_secs(PPQ, tick)), tick);
+            }
+        }
+    }
+
+    #[test]
+    fn sorted_ticks_to_secs_matches_tick_to_secs() {
+        for playback in [two_tempos(), three_tempos()] {
+            let mut secs = TICKS;
+            playback.sorted_ticks_to_secs(PPQ, secs.iter_mut());
+            for (tick, secs) in TICKS.iter().zip(secs) {
+                assert_close(secs, playback.tick_to_secs(PPQ, *tick));
+            }
+        }
+    }
+}
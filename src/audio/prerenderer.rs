@@ -1,11 +1,15 @@
-use std::{path::{Path, PathBuf}, str::FromStr, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}, thread::JoinHandle, time::Duration};
+use std::{path::{Path, PathBuf}, str::FromStr, sync::{atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering}, Arc, Mutex}, thread::JoinHandle, time::{Duration, Instant}};
 use rand::Rng;
 
 use cpal::{traits::{DeviceTrait, HostTrait}, BufferSize, Device, StreamConfig};
-use xsynth_core::{channel::{ChannelAudioEvent, ChannelConfigEvent, ChannelEvent, ChannelInitOptions}, channel_group::{ChannelGroup, ChannelGroupConfig, ParallelismOptions, SynthEvent, SynthFormat, ThreadCount}, soundfont::{EnvelopeCurveType, EnvelopeOptions, Interpolator, SampleSoundfont, SoundfontBase, SoundfontInitOptions}, AudioPipe, AudioStreamParams, ChannelCount};
+use xsynth_core::{channel::{ChannelAudioEvent, ChannelConfigEvent, ChannelEvent, ChannelInitOptions, ControlEvent as SynthControlEvent}, channel_group::{ChannelGroup, ChannelGroupConfig, ParallelismOptions, SynthEvent, SynthFormat, ThreadCount}, soundfont::{SampleSoundfont, SoundfontBase, SoundfontInitOptions}, AudioPipe, AudioStreamParams, ChannelCount};
 
 use std::sync::atomic::AtomicBool;
-use crate::{audio, midi::events::{MIDIEvent, MIDIEventType}};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use crate::{audio::{audio_error::AudioError, channel_pitch::ChannelPitch, high_pass::HighPass, output_capture::OutputCapture, peak_meter::PeakMeter, soundfont_info::SoundfontInfo}, midi::{events::{MIDIEvent, MIDIEventType}, io::midi_output::MIDIOutput}};
+
+/// How far full modulation swings the pitch either way, in semitones. GM's default modulation depth.
+pub const DEFAULT_VIBRATO_DEPTH: f32 = 0.5;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum RenderMode {
@@ -13,6 +17,53 @@ pub enum RenderMode {
     Rendering
 }
 
+/// What the output is mixed down to. The synth always renders stereo, mono is summed from it on the way out.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMode {
+    #[default]
+    Stereo,
+    Mono
+}
+
+impl ChannelMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChannelMode::Stereo => "Stereo",
+            ChannelMode::Mono => "Mono"
+        }
+    }
+}
+
+/// The most sets of 16 MIDI channels the synth can be built with.
+pub const MAX_SYNTH_PORTS: u32 = 4;
+
+/// The synth format with `[ports]` sets of 16 MIDI channels, xsynth's standard MIDI format for one.
+pub fn synth_format(ports: u32) -> SynthFormat {
+    match ports.clamp(1, MAX_SYNTH_PORTS) {
+        1 => SynthFormat::Midi,
+        ports => SynthFormat::Custom { channels: ports * 16 }
+    }
+}
+
+/// How many sets of 16 MIDI channels `[format]` has.
+pub fn format_ports(format: SynthFormat) -> u32 {
+    match format {
+        SynthFormat::Midi => 1,
+        SynthFormat::Custom { channels } => (channels / 16).max(1)
+    }
+}
+
+/// The synth channel MIDI channel `[channel]` of `[track]` plays on. The tracks take turns at the `[ports]` sets of 16 channels,
+/// so with more than one, tracks using the same MIDI channel get instruments and controllers of their own.
+pub fn synth_channel(track: u16, channel: u8, ports: u32) -> u8 {
+    ((track as u32 % ports.max(1)) * 16 + (channel & 0x0F) as u32) as u8
+}
+
+/// Sums the left and right sample of a stereo `[frame]` at -3 dB, so a sound in the center keeps about its loudness.
+pub fn mono_sum(frame: &[f32]) -> f32 {
+    (frame[0] + frame[1]) * std::f32::consts::FRAC_1_SQRT_2
+}
+
 pub struct Limiter {
     loudness_l: f32,
     loudness_r: f32,
@@ -22,6 +73,10 @@ pub struct Limiter {
     pub falloff: f32,
     strength: f32,
     min_thresh: f32,
+    /// the interleaved samples waiting to be limited when looking ahead, empty when the limiter reacts right away.
+    /// The loudness is followed on the samples going in, so the gain is already down by the time a peak comes out
+    delay_line: Vec<f32>,
+    delay_pos: usize,
 }
 
 impl Limiter {
@@ -35,9 +90,30 @@ impl Limiter {
             falloff: release * sample_rate,
             strength: 1.0,
             min_thresh: 0.4,
+            delay_line: Vec::new(),
+            delay_pos: 0,
         }
     }
 
+    /// Delays the output by `[frames]` so the gain comes down ahead of peaks, 0 to react as they arrive.
+    /// Changing it drops what's in the delay, so it's only done when the length actually changes.
+    pub fn set_lookahead(&mut self, frames: usize) {
+        if self.delay_line.len() == frames * 2 { return; }
+        self.delay_line = vec![0.0; frames * 2];
+        self.delay_pos = 0;
+    }
+
+    /// Puts a frame into the delay line and returns the one coming out of it, or the same frame without lookahead.
+    fn delay(&mut self, l: f32, r: f32) -> (f32, f32) {
+        if self.delay_line.is_empty() { return (l, r); }
+        let pos = self.delay_pos;
+        let out = (self.delay_line[pos], self.delay_line[pos + 1]);
+        self.delay_line[pos] = l;
+        self.delay_line[pos + 1] = r;
+        self.delay_pos = (pos + 2) % self.delay_line.len();
+        out
+    }
+
     /// applies a filter to prevent audio clipping above 1 dB. 
     /// * `buffer` - the slice of the samples to apply the filter to
     pub fn apply_limiter(&mut self, buffer: &mut [f32]) -> () {
@@ -61,12 +137,13 @@ impl Limiter {
             if self.loudness_l < self.min_thresh { self.loudness_l = self.min_thresh; }
             if self.loudness_r < self.min_thresh { self.loudness_r = self.min_thresh; }
 
-            l = buffer[i] / (self.loudness_l * self.strength + 2.0 * (1.0 - self.strength)) / 2.0;
-            r = buffer[i + 1] / (self.loudness_r * self.strength + 2.0 * (1.0 - self.strength)) / 2.0;
+            let (out_l, out_r) = self.delay(buffer[i], buffer[i + 1]);
+            l = out_l / (self.loudness_l * self.strength + 2.0 * (1.0 - self.strength)) / 2.0;
+            r = out_r / (self.loudness_r * self.strength + 2.0 * (1.0 - self.strength)) / 2.0;
 
             if i != 0 {
-                let dl = (buffer[i] - l).abs();
-                let dr = (buffer[i+1] - r).abs();
+                let dl = (out_l - l).abs();
+                let dr = (out_r - r).abs();
 
                 if self.velocity_l > dl {
                     self.velocity_l = (self.velocity_l * self.falloff + dl) / (self.falloff + 1.0);
@@ -90,57 +167,108 @@ impl Limiter {
 struct PrerenderBuffer {
     pub read_pos: AtomicUsize,
     pub write_pos: AtomicUsize,
-    pub audio_buffer: Arc<Mutex<Vec<f32>>>,
+    /// interleaved stereo samples stored as their f32 bits, so the render thread and the audio callback
+    /// can share the ring without a lock. `write_pos` is only advanced after the samples are stored.
+    samples: Box<[AtomicU32]>,
     pub sample_rate: u32,
+    /// how quickly quiet notes are dropped when rendering falls behind, stored as f32 bits. 0.0 never drops any
+    skip_aggressiveness: AtomicU32,
+    /// soundfonts that finished loading while the render thread had the synth, swapped in between events
+    pending_soundfonts: Mutex<Option<Vec<Arc<dyn SoundfontBase>>>>,
+    /// the synth's active voices, as of the last time someone holding it looked
+    voice_count: AtomicU64,
 }
 
 impl PrerenderBuffer {
-    pub fn new(audio_buffer: Arc<Mutex<Vec<f32>>>, sample_rate: u32, buffer_length: f32) -> Self {
-        {
-            let mut buffer = audio_buffer.lock().unwrap();
-            *buffer = vec![0.0; (buffer_length * sample_rate as f32) as usize * 2]
-        }
+    pub fn new(sample_rate: u32, buffer_length: f32) -> Self {
+        let len = (buffer_length * sample_rate as f32) as usize * 2;
         Self {
             read_pos: AtomicUsize::new(0),
             write_pos: AtomicUsize::new(0),
-            audio_buffer: audio_buffer,
-            sample_rate
+            samples: (0..len).map(|_| AtomicU32::new(0)).collect(),
+            sample_rate,
+            skip_aggressiveness: AtomicU32::new(0.0f32.to_bits()),
+            pending_soundfonts: Mutex::new(None),
+            voice_count: AtomicU64::new(0)
         }
     }
 
+    /// The length of the ring in samples, twice the length in frames.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// The sample at `[index]`, wrapping around the ring.
+    pub fn sample(&self, index: usize) -> f32 {
+        f32::from_bits(self.samples[index % self.samples.len()].load(Ordering::Relaxed))
+    }
+
     /// Writes to the audio buffer, wrapping back to the beginning if start + count exceeds the buffer length.
-    pub fn write_wrapped(&self, xsynth: &mut ChannelGroup, start: usize, count: usize) {
-        {
-            let mut audio_buffer = self.audio_buffer.lock().unwrap();
-            let buff_len = audio_buffer.len();
-            let start = (start * 2) % buff_len; 
-            let mut count = count * 2;
-            if start + count > buff_len {
-                xsynth.read_samples(&mut audio_buffer[start..buff_len]);
-                count -= buff_len - start;
-                xsynth.read_samples(&mut audio_buffer[0..count]);
-            } else {
-                xsynth.read_samples(&mut audio_buffer[start..start+count]);
-            }
+    /// The samples are rendered into `[scratch]` first since xsynth needs a plain slice.
+    pub fn write_wrapped(&self, xsynth: &mut ChannelGroup, pitch: &mut ChannelPitch, start: usize, count: usize, scratch: &mut Vec<f32>) {
+        scratch.clear();
+        scratch.resize(count * 2, 0.0);
+        pitch.read_samples(xsynth, scratch);
+        self.store_wrapped(start, scratch);
+    }
+
+    /// Stores interleaved stereo `[samples]` from frame `[start]` on, wrapping around the ring.
+    fn store_wrapped(&self, start: usize, samples: &[f32]) {
+        let buff_len = self.samples.len();
+        let start = (start * 2) % buff_len;
+        for (i, sample) in samples.iter().enumerate() {
+            self.samples[(start + i) % buff_len].store(sample.to_bits(), Ordering::Relaxed);
         }
     }
 
+    /// Fills interleaved stereo `[out]` from `read_pos` with what's been rendered, and with silence past `write_pos`.
+    /// Always moves `read_pos` on by the whole of `[out]` so the audio stays in time with the playhead,
+    /// whatever the render thread was too late for is still rendered but never played.
+    /// Returns how many frames were rendered in time.
+    pub fn read_into(&self, out: &mut [f32]) -> usize {
+        let frames = out.len() / 2;
+        let read = self.read_pos.load(Ordering::SeqCst);
+        let write = self.write_pos.load(Ordering::SeqCst);
+        let available = write.saturating_sub(read).min(frames);
+
+        for (i, sample) in out[..available * 2].iter_mut().enumerate() {
+            *sample = self.sample(read * 2 + i);
+        }
+        // the render thread fell behind, play silence for what isn't there yet
+        out[available * 2..].fill(0.0);
+
+        self.read_pos.fetch_add(frames, Ordering::SeqCst);
+        available
+    }
+
     /// The function to render raw audio samples to the audio buffer.
-    pub fn generator_func(self: Arc<Self>, xsynth: Arc<Mutex<ChannelGroup>>, events: Vec<MIDIEvent>, reset_flag: Arc<AtomicBool>) {
+    /// `[vibrato_depth]` - how far full modulation swings the pitch, in semitones
+    pub fn generator_func(self: Arc<Self>, xsynth: Arc<Mutex<ChannelGroup>>, events: Vec<MIDIEvent>, vibrato_depth: f32, reset_flag: Arc<AtomicBool>) {
         self.write_pos.store(0, Ordering::SeqCst);
         self.read_pos.store(0, Ordering::SeqCst);
 
         let mut xsynth = xsynth.lock().unwrap();
 
-        let buf_len = {
-            let v = self.audio_buffer.lock().unwrap();
-            v.len()
-        };
+        let buf_len = self.len();
+        let mut scratch = Vec::new();
+        let mut pitch = ChannelPitch::new(vibrato_depth, self.sample_rate);
+
+        // a pedal the last playback left down shouldn't hold this one's notes
+        xsynth.send_event(SynthEvent::AllChannels(ChannelEvent::Audio(ChannelAudioEvent::ResetControl)));
 
         for e in events {
-            std::thread::sleep(Duration::from_millis(2));
             if reset_flag.load(Ordering::SeqCst) { break; }
 
+            // notes already playing keep the soundfont they started with until they're released
+            if let Ok(mut pending) = self.pending_soundfonts.try_lock()
+                && let Some(soundfonts) = pending.take() {
+                xsynth.send_event(SynthEvent::AllChannels(
+                    ChannelEvent::Config(ChannelConfigEvent::SetSoundfonts(soundfonts))));
+            }
+
+            // the audio callback already played silence for what wasn't rendered in time, carry on from where it is now
+            self.write_pos.fetch_max(self.read_pos.load(Ordering::SeqCst), Ordering::SeqCst);
+
             let offset_samples = 
                 (e.time * self.sample_rate as f32) as isize -  self.write_pos.load(Ordering::SeqCst) as isize;
             
@@ -154,67 +282,31 @@ impl PrerenderBuffer {
                         }
                         if spare != 0 {
                             let spare = spare as usize;
-                            self.write_wrapped(&mut xsynth, self.write_pos.load(Ordering::SeqCst), spare);
+                            self.write_wrapped(&mut xsynth, &mut pitch, self.write_pos.load(Ordering::SeqCst), spare, &mut scratch);
                             self.write_pos.fetch_add(spare, Ordering::SeqCst);
                             remaining -= spare;
                         }
                         if remaining == 0 { break; }
+                    } else {
+                        // the ring is full, wait for the audio callback to play some of it
+                        std::thread::sleep(Duration::from_millis(1));
                     }
                     if reset_flag.load(Ordering::SeqCst) {
                         break;
                     }
                 }
                 if remaining != 0 {
-                    self.write_wrapped(&mut xsynth, self.write_pos.load(Ordering::SeqCst), remaining);
+                    self.write_wrapped(&mut xsynth, &mut pitch, self.write_pos.load(Ordering::SeqCst), remaining, &mut scratch);
                 }
                 self.write_pos.fetch_add(remaining, Ordering::SeqCst);
             }
 
-            /*if self.write_pos < self.read_pos.load(Ordering::SeqCst) {
-                self.write_pos = self.read_pos.load(Ordering::SeqCst);
-            }
-            let ev_time = e.time;
-            let offset = ev_time;
-            let samples = (offset * self.sample_rate as f32) as isize - self.write_pos as isize;
-
-            if samples > 0 {
-                let mut samples = samples as usize;
-                while self.write_pos + samples > self.read_pos.load(Ordering::SeqCst) + audio_buffer_len / 2 {
-                    let mut spare = (self.read_pos.load(Ordering::SeqCst) + audio_buffer_len / 2) - self.write_pos;
-                    if spare > 0 {
-                        if spare > samples { spare = samples; }
-                        if spare != 0 {
-                            self.write_wrapped(&mut xsynth, self.write_pos, spare);
-                            samples -= spare;
-                            self.write_pos += spare;
-                        }
-                        if samples == 0 { break; }
-                    }
-                    std::thread::sleep(Duration::from_millis(1));
-                    if *reset_requested.lock().unwrap() { break; }
-                }
-                if samples != 0 {
-                    self.write_wrapped(&mut xsynth, self.write_pos, samples);
-                }
-                self.write_pos += samples;
-            }*/
-
-            match e.event_type {
-                MIDIEventType::NoteOn => {
-                    let vel = e.data[2];
-                    if vel < self.get_skipping_velocity() { continue; }
-                    (*xsynth).send_event(SynthEvent::Channel(
-                        (e.data[0] & 0xF) as u32, ChannelEvent::Audio(
-                            ChannelAudioEvent::NoteOn { key: e.data[1], vel: e.data[2] }
-                        )));
-                },
-                MIDIEventType::NoteOff => {
-                    (*xsynth).send_event(SynthEvent::Channel(
-                        (e.data[0] & 0xF) as u32, ChannelEvent::Audio(
-                            ChannelAudioEvent::NoteOff { key: e.data[1] }
-                        )));
-                }
-            }
+            self.voice_count.store(xsynth.voice_count(), Ordering::Relaxed);
+
+            if let MIDIEventType::NoteOn = e.event_type
+                && let Some(threshold) = self.get_skipping_velocity()
+                && e.data[2] > 0 && e.data[2] < threshold { continue; }
+            pitch.send(&mut xsynth, &e);
         }
 
         (*xsynth).send_event(SynthEvent::AllChannels(
@@ -222,116 +314,447 @@ impl PrerenderBuffer {
                 ChannelAudioEvent::AllNotesKilled
             )
         ));
+        // or the notes played live once it's stopped
+        xsynth.send_event(SynthEvent::AllChannels(ChannelEvent::Audio(ChannelAudioEvent::ResetControl)));
     }
 
-    pub fn get_skipping_velocity(&self) -> u8 {
+    /// The velocity below which note ons are dropped to let rendering catch up, or `None` if note skipping is off.
+    /// Starts out skipping every note and lowers as more audio is buffered ahead of playback.
+    pub fn get_skipping_velocity(&self) -> Option<u8> {
+        let aggressiveness = f32::from_bits(self.skip_aggressiveness.load(Ordering::Relaxed));
+        if aggressiveness <= 0.0 { return None; }
+
         let wr = self.write_pos.load(Ordering::SeqCst);
         let rd = self.read_pos.load(Ordering::SeqCst);
-        let mut diff = 127 + 10 - (wr as i32 - rd as i32) / 100;
-        if diff > 127 { diff = 127; }
-        if diff < 0 { diff = 0; }
-        diff as u8
+        let ahead = (wr as f32 - rd as f32) / (100.0 * aggressiveness);
+        Some((127.0 + 10.0 - ahead).clamp(0.0, 127.0) as u8)
+    }
+}
+
+/// Plays notes on the synth from other threads, like the MIDI input callback.
+#[derive(Clone)]
+pub struct SynthHandle {
+    xsynth: Arc<Mutex<ChannelGroup>>,
+    render_mode: Arc<Mutex<RenderMode>>,
+    midi_output: Arc<Mutex<MIDIOutput>>,
+}
+
+impl SynthHandle {
+    fn send_event(&self, event: SynthEvent) {
+        if !self.midi_output.lock().unwrap().plays_synth() { return; }
+        // the render thread holds the synth for the whole playback, so live notes can only be played while stopped
+        if *self.render_mode.lock().unwrap() != RenderMode::Realtime { return; }
+        if let Ok(mut xsynth) = self.xsynth.lock() {
+            xsynth.send_event(event);
+        }
+    }
+
+    /// Also plays on the MIDI output, if there is one, whether or not playback is running.
+    pub fn note_on(&self, channel: u32, key: u8, velocity: u8) {
+        self.midi_output.lock().unwrap().note_on(channel, key, velocity);
+        self.send_event(SynthEvent::Channel(channel,
+            ChannelEvent::Audio(ChannelAudioEvent::NoteOn { key, vel: velocity })));
+    }
+
+    pub fn note_off(&self, channel: u32, key: u8) {
+        self.midi_output.lock().unwrap().note_off(channel, key);
+        self.send_event(SynthEvent::Channel(channel,
+            ChannelEvent::Audio(ChannelAudioEvent::NoteOff { key })));
+    }
+
+    pub fn all_notes_off(&self) {
+        self.midi_output.lock().unwrap().all_notes_off();
+        self.send_event(SynthEvent::AllChannels(
+            ChannelEvent::Audio(ChannelAudioEvent::AllNotesOff)));
+    }
+}
+
+/// Plays `[e]` on the synth. A note on with velocity 0 is sent as a note off.
+/// The channel is the synth's, see `synth_channel`.
+pub fn send_midi_event(xsynth: &mut ChannelGroup, e: &MIDIEvent) {
+    let channel = e.data[0] as u32;
+    let event = match e.event_type {
+        MIDIEventType::NoteOn if e.data[2] > 0 => ChannelAudioEvent::NoteOn { key: e.data[1], vel: e.data[2] },
+        MIDIEventType::NoteOn | MIDIEventType::NoteOff => ChannelAudioEvent::NoteOff { key: e.data[1] },
+        // xsynth holds the released notes itself while the damper (CC 64) is down
+        MIDIEventType::ControlChange => ChannelAudioEvent::Control(SynthControlEvent::Raw(e.data[1], e.data[2])),
+        MIDIEventType::PitchBend => {
+            let value = ((e.data[2] as u16) << 7) | e.data[1] as u16;
+            ChannelAudioEvent::Control(SynthControlEvent::PitchBendValue(((value as f32 - 8192.0) / 8192.0).clamp(-1.0, 1.0)))
+        }
+    };
+    xsynth.send_event(SynthEvent::Channel(channel, ChannelEvent::Audio(event)));
+}
+
+/// How the live synth is set up, for building another one like it on a worker thread, like for rendering to a file.
+#[derive(Clone)]
+pub struct SynthSetup {
+    pub stream_params: AudioStreamParams,
+    parallelism: ParallelismOptions,
+    channel_init_options: ChannelInitOptions,
+    format: SynthFormat,
+    soundfonts: Vec<Arc<dyn SoundfontBase>>,
+    layer_count: Option<usize>,
+    /// the linear gain the live output is scaled by
+    pub master_gain: f32,
+    pub channel_mode: ChannelMode,
+    /// how far full modulation swings the pitch, in semitones
+    pub vibrato_depth: f32,
+    /// the cutoff of the high-pass on the output in Hz, `None` if it's off
+    pub high_pass_cutoff: Option<f32>,
+}
+
+impl SynthSetup {
+    /// A new synth with the soundfonts and layer count of the live one and no voices playing.
+    pub fn build(&self) -> ChannelGroup {
+        let mut synth = PrerenderedAudio::create_synth(self.stream_params, self.parallelism, self.channel_init_options, self.format);
+        synth.send_event(SynthEvent::AllChannels(
+            ChannelEvent::Config(ChannelConfigEvent::SetSoundfonts(self.soundfonts.clone()))));
+        if let Some(layer_count) = self.layer_count {
+            synth.send_event(SynthEvent::AllChannels(
+                ChannelEvent::Config(ChannelConfigEvent::SetLayerCount(Some(layer_count)))));
+        }
+        synth
     }
 }
 
+/// A loaded soundfont and what it says about itself.
+type LoadedSoundfont = (Arc<dyn SoundfontBase>, Option<SoundfontInfo>);
+
+/// A soundfont being loaded on a worker thread by `PrerenderedAudio::reload_soundfont`.
+/// xsynth reads the whole file in one call, so there's no fraction done, only how long it's been going.
+pub struct SoundfontLoad {
+    pub path: String,
+    /// the size of the soundfont file, `None` if it couldn't be read
+    pub file_size: Option<u64>,
+    pub started: Instant,
+    receiver: Receiver<Result<LoadedSoundfont, AudioError>>,
+}
+
 pub struct PrerenderedAudio {
     pub render_mode: Arc<Mutex<RenderMode>>,
     audio_buffer: Arc<PrerenderBuffer>,
 
     xsynth: Arc<Mutex<ChannelGroup>>,
+    /// what the synth was last given, so it can be set up the same way when it's rebuilt
+    soundfonts: Vec<Arc<dyn SoundfontBase>>,
+    layer_count: Option<usize>,
+    parallelism: ParallelismOptions,
+    channel_init_options: ChannelInitOptions,
+    format: SynthFormat,
     stream_params: AudioStreamParams,
     pub events: Arc<Mutex<Vec<MIDIEvent>>>,
-    device: Device,
+    /// frozen tracks, interleaved stereo samples from the start of the song mixed in while playing
+    clips: Arc<Mutex<Vec<Arc<[f32]>>>>,
+    /// the frame of the song playback starts at, where the clips are read from
+    clip_start: Arc<AtomicUsize>,
+    /// `None` when there is no usable output device, the synth still works but nothing is heard
+    device: Option<Device>,
+    device_error: Option<AudioError>,
     cfg: StreamConfig,
 
     generator_thread: Option<JoinHandle<()>>,
     reset_requested: Arc<AtomicBool>,
-    buffer: Arc<Mutex<Vec<f32>>>,
-    limiter: Arc<Mutex<Limiter>>
+    soundfont_load: Option<SoundfontLoad>,
+    /// what the soundfont in use says about itself, `None` if it has no such information or none is loaded yet
+    soundfont_info: Option<SoundfontInfo>,
+    limiter: Arc<Mutex<Limiter>>,
+    /// the linear gain applied to the output before the limiter, as f32 bits
+    master_gain: Arc<AtomicU32>,
+    /// if the output is summed to mono, see `ChannelMode`
+    mono: Arc<AtomicBool>,
+    /// the cutoff of the high-pass before the limiter in Hz as f32 bits, 0.0 when it's off
+    high_pass_cutoff: Arc<AtomicU32>,
+    /// how far full modulation swings the pitch, in semitones. Taken up when playback starts
+    vibrato_depth: f32,
+    meter: Arc<PeakMeter>,
+    capture: Arc<OutputCapture>,
+    /// the external synth playback and auditioned notes go to as well as, or instead of, the built-in one
+    midi_output: Arc<Mutex<MIDIOutput>>,
+    callback_load: Arc<AtomicU32>
 }
 
 impl PrerenderedAudio {
+    /// Opens the default output device. If there isn't one the synth runs at 48kHz with nothing to play to,
+    /// and `build_stream` returns why.
     pub fn new() -> Self {
-        let host = cpal::default_host();
-        let device = host.default_output_device().unwrap();
-        let cfg = device.default_output_config().unwrap();
-        let mut cfg: StreamConfig = cfg.into();
+        let (device, device_error, mut cfg) = match Self::open_output_device() {
+            Ok((device, cfg)) => (Some(device), None, cfg),
+            Err(err) => (None, Some(err), StreamConfig {
+                channels: 2,
+                sample_rate: cpal::SampleRate(48000),
+                buffer_size: BufferSize::Default
+            })
+        };
         cfg.buffer_size = BufferSize::Fixed(1024);
 
         let sr = cfg.sample_rate.0;
         let stream_params = AudioStreamParams::new(cfg.sample_rate.0, ChannelCount::Stereo);
-        let buffer = Arc::new(Mutex::new(Vec::new()));
+
+        let parallelism = ParallelismOptions {
+            channel: ThreadCount::Auto,
+            key: ThreadCount::None
+        };
+        let channel_init_options = ChannelInitOptions {
+            fade_out_killing: false
+        };
 
         let s = Self {
             render_mode: Arc::new(Mutex::new(RenderMode::Realtime)),
             audio_buffer: Arc::new(
-                PrerenderBuffer::new(buffer.clone(), sr, 60.0)
+                PrerenderBuffer::new(sr, 60.0)
             ),
-            xsynth: Arc::new(Mutex::new(ChannelGroup::new(
-                ChannelGroupConfig {
-                    channel_init_options: ChannelInitOptions {
-                        fade_out_killing: false
-                    },
-                    format: SynthFormat::Midi,
-                    audio_params: stream_params,
-                    parallelism: ParallelismOptions {
-                        channel: ThreadCount::Auto,
-                        key: ThreadCount::None
-                    }
-                }
-            ))),
+            xsynth: Arc::new(Mutex::new(Self::create_synth(stream_params, parallelism, channel_init_options, SynthFormat::Midi))),
+            soundfonts: Vec::new(),
+            layer_count: None,
+            parallelism,
+            channel_init_options,
+            format: SynthFormat::Midi,
             stream_params,
             device,
+            device_error,
             cfg,
             events: Arc::new(Mutex::new(Vec::new())),
+            clips: Arc::new(Mutex::new(Vec::new())),
+            clip_start: Arc::new(AtomicUsize::new(0)),
 
             generator_thread: None,
             reset_requested: Arc::new(AtomicBool::new(false)),
-            buffer,
-            limiter: Arc::new(Mutex::new(Limiter::new(0.01, 0.1, sr as f32)))
+            soundfont_load: None,
+            soundfont_info: None,
+            limiter: Arc::new(Mutex::new(Limiter::new(0.01, 0.1, sr as f32))),
+            master_gain: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            mono: Arc::new(AtomicBool::new(false)),
+            high_pass_cutoff: Arc::new(AtomicU32::new(0.0f32.to_bits())),
+            vibrato_depth: DEFAULT_VIBRATO_DEPTH,
+            meter: Arc::new(PeakMeter::default()),
+            capture: Arc::new(OutputCapture::default()),
+            midi_output: Arc::new(Mutex::new(MIDIOutput::new())),
+            callback_load: Arc::new(AtomicU32::new(0))
         };
         s
     }
 
-    pub fn load_soundfonts(&mut self, sfs: &[String]) {
-        let mut synth_soundfont: Vec<Arc<dyn SoundfontBase>> = Vec::new();
-        for sf in sfs {
-            synth_soundfont.push(Arc::new(
-                SampleSoundfont::new(Path::new(sf), self.stream_params, SoundfontInitOptions {
-                    bank: None,
-                    preset: None,
-                    vol_envelope_options: EnvelopeOptions {
-                        attack_curve: EnvelopeCurveType::Linear,
-                        decay_curve: EnvelopeCurveType::Linear,
-                        release_curve: EnvelopeCurveType::Linear
-                    },
-                    use_effects: false,
-                    interpolator: Interpolator::Linear
-                }).unwrap()
-            ))
+    fn create_synth(stream_params: AudioStreamParams, parallelism: ParallelismOptions, channel_init_options: ChannelInitOptions, format: SynthFormat) -> ChannelGroup {
+        let mut synth = ChannelGroup::new(
+            ChannelGroupConfig {
+                channel_init_options,
+                format,
+                audio_params: stream_params,
+                parallelism
+            }
+        );
+        // xsynth only makes channel 10 percussion in the MIDI format, every set of 16 has its own here
+        if let SynthFormat::Custom { .. } = format {
+            for port in 0..format_ports(format) {
+                synth.send_event(SynthEvent::Channel(port * 16 + 9,
+                    ChannelEvent::Config(ChannelConfigEvent::SetPercussionMode(true))));
+            }
+        }
+        synth
+    }
+
+    /// Replaces the synth with one built with `[parallelism]`, `[channel_init_options]` and `[format]`, keeping its soundfonts
+    /// and layer count. These can only be set when the synth is created. Stops playback first since the render thread holds
+    /// the synth while playing, so this is safe to call at any time.
+    pub fn rebuild_synth(&mut self, parallelism: ParallelismOptions, channel_init_options: ChannelInitOptions, format: SynthFormat) {
+        self.switch_render_mode(RenderMode::Realtime);
+
+        let mut synth = Self::create_synth(self.stream_params, parallelism, channel_init_options, format);
+        // the new synth gets them directly, so nothing is left for the render thread to swap in
+        self.audio_buffer.pending_soundfonts.lock().unwrap().take();
+        synth.send_event(SynthEvent::AllChannels(
+            ChannelEvent::Config(ChannelConfigEvent::SetSoundfonts(self.soundfonts.clone()))));
+        if let Some(layer_count) = self.layer_count {
+            synth.send_event(SynthEvent::AllChannels(
+                ChannelEvent::Config(ChannelConfigEvent::SetLayerCount(Some(layer_count)))));
         }
 
-        if let Ok(mut xsynth) = self.xsynth.lock() {
+        *self.xsynth.lock().unwrap() = synth;
+        self.parallelism = parallelism;
+        self.channel_init_options = channel_init_options;
+        self.format = format;
+    }
+
+    pub fn setup(&self) -> SynthSetup {
+        SynthSetup {
+            stream_params: self.stream_params,
+            parallelism: self.parallelism,
+            channel_init_options: self.channel_init_options,
+            format: self.format,
+            soundfonts: self.soundfonts.clone(),
+            layer_count: self.layer_count,
+            master_gain: f32::from_bits(self.master_gain.load(Ordering::Relaxed)),
+            channel_mode: self.channel_mode(),
+            vibrato_depth: self.vibrato_depth,
+            high_pass_cutoff: self.high_pass_cutoff()
+        }
+    }
+
+    /// If a soundfont has been loaded, without one everything renders silent.
+    pub fn has_soundfont(&self) -> bool {
+        !self.soundfonts.is_empty()
+    }
+
+    pub fn parallelism(&self) -> ParallelismOptions {
+        self.parallelism
+    }
+
+    pub fn channel_init_options(&self) -> ChannelInitOptions {
+        self.channel_init_options
+    }
+
+    pub fn format(&self) -> SynthFormat {
+        self.format
+    }
+
+    /// How many sets of 16 MIDI channels the synth has, for `synth_channel`.
+    pub fn ports(&self) -> u32 {
+        format_ports(self.format)
+    }
+
+    fn open_output_device() -> Result<(Device, StreamConfig), AudioError> {
+        let host = cpal::default_host();
+        let device = host.default_output_device().ok_or(AudioError::NoOutputDevice)?;
+        let cfg = device.default_output_config()?;
+        Ok((device, cfg.into()))
+    }
+
+    fn load_soundfont(path: &str, stream_params: AudioStreamParams, options: SoundfontInitOptions) -> Result<Arc<dyn SoundfontBase>, AudioError> {
+        let soundfont = SampleSoundfont::new(Path::new(path), stream_params, options)
+            .map_err(|err| AudioError::Soundfont { path: path.to_string(), err })?;
+        Ok(Arc::new(soundfont))
+    }
+
+    /// Starts loading the soundfont at `[path]` on a worker thread so big SF2s don't freeze the UI.
+    /// The current soundfont keeps playing until `poll_soundfont` swaps the new one in.
+    /// Also used to apply new `[options]` to the current soundfont, since they're baked in when it's loaded.
+    pub fn reload_soundfont(&mut self, path: String, options: SoundfontInitOptions) {
+        let (sender, receiver) = mpsc::channel();
+        let stream_params = self.stream_params;
+        let thread_path = path.clone();
+        std::thread::spawn(move || {
+            // the receiver is gone if another soundfont was chosen in the meantime
+            let loaded = Self::load_soundfont(&thread_path, stream_params, options)
+                .map(|soundfont| (soundfont, SoundfontInfo::read(&thread_path)));
+            let _ = sender.send(loaded);
+        });
+        self.soundfont_load = Some(SoundfontLoad {
+            file_size: std::fs::metadata(&path).ok().map(|metadata| metadata.len()),
+            path,
+            started: Instant::now(),
+            receiver
+        });
+    }
+
+    /// The soundfont `reload_soundfont` is loading, if any.
+    pub fn soundfont_load(&self) -> Option<&SoundfontLoad> {
+        self.soundfont_load.as_ref()
+    }
+
+    /// Swaps in the soundfont from `reload_soundfont` once it's loaded, returning its path and the load result.
+    /// If it failed the current soundfont is kept.
+    pub fn poll_soundfont(&mut self) -> Option<(String, Result<(), AudioError>)> {
+        self.apply_pending_soundfonts();
+
+        let result = match self.soundfont_load.as_ref()?.receiver.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => {
+                self.soundfont_load = None;
+                return None;
+            }
+        };
+        let path = self.soundfont_load.take().map(|load| load.path).unwrap_or_default();
+
+        let (soundfont, info) = match result {
+            Ok(loaded) => loaded,
+            Err(err) => return Some((path, Err(err)))
+        };
+        self.queue_soundfonts(vec![soundfont]);
+        self.soundfont_info = info;
+        Some((path, Ok(())))
+    }
+
+    pub fn soundfont_info(&self) -> Option<&SoundfontInfo> {
+        self.soundfont_info.as_ref()
+    }
+
+    fn queue_soundfonts(&mut self, soundfonts: Vec<Arc<dyn SoundfontBase>>) {
+        self.soundfonts = soundfonts.clone();
+        *self.audio_buffer.pending_soundfonts.lock().unwrap() = Some(soundfonts);
+        self.apply_pending_soundfonts();
+    }
+
+    /// Gives the synth any loaded soundfonts if it's free, otherwise the render thread picks them up.
+    fn apply_pending_soundfonts(&mut self) {
+        let Ok(mut xsynth) = self.xsynth.try_lock() else { return; };
+        if let Some(soundfonts) = self.audio_buffer.pending_soundfonts.lock().unwrap().take() {
             xsynth.send_event(
                 SynthEvent::AllChannels(
                     ChannelEvent::Config(
-                        ChannelConfigEvent::SetSoundfonts(
-                            synth_soundfont.clone()
-                        )
+                        ChannelConfigEvent::SetSoundfonts(soundfonts)
                     )
                 )
             );
         }
     }
 
-    /// Sets the MIDI events for the Prerenderer to loop through when rendering. Ineffective if `[events]` has a length of zero.
+    /// Sets the MIDI events for the Prerenderer to loop through when rendering.
+    /// The render thread takes them when it starts, so set them again before every playback.
     pub fn set_events(&mut self, events: Vec<MIDIEvent>) {
-        if events.len() > 0 {
-            *self.events.lock().unwrap() = events;
-        }
+        *self.events.lock().unwrap() = events;
+    }
+
+    /// Sets the frozen tracks mixed in from the next playback, which starts `[start_secs]` into the song.
+    /// Set them again with the events before every playback.
+    pub fn set_clips(&mut self, clips: Vec<Arc<[f32]>>, start_secs: f32) {
+        *self.clips.lock().unwrap() = clips;
+        self.clip_start.store((start_secs.max(0.0) * self.stream_params.sample_rate as f32) as usize, Ordering::SeqCst);
+    }
+
+    /// Lets the render thread drop quiet notes when it falls behind, which keeps huge MIDIs playable.
+    /// `None` turns it off so every note is heard. Applies from the next playback.
+    pub fn set_note_skipping(&mut self, aggressiveness: Option<f32>) {
+        let aggressiveness = aggressiveness.unwrap_or(0.0).max(0.0);
+        self.audio_buffer.skip_aggressiveness.store(aggressiveness.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Scales the output by `[gain]` before it reaches the limiter, 1.0 leaves it as it is. Applies right away.
+    pub fn set_master_gain(&mut self, gain: f32) {
+        self.master_gain.store(gain.max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn set_channel_mode(&mut self, mode: ChannelMode) {
+        self.mono.store(mode == ChannelMode::Mono, Ordering::Relaxed);
+    }
+
+    pub fn channel_mode(&self) -> ChannelMode {
+        if self.mono.load(Ordering::Relaxed) { ChannelMode::Mono } else { ChannelMode::Stereo }
+    }
+
+    /// Delays the output by `[secs]` so the limiter turns down ahead of peaks, or reacts as they come if `None`.
+    /// The delay is heard as latency on everything played live, so it should stay at a few milliseconds.
+    pub fn set_limiter_lookahead(&mut self, secs: Option<f32>) {
+        let frames = (secs.unwrap_or(0.0).max(0.0) * self.cfg.sample_rate.0 as f32) as usize;
+        self.limiter.lock().unwrap().set_lookahead(frames);
+    }
+
+    /// Filters out what's below `[cutoff]` Hz before the limiter, or nothing if `None`. Applies right away.
+    pub fn set_high_pass(&mut self, cutoff: Option<f32>) {
+        self.high_pass_cutoff.store(cutoff.unwrap_or(0.0).max(0.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn high_pass_cutoff(&self) -> Option<f32> {
+        Some(f32::from_bits(self.high_pass_cutoff.load(Ordering::Relaxed))).filter(|cutoff| *cutoff > 0.0)
+    }
+
+    pub fn set_vibrato_depth(&mut self, depth: f32) {
+        self.vibrato_depth = depth.max(0.0);
     }
 
     pub fn set_layer_count(&mut self, layer_count: usize) {
+        self.layer_count = Some(layer_count);
         if let Ok(mut xsynth) = self.xsynth.lock() {
             xsynth.send_event(
                 SynthEvent::AllChannels(
@@ -346,6 +769,9 @@ impl PrerenderedAudio {
     }
 
     pub fn note_on(&mut self, channel: u32, key: u8, velocity: u8) {
+        let midi_output = self.midi_output.lock().unwrap();
+        midi_output.note_on(channel, key, velocity);
+        if !midi_output.plays_synth() { return; }
         if let Ok(mut xsynth) = self.xsynth.lock() {
             xsynth.send_event(
                 SynthEvent::Channel(channel, 
@@ -358,6 +784,9 @@ impl PrerenderedAudio {
     }
 
     pub fn note_off(&mut self, channel: u32, key: u8) {
+        let midi_output = self.midi_output.lock().unwrap();
+        midi_output.note_off(channel, key);
+        if !midi_output.plays_synth() { return; }
         if let Ok(mut xsynth) = self.xsynth.lock() {
             xsynth.send_event(
                 SynthEvent::Channel(channel, 
@@ -370,16 +799,54 @@ impl PrerenderedAudio {
     }
 
 
-    pub fn build_stream(&mut self) -> cpal::Stream {
+    /// Silences every voice at once and resets the controllers and pitch bend on all channels, for when notes get stuck.
+    /// Switches to `RenderMode::Realtime` first if the render thread has the synth, which also drops what it prerendered.
+    pub fn panic(&mut self) {
+        if *self.render_mode.lock().unwrap() == RenderMode::Rendering {
+            self.switch_render_mode(RenderMode::Realtime);
+        }
+        self.midi_output.lock().unwrap().panic();
+        if let Ok(mut xsynth) = self.xsynth.lock() {
+            xsynth.send_event(SynthEvent::AllChannels(ChannelEvent::Audio(ChannelAudioEvent::AllNotesKilled)));
+            xsynth.send_event(SynthEvent::AllChannels(ChannelEvent::Audio(ChannelAudioEvent::ResetControl)));
+        }
+    }
+
+    pub fn handle(&self) -> SynthHandle {
+        SynthHandle {
+            xsynth: self.xsynth.clone(),
+            render_mode: self.render_mode.clone(),
+            midi_output: self.midi_output.clone()
+        }
+    }
+
+    pub fn build_stream(&mut self) -> Result<cpal::Stream, AudioError> {
+        let Some(device) = self.device.as_ref() else {
+            return Err(self.device_error.take().unwrap_or(AudioError::NoOutputDevice));
+        };
+
         let xs = self.xsynth.clone();
         let rm = self.render_mode.clone();
         let rr = self.reset_requested.clone();
         let lim = self.limiter.clone();
+        let gain = self.master_gain.clone();
+        let mono = self.mono.clone();
+        let high_pass_cutoff = self.high_pass_cutoff.clone();
+        let sample_rate = self.cfg.sample_rate.0;
+        // the filter's state lives with the callback, so it carries on from one block to the next
+        let mut high_pass = HighPass::new(0.0, sample_rate);
+        let mut filter_cutoff = 0.0;
+        let meter = self.meter.clone();
+        let capture = self.capture.clone();
+        let clips = self.clips.clone();
+        let clip_start = self.clip_start.clone();
 
         let audio_buffer = Arc::clone(&self.audio_buffer);
-        let buffer = self.buffer.clone();
+        let load = self.callback_load.clone();
+        let frame_rate = self.cfg.sample_rate.0 as f32 * self.cfg.channels as f32;
 
-        self.device.build_output_stream(&self.cfg, move |data: &mut [f32], _| {
+        let stream = device.build_output_stream(&self.cfg, move |data: &mut [f32], _| {
+            let callback_start = Instant::now();
             let mode = *rm.lock().unwrap();
             match mode {
                 RenderMode::Realtime => {
@@ -387,62 +854,111 @@ impl PrerenderedAudio {
                         .read_samples(data);
                 },
                 RenderMode::Rendering => {
-                    let count = data.len();
                     if rr.load(Ordering::SeqCst) {
                         data.fill(0.0);
                         return;
                     }
 
-                    //let rp = audio_buffer.read_pos.load(Ordering::SeqCst);
-                    //let wp = audio_buffer.write_pos.load(Ordering::SeqCst);
-                    let read = { 
-                        let buf = buffer.lock().unwrap();
-                        audio_buffer.read_pos.load(Ordering::SeqCst) % (buf.len() / 2)
-                    };
-                    if audio_buffer.read_pos.load(Ordering::SeqCst) + count / 2 > audio_buffer.write_pos.load(Ordering::SeqCst) {
-                        let mut copy_count = audio_buffer.read_pos.load(Ordering::SeqCst) as isize - (audio_buffer.write_pos.load(Ordering::SeqCst) + count / 2) as isize;
-                        if copy_count > count as isize / 2 {
-                            copy_count = count as isize / 2;
-                        }
-                        if copy_count > 0 {
-                            let buf = buffer.lock().unwrap();
-                            for i in 0..(copy_count * 2) {
-                                let i = i as usize;
-                                data[i] = buf[(i + read * 2) % buf.len()];
-                            }
-                        } else {
-                            copy_count = 0;
-                        }
-                        for i in (copy_count * 2)..(count as isize) {
-                            data[i as usize] = 0.0;
-                        }
-                    } else {
-                        let buf = buffer.lock().unwrap();
-                        for i in 0..count {
-                            data[i] = buf[(i + read * 2) % buf.len()];
+                    // the frame this callback starts at, the clips are read from there too
+                    let read = audio_buffer.read_pos.load(Ordering::SeqCst);
+                    audio_buffer.read_into(data);
+
+                    // a clip is never held for long, but if it is this callback goes without rather than waiting
+                    if let Ok(clips) = clips.try_lock() {
+                        let start = (clip_start.load(Ordering::SeqCst) + read) * 2;
+                        for clip in clips.iter().filter(|clip| start < clip.len()) {
+                            data.iter_mut().zip(&clip[start..]).for_each(|(sample, clip_sample)| *sample += clip_sample);
                         }
                     }
-
-                    audio_buffer.read_pos
-                        .fetch_add(data.len() / 2, Ordering::SeqCst);
                 }
             }
+            let gain = f32::from_bits(gain.load(Ordering::Relaxed));
+            if gain != 1.0 {
+                data.iter_mut().for_each(|sample| *sample *= gain);
+            }
+            let cutoff = f32::from_bits(high_pass_cutoff.load(Ordering::Relaxed));
+            if cutoff > 0.0 {
+                if cutoff != filter_cutoff {
+                    high_pass.set_cutoff(cutoff, sample_rate);
+                    filter_cutoff = cutoff;
+                }
+                high_pass.process(data);
+            }
+            // both sides get the sum, so the device is fed the same channels either way
+            if mono.load(Ordering::Relaxed) {
+                for frame in data.chunks_exact_mut(2) {
+                    let sum = mono_sum(frame);
+                    frame.fill(sum);
+                }
+            }
+            meter.record(data);
+            capture.record(data);
             lim.lock().unwrap().apply_limiter(data);
+
+            let budget = data.len() as f32 / frame_rate;
+            let used = callback_start.elapsed().as_secs_f32() / budget;
+            load.store(used.to_bits(), Ordering::Relaxed);
         }, |err| {
             println!("{}", err.to_string());
-        }, None).unwrap()
+        }, None)?;
+        Ok(stream)
+    }
+
+    /// How many voices the synth is playing. The render thread holds the synth while rendering,
+    /// so then this is the count at the render position, slightly ahead of what's heard.
+    pub fn voice_count(&self) -> u64 {
+        if let Ok(xsynth) = self.xsynth.try_lock() {
+            self.audio_buffer.voice_count.store(xsynth.voice_count(), Ordering::Relaxed);
+        }
+        self.audio_buffer.voice_count.load(Ordering::Relaxed)
+    }
+
+    /// The output levels, for showing a meter.
+    pub fn meter(&self) -> &PeakMeter {
+        &self.meter
+    }
+
+    /// Where playback and auditioned notes are sent besides the built-in synth, for choosing the port.
+    pub fn midi_output(&self) -> &Arc<Mutex<MIDIOutput>> {
+        &self.midi_output
+    }
+
+    /// The most recent output, for drawing the waveform and spectrum.
+    pub fn capture(&self) -> &Arc<OutputCapture> {
+        &self.capture
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.cfg.sample_rate.0
+    }
+
+    /// The fraction of the last audio callback's time budget spent producing samples.
+    /// Values approaching 1.0 mean the audio thread is about to underrun.
+    pub fn get_audio_load(&self) -> f32 {
+        f32::from_bits(self.callback_load.load(Ordering::Relaxed))
     }
 
     pub fn start_render_thread(&mut self) -> std::thread::JoinHandle<()> {
         let pr = self.audio_buffer.clone();
         let xsynth = self.xsynth.clone();
-        let evs = std::mem::take(&mut *self.events.lock().unwrap());
+        let mut evs = std::mem::take(&mut *self.events.lock().unwrap());
+        {
+            // the external synth is sent the events as they come due, the render thread works ahead of time
+            let mut midi_output = self.midi_output.lock().unwrap();
+            if midi_output.is_connected() {
+                midi_output.play(evs.clone());
+            }
+            if !midi_output.plays_synth() {
+                evs.clear();
+            }
+        }
 
         let rr = self.reset_requested.clone();
+        let vibrato_depth = self.vibrato_depth;
 
         std::thread::spawn(move || {
             //audio_buffer.lock().unwrap().generator_func(xsynth, evs, rr);
-            pr.generator_func(xsynth, evs, rr);
+            pr.generator_func(xsynth, evs, vibrato_depth, rr);
         })
     }
 
@@ -461,6 +977,7 @@ impl PrerenderedAudio {
 
     pub fn stop(&mut self) {
         self.kill_last_generator();
+        self.midi_output.lock().unwrap().stop();
         self.reset_requested.store(true, Ordering::SeqCst);
         self.generator_thread = None;
         self.audio_buffer.read_pos.store(0, Ordering::SeqCst);
@@ -483,4 +1000,66 @@ impl PrerenderedAudio {
             *render_mode = rm;
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::PrerenderBuffer;
+
+    /// Interleaved stereo frames `[frames]`, the left sample of each its index and the right one its negative.
+    fn ramp(frames: std::ops::Range<usize>) -> Vec<f32> {
+        frames.flat_map(|frame| [frame as f32, -(frame as f32)]).collect()
+    }
+
+    /// A ring 8 frames long.
+    fn ring() -> PrerenderBuffer {
+        PrerenderBuffer::new(8, 1.0)
+    }
+
+    #[test]
+    fn reads_what_was_written() {
+        let buffer = ring();
+        buffer.store_wrapped(0, &ramp(0..6));
+        buffer.write_pos.store(6, Ordering::SeqCst);
+
+        let mut out = vec![1.0; 8];
+        assert_eq!(buffer.read_into(&mut out), 4);
+        assert_eq!(out, ramp(0..4));
+
+        // only 2 of these are rendered, the rest is silence and the read position still moves on by all 4
+        assert_eq!(buffer.read_into(&mut out), 2);
+        assert_eq!(out[..4], ramp(4..6)[..]);
+        assert_eq!(out[4..], [0.0; 4]);
+        assert_eq!(buffer.read_pos.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn reads_across_the_end_of_the_ring() {
+        let buffer = ring();
+        buffer.store_wrapped(0, &ramp(0..6));
+        buffer.write_pos.store(6, Ordering::SeqCst);
+        buffer.read_into(&mut [0.0; 12]);
+
+        // frames 8 to 11 land at the start of the ring again
+        buffer.store_wrapped(6, &ramp(6..12));
+        buffer.write_pos.store(12, Ordering::SeqCst);
+        let mut out = vec![0.0; 12];
+        assert_eq!(buffer.read_into(&mut out), 6);
+        assert_eq!(out, ramp(6..12));
+    }
+
+    #[test]
+    fn plays_silence_once_read_passes_write() {
+        let buffer = ring();
+        buffer.store_wrapped(0, &ramp(0..8));
+        buffer.write_pos.store(8, Ordering::SeqCst);
+        buffer.read_pos.store(10, Ordering::SeqCst);
+
+        let mut out = vec![1.0; 8];
+        assert_eq!(buffer.read_into(&mut out), 0);
+        assert_eq!(out, [0.0; 8]);
+        assert_eq!(buffer.read_pos.load(Ordering::SeqCst), 14);
+    }
+}
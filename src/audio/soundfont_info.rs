@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// The size of a preset header record in the SF2 `phdr` chunk.
+const PRESET_HEADER_SIZE: u32 = 38;
+/// The size of a sample header record in the SF2 `shdr` chunk.
+const SAMPLE_HEADER_SIZE: u32 = 46;
+
+/// One of the instruments a soundfont offers, selected by bank and program change.
+#[derive(Clone)]
+pub struct PresetInfo {
+    pub bank: u16,
+    pub preset: u16,
+    pub name: String,
+}
+
+/// What a soundfont says about itself. xsynth doesn't keep any of this, so it's read from the file separately.
+#[derive(Clone, Default)]
+pub struct SoundfontInfo {
+    /// the name in the soundfont's INFO chunk, `None` if it has none
+    pub name: Option<String>,
+    /// sorted by bank then preset
+    pub presets: Vec<PresetInfo>,
+    pub sample_count: usize,
+}
+
+impl SoundfontInfo {
+    /// Reads the name, presets and sample count of an SF2 without loading its samples.
+    /// Returns `None` for SFZs and anything else that isn't an SF2, they don't carry this information.
+    pub fn read(path: impl AsRef<Path>) -> Option<Self> {
+        let path = path.as_ref();
+        let is_sf2 = path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("sf2"));
+        if !is_sf2 { return None; }
+
+        let mut rdr = BufReader::new(File::open(path).ok()?);
+        Self::read_sf2(&mut rdr).ok()
+    }
+
+    fn read_sf2(rdr: &mut (impl Read + Seek)) -> io::Result<Self> {
+        let (magic, _) = read_chunk_header(rdr)?;
+        let mut form = [0u8; 4];
+        rdr.read_exact(&mut form)?;
+        if &magic != b"RIFF" || &form != b"sfbk" {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an SF2"));
+        }
+
+        let mut info = Self::default();
+        // the top level is a run of LIST chunks, the sample data one is skipped without reading it
+        while let Ok((magic, len)) = read_chunk_header(rdr) {
+            let end = rdr.stream_position()? + len as u64 + (len & 1) as u64;
+            if &magic == b"LIST" {
+                let mut list_type = [0u8; 4];
+                rdr.read_exact(&mut list_type)?;
+                match &list_type {
+                    b"INFO" | b"pdta" => info.read_list(rdr, end)?,
+                    _ => {}
+                }
+            }
+            rdr.seek(SeekFrom::Start(end))?;
+        }
+
+        info.presets.sort_by_key(|preset| (preset.bank, preset.preset));
+        Ok(info)
+    }
+
+    /// Reads the sub-chunks of an INFO or pdta list ending at `[end]`.
+    fn read_list(&mut self, rdr: &mut (impl Read + Seek), end: u64) -> io::Result<()> {
+        while rdr.stream_position()? + 8 <= end {
+            let (magic, len) = read_chunk_header(rdr)?;
+            let next = rdr.stream_position()? + len as u64 + (len & 1) as u64;
+            match &magic {
+                b"INAM" => {
+                    let mut name = vec![0u8; len as usize];
+                    rdr.read_exact(&mut name)?;
+                    let name = fixed_string(&name);
+                    self.name = (!name.is_empty()).then_some(name);
+                }
+                b"phdr" => {
+                    // the last record only marks the end of the list
+                    for _ in 0..(len / PRESET_HEADER_SIZE).saturating_sub(1) {
+                        let mut record = [0u8; PRESET_HEADER_SIZE as usize];
+                        rdr.read_exact(&mut record)?;
+                        self.presets.push(PresetInfo {
+                            name: fixed_string(&record[..20]),
+                            preset: u16::from_le_bytes([record[20], record[21]]),
+                            bank: u16::from_le_bytes([record[22], record[23]])
+                        });
+                    }
+                }
+                b"shdr" => {
+                    // the last record only marks the end of the list
+                    self.sample_count = (len / SAMPLE_HEADER_SIZE).saturating_sub(1) as usize;
+                }
+                _ => {}
+            }
+            rdr.seek(SeekFrom::Start(next))?;
+        }
+        Ok(())
+    }
+}
+
+fn read_chunk_header(rdr: &mut impl Read) -> io::Result<([u8; 4], u32)> {
+    let mut header = [0u8; 8];
+    rdr.read_exact(&mut header)?;
+    let magic = [header[0], header[1], header[2], header[3]];
+    Ok((magic, u32::from_le_bytes([header[4], header[5], header[6], header[7]])))
+}
+
+/// A zero padded string from a fixed size field.
+fn fixed_string(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).trim().to_string()
+}
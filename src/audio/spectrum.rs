@@ -0,0 +1,166 @@
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use rustfft::{num_complex::Complex, FftPlanner};
+
+use super::output_capture::OutputCapture;
+
+/// The FFT sizes that can be picked, all of them fit in the `OutputCapture`.
+pub const FFT_SIZES: [usize; 4] = [1024, 2048, 4096, 8192];
+/// How often the spectrum is recomputed, about 30 times a second.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(33);
+/// How fast a bin falls back once it's louder than the next update, in dB per update.
+const FALL_DB: f32 = 3.0;
+/// The quietest level shown, bins are never quieter than this.
+pub const FLOOR_DB: f32 = -120.0;
+
+/// The window the samples are shaped with before the FFT. The smoother ones leak less between bins but blur peaks more.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum FftWindow {
+    Rectangular,
+    #[default]
+    Hann,
+    BlackmanHarris
+}
+
+impl FftWindow {
+    pub const ALL: [FftWindow; 3] = [FftWindow::Rectangular, FftWindow::Hann, FftWindow::BlackmanHarris];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FftWindow::Rectangular => "Rectangular",
+            FftWindow::Hann => "Hann",
+            FftWindow::BlackmanHarris => "Blackman-Harris"
+        }
+    }
+
+    /// The `[size]` weights of the window.
+    fn coefficients(&self, size: usize) -> Vec<f32> {
+        let phase = |i: usize| 2.0 * PI * i as f32 / size as f32;
+        (0..size).map(|i| match self {
+            FftWindow::Rectangular => 1.0,
+            FftWindow::Hann => 0.5 - 0.5 * phase(i).cos(),
+            FftWindow::BlackmanHarris => 0.35875 - 0.48829 * phase(i).cos() + 0.14128 * (2.0 * phase(i)).cos()
+                - 0.01168 * (3.0 * phase(i)).cos()
+        }).collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SpectrumSettings {
+    pub fft_size: usize,
+    pub window: FftWindow
+}
+
+impl Default for SpectrumSettings {
+    fn default() -> Self {
+        Self {
+            fft_size: 4096,
+            window: FftWindow::default()
+        }
+    }
+}
+
+/// An FFT planned for `settings` and the window it's shaped with, kept until the settings change.
+struct PlannedFft {
+    settings: SpectrumSettings,
+    fft: Arc<dyn rustfft::Fft<f32>>,
+    window: Vec<f32>
+}
+
+/// Computes the spectrum of the output on a thread of its own from what the `OutputCapture` holds.
+/// The thread stops when this is dropped.
+pub struct SpectrumAnalyzer {
+    settings: Arc<Mutex<SpectrumSettings>>,
+    paused: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    /// the level of every bin from 0Hz up to half the sample rate in dB, `fft_size / 2` of them
+    levels: Arc<Mutex<Vec<f32>>>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl SpectrumAnalyzer {
+    pub fn start(capture: Arc<OutputCapture>, settings: SpectrumSettings) -> Self {
+        let settings = Arc::new(Mutex::new(settings));
+        let paused = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let levels = Arc::new(Mutex::new(Vec::new()));
+
+        let thread = {
+            let (settings, paused, stop, levels) = (settings.clone(), paused.clone(), stop.clone(), levels.clone());
+            std::thread::spawn(move || {
+                let mut planner = FftPlanner::new();
+                let mut planned: Option<PlannedFft> = None;
+                let mut last_written = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(UPDATE_INTERVAL);
+                    let written = capture.frames_written();
+                    if paused.load(Ordering::Relaxed) || written == last_written { continue; }
+                    last_written = written;
+
+                    let settings = *settings.lock().unwrap();
+                    if planned.as_ref().is_none_or(|planned| planned.settings != settings) {
+                        planned = Some(PlannedFft {
+                            settings,
+                            fft: planner.plan_fft_forward(settings.fft_size),
+                            window: settings.window.coefficients(settings.fft_size)
+                        });
+                    }
+                    let Some(PlannedFft { fft, window, .. }) = planned.as_ref() else { continue; };
+
+                    let frames = capture.latest(settings.fft_size);
+                    if frames.len() < settings.fft_size { continue; }
+                    let mut buffer = frames.iter().zip(window)
+                        .map(|(frame, weight)| Complex::new((frame[0] + frame[1]) * 0.5 * weight, 0.0))
+                        .collect::<Vec<_>>();
+                    fft.process(&mut buffer);
+
+                    // scaled so a full scale sine reads 0dB whatever the window
+                    let scale = 2.0 / window.iter().sum::<f32>();
+                    let mut levels = levels.lock().unwrap();
+                    if levels.len() != settings.fft_size / 2 {
+                        *levels = vec![FLOOR_DB; settings.fft_size / 2];
+                    }
+                    for (level, bin) in levels.iter_mut().zip(&buffer) {
+                        let db = (20.0 * (bin.norm() * scale).max(1e-9).log10()).max(FLOOR_DB);
+                        *level = db.max(*level - FALL_DB);
+                    }
+                }
+            })
+        };
+
+        Self {
+            settings,
+            paused,
+            stop,
+            levels,
+            thread: Some(thread)
+        }
+    }
+
+    pub fn set_settings(&self, settings: SpectrumSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+
+    /// Keeps the spectrum as it is until unpaused, like while nothing is playing.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// The level of every bin in dB, from 0Hz up to half the sample rate. Empty until the first update.
+    pub fn levels(&self) -> Vec<f32> {
+        self.levels.lock().unwrap().clone()
+    }
+}
+
+impl Drop for SpectrumAnalyzer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
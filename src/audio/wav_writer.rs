@@ -0,0 +1,57 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The WAV format tag for IEEE float samples.
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// Streams interleaved 32-bit float samples to a WAV file, so long renders don't have to fit in memory.
+/// The sizes in the header are only known at the end, `finish` fills them in.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    channels: u16,
+    /// samples written so far, across all channels
+    sample_count: u64,
+}
+
+impl WavWriter {
+    pub fn create(path: impl AsRef<Path>, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = channels * 4;
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&32u16.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { file, channels, sample_count: 0 })
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.sample_count += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header and flushes the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        // a partial frame would leave the data misaligned, WAV sizes are whole frames
+        let frames = self.sample_count / self.channels.max(1) as u64;
+        let data_len = u32::try_from(frames * self.channels as u64 * 4)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "too long for a WAV file"))?;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(data_len + 36).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&data_len.to_le_bytes())?;
+        self.file.flush()
+    }
+}
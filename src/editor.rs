@@ -1,3 +1,15 @@
 pub mod navigation;
 pub mod settings;
-pub mod project_settings;
\ No newline at end of file
+pub mod project_settings;
+pub mod keyboard_audition;
+pub mod track_list;
+pub mod edit_tool;
+pub mod recorder;
+pub mod markers;
+pub mod note_tools;
+pub mod grid;
+pub mod layout;
+pub mod tap_tempo;
+pub mod tempo_lane;
+pub mod minimap;
+pub mod theme;
@@ -0,0 +1,11 @@
+/// What dragging with the left mouse button in the piano roll does.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum EditTool {
+    /// click notes to select them, drag on empty space to select everything in a rectangle
+    #[default]
+    Pointer,
+    /// play the key under the pointer while the button is held
+    Audition,
+    /// click empty space to add a note one grid step long, click a note to select it
+    Pencil
+}
@@ -0,0 +1,55 @@
+/// The note value the editing grid is divided into, like 1/16 or 1/8 triplets.
+/// Moving notes snaps to it and the piano roll draws its lines.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct GridResolution {
+    /// 4 for quarter notes
+    pub denominator: u16,
+    /// three notes in the time of two
+    pub triplet: bool,
+}
+
+impl Default for GridResolution {
+    fn default() -> Self {
+        Self::straight(16)
+    }
+}
+
+impl GridResolution {
+    /// Every resolution offered in the grid picker, coarsest first.
+    pub const CHOICES: [GridResolution; 12] = [
+        Self::straight(1), Self::triplet(1),
+        Self::straight(2), Self::triplet(2),
+        Self::straight(4), Self::triplet(4),
+        Self::straight(8), Self::triplet(8),
+        Self::straight(16), Self::triplet(16),
+        Self::straight(32), Self::triplet(32),
+    ];
+
+    const fn straight(denominator: u16) -> Self {
+        Self { denominator, triplet: false }
+    }
+
+    const fn triplet(denominator: u16) -> Self {
+        Self { denominator, triplet: true }
+    }
+
+    /// The length of a grid step in ticks, never less than one tick.
+    pub fn ticks(&self, ppq: u16) -> u32 {
+        let whole_note = ppq as u32 * 4;
+        let ticks = if self.triplet {
+            // a triplet fits three notes where two would go
+            whole_note * 2 / (3 * self.denominator as u32)
+        } else {
+            whole_note / self.denominator as u32
+        };
+        ticks.max(1)
+    }
+
+    pub fn name(&self) -> String {
+        if self.triplet {
+            format!("1/{}T", self.denominator)
+        } else {
+            format!("1/{}", self.denominator)
+        }
+    }
+}
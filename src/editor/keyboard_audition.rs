@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use eframe::egui::{Key, Modifiers};
+
+/// Plays notes from the computer keyboard using the classic tracker layout.
+/// The Z-M row (with S, D, G, H, J as the sharps) plays the lower octave and
+/// the Q-P row (with 2, 3, 5, 6, 7, 9, 0 as the sharps) plays the octave above it.
+pub struct KeyboardAudition {
+    /// the octave of the Z key, where octave 5 starts at middle C (key 60)
+    pub base_octave: u8,
+    pub velocity: u8,
+    /// if the base octave should follow the bottom of the piano roll view
+    pub follow_view: bool,
+    held_keys: HashMap<Key, u8>,
+}
+
+impl Default for KeyboardAudition {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyboardAudition {
+    pub fn new() -> Self {
+        Self {
+            base_octave: 5,
+            velocity: 100,
+            follow_view: true,
+            held_keys: HashMap::new(),
+        }
+    }
+
+    /// The semitone offset from the base octave's C for a key in the tracker layout.
+    pub fn key_offset(key: Key) -> Option<u8> {
+        let offset = match key {
+            Key::Z => 0, Key::S => 1, Key::X => 2, Key::D => 3, Key::C => 4,
+            Key::V => 5, Key::G => 6, Key::B => 7, Key::H => 8, Key::N => 9,
+            Key::J => 10, Key::M => 11,
+
+            Key::Q => 12, Key::Num2 => 13, Key::W => 14, Key::Num3 => 15, Key::E => 16,
+            Key::R => 17, Key::Num5 => 18, Key::T => 19, Key::Num6 => 20, Key::Y => 21,
+            Key::Num7 => 22, Key::U => 23, Key::I => 24, Key::Num9 => 25, Key::O => 26,
+            Key::Num0 => 27, Key::P => 28,
+            _ => return None
+        };
+        Some(offset)
+    }
+
+    /// If keyboard audition plays a note or changes octave when `[key]` is pressed with `[modifiers]`.
+    /// Only Ctrl, Alt and Cmd leave a key to the shortcuts, Shift doesn't.
+    pub fn uses_key(key: Key, modifiers: Modifiers) -> bool {
+        !(modifiers.ctrl || modifiers.alt || modifiers.command)
+            && (Self::key_offset(key).is_some() || matches!(key, Key::OpenBracket | Key::CloseBracket))
+    }
+
+    /// Moves the base octave to the lowest C that's visible in the view.
+    pub fn follow_key_pos(&mut self, key_pos: f32) {
+        if self.follow_view {
+            self.base_octave = ((key_pos / 12.0).ceil() as u8).min(10);
+        }
+    }
+
+    pub fn shift_octave(&mut self, by: i8) {
+        self.follow_view = false;
+        self.base_octave = (self.base_octave as i8 + by).clamp(0, 10) as u8;
+    }
+
+    /// Registers a key press, returning the MIDI key to play if it wasn't already held.
+    /// Key-repeats of a held key return `None` so they don't retrigger the note.
+    pub fn press(&mut self, key: Key) -> Option<u8> {
+        if self.held_keys.contains_key(&key) { return None; }
+
+        let midi_key = self.base_octave as u16 * 12 + Self::key_offset(key)? as u16;
+        if midi_key > 127 { return None; }
+
+        self.held_keys.insert(key, midi_key as u8);
+        Some(midi_key as u8)
+    }
+
+    /// Registers a key release, returning the MIDI key that was playing for it.
+    /// Uses the key that was pressed, so changing octaves while holding a key doesn't leave a stuck note.
+    pub fn release(&mut self, key: Key) -> Option<u8> {
+        self.held_keys.remove(&key)
+    }
+
+    pub fn is_holding(&self, midi_key: u8) -> bool {
+        self.held_keys.values().any(|k| *k == midi_key)
+    }
+
+    /// Releases every held key, returning the MIDI keys that were playing.
+    pub fn release_all(&mut self) -> Vec<u8> {
+        self.held_keys.drain().map(|(_, k)| k).collect()
+    }
+}
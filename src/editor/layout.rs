@@ -0,0 +1,102 @@
+use std::{fs, io, path::Path};
+
+/// Where the layout is remembered between sessions, next to the bundled assets.
+pub const LAYOUT_PATH: &str = "./layout.cfg";
+
+/// The window geometry and panel layout, saved on exit and restored on launch.
+/// Stored as `key = value` lines so a missing or hand-edited entry just falls back to its default.
+#[derive(Clone, PartialEq)]
+pub struct WindowLayout {
+    /// the size of the window's contents in points, the platform default if `None`
+    pub inner_size: Option<[f32; 2]>,
+    /// the position of the window's outer corner in points, placed by the platform if `None`
+    pub position: Option<[f32; 2]>,
+    pub maximized: bool,
+    pub show_track_list: bool,
+    pub track_list_width: f32,
+    pub show_markers: bool,
+    pub show_minimap: bool,
+    pub show_tempo_lane: bool,
+    pub show_scope: bool,
+    pub show_spectrum: bool,
+    pub markers_width: f32,
+    /// the settings tab that was open, empty if the settings window was closed
+    pub settings_tab: String,
+}
+
+impl Default for WindowLayout {
+    fn default() -> Self {
+        Self {
+            inner_size: None,
+            position: None,
+            maximized: false,
+            show_track_list: false,
+            track_list_width: 150.0,
+            show_markers: false,
+            show_minimap: true,
+            show_tempo_lane: true,
+            show_scope: false,
+            show_spectrum: false,
+            markers_width: 180.0,
+            settings_tab: String::new(),
+        }
+    }
+}
+
+impl WindowLayout {
+    /// Reads the layout from `[path]`. Anything unreadable is left at its default, so a broken file never stops the app from starting.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let mut layout = Self::default();
+        let Ok(contents) = fs::read_to_string(path) else {
+            return layout;
+        };
+
+        let pair = |value: &str| -> Option<[f32; 2]> {
+            let (x, y) = value.split_once(',')?;
+            Some([x.trim().parse().ok()?, y.trim().parse().ok()?])
+        };
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "inner_size" => layout.inner_size = pair(value),
+                "position" => layout.position = pair(value),
+                "maximized" => layout.maximized = value.parse().unwrap_or(layout.maximized),
+                "show_track_list" => layout.show_track_list = value.parse().unwrap_or(layout.show_track_list),
+                "track_list_width" => layout.track_list_width = value.parse().unwrap_or(layout.track_list_width),
+                "show_markers" => layout.show_markers = value.parse().unwrap_or(layout.show_markers),
+                "show_minimap" => layout.show_minimap = value.parse().unwrap_or(layout.show_minimap),
+                "show_tempo_lane" => layout.show_tempo_lane = value.parse().unwrap_or(layout.show_tempo_lane),
+                "show_scope" => layout.show_scope = value.parse().unwrap_or(layout.show_scope),
+                "show_spectrum" => layout.show_spectrum = value.parse().unwrap_or(layout.show_spectrum),
+                "markers_width" => layout.markers_width = value.parse().unwrap_or(layout.markers_width),
+                "settings_tab" => layout.settings_tab = value.to_string(),
+                _ => {}
+            }
+        }
+        layout
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut contents = String::new();
+        if let Some([width, height]) = self.inner_size {
+            contents += &format!("inner_size = {}, {}\n", width, height);
+        }
+        if let Some([x, y]) = self.position {
+            contents += &format!("position = {}, {}\n", x, y);
+        }
+        contents += &format!("maximized = {}\n", self.maximized);
+        contents += &format!("show_track_list = {}\n", self.show_track_list);
+        contents += &format!("track_list_width = {}\n", self.track_list_width);
+        contents += &format!("show_markers = {}\n", self.show_markers);
+        contents += &format!("show_minimap = {}\n", self.show_minimap);
+        contents += &format!("show_tempo_lane = {}\n", self.show_tempo_lane);
+        contents += &format!("show_scope = {}\n", self.show_scope);
+        contents += &format!("show_spectrum = {}\n", self.show_spectrum);
+        contents += &format!("markers_width = {}\n", self.markers_width);
+        contents += &format!("settings_tab = {}\n", self.settings_tab);
+        fs::write(path, contents)
+    }
+}
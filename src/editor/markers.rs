@@ -0,0 +1,34 @@
+use crate::midi::events::Marker;
+
+/// The project's named markers, kept sorted by tick.
+#[derive(Default)]
+pub struct MarkerList {
+    pub markers: Vec<Marker>,
+}
+
+impl MarkerList {
+    pub fn new(mut markers: Vec<Marker>) -> Self {
+        markers.sort_by_key(|marker| marker.tick);
+        Self { markers }
+    }
+
+    /// Adds a marker at `[tick]`, after any markers already on that tick. Returns its index.
+    pub fn add(&mut self, tick: u64, name: String) -> usize {
+        let index = self.markers.partition_point(|marker| marker.tick <= tick);
+        self.markers.insert(index, Marker { tick, name });
+        index
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.markers.len() {
+            self.markers.remove(index);
+        }
+    }
+
+    /// The markers between `[start]` and `[end]` ticks, for drawing the ones in view.
+    pub fn in_range(&self, start: u64, end: u64) -> &[Marker] {
+        let first = self.markers.partition_point(|marker| marker.tick < start);
+        let last = self.markers.partition_point(|marker| marker.tick <= end);
+        &self.markers[first..last.max(first)]
+    }
+}
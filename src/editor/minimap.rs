@@ -0,0 +1,51 @@
+use crate::midi::notes::ProjectNoteManager;
+
+/// How many columns the whole song is split into.
+const MINIMAP_BINS: usize = 1024;
+
+/// What a drag on the minimap is moving.
+#[derive(Clone, Copy)]
+pub enum MinimapDrag {
+    /// the whole view, holding where its left edge is being dragged to
+    Pan(f32),
+    /// the view's left edge, holding where it's being dragged to and where the right edge was when the drag started
+    ResizeStart { start: f32, end: f32 },
+    /// the view's right edge, only the zoom changes
+    ResizeEnd
+}
+
+/// A downsampled overview of the whole song for the minimap strip: how many notes start in each slice of it.
+#[derive(Default)]
+pub struct Minimap {
+    /// the `ProjectNoteManager::revision` the bins were counted at
+    revision: Option<u64>,
+    pub bins: Vec<u32>,
+    /// the most notes in any bin, for scaling the strip
+    pub peak: u32,
+    /// the tick the last note ends at, the bins cover `0..end_tick`
+    pub end_tick: u32,
+}
+
+impl Minimap {
+    /// Recounts the bins if the notes changed since they were last counted.
+    pub fn update(&mut self, notes: &ProjectNoteManager) {
+        if self.revision == Some(notes.revision()) { return; }
+        self.revision = Some(notes.revision());
+
+        self.end_tick = notes.notes().map(|note| note.end()).max().unwrap_or(0);
+        self.bins = vec![0; MINIMAP_BINS];
+        if self.end_tick > 0 {
+            let ticks_per_bin = (self.end_tick as u64).div_ceil(MINIMAP_BINS as u64).max(1);
+            for note in notes.notes() {
+                let bin = (note.start as u64 / ticks_per_bin) as usize;
+                self.bins[bin.min(MINIMAP_BINS - 1)] += 1;
+            }
+        }
+        self.peak = self.bins.iter().copied().max().unwrap_or(0);
+    }
+
+    /// The ticks each bin covers.
+    pub fn ticks_per_bin(&self) -> f32 {
+        (self.end_tick as f32 / MINIMAP_BINS as f32).max(1.0)
+    }
+}
@@ -1,3 +1,17 @@
+use eframe::egui::{pos2, Pos2, Rect};
+
+/// How close to an edge of the piano roll a drag has to get before the view follows it, in points.
+pub const EDGE_SCROLL_MARGIN: f32 = 24.0;
+
+/// What the mouse wheel does in the piano roll without Ctrl held, Ctrl+wheel does the other one.
+/// Shift+wheel always pans horizontally and Alt moves either onto the key axis.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum WheelAction {
+    #[default]
+    Zoom,
+    Pan
+}
+
 pub struct Navigation {
     pub tick_pos: f32,
     pub key_pos: f32,
@@ -15,6 +29,34 @@ impl Navigation {
         }
     }
 
+    /// The tick and key under `[pos]` in the piano roll drawn in `[rect]`. The key is fractional, floor it for the key index.
+    pub fn pos_to_tick_key(&self, rect: Rect, pos: Pos2) -> (f32, f32) {
+        let tick = self.tick_pos + (pos.x - rect.min.x) / rect.width() * self.zoom_ticks;
+        let key = self.key_pos + (1.0 - (pos.y - rect.min.y) / rect.height()) * self.zoom_keys;
+        (tick, key)
+    }
+
+    /// The inverse of `pos_to_tick_key`.
+    pub fn tick_key_to_pos(&self, rect: Rect, tick: f32, key: f32) -> Pos2 {
+        pos2(
+            rect.min.x + (tick - self.tick_pos) / self.zoom_ticks * rect.width(),
+            rect.min.y + (1.0 - (key - self.key_pos) / self.zoom_keys) * rect.height()
+        )
+    }
+
+    /// Pans the view while `[pos]` is dragged within `EDGE_SCROLL_MARGIN` of an edge of `[rect]` or past it,
+    /// up to a whole view per second the further in it is. Moves the keys right away and returns the tick position
+    /// to move to, so the caller can pass it to `change_tick_pos`.
+    pub fn edge_scroll(&mut self, rect: Rect, pos: Pos2, dt: f32) -> Option<f32> {
+        let depth = |distance: f32| ((EDGE_SCROLL_MARGIN - distance) / EDGE_SCROLL_MARGIN).clamp(0.0, 1.0);
+        let horizontal = depth(rect.max.x - pos.x) - depth(pos.x - rect.min.x);
+        let vertical = depth(pos.y - rect.min.y) - depth(rect.max.y - pos.y);
+
+        self.key_pos = (self.key_pos + vertical * self.zoom_keys * dt).clamp(0.0, (128.0 - self.zoom_keys).max(0.0));
+        let tick_pos = (self.tick_pos + horizontal * self.zoom_ticks * dt).max(0.0);
+        (tick_pos != self.tick_pos).then_some(tick_pos)
+    }
+
     pub fn change_tick_pos(&mut self, tick_pos: f32, mut change_fn: impl FnMut(f32)) {
         self.tick_pos = tick_pos;
         change_fn(self.tick_pos);
@@ -0,0 +1,67 @@
+use super::grid::GridResolution;
+
+/// The amounts for the humanize tool, kept between uses of its dialog.
+#[derive(Clone, Copy)]
+pub struct HumanizeOptions {
+    /// how far a note's start can move either way, in ticks
+    pub max_ticks: u32,
+    /// how far a note's velocity can change either way
+    pub max_velocity: u8,
+    /// the same seed on the same notes always gives the same result
+    pub seed: u64,
+}
+
+impl Default for HumanizeOptions {
+    fn default() -> Self {
+        Self {
+            max_ticks: 10,
+            max_velocity: 8,
+            seed: 0,
+        }
+    }
+}
+
+/// Where the time scale tool stretches the selection from.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScaleAnchor {
+    /// the start of the earliest selected note stays put
+    #[default]
+    SelectionStart,
+    Playhead
+}
+
+/// The settings of the time scale tool, kept between uses of its dialog.
+#[derive(Clone, Copy)]
+pub struct ScaleOptions {
+    /// what starts and lengths are multiplied by, 2.0 doubles them
+    pub factor: f32,
+    pub anchor: ScaleAnchor,
+}
+
+impl Default for ScaleOptions {
+    fn default() -> Self {
+        Self {
+            factor: 2.0,
+            anchor: ScaleAnchor::SelectionStart,
+        }
+    }
+}
+
+
+/// The settings of the quantize tool, kept between uses of its dialog.
+#[derive(Clone, Copy)]
+pub struct QuantizeOptions {
+    /// the note value the starts snap to
+    pub grid: GridResolution,
+    /// how late the second step of each pair is, in percent of the pair. 50 is straight
+    pub swing: f32,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        Self {
+            grid: GridResolution::default(),
+            swing: 50.0,
+        }
+    }
+}
@@ -1,13 +1,20 @@
+use crate::midi::{scales::MusicalScale, time_signatures::TimeSignatureMap};
+
 pub struct ProjectSettings {
     pub initial_bpm: f32,
-    pub ppq: u16
+    pub ppq: u16,
+    pub time_signatures: TimeSignatureMap,
+    /// the scale whose keys are highlighted on the keyboard and piano roll, `None` to highlight nothing
+    pub highlight_scale: Option<MusicalScale>
 }
 
 impl Default for ProjectSettings {
     fn default() -> Self {
         Self {
             initial_bpm: 160.0,
-            ppq: 1920
+            ppq: 1920,
+            time_signatures: TimeSignatureMap::default(),
+            highlight_scale: None
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+
+use crate::midi::notes::Note;
+
+/// Turns the notes played on a MIDI controller during playback into project notes.
+#[derive(Default)]
+pub struct MIDIRecorder {
+    /// if notes should be recorded the next time playback runs
+    pub armed: bool,
+    /// the track recorded notes are put on
+    track: u16,
+    channel: u8,
+    /// the start tick and velocity of every key that's held down
+    held: HashMap<u8, (u32, u8)>,
+}
+
+impl MIDIRecorder {
+    /// Starts a take, putting its notes on `[track]` and `[channel]`.
+    pub fn start(&mut self, track: u16, channel: u8) {
+        self.track = track;
+        self.channel = channel;
+        self.held.clear();
+    }
+
+    pub fn note_on(&mut self, key: u8, velocity: u8, tick: u32) {
+        self.held.insert(key, (tick, velocity));
+    }
+
+    /// Returns the finished note if `[key]` was being held.
+    pub fn note_off(&mut self, key: u8, tick: u32) -> Option<Note> {
+        let (start, velocity) = self.held.remove(&key)?;
+        Some(Note {
+            start,
+            length: tick.saturating_sub(start).max(1),
+            channel: self.channel,
+            track: self.track,
+            key,
+            velocity
+        })
+    }
+
+    /// Ends the take, closing every note that's still held at `[tick]`.
+    pub fn finish(&mut self, tick: u32) -> Vec<Note> {
+        let keys = self.held.keys().copied().collect::<Vec<u8>>();
+        keys.into_iter()
+            .filter_map(|key| self.note_off(key, tick))
+            .collect()
+    }
+}
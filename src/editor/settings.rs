@@ -1,15 +1,77 @@
+use std::{collections::BTreeMap, fs, io, path::Path, time::Duration};
 use std::sync::Arc;
 
+use crate::audio::high_pass::DEFAULT_HIGH_PASS_CUTOFF;
+use crate::audio::prerenderer::{synth_format, ChannelMode, DEFAULT_VIBRATO_DEPTH};
+use crate::midi::{io::midi_track_parser::NotePairing, time_signatures::SnapMode};
+use super::keyboard_audition::KeyboardAudition;
+use super::navigation::WheelAction;
+use super::project_settings::ProjectSettings;
+use super::theme::Theme;
+
+use eframe::egui::{Event, InputState, Key, KeyboardShortcut, Modifiers};
+
+use xsynth_core::{channel::ChannelInitOptions, channel_group::{ParallelismOptions, SynthFormat, ThreadCount}, soundfont::{EnvelopeCurveType, EnvelopeOptions, Interpolator, SoundfontInitOptions}};
+
 pub struct AudioSettings {
     pub soundfont_path: String,
-    pub num_layers: usize
+    pub num_layers: usize,
+    /// if quiet notes may be dropped while rendering falls behind playback
+    pub enable_note_skipping: bool,
+    /// how readily notes are dropped when note skipping is on, 1.0 is the default
+    pub note_skipping_aggressiveness: f32,
+    /// the threads xsynth renders the MIDI channels on
+    pub channel_threads: ThreadCount,
+    /// the threads xsynth renders each channel's keys on
+    pub key_threads: ThreadCount,
+    /// if voices stolen at the layer limit fade out instead of cutting off, avoiding clicks at a small cost
+    pub fade_out_killing: bool,
+    /// how many sets of 16 MIDI channels the synth has, the tracks take turns at them
+    pub synth_ports: u32,
+    pub attack_curve: EnvelopeCurveType,
+    pub decay_curve: EnvelopeCurveType,
+    pub release_curve: EnvelopeCurveType,
+    /// if voices may use the soundfont's signal processing. xsynth currently only implements its cutoff filter
+    pub use_soundfont_effects: bool,
+    /// the gain applied to the output before the limiter, in dB
+    pub master_gain_db: f32,
+    /// if the output and exports are summed to mono
+    pub channel_mode: ChannelMode,
+    /// how far the mod wheel's vibrato swings the pitch either way at full modulation, in semitones
+    pub vibrato_depth: f32,
+    /// if DC offset and subsonic content are filtered out of the output and exports
+    pub high_pass: bool,
+    /// where the high-pass starts cutting, in Hz
+    pub high_pass_cutoff: f32,
+    /// if the limiter looks ahead to turn down before peaks instead of reacting to them
+    pub limiter_lookahead: bool,
+    /// how far the limiter looks ahead, in milliseconds. The output is delayed by as much
+    pub limiter_lookahead_ms: f32
 }
 
 impl Default for AudioSettings {
     fn default() -> Self {
         Self {
-            soundfont_path: String::from("/assets/soundfonts/Sinufont.sf2"),
-            num_layers: 5
+            soundfont_path: String::from("./assets/soundfonts/Sinufont.sf2"),
+            num_layers: 5,
+            enable_note_skipping: false,
+            note_skipping_aggressiveness: 1.0,
+            channel_threads: ThreadCount::Auto,
+            key_threads: ThreadCount::None,
+            fade_out_killing: false,
+            synth_ports: 1,
+            attack_curve: EnvelopeCurveType::Linear,
+            decay_curve: EnvelopeCurveType::Linear,
+            // sounds more natural than linear for decaying instruments like piano
+            release_curve: EnvelopeCurveType::Exponential,
+            use_soundfont_effects: false,
+            master_gain_db: 0.0,
+            channel_mode: ChannelMode::Stereo,
+            vibrato_depth: DEFAULT_VIBRATO_DEPTH,
+            high_pass: false,
+            high_pass_cutoff: DEFAULT_HIGH_PASS_CUTOFF,
+            limiter_lookahead: false,
+            limiter_lookahead_ms: 2.0
         }
     }
 }
@@ -22,22 +84,459 @@ impl AudioSettings {
     pub fn set_soundfont_path(&mut self, sf_path: String) {
         self.soundfont_path = sf_path;
     }
+
+    pub fn parallelism(&self) -> ParallelismOptions {
+        ParallelismOptions {
+            channel: self.channel_threads,
+            key: self.key_threads
+        }
+    }
+
+    pub fn channel_init_options(&self) -> ChannelInitOptions {
+        ChannelInitOptions {
+            fade_out_killing: self.fade_out_killing
+        }
+    }
+
+    pub fn synth_format(&self) -> SynthFormat {
+        synth_format(self.synth_ports)
+    }
+
+    /// The options soundfonts are loaded with. Changing them means reloading the soundfont.
+    pub fn soundfont_options(&self) -> SoundfontInitOptions {
+        SoundfontInitOptions {
+            bank: None,
+            preset: None,
+            vol_envelope_options: EnvelopeOptions {
+                attack_curve: self.attack_curve,
+                decay_curve: self.decay_curve,
+                release_curve: self.release_curve
+            },
+            use_effects: self.use_soundfont_effects,
+            interpolator: Interpolator::Linear
+        }
+    }
+
+    /// The linear gain to pass to `PrerenderedAudio::set_master_gain`.
+    pub fn master_gain(&self) -> f32 {
+        10f32.powf(self.master_gain_db / 20.0)
+    }
+
+    /// The cutoff to pass to `PrerenderedAudio::set_high_pass`.
+    pub fn output_high_pass(&self) -> Option<f32> {
+        self.high_pass.then_some(self.high_pass_cutoff)
+    }
+
+    /// The lookahead to pass to `PrerenderedAudio::set_limiter_lookahead`, in seconds.
+    pub fn limiter_lookahead(&self) -> Option<f32> {
+        self.limiter_lookahead.then_some(self.limiter_lookahead_ms / 1000.0)
+    }
+
+    /// The aggressiveness to pass to `PrerenderedAudio::set_note_skipping`.
+    pub fn note_skipping(&self) -> Option<f32> {
+        self.enable_note_skipping.then_some(self.note_skipping_aggressiveness)
+    }
+}
+
+pub struct EditSettings {
+    /// what seeking the playhead snaps to, holding Alt seeks freely
+    pub seek_snap: SnapMode,
+    pub wheel_action: WheelAction,
+    /// the resolution a new project starts with, in ticks per quarter note
+    pub default_ppq: u16,
+    /// the tempo a new project starts at
+    pub default_bpm: f32
+}
+
+impl Default for EditSettings {
+    fn default() -> Self {
+        let project = ProjectSettings::default();
+        Self {
+            seek_snap: SnapMode::Off,
+            wheel_action: WheelAction::Zoom,
+            default_ppq: project.ppq,
+            default_bpm: project.initial_bpm
+        }
+    }
+}
+
+pub struct ViewSettings {
+    /// the most frames drawn per second while something animates, like playback or the level meters falling. 0 for no limit
+    pub max_fps: u32,
+    /// if the view scrolls along with playback when the app starts, the View menu toggles it after that
+    pub follow_playback: bool
+}
+
+impl Default for ViewSettings {
+    fn default() -> Self {
+        Self {
+            max_fps: 60,
+            follow_playback: true
+        }
+    }
+}
+
+impl ViewSettings {
+    /// How long to wait before drawing the next frame of an animation, `None` to draw it right away.
+    pub fn frame_interval(&self) -> Option<Duration> {
+        (self.max_fps > 0).then(|| Duration::from_secs_f32(1.0 / self.max_fps as f32))
+    }
+}
+
+#[derive(Default)]
+pub struct ImportSettings {
+    /// which note a note off ends when a MIDI holds the same key on a channel more than once
+    pub note_pairing: NotePairing,
+    /// if the notes on each channel of a track are imported onto a track of their own
+    pub split_by_channel: bool,
+    /// if imported ticks are rescaled to the project's PPQ instead of the project taking the file's
+    pub keep_project_ppq: bool
+}
+
+/// Something a key can be bound to.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Action {
+    PlayPause,
+    StopToStart,
+    Undo,
+    Redo,
+    SelectAll,
+    InvertSelection,
+    ClearSelection,
+    Duplicate,
+    Delete,
+    TransposeUp,
+    TransposeDown,
+    OctaveUp,
+    OctaveDown,
+    NudgeRight,
+    NudgeLeft,
+    NudgeRightFar,
+    NudgeLeftFar,
+    SplitAtPlayhead,
+    NextNote,
+    PreviousNote,
+    TapTempo,
+    GoToBar,
+    Save,
+    Panic
+}
+
+impl Action {
+    pub const ALL: [Action; 24] = [
+        Action::PlayPause, Action::StopToStart,
+        Action::Undo, Action::Redo,
+        Action::SelectAll, Action::InvertSelection, Action::ClearSelection,
+        Action::Duplicate, Action::Delete,
+        Action::TransposeUp, Action::TransposeDown, Action::OctaveUp, Action::OctaveDown,
+        Action::NudgeRight, Action::NudgeLeft, Action::NudgeRightFar, Action::NudgeLeftFar,
+        Action::SplitAtPlayhead, Action::NextNote, Action::PreviousNote,
+        Action::TapTempo, Action::GoToBar, Action::Save, Action::Panic
+    ];
+
+    /// The name the action's keys are saved under.
+    pub fn key(&self) -> &'static str {
+        match self {
+            Action::PlayPause => "play_pause",
+            Action::StopToStart => "stop_to_start",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::SelectAll => "select_all",
+            Action::InvertSelection => "invert_selection",
+            Action::ClearSelection => "clear_selection",
+            Action::Duplicate => "duplicate",
+            Action::Delete => "delete",
+            Action::TransposeUp => "transpose_up",
+            Action::TransposeDown => "transpose_down",
+            Action::OctaveUp => "octave_up",
+            Action::OctaveDown => "octave_down",
+            Action::NudgeRight => "nudge_right",
+            Action::NudgeLeft => "nudge_left",
+            Action::NudgeRightFar => "nudge_right_far",
+            Action::NudgeLeftFar => "nudge_left_far",
+            Action::SplitAtPlayhead => "split_at_playhead",
+            Action::NextNote => "next_note",
+            Action::PreviousNote => "previous_note",
+            Action::TapTempo => "tap_tempo",
+            Action::GoToBar => "go_to_bar",
+            Action::Save => "save",
+            Action::Panic => "panic"
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::PlayPause => "Play / pause",
+            Action::StopToStart => "Stop and go to the start",
+            Action::Undo => "Undo",
+            Action::Redo => "Redo",
+            Action::SelectAll => "Select all",
+            Action::InvertSelection => "Invert selection",
+            Action::ClearSelection => "Deselect all",
+            Action::Duplicate => "Duplicate",
+            Action::Delete => "Delete",
+            Action::TransposeUp => "Transpose up a semitone",
+            Action::TransposeDown => "Transpose down a semitone",
+            Action::OctaveUp => "Transpose up an octave",
+            Action::OctaveDown => "Transpose down an octave",
+            Action::NudgeRight => "Move right a grid step",
+            Action::NudgeLeft => "Move left a grid step",
+            Action::NudgeRightFar => "Move right four grid steps",
+            Action::NudgeLeftFar => "Move left four grid steps",
+            Action::SplitAtPlayhead => "Split at playhead",
+            Action::NextNote => "Next note",
+            Action::PreviousNote => "Previous note",
+            Action::TapTempo => "Tap tempo",
+            Action::GoToBar => "Go to bar",
+            Action::Save => "Save",
+            Action::Panic => "Panic"
+        }
+    }
+
+    fn default_keys(&self) -> [Option<KeyboardShortcut>; 2] {
+        let shortcut = |modifiers, key| Some(KeyboardShortcut::new(modifiers, key));
+        let command = |key| shortcut(Modifiers::COMMAND, key);
+        let plain = |key| shortcut(Modifiers::NONE, key);
+        match self {
+            Action::PlayPause => [plain(Key::Space), None],
+            Action::StopToStart => [plain(Key::Enter), None],
+            Action::Undo => [command(Key::Z), None],
+            Action::Redo => [shortcut(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z), command(Key::Y)],
+            Action::SelectAll => [command(Key::A), None],
+            Action::InvertSelection => [command(Key::I), None],
+            Action::ClearSelection => [plain(Key::Escape), None],
+            Action::Duplicate => [command(Key::D), None],
+            Action::Delete => [plain(Key::Delete), plain(Key::Backspace)],
+            Action::TransposeUp => [command(Key::ArrowUp), None],
+            Action::TransposeDown => [command(Key::ArrowDown), None],
+            Action::OctaveUp => [shortcut(Modifiers::COMMAND | Modifiers::SHIFT, Key::ArrowUp), None],
+            Action::OctaveDown => [shortcut(Modifiers::COMMAND | Modifiers::SHIFT, Key::ArrowDown), None],
+            Action::NudgeRight => [plain(Key::ArrowRight), None],
+            Action::NudgeLeft => [plain(Key::ArrowLeft), None],
+            Action::NudgeRightFar => [shortcut(Modifiers::SHIFT, Key::ArrowRight), None],
+            Action::NudgeLeftFar => [shortcut(Modifiers::SHIFT, Key::ArrowLeft), None],
+            // with Alt so keyboard audition leaves it alone
+            Action::SplitAtPlayhead => [shortcut(Modifiers::ALT, Key::S), None],
+            Action::NextNote => [plain(Key::Tab), None],
+            Action::PreviousNote => [shortcut(Modifiers::SHIFT, Key::Tab), None],
+            // T plays a note in keyboard audition
+            Action::TapTempo => [shortcut(Modifiers::ALT, Key::T), None],
+            Action::GoToBar => [command(Key::G), None],
+            Action::Save => [command(Key::S), None],
+            Action::Panic => [shortcut(Modifiers::COMMAND | Modifiers::SHIFT, Key::P), None]
+        }
+    }
+}
+
+/// The keys each action is bound to, up to two per action.
+#[derive(Clone, PartialEq)]
+pub struct KeyBindings {
+    keys: BTreeMap<Action, [Option<KeyboardShortcut>; 2]>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            keys: Action::ALL.into_iter().map(|action| (action, action.default_keys())).collect()
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn keys(&self, action: Action) -> [Option<KeyboardShortcut>; 2] {
+        self.keys.get(&action).copied().unwrap_or_default()
+    }
+
+    /// Binds slot `[slot]` of `[action]`, or unbinds it if `[shortcut]` is `None`.
+    pub fn set(&mut self, action: Action, slot: usize, shortcut: Option<KeyboardShortcut>) {
+        if let Some(slot) = self.keys.entry(action).or_default().get_mut(slot) {
+            *slot = shortcut;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// The first key bound to `[action]`, for showing next to menu entries.
+    pub fn first(&self, action: Action) -> Option<KeyboardShortcut> {
+        self.keys(action).into_iter().flatten().next()
+    }
+
+    /// How many times the keys of `[action]` were pressed this frame, counting key repeats. The presses are consumed.
+    /// Unlike `InputState::consume_key` the modifiers have to match exactly, so Shift+Tab is never taken for Tab
+    /// and the actions can be checked in any order.
+    pub fn count_and_consume(&self, input: &mut InputState, action: Action) -> usize {
+        let keys = self.keys(action);
+        let mut count = 0;
+        input.events.retain(|event| {
+            let Event::Key { key, pressed: true, modifiers, .. } = event else { return true; };
+            let bound = keys.iter().flatten()
+                .any(|shortcut| shortcut.logical_key == *key && modifiers.matches_exact(shortcut.modifiers));
+            count += bound as usize;
+            !bound
+        });
+        count
+    }
+
+    pub fn consume(&self, input: &mut InputState, action: Action) -> bool {
+        self.count_and_consume(input, action) > 0
+    }
+
+    /// Every key bound to more than one action, with the actions it's bound to. A conflicting key does all of them.
+    pub fn conflicts(&self) -> Vec<(KeyboardShortcut, Vec<Action>)> {
+        let mut actions_of: Vec<(KeyboardShortcut, Vec<Action>)> = Vec::new();
+        for (action, keys) in &self.keys {
+            for shortcut in keys.iter().flatten() {
+                match actions_of.iter_mut().find(|(bound, _)| bound == shortcut) {
+                    Some((_, actions)) if !actions.contains(action) => actions.push(*action),
+                    Some(_) => {}
+                    None => actions_of.push((*shortcut, vec![*action]))
+                }
+            }
+        }
+        actions_of.retain(|(_, actions)| actions.len() > 1);
+        actions_of
+    }
+
+    /// Every key that keyboard audition plays too, with the action it's bound to.
+    /// The action takes the key, so its note can't be auditioned.
+    pub fn audition_conflicts(&self) -> Vec<(KeyboardShortcut, Action)> {
+        self.keys.iter()
+            .flat_map(|(action, keys)| keys.iter().flatten().map(|shortcut| (*shortcut, *action)))
+            .filter(|(shortcut, _)| KeyboardAudition::uses_key(shortcut.logical_key, shortcut.modifiers))
+            .collect()
+    }
+}
+
+/// A shortcut as it's saved, like `Ctrl+Shift+Z`. Ctrl stands for Cmd on macOS like in the defaults.
+fn format_shortcut(shortcut: &KeyboardShortcut) -> String {
+    let modifiers = shortcut.modifiers;
+    let mut text = String::new();
+    for (held, name) in [(modifiers.command || modifiers.ctrl, "Ctrl"), (modifiers.alt, "Alt"), (modifiers.shift, "Shift")] {
+        if held {
+            text += name;
+            text += "+";
+        }
+    }
+    text + shortcut.logical_key.name()
 }
 
+fn parse_shortcut(text: &str) -> Option<KeyboardShortcut> {
+    let mut parts = text.split('+').map(str::trim).collect::<Vec<_>>();
+    let key = Key::from_name(parts.pop()?)?;
+    let mut modifiers = Modifiers::NONE;
+    for part in parts {
+        match part {
+            "Ctrl" => modifiers |= Modifiers::COMMAND,
+            "Alt" => modifiers |= Modifiers::ALT,
+            "Shift" => modifiers |= Modifiers::SHIFT,
+            _ => return None
+        }
+    }
+    Some(KeyboardShortcut::new(modifiers, key))
+}
+
+/// Where the settings are remembered between sessions, next to the window layout.
+pub const SETTINGS_PATH: &str = "./settings.cfg";
+
 pub struct ApplicationSettings {
-    pub audio_settings: AudioSettings
+    pub audio_settings: AudioSettings,
+    pub edit_settings: EditSettings,
+    pub import_settings: ImportSettings,
+    pub view_settings: ViewSettings,
+    pub theme: Theme,
+    pub key_bindings: KeyBindings
 }
 
 impl ApplicationSettings {
     pub fn get_audio_settings(&mut self) -> &mut AudioSettings {
         &mut self.audio_settings
     }
+
+    /// Reads the settings from `[path]` as `key = value` lines like the window layout.
+    /// Anything missing or unreadable keeps its default.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let mut settings = Self::default();
+        let Ok(contents) = fs::read_to_string(path) else {
+            return settings;
+        };
+
+        let color = |value: &str| -> Option<[f32; 3]> {
+            let mut parts = value.split(',').map(|part| part.trim().parse::<f32>().ok());
+            let color = [parts.next()??, parts.next()??, parts.next()??];
+            Some(color.map(|c| c.clamp(0.0, 1.0)))
+        };
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if key == "max_fps" {
+                settings.view_settings.max_fps = value.parse().unwrap_or(settings.view_settings.max_fps);
+            } else if key == "follow_playback" {
+                settings.view_settings.follow_playback = value.parse().unwrap_or(settings.view_settings.follow_playback);
+            } else if key == "seek_snap" {
+                let edit = &mut settings.edit_settings;
+                edit.seek_snap = SnapMode::ALL.into_iter().find(|mode| mode.key() == value).unwrap_or(edit.seek_snap);
+            } else if key == "default_ppq" {
+                let edit = &mut settings.edit_settings;
+                edit.default_ppq = value.parse().ok().filter(|ppq| *ppq > 0).unwrap_or(edit.default_ppq);
+            } else if key == "default_bpm" {
+                let edit = &mut settings.edit_settings;
+                edit.default_bpm = value.parse::<f32>().map(|bpm| bpm.clamp(10.0, 1000.0)).unwrap_or(edit.default_bpm);
+            } else if let Some(key) = key.strip_prefix("theme.") {
+                for (name, _, slot) in settings.theme.colors_mut() {
+                    if name == key && let Some(picked) = color(value) {
+                        *slot = picked;
+                    }
+                }
+            } else if let Some(key) = key.strip_prefix("keys.")
+                && let Some(action) = Action::ALL.into_iter().find(|action| action.key() == key) {
+                // an empty value unbinds the action, one with a key that can't be read keeps the defaults
+                let shortcuts = value.split(',')
+                    .map(str::trim)
+                    .filter(|text| !text.is_empty())
+                    .map(parse_shortcut)
+                    .collect::<Option<Vec<_>>>();
+                if let Some(shortcuts) = shortcuts {
+                    for slot in 0..2 {
+                        settings.key_bindings.set(action, slot, shortcuts.get(slot).copied());
+                    }
+                }
+            }
+        }
+        settings
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut contents = String::new();
+        contents += &format!("max_fps = {}\n", self.view_settings.max_fps);
+        contents += &format!("follow_playback = {}\n", self.view_settings.follow_playback);
+        contents += &format!("seek_snap = {}\n", self.edit_settings.seek_snap.key());
+        contents += &format!("default_ppq = {}\n", self.edit_settings.default_ppq);
+        contents += &format!("default_bpm = {}\n", self.edit_settings.default_bpm);
+        let mut theme = self.theme;
+        for (name, _, [r, g, b]) in theme.colors_mut() {
+            contents += &format!("theme.{} = {}, {}, {}\n", name, r, g, b);
+        }
+        for action in Action::ALL {
+            let keys = self.key_bindings.keys(action).iter().flatten().map(format_shortcut).collect::<Vec<_>>();
+            contents += &format!("keys.{} = {}\n", action.key(), keys.join(", "));
+        }
+        fs::write(path, contents)
+    }
 }
 
 impl Default for ApplicationSettings {
     fn default() -> Self {
         Self {
-            audio_settings: Default::default()
+            audio_settings: Default::default(),
+            edit_settings: Default::default(),
+            import_settings: Default::default(),
+            view_settings: Default::default(),
+            theme: Default::default(),
+            key_bindings: Default::default()
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,46 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A gap between taps longer than this starts counting again, 30 BPM is slow enough for anything.
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+/// How many of the latest intervals between taps are averaged.
+const TAP_INTERVALS: usize = 4;
+
+/// Works out a tempo from the times a key or button is tapped on the beat.
+#[derive(Default)]
+pub struct TapTempo {
+    /// the latest taps, oldest first. At most one more than `TAP_INTERVALS`
+    taps: VecDeque<Instant>,
+}
+
+impl TapTempo {
+    /// Records a tap at `[now]` and returns the tempo in BPM once there are two taps to measure between.
+    pub fn tap(&mut self, now: Instant) -> Option<f32> {
+        if self.taps.back().is_some_and(|last| now.saturating_duration_since(*last) > TAP_TIMEOUT) {
+            self.taps.clear();
+        }
+        self.taps.push_back(now);
+        if self.taps.len() > TAP_INTERVALS + 1 {
+            self.taps.pop_front();
+        }
+        self.bpm()
+    }
+
+    /// The tempo the taps so far work out to, `None` until there are two of them.
+    pub fn bpm(&self) -> Option<f32> {
+        let (first, last) = (self.taps.front()?, self.taps.back()?);
+        let intervals = self.taps.len() - 1;
+        if intervals == 0 { return None; }
+        let beat_secs = last.duration_since(*first).as_secs_f32() / intervals as f32;
+        (beat_secs > 0.0).then(|| 60.0 / beat_secs)
+    }
+
+    /// How many taps are being averaged, counting the first one.
+    pub fn tap_count(&self) -> usize {
+        self.taps.len()
+    }
+
+    pub fn reset(&mut self) {
+        self.taps.clear();
+    }
+}
@@ -0,0 +1,15 @@
+/// A tempo change being added or edited in the tempo point dialog.
+#[derive(Clone, Copy)]
+pub struct TempoPointEdit {
+    /// the tick of the tempo change being edited, `None` if it's a new one
+    pub original_tick: Option<u64>,
+    pub tick: u64,
+    pub bpm: f32,
+}
+
+impl TempoPointEdit {
+    /// If the tempo change can be deleted, the one at tick 0 is what the song starts at so it has to stay.
+    pub fn can_delete(&self) -> bool {
+        self.original_tick.is_some_and(|tick| tick > 0)
+    }
+}
@@ -0,0 +1,82 @@
+/// The colors the piano roll is drawn with. The background ones are passed to the shaders as uniforms,
+/// so a color change never needs a shader edit.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Theme {
+    /// behind the key rows, seen past the top and bottom keys
+    pub background: [f32; 3],
+    /// the rows of the white keys, the black keys' rows are a shade darker
+    pub key_rows: [f32; 3],
+    pub bar_line: [f32; 3],
+    pub beat_line: [f32; 3],
+    /// the editing grid's lines between the beats
+    pub grid_line: [f32; 3],
+    pub playhead: [f32; 3],
+    /// the tint of selected notes and the selection marquee
+    pub selection: [f32; 3],
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        ThemePreset::Dark.theme()
+    }
+}
+
+impl Theme {
+    /// Every color with the key it's saved under and how the settings window names it.
+    pub fn colors_mut(&mut self) -> [(&'static str, &'static str, &mut [f32; 3]); 7] {
+        [
+            ("background", "Background", &mut self.background),
+            ("key_rows", "Key rows", &mut self.key_rows),
+            ("bar_line", "Bar lines", &mut self.bar_line),
+            ("beat_line", "Beat lines", &mut self.beat_line),
+            ("grid_line", "Grid lines", &mut self.grid_line),
+            ("playhead", "Playhead", &mut self.playhead),
+            ("selection", "Selection", &mut self.selection)
+        ]
+    }
+
+    /// The preset these colors are, `None` once any of them was picked by hand.
+    pub fn preset(&self) -> Option<ThemePreset> {
+        ThemePreset::ALL.into_iter().find(|preset| preset.theme() == *self)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ThemePreset {
+    Dark,
+    Light
+}
+
+impl ThemePreset {
+    pub const ALL: [ThemePreset; 2] = [ThemePreset::Dark, ThemePreset::Light];
+
+    pub fn theme(&self) -> Theme {
+        match self {
+            ThemePreset::Dark => Theme {
+                background: [0.0, 0.0, 0.0],
+                key_rows: [0.2, 0.2, 0.25],
+                bar_line: [0.02, 0.02, 0.025],
+                beat_line: [0.02, 0.02, 0.025],
+                grid_line: [0.12, 0.12, 0.15],
+                playhead: [1.0, 0.35, 0.3],
+                selection: [0.47, 0.67, 1.0]
+            },
+            ThemePreset::Light => Theme {
+                background: [0.7, 0.7, 0.73],
+                key_rows: [0.93, 0.93, 0.95],
+                bar_line: [0.35, 0.35, 0.4],
+                beat_line: [0.6, 0.6, 0.65],
+                grid_line: [0.8, 0.8, 0.84],
+                playhead: [0.85, 0.2, 0.15],
+                selection: [0.15, 0.4, 0.9]
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThemePreset::Dark => "Dark",
+            ThemePreset::Light => "Light"
+        }
+    }
+}
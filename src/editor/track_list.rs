@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Default)]
+pub struct TrackState {
+    pub muted: bool,
+    pub solo: bool,
+    /// the color the track's notes are drawn with, the channel palette is used if `None`
+    pub color: Option<[f32; 3]>,
+}
+
+impl TrackState {
+    /// If the track should be heard. When any track is soloed only the soloed tracks are heard,
+    /// otherwise every track that isn't muted is.
+    pub fn is_audible(&self, any_solo: bool) -> bool {
+        if any_solo {
+            self.solo
+        } else {
+            !self.muted
+        }
+    }
+}
+
+/// What one entry of the track list stands for, so mute, solo and picked colors all agree on it.
+/// Each mode keys its entries by part of `ProjectNote::channel_track`, which is packed as `00TTTTCC`.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoteGrouping {
+    /// the track bits, `channel_track >> 8`
+    #[default]
+    Track,
+    /// the channel bits, `channel_track & 0xFF`, so the same channel on every track is one entry
+    Channel,
+    /// the whole `channel_track`, so every channel of every track is an entry of its own
+    TrackChannel
+}
+
+impl NoteGrouping {
+    pub const ALL: [NoteGrouping; 3] = [NoteGrouping::Track, NoteGrouping::Channel, NoteGrouping::TrackChannel];
+
+    /// The entry a note with `[channel_track]` belongs to.
+    pub fn group_of(&self, channel_track: u32) -> u32 {
+        match self {
+            NoteGrouping::Track => channel_track >> 8,
+            NoteGrouping::Channel => channel_track & 0xFF,
+            NoteGrouping::TrackChannel => channel_track
+        }
+    }
+
+    /// Which palette color an entry gets when it has no picked color.
+    pub fn palette_index(&self, group: u32) -> usize {
+        match self {
+            NoteGrouping::Track | NoteGrouping::Channel => group as usize,
+            // neighbouring channels of a track get neighbouring colors
+            NoteGrouping::TrackChannel => ((group >> 8) * 16 + (group & 0xFF)) as usize
+        }
+    }
+
+    /// How an entry is named in the track list. Channels are counted from 1 like in most MIDI software.
+    pub fn label(&self, group: u32) -> String {
+        match self {
+            NoteGrouping::Track => format!("Track {}", group),
+            NoteGrouping::Channel => format!("Channel {}", group + 1),
+            NoteGrouping::TrackChannel => format!("Track {} ch {}", group >> 8, (group & 0xFF) + 1)
+        }
+    }
+
+    /// How the grouping is written in a saved project.
+    pub fn key(&self) -> &'static str {
+        match self {
+            NoteGrouping::Track => "track",
+            NoteGrouping::Channel => "channel",
+            NoteGrouping::TrackChannel => "track_channel"
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoteGrouping::Track => "By track",
+            NoteGrouping::Channel => "By channel",
+            NoteGrouping::TrackChannel => "By track and channel"
+        }
+    }
+}
+
+/// Per-entry flags, keyed by `NoteGrouping::group_of` the voices (`channel_track`s) that have notes.
+#[derive(Default)]
+pub struct TrackList {
+    pub tracks: BTreeMap<u32, TrackState>,
+    grouping: NoteGrouping,
+    /// the voices last synced, each one belongs to an entry of `tracks`
+    voices: Vec<u32>,
+    /// the entries played back from a render instead of the synth, as interleaved stereo samples from the start of the song,
+    /// and the `ProjectNoteManager::revision` the render was started at
+    frozen: HashMap<u32, (Arc<[f32]>, u64)>,
+}
+
+impl TrackList {
+    /// Adds an entry for every group that has notes and drops the ones that don't anymore.
+    pub fn sync(&mut self, voices: impl Iterator<Item = u32>) {
+        self.voices = voices.collect();
+        let mut synced = BTreeMap::new();
+        for voice in &self.voices {
+            let group = self.grouping.group_of(*voice);
+            synced.insert(group, self.tracks.get(&group).copied().unwrap_or_default());
+        }
+        self.frozen.retain(|group, _| synced.contains_key(group));
+        self.tracks = synced;
+    }
+
+    pub fn grouping(&self) -> NoteGrouping {
+        self.grouping
+    }
+
+    /// Regroups the entries. Their flags and colors don't carry over, since the entries mean something else now.
+    pub fn set_grouping(&mut self, grouping: NoteGrouping) {
+        if grouping == self.grouping { return; }
+        self.grouping = grouping;
+        self.tracks.clear();
+        self.frozen.clear();
+        let voices = std::mem::take(&mut self.voices);
+        self.sync(voices.into_iter());
+    }
+
+    pub fn any_solo(&self) -> bool {
+        self.tracks.values().any(|t| t.solo)
+    }
+
+    /// Every voice that shouldn't be heard, for checking lots of notes at once.
+    pub fn silenced_voices(&self) -> HashSet<u32> {
+        let any_solo = self.any_solo();
+        self.voices.iter()
+            .filter(|voice| self.tracks.get(&self.grouping.group_of(**voice)).is_some_and(|state| !state.is_audible(any_solo)))
+            .copied()
+            .collect()
+    }
+
+    /// The voices of entry `[group]`.
+    pub fn group_voices(&self, group: u32) -> HashSet<u32> {
+        self.voices.iter()
+            .filter(|voice| self.grouping.group_of(**voice) == group)
+            .copied()
+            .collect()
+    }
+
+    pub fn is_frozen(&self, group: u32) -> bool {
+        self.frozen.contains_key(&group)
+    }
+
+    /// `[revision]` - the `ProjectNoteManager::revision` the notes were at when `[clip]` started rendering
+    pub fn freeze(&mut self, group: u32, clip: Arc<[f32]>, revision: u64) {
+        self.frozen.insert(group, (clip, revision));
+    }
+
+    pub fn unfreeze(&mut self, group: u32) {
+        self.frozen.remove(&group);
+    }
+
+    pub fn unfreeze_all(&mut self) {
+        self.frozen.clear();
+    }
+
+    /// Unfreezes the entries whose notes changed since their render was started, since it doesn't play them anymore.
+    /// `[voice_revisions]` is `ProjectNoteManager::voice_revisions`. Returns the entries that were unfrozen.
+    pub fn unfreeze_edited(&mut self, voice_revisions: &HashMap<u32, u64>) -> Vec<u32> {
+        let mut edited = voice_revisions.iter()
+            .map(|(voice, revision)| (self.grouping.group_of(*voice), *revision))
+            .filter(|(group, revision)| self.frozen.get(group).is_some_and(|(_, frozen_at)| revision > frozen_at))
+            .map(|(group, _)| group)
+            .collect::<Vec<_>>();
+        edited.sort_unstable();
+        edited.dedup();
+        for group in &edited {
+            self.frozen.remove(group);
+        }
+        edited
+    }
+
+    /// Every voice of a frozen entry, the synth doesn't play these since their clips are played instead.
+    pub fn frozen_voices(&self) -> HashSet<u32> {
+        self.voices.iter()
+            .filter(|voice| self.frozen.contains_key(&self.grouping.group_of(**voice)))
+            .copied()
+            .collect()
+    }
+
+    /// The clips of the frozen entries that should be heard, muting and soloing applies to them like to the notes.
+    pub fn audible_clips(&self) -> Vec<Arc<[f32]>> {
+        let any_solo = self.any_solo();
+        self.frozen.iter()
+            .filter(|(group, _)| self.tracks.get(group).is_some_and(|state| state.is_audible(any_solo)))
+            .map(|(_, (clip, _))| clip.clone())
+            .collect()
+    }
+
+    /// The picked color of every voice in an entry that has one.
+    pub fn voice_colors(&self) -> HashMap<u32, [f32; 3]> {
+        self.voices.iter()
+            .filter_map(|voice| {
+                let color = self.tracks.get(&self.grouping.group_of(*voice))?.color?;
+                Some((*voice, color))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TrackList;
+    use crate::midi::notes::{Note, ProjectNoteManager};
+
+    fn note(track: u16, key: u8) -> Note {
+        Note { start: 0, length: 96, channel: 0, track, key, velocity: 100 }
+    }
+
+    #[test]
+    fn editing_a_frozen_track_unfreezes_it() {
+        let mut notes = ProjectNoteManager::new();
+        let first = notes.add_note(note(0, 60));
+        let second = notes.add_note(note(1, 64));
+        let mut track_list = TrackList::default();
+        track_list.sync(notes.voices());
+        track_list.freeze(1, Vec::new().into(), notes.revision());
+
+        notes.transpose(&[first], 2);
+        assert!(track_list.unfreeze_edited(notes.voice_revisions()).is_empty());
+        assert!(track_list.is_frozen(1));
+
+        notes.transpose(&[second], 2);
+        assert_eq!(track_list.unfreeze_edited(notes.voice_revisions()), vec![1]);
+        assert!(!track_list.is_frozen(1));
+    }
+
+    #[test]
+    fn deleting_a_frozen_tracks_notes_unfreezes_it() {
+        let mut notes = ProjectNoteManager::new();
+        notes.add_note(note(0, 60));
+        let second = notes.add_note(note(1, 64));
+        let mut track_list = TrackList::default();
+        track_list.sync(notes.voices());
+        track_list.freeze(1, Vec::new().into(), notes.revision());
+
+        notes.delete_notes(&[second]);
+        assert_eq!(track_list.unfreeze_edited(notes.voice_revisions()), vec![1]);
+    }
+}
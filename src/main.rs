@@ -1,24 +1,41 @@
-use audio::{playback::Playback, prerenderer::{PrerenderedAudio, RenderMode}};
+use audio::{audio_error::AudioError, audio_render::{AudioRender, FreezeRender, RenderPass}, playback::Playback, prerenderer::{ChannelMode, PrerenderedAudio, RenderMode, SoundfontLoad, MAX_SYNTH_PORTS}, spectrum::{FftWindow, SpectrumAnalyzer, SpectrumSettings, FFT_SIZES, FLOOR_DB}};
 use cpal::{traits::StreamTrait, Stream};
-use editor::{navigation::Navigation, project_settings::ProjectSettings, settings::ApplicationSettings};
-use eframe::{egui::{self, vec2, Color32, Event, EventFilter, Key, Layout, PointerButton, RichText, Style, Ui}, egui_glow::CallbackFn, glow};
+use editor::{edit_tool::EditTool, keyboard_audition::KeyboardAudition, grid::GridResolution, layout::{WindowLayout, LAYOUT_PATH}, markers::MarkerList, minimap::{Minimap, MinimapDrag}, note_tools::{HumanizeOptions, QuantizeOptions, ScaleAnchor, ScaleOptions}, navigation::{Navigation, WheelAction, EDGE_SCROLL_MARGIN}, project_settings::ProjectSettings, tap_tempo::TapTempo, tempo_lane::TempoPointEdit, recorder::MIDIRecorder, settings::{Action, ApplicationSettings, SETTINGS_PATH}, theme::ThemePreset, track_list::{NoteGrouping, TrackList}};
+use eframe::{egui::{self, vec2, Color32, Event, EventFilter, Key, KeyboardShortcut, Layout, PointerButton, Rect, RichText, Stroke, StrokeKind, Style, Ui}, egui_glow::CallbackFn, glow};
 use eframe::glow::HasContext;
-use midi::{events::{MIDIEvent, TempoEvent}, io::midi_file::MIDIFile, notes::{Note, ProjectNoteManager}};
-use rendering::piano_roll::{PianoRollRenderer, Renderer};
-use std::{ops::DerefMut, path::absolute, process::exit};
+use midi::{time_signatures::{SnapMode, TimeSignatureMap}, events::{MIDIEvent, TempoEvent}, io::{midi_input::MIDIInput, midi_output::MIDIOutput, midi_loader::{LoadedMIDI, MIDILoader}, midi_track_parser::NotePairing, midi_writer::write_midi_file, project_data::ProjectData}, notes::{Note, ProjectNoteManager, ProjectStats}, scales::{key_name, MusicalScale, ScaleKind, PITCH_NAMES}};
+use rendering::{frame_sequence::{draw_playhead, FrameSequence, SequenceScroll, SequenceSettings}, offscreen::{save_png, OffscreenTarget}, piano_roll::{NoteColorMode, NoteStyle, PianoRollRenderer, Renderer}};
+use std::{collections::{BTreeMap, HashSet}, ops::DerefMut, path::{absolute, Path, PathBuf}, time::{Duration, Instant}};
 use std::sync::{Arc, Mutex};
-use sysinfo::System;
+use sysinfo::{System, MINIMUM_CPU_UPDATE_INTERVAL};
+use xsynth_core::{channel_group::ThreadCount, soundfont::EnvelopeCurveType};
 
 mod rendering;
 mod editor;
 mod audio;
 mod midi;
 
+const STATUS_MESSAGE_DURATION: Duration = Duration::from_secs(4);
+const RULER_HEIGHT: f32 = 20.0;
+const MINIMAP_HEIGHT: f32 = 28.0;
+const KEYBOARD_WIDTH: f32 = 56.0;
+const TEMPO_LANE_HEIGHT: f32 = 22.0;
+const VOICE_COUNT_INTERVAL: Duration = Duration::from_millis(250);
+/// How many frames the oscilloscope shows, about 20ms at 48kHz
+const SCOPE_FRAMES: usize = 1024;
+/// How much of the oscilloscope's half height full scale reaches, leaving room to see the wave go past it
+const SCOPE_SCALE: f32 = 0.8;
+/// The span of levels the spectrum shows, down from 0dB at the top
+const SPECTRUM_RANGE_DB: f32 = -96.0;
+/// How long rendering an image sequence may hold up each update before the window is drawn again
+const FRAME_SEQUENCE_BUDGET: Duration = Duration::from_millis(100);
+
 #[derive(PartialEq, Eq)]
 enum CurrentAppSettings {
     None,
     General,
-    Audio
+    Audio,
+    Keys
 }
 
 impl Default for CurrentAppSettings {
@@ -27,14 +44,45 @@ impl Default for CurrentAppSettings {
     }
 }
 
+impl CurrentAppSettings {
+    /// The name the open tab is remembered by in the window layout, empty while the settings are closed.
+    fn name(&self) -> &'static str {
+        match self {
+            CurrentAppSettings::None => "",
+            CurrentAppSettings::General => "general",
+            CurrentAppSettings::Audio => "audio",
+            CurrentAppSettings::Keys => "keys"
+        }
+    }
+
+    fn from_name(name: &str) -> Self {
+        match name {
+            "general" => CurrentAppSettings::General,
+            "audio" => CurrentAppSettings::Audio,
+            "keys" => CurrentAppSettings::Keys,
+            _ => CurrentAppSettings::None
+        }
+    }
+}
+
 #[derive(Default)]
 struct MainWindow {
     sys: System,
+    last_cpu_refresh: Option<Instant>,
+    /// the synth's active voices, sampled every `VOICE_COUNT_INTERVAL`
+    voice_count: u64,
+    last_voice_count_refresh: Option<Instant>,
+    /// the left and right output levels shown by the meter, falling back slowly after a peak
+    meter_levels: [f32; 2],
+    /// if the output clipped before the limiter since the clip light was last clicked
+    clipped: bool,
     gl: Option<Arc<glow::Context>>,
     renderer: Option<Arc<Mutex<dyn Renderer + Send + Sync>>>,
     nav: Option<Arc<Mutex<Navigation>>>,
 
     window_settings: CurrentAppSettings,
+    /// the window geometry and panel widths, kept up to date every frame and saved on exit
+    layout: WindowLayout,
     app_settings: Arc<Mutex<ApplicationSettings>>,
     project_settings: ProjectSettings,
     synth: Option<PrerenderedAudio>,
@@ -42,44 +90,2348 @@ struct MainWindow {
     synth_init: bool,
     curr_pointer_key: u8,
     note_playing: bool,
+    keyboard_audition: KeyboardAudition,
+    /// the action and which of its two keys the Keys settings tab is waiting for a key press to bind
+    capturing_key: Option<(Action, usize)>,
+    midi_input: MIDIInput,
+    recorder: MIDIRecorder,
+    /// the ids of the notes recorded since playback started, undone together
+    recorded_take: Vec<u32>,
+    /// why nothing can be heard, if the audio output couldn't be opened
+    audio_error: Option<String>,
+    /// why the last soundfonts chosen couldn't be loaded, shown in the Audio settings
+    soundfont_errors: Vec<String>,
+    status_message: Option<(String, Instant)>,
+    midi_loader: Option<MIDILoader>,
+    show_export_audio: bool,
+    /// if exporting audio writes a file per track instead of one of the whole song
+    export_stems: bool,
+    audio_render: Option<AudioRender>,
+    /// the track list entry being frozen, the `ProjectNoteManager::revision` its render started at and the render
+    freezing: Option<(u32, u64, FreezeRender)>,
+    /// the files picked for the import dialog, `None` while it's closed
+    pending_import: Option<Vec<PathBuf>>,
+    error_message: Option<String>,
+    show_stats: bool,
+    /// cleared whenever the notes or tempo map change, recomputed when the statistics window is shown
+    project_stats: Option<ProjectStats>,
+    track_list: TrackList,
+    show_track_list: bool,
+    color_mode: NoteColorMode,
+    note_style: NoteStyle,
+    /// if the piano roll smooths its lines and note edges, on by default
+    antialiasing: bool,
+    /// if hovering the piano roll shows the position and key under the pointer, on by default
+    show_hover_position: bool,
+    edit_tool: EditTool,
+    /// the velocity the pencil gives new notes
+    draw_velocity: u8,
+    /// if the pencil uses `last_velocity` instead of `draw_velocity` once there is one
+    draw_last_velocity: bool,
+    /// the velocity of the note last clicked, drawn or given a velocity
+    last_velocity: Option<u8>,
+    /// how long a clicked note is drawn, one step of `grid` if `None`
+    draw_length: Option<GridResolution>,
+    /// the note the pencil is dragging out, added but not recorded for undo until the drag ends
+    drawing_note: Option<u32>,
+    /// the editing grid, the arrow keys move notes by a step of it
+    grid: GridResolution,
+    markers: MarkerList,
+    show_markers: bool,
+    minimap: Minimap,
+    show_minimap: bool,
+    minimap_drag: Option<MinimapDrag>,
+    humanize_options: HumanizeOptions,
+    show_humanize: bool,
+    scale_options: ScaleOptions,
+    show_scale: bool,
+    quantize_options: QuantizeOptions,
+    show_quantize: bool,
+    /// how far past the next note's start the legato tool makes notes last, in ticks
+    legato_overlap: i32,
+    show_legato: bool,
+    /// the bar typed into the go to bar dialog, `None` while it's closed
+    goto_bar: Option<u64>,
+    /// the width and height in pixels typed into the snapshot dialog, `None` while it's closed
+    snapshot_size: Option<(u32, u32)>,
+    show_image_sequence: bool,
+    sequence_settings: SequenceSettings,
+    /// the image sequence being rendered and the tick the view was at before, to go back to once it's done
+    frame_sequence: Option<(FrameSequence, f32)>,
+    show_tempo: bool,
+    show_tempo_lane: bool,
+    /// if the oscilloscope of the output is shown under the piano roll
+    show_scope: bool,
+    /// if the spectrum of the output is shown under the piano roll
+    show_spectrum: bool,
+    spectrum_settings: SpectrumSettings,
+    /// running while the spectrum is shown
+    spectrum: Option<SpectrumAnalyzer>,
+    /// the tempo change open in the tempo point dialog, `None` while it's closed
+    tempo_point: Option<TempoPointEdit>,
+    tap_tempo: TapTempo,
+    /// the velocity typed into the set velocity dialog, `None` while it's closed
+    set_velocity: Option<u8>,
+    /// the semitones typed into the transpose dialog, `None` while it's closed
+    transpose_by: Option<i32>,
+    /// the start and end velocities in the velocity ramp dialog, `None` while it's closed
+    velocity_ramp: Option<(u8, u8)>,
+    /// the key that was right-clicked on the piano roll, for the context menu's select same pitch
+    context_key: Option<u8>,
+    /// the notes copied or cut last, with their starts relative to the earliest one
+    clipboard: Vec<Note>,
+    /// the tick being scrubbed to while the ruler is dragged
+    ruler_scrub: Option<f32>,
+    /// the (tick, key) corners of the selection rectangle being dragged
+    marquee: Option<((f32, f32), (f32, f32))>,
     stream: Option<Stream>,
     playback: Playback,
-    last_tick: f32,
+    /// if the view scrolls along with playback, otherwise the playhead moves across the view
+    follow_playback: bool,
+    /// if the project changed since it was last saved, closing asks to save it first
+    dirty: bool,
+    /// the file the project was last saved to, saving again writes there without asking
+    project_path: Option<PathBuf>,
+    /// if the unsaved changes prompt is open because the window is closing
+    show_close_prompt: bool,
+    /// set once the user chose to close, so the next close request isn't cancelled again
+    close_confirmed: bool,
+
+    project_note_manager: ProjectNoteManager
+}
+
+impl MainWindow {
+    /// `[midi_file]` - a MIDI file to import once the window opens, like one passed on the command line
+    fn new(cc: &eframe::CreationContext<'_>, layout: WindowLayout, midi_file: Option<PathBuf>) -> Self {
+        let mut s = Self {
+            app_settings: Arc::new(Mutex::new(ApplicationSettings::load(SETTINGS_PATH))),
+            ..Default::default()
+        };
+
+        let mut synth = PrerenderedAudio::new();
+        {
+            let app_settings = s.app_settings.lock().unwrap();
+            let audio_settings = &app_settings.audio_settings;
+            synth.set_master_gain(audio_settings.master_gain());
+            synth.set_channel_mode(audio_settings.channel_mode);
+            synth.set_vibrato_depth(audio_settings.vibrato_depth);
+            synth.set_high_pass(audio_settings.output_high_pass());
+            synth.set_limiter_lookahead(audio_settings.limiter_lookahead());
+            s.project_settings.ppq = app_settings.edit_settings.default_ppq;
+            s.project_settings.initial_bpm = app_settings.edit_settings.default_bpm;
+            s.follow_playback = app_settings.view_settings.follow_playback;
+            // loaded in the background so a big soundfont doesn't hold up the window opening.
+            // The app still works without it, it just can't be heard until one is chosen
+            synth.reload_soundfont(audio_settings.soundfont_path.clone(), audio_settings.soundfont_options());
+        }
+        synth.set_layer_count(2);
+
+        let initial_tempo = s.project_settings.initial_bpm;
+        let initial_tempo_raw = (60000000.0 / initial_tempo) as u32;
+
+        s.playback.tempo_events.push(TempoEvent {
+            time: 0,
+            time_norm: 0.0,
+            tempo: initial_tempo
+        });
+
+        match synth.build_stream() {
+            Ok(stream) => s.stream = Some(stream),
+            Err(err) => s.audio_error = Some(err.to_string())
+        }
+        s.synth = Some(synth);
+
+        s.show_track_list = layout.show_track_list;
+        s.show_markers = layout.show_markers;
+        s.show_minimap = layout.show_minimap;
+        s.show_tempo_lane = layout.show_tempo_lane;
+        s.show_scope = layout.show_scope;
+        s.show_spectrum = layout.show_spectrum;
+        s.window_settings = CurrentAppSettings::from_name(&layout.settings_tab);
+        s.layout = layout;
+        s.antialiasing = true;
+        s.show_hover_position = true;
+        s.draw_velocity = 100;
+
+        // imported the same way as a file picked from the menu, so a bad path ends up in the error dialog
+        if let Some(path) = midi_file {
+            s.import_midi_files(vec![path]);
+        }
+        s
+    }
+
+    fn init_gl(&mut self) {
+        let gl = self.gl.as_ref().unwrap();
+
+        let nav = Arc::new(Mutex::new(Navigation::new()));
+        let mut renderer = PianoRollRenderer::new(nav.clone(), gl.clone());
+        renderer.update_ppq(self.project_settings.ppq);
+        renderer.set_color_mode(self.color_mode);
+        renderer.set_note_style(self.note_style);
+        renderer.set_antialiasing(self.antialiasing);
+        renderer.set_scale_mask(self.project_settings.highlight_scale.map_or(0, |scale| scale.pitch_mask()));
+        renderer.set_theme(self.app_settings.lock().unwrap().theme);
+        renderer.update_grid(self.grid.ticks(self.project_settings.ppq));
+        renderer.update_time_signatures(self.project_settings.time_signatures.clone());
+        self.nav = Some(nav);
+        self.renderer = Some(Arc::new(Mutex::new(renderer)));
+    }
+
+    /// Starts importing the MIDI file at `[path]` in the background. The result is applied by `poll_midi_loader`.
+    fn import_midi(&mut self, path: &Path) {
+        self.import_midi_files(vec![path.to_path_buf()]);
+    }
+
+    /// Starts importing `[paths]` in the background, merged into one project with the first file's tempo map.
+    fn import_midi_files(&mut self, paths: Vec<PathBuf>) {
+        if self.midi_loader.is_some() {
+            self.set_status("Another MIDI file is still being imported");
+            return;
+        }
+        if paths.is_empty() { return; }
+        let (pairing, split_by_channel, keep_project_ppq) = {
+            let import_settings = &self.app_settings.lock().unwrap().import_settings;
+            (import_settings.note_pairing, import_settings.split_by_channel, import_settings.keep_project_ppq)
+        };
+        let resample_ppq = keep_project_ppq.then_some(self.project_settings.ppq);
+        self.midi_loader = Some(MIDILoader::start(paths, pairing, split_by_channel, resample_ppq));
+    }
+
+    /// Asks how the files picked from the File menu should be imported.
+    fn show_import_window(&mut self, ctx: &egui::Context) {
+        let Some(paths) = self.pending_import.as_ref() else { return; };
+        let mut open = true;
+        let mut import = false;
+        egui::Window::new("Import MIDI")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                for path in paths {
+                    ui.label(path.file_name().unwrap_or_default().to_string_lossy());
+                }
+                ui.separator();
+                let mut app_settings = self.app_settings.lock().unwrap();
+                ui.checkbox(&mut app_settings.import_settings.split_by_channel, "Split tracks by channel")
+                    .on_hover_text("Put the notes of each channel on a track of their own, for MIDIs that have every channel on one track. \
+                        They can then be colored, muted and soloed separately");
+                ui.checkbox(&mut app_settings.import_settings.keep_project_ppq, format!("Keep the project's PPQ ({})", self.project_settings.ppq))
+                    .on_hover_text("Rescale the imported notes, tempo changes and markers to the project's resolution. \
+                        Otherwise the project switches to the file's PPQ");
+                import = ui.button("Import").clicked();
+            });
+
+        if import {
+            let paths = self.pending_import.take().unwrap_or_default();
+            self.import_midi_files(paths);
+        } else if !open || ctx.input(|i| i.key_pressed(Key::Escape)) {
+            self.pending_import = None;
+        }
+    }
+
+    fn apply_loaded_midi(&mut self, midi: LoadedMIDI) {
+        self.project_settings.ppq = midi.ppq;
+        if let Some(renderer) = self.renderer.as_ref() {
+            let mut renderer = renderer.lock().unwrap();
+            renderer.update_ppq(midi.ppq);
+            renderer.update_grid(self.grid.ticks(midi.ppq));
+        }
+
+        self.playback.tempo_events = midi.tempo_evs;
+        self.playback.update_tempo_times(midi.ppq);
+        if let Some(first) = self.playback.tempo_events.first().filter(|ev| ev.time == 0) {
+            self.project_settings.initial_bpm = first.tempo;
+        }
+        // a MIDI file has no pickup of its own, bar 1 starts at the start unless the project saved one
+        self.project_settings.time_signatures = TimeSignatureMap::new(midi.time_sig_evs);
+        if let Some(project_data) = midi.project_data.as_ref() {
+            let pickup = project_data.pickup.min(self.project_settings.time_signatures.first_bar_ticks(midi.ppq));
+            self.project_settings.time_signatures.set_pickup(pickup);
+        }
+        if let Some(renderer) = self.renderer.as_ref() {
+            renderer.lock().unwrap().update_time_signatures(self.project_settings.time_signatures.clone());
+        }
+        self.markers = MarkerList::new(midi.markers);
+        self.project_stats = None;
+
+        // the renders are of the notes that were there before
+        self.track_list.unfreeze_all();
+        if let Some((_, _, render)) = self.freezing.take() {
+            render.cancel();
+        }
+        self.project_note_manager.convert_notes(midi.notes.into_iter().flatten());
+        self.project_note_manager.insert_controls(midi.controls);
+        self.project_note_manager.render_needs_update = true;
+
+        if let Some(project_data) = midi.project_data {
+            // the entries are kept for the voices they have notes of once the track list syncs
+            self.track_list.set_grouping(project_data.grouping);
+            self.track_list.tracks = project_data.tracks;
+            if let Some(renderer) = self.renderer.as_ref() {
+                renderer.lock().unwrap().set_grouping(project_data.grouping);
+            }
+        }
+
+        if !midi.tempo_conflicts.is_empty() {
+            let names = midi.tempo_conflicts.iter()
+                .map(|path| path.file_name().unwrap_or_default().to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+            self.set_status(&format!("The first file's tempo map was used, {} had a different one", names.join(", ")));
+        }
+    }
+
+    /// Applies the background MIDI import once it's done, showing its progress until then.
+    fn poll_midi_loader(&mut self, ctx: &egui::Context) {
+        let Some(loader) = self.midi_loader.as_ref() else { return; };
+
+        match loader.poll() {
+            Some(result) => {
+                self.midi_loader = None;
+                match result {
+                    Ok(midi) => self.apply_loaded_midi(midi),
+                    Err((path, err)) => {
+                        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                        self.error_message = Some(format!("Failed to import {}:\n{}", file_name, err));
+                    }
+                }
+            },
+            None => {
+                let progress = loader.progress();
+                egui::Window::new("Importing MIDI")
+                    .collapsible(false)
+                    .resizable(false)
+                    .anchor(egui::Align2::CENTER_CENTER, vec2(0.0, 0.0))
+                    .show(ctx, |ui| {
+                        ui.label(loader.name());
+                        ui.add(egui::ProgressBar::new(progress).show_percentage().desired_width(300.0));
+                    });
+                // keep repainting so the progress bar moves even without input
+                ctx.request_repaint_after(Duration::from_millis(50));
+            }
+        }
+    }
+
+    /// Asks whether to render the whole song or a file per track, then where to put it.
+    fn show_export_audio_window(&mut self, ctx: &egui::Context) {
+        let mut open = true;
+        let mut export = false;
+        let has_soundfont = self.synth.as_ref().is_some_and(|synth| synth.has_soundfont());
+        egui::Window::new("Export audio")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.radio_value(&mut self.export_stems, false, "Whole song")
+                    .on_hover_text("One WAV of what's heard when playing, muted and unsoloed tracks are left out");
+                ui.radio_value(&mut self.export_stems, true, "One file per track")
+                    .on_hover_text("A WAV of every track on its own, named track_00.wav, track_01.wav... for mixing elsewhere");
+                ui.separator();
+                let can_export = has_soundfont && self.audio_render.is_none() && self.project_note_manager.note_count() > 0;
+                export = ui.add_enabled(can_export, egui::Button::new("Export...")).clicked();
+                if !has_soundfont {
+                    ui.label(RichText::new("Choose a soundfont in the Audio settings first").color(Color32::YELLOW));
+                }
+            });
+
+        if export {
+            self.export_audio();
+        }
+        if !open || export {
+            self.show_export_audio = false;
+        }
+    }
+
+    /// Picks where to render to and starts rendering. The whole song leaves out what the track list silences,
+    /// a file per track renders each track with every other one filtered out.
+    fn export_audio(&mut self) {
+        let Some(synth) = self.synth.as_ref() else { return; };
+        let ppq = self.project_settings.ppq;
+        let ports = synth.ports();
+        let notes = &self.project_note_manager;
+        let passes = if self.export_stems {
+            let Some(folder) = rfd::FileDialog::new().pick_folder() else { return; };
+            let mut tracks: BTreeMap<u16, Vec<u32>> = BTreeMap::new();
+            for voice in notes.voices() {
+                tracks.entry((voice >> 8) as u16).or_default().push(voice);
+            }
+            tracks.keys()
+                .map(|track| {
+                    let silenced = notes.voices().filter(|voice| (voice >> 8) as u16 != *track).collect();
+                    RenderPass {
+                        path: folder.join(format!("track_{:02}.wav", track)),
+                        events: notes.get_events(ppq, &self.playback, &silenced, 0.0, ports)
+                    }
+                })
+                .collect()
+        } else {
+            let picked = rfd::FileDialog::new()
+                .add_filter("WAV Files", &["wav"])
+                .set_file_name("song.wav")
+                .save_file();
+            let Some(path) = picked else { return; };
+            let silenced = self.track_list.silenced_voices();
+            vec![RenderPass { path, events: notes.get_events(ppq, &self.playback, &silenced, 0.0, ports) }]
+        };
+        self.audio_render = Some(AudioRender::start(synth.setup(), passes));
+    }
+
+    /// Unfreezes track list entry `[group]` if it's frozen, otherwise starts rendering it to freeze it.
+    fn toggle_freeze(&mut self, group: u32) {
+        if self.track_list.is_frozen(group) {
+            self.track_list.unfreeze(group);
+            if self.playback.is_playing {
+                self.set_status("The track is synthesized again from the next time playback starts");
+            }
+            return;
+        }
+        let Some(synth) = self.synth.as_ref() else { return; };
+        if self.freezing.is_some() { return; }
+
+        let voices = self.track_list.group_voices(group);
+        let silenced = self.project_note_manager.voices().filter(|voice| !voices.contains(voice)).collect();
+        let events = self.project_note_manager.get_events(self.project_settings.ppq, &self.playback, &silenced, 0.0, synth.ports());
+        let revision = self.project_note_manager.revision();
+        self.freezing = Some((group, revision, FreezeRender::start(synth.setup(), events)));
+    }
+
+    /// Swaps in the render of the entry being frozen once it's done.
+    fn poll_freeze(&mut self, ctx: &egui::Context) {
+        let Some((group, revision, render)) = self.freezing.as_ref() else { return; };
+        match render.poll() {
+            Some(clip) => {
+                let (group, revision) = (*group, *revision);
+                self.freezing = None;
+                self.track_list.freeze(group, clip.into(), revision);
+                if !self.track_list.unfreeze_edited(self.project_note_manager.voice_revisions()).is_empty() {
+                    self.set_status("The track was edited while it was being frozen, it's synthesized instead");
+                    return;
+                }
+                let label = self.track_list.grouping().label(group);
+                if self.playback.is_playing {
+                    self.set_status(&format!("{} frozen, it's played from the render from the next time playback starts", label));
+                } else {
+                    self.set_status(&format!("{} frozen", label));
+                }
+            }
+            // keep repainting so the progress bar moves even without input
+            None => ctx.request_repaint_after(Duration::from_millis(50))
+        }
+    }
+
+    /// Shows how far the audio export is, and reports how it went once it's done.
+    fn poll_audio_render(&mut self, ctx: &egui::Context) {
+        let Some(render) = self.audio_render.as_ref() else { return; };
+
+        match render.poll() {
+            Some(Ok(())) => {
+                let files = render.pass_count;
+                self.audio_render = None;
+                self.set_status(&if files == 1 { "Exported the audio".to_string() } else { format!("Exported {} track files", files) });
+            }
+            Some(Err((path, err))) => {
+                self.audio_render = None;
+                self.error_message = Some(format!("Could not export {}:\n{}", path.display(), err));
+            }
+            None => {
+                let (pass, progress) = render.progress();
+                let mut cancel = false;
+                egui::Window::new("Exporting audio")
+                    .collapsible(false)
+                    .resizable(false)
+                    .anchor(egui::Align2::CENTER_CENTER, vec2(0.0, 0.0))
+                    .show(ctx, |ui| {
+                        if render.pass_count > 1 {
+                            ui.label(format!("Track file {} of {}", pass + 1, render.pass_count));
+                        }
+                        ui.add(egui::ProgressBar::new(progress).show_percentage().desired_width(300.0));
+                        cancel = ui.button("Cancel").clicked();
+                    });
+                if cancel {
+                    render.cancel();
+                    self.audio_render = None;
+                    self.set_status("Export cancelled");
+                }
+                // keep repainting so the progress bar moves even without input
+                ctx.request_repaint_after(Duration::from_millis(50));
+            }
+        }
+    }
+
+    /// Saves the soundfont chosen in the settings once it's loaded and swapped in.
+    fn poll_soundfont_loader(&mut self, ctx: &egui::Context) {
+        let Some(synth) = self.synth.as_mut() else { return; };
+        let Some((path, result)) = synth.poll_soundfont() else {
+            if synth.soundfont_load().is_some() {
+                // keeps the elapsed time counting up
+                ctx.request_repaint_after(Duration::from_millis(100));
+            }
+            return;
+        };
+
+        match result {
+            Ok(()) => {
+                self.soundfont_errors.clear();
+                self.app_settings.lock().unwrap().audio_settings.set_soundfont_path(path);
+            },
+            // the old soundfont is still loaded
+            Err(err) => self.soundfont_errors = vec![err.to_string()]
+        }
+    }
+
+    /// Imports the first MIDI file out of the files dropped onto the window, if any.
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped = ctx.input(|i| i.raw.dropped_files.clone());
+        if dropped.is_empty() { return; }
+
+        let midi_paths = dropped.iter()
+            .filter_map(|f| f.path.clone())
+            .filter(|p| p.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ["mid", "midi", "rmi"].iter().any(|midi_ext| ext.eq_ignore_ascii_case(midi_ext))))
+            .collect::<Vec<_>>();
+
+        let Some(path) = midi_paths.first() else {
+            self.set_status("Dropped file is not a MIDI file, ignoring");
+            return;
+        };
+
+        self.import_midi(path);
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        if midi_paths.len() > 1 {
+            self.set_status(&format!("Importing {} ({} MIDI files dropped, only the first is imported)", file_name, midi_paths.len()));
+        } else {
+            self.set_status(&format!("Importing {}", file_name));
+        }
+    }
+
+    /// Asks for the next frame of something animating, no sooner than the frame rate cap allows.
+    /// Without one egui only draws a frame when there's input, so nothing is redrawn while idle.
+    fn request_animation_frame(&self, ctx: &egui::Context) {
+        match self.app_settings.lock().unwrap().view_settings.frame_interval() {
+            Some(interval) => ctx.request_repaint_after(interval),
+            None => ctx.request_repaint()
+        }
+    }
+
+    /// Shows a message in the status bar for a few seconds.
+    fn set_status(&mut self, message: &str) {
+        self.status_message = Some((message.to_string(), Instant::now()));
+    }
+
+    fn show_stats_window(&mut self, ctx: &egui::Context) {
+        let ppq = self.project_settings.ppq;
+        let stats = self.project_stats.get_or_insert_with(||
+            self.project_note_manager.stats(ppq, &self.playback, &self.project_settings.time_signatures));
+
+        egui::Window::new("Statistics")
+            .open(&mut self.show_stats)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("stats_grid").striped(true).show(ui, |ui| {
+                    ui.label("Notes");
+                    ui.label(format!("{}", stats.note_count));
+                    ui.end_row();
+
+                    ui.label("Key range");
+                    match stats.key_range {
+                        Some((low, high)) => ui.label(format!("{} - {}", low, high)),
+                        None => ui.label("-")
+                    };
+                    ui.end_row();
+
+                    ui.label("Length");
+                    let secs = stats.length_secs;
+                    ui.label(format!("{:.2} bars, {}:{:06.3}", stats.length_bars, (secs / 60.0) as u32, secs % 60.0));
+                    ui.end_row();
+                });
+
+                ui.separator();
+                ui.collapsing("Notes per track", |ui| {
+                    egui::ScrollArea::vertical().max_height(200.0).id_salt("stats_tracks").show(ui, |ui| {
+                        for (track, count) in &stats.notes_per_track {
+                            ui.label(format!("Track {}: {}", track, count));
+                        }
+                    });
+                });
+                ui.collapsing("Notes per channel", |ui| {
+                    for (channel, count) in stats.notes_per_channel.iter().enumerate() {
+                        if *count > 0 {
+                            ui.label(format!("Channel {}: {}", channel + 1, count));
+                        }
+                    }
+                });
+            });
+    }
+
+    /// The list of tracks with their mute and solo toggles and note colors.
+    /// Its entries are tracks, channels or the channels of each track, depending on the grouping picked at the top.
+    fn show_track_list_panel(&mut self, ctx: &egui::Context) {
+        let mut changed = false;
+        let mut colors_changed = false;
+        let mut grouping = self.track_list.grouping();
+        let mut toggle_freeze = None;
+        let mut cancel_freeze = false;
+        let can_freeze = self.freezing.is_none() && self.synth.as_ref().is_some_and(|synth| synth.has_soundfont());
+        let panel = egui::SidePanel::left("track_list")
+            .resizable(true)
+            .default_width(self.layout.track_list_width)
+            .show(ctx, |ui| {
+                ui.heading("Tracks");
+                egui::ComboBox::from_id_salt("track_grouping")
+                    .selected_text(grouping.name())
+                    .show_ui(ui, |ui| {
+                        for option in NoteGrouping::ALL {
+                            ui.selectable_value(&mut grouping, option, option.name());
+                        }
+                    }).response.on_hover_text("What each entry stands for. Mute, solo and the picked colors apply to the entries, \
+                        and coloring notes by group follows them too");
+                ui.separator();
+                if self.track_list.tracks.is_empty() {
+                    ui.label("No tracks");
+                    return;
+                }
+
+                let any_solo = self.track_list.any_solo();
+                let frozen = self.track_list.tracks.keys()
+                    .filter(|group| self.track_list.is_frozen(**group))
+                    .copied()
+                    .collect::<HashSet<_>>();
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    let grouping = self.track_list.grouping();
+                    for (group, state) in self.track_list.tracks.iter_mut() {
+                        ui.horizontal(|ui| {
+                            changed |= ui.toggle_value(&mut state.muted, "M")
+                                .on_hover_text("Mute this track").changed();
+                            changed |= ui.toggle_value(&mut state.solo, "S")
+                                .on_hover_text("Solo this track. While any track is soloed only soloed tracks play").changed();
+
+                            let mut color = state.color.unwrap_or([0.5; 3]);
+                            if ui.color_edit_button_rgb(&mut color)
+                                .on_hover_text("The color of this track's notes. Uses the channel colors until one is picked")
+                                .changed() {
+                                state.color = Some(color);
+                                colors_changed = true;
+                            }
+                            if state.color.is_some() && ui.small_button("x").on_hover_text("Use the channel colors").clicked() {
+                                state.color = None;
+                                colors_changed = true;
+                            }
+
+                            let is_frozen = frozen.contains(group);
+                            match self.freezing.as_ref() {
+                                Some((freezing, _, render)) if freezing == group => {
+                                    ui.add(egui::ProgressBar::new(render.progress()).desired_width(40.0));
+                                    cancel_freeze = ui.small_button("x").on_hover_text("Stop freezing").clicked();
+                                }
+                                _ => {
+                                    let freeze = ui.add_enabled(is_frozen || can_freeze, egui::Button::new("F").selected(is_frozen))
+                                        .on_hover_text(if is_frozen {
+                                            "Frozen, played from a render instead of the synth. Click to unfreeze. \
+                                                Editing its notes unfreezes it"
+                                        } else {
+                                            "Freeze, render this track once and play that back instead of synthesizing it, to save CPU"
+                                        });
+                                    if freeze.clicked() {
+                                        toggle_freeze = Some(*group);
+                                    }
+                                }
+                            }
+
+                            let label = RichText::new(grouping.label(*group));
+                            let label = if is_frozen { label.color(Color32::from_rgb(140, 200, 255)) } else { label };
+                            ui.label(if state.is_audible(any_solo) { label } else { label.weak() });
+                        });
+                    }
+                });
+            });
+        self.layout.track_list_width = panel.response.rect.width();
+
+        if cancel_freeze && let Some((_, _, render)) = self.freezing.take() {
+            render.cancel();
+        }
+        if let Some(group) = toggle_freeze {
+            self.toggle_freeze(group);
+        }
+
+        if grouping != self.track_list.grouping() {
+            // the entries mean something else now, so a freeze in progress is for an entry that's gone
+            if let Some((_, _, render)) = self.freezing.take() {
+                render.cancel();
+            }
+            self.track_list.set_grouping(grouping);
+            if let Some(renderer) = self.renderer.as_ref() {
+                renderer.lock().unwrap().set_grouping(grouping);
+            }
+            // the old entries' flags and colors are gone
+            colors_changed = true;
+            changed = true;
+        }
+
+        // they're saved with the project
+        self.dirty |= changed || colors_changed;
+
+        if colors_changed && let Some(renderer) = self.renderer.as_ref() {
+            renderer.lock().unwrap().update_voice_colors(self.track_list.voice_colors());
+        }
+
+        if changed {
+            if let Some(renderer) = self.renderer.as_ref() {
+                renderer.lock().unwrap().update_silenced_voices(self.track_list.silenced_voices());
+            }
+            self.restart_synth_playback();
+        }
+    }
+
+    fn show_markers_panel(&mut self, ctx: &egui::Context) {
+        let mut jump_to = None;
+        let mut remove = None;
+        let mut add = false;
+        let mut renamed = false;
+        let panel = egui::SidePanel::right("markers")
+            .resizable(true)
+            .default_width(self.layout.markers_width)
+            .show(ctx, |ui| {
+                ui.heading("Markers");
+                ui.separator();
+                add = ui.button("Add at playhead").clicked();
+                if self.markers.markers.is_empty() {
+                    ui.label("No markers");
+                    return;
+                }
+
+                let ppq = self.project_settings.ppq;
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (i, marker) in self.markers.markers.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            let (bar, beat, _) = self.project_settings.time_signatures.bar_beat_tick(ppq, marker.tick);
+                            if ui.button(format!("{}:{}", bar, beat)).on_hover_text("Jump to this marker").clicked() {
+                                jump_to = Some(marker.tick);
+                            }
+                            renamed |= ui.add(egui::TextEdit::singleline(&mut marker.name).desired_width(100.0)).changed();
+                            if ui.small_button("x").on_hover_text("Delete this marker").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                    }
+                });
+            });
+        self.layout.markers_width = panel.response.rect.width();
+
+        if add {
+            let tick = self.playhead_tick();
+            let name = format!("Marker {}", self.markers.markers.len() + 1);
+            self.markers.add(tick as u64, name);
+        }
+        if let Some(index) = remove {
+            self.markers.remove(index);
+        }
+        self.dirty |= add || renamed || remove.is_some();
+        if let Some(tick) = jump_to {
+            self.set_play_position(ctx, tick as f32);
+        }
+    }
+
+    /// Draws the last few milliseconds of the output, left and right over each other. The drawing starts where the
+    /// signal last rose through zero, so a steady tone stands still instead of scrolling.
+    fn show_scope_panel(&mut self, ctx: &egui::Context) {
+        let frames = self.synth.as_ref().map(|synth| synth.capture().latest(SCOPE_FRAMES * 2)).unwrap_or_default();
+        egui::TopBottomPanel::bottom("oscilloscope")
+            .resizable(true)
+            .default_height(120.0)
+            .show(ctx, |ui| {
+                let (rect, _) = ui.allocate_exact_size(ui.available_size(), egui::Sense::hover());
+                let painter = ui.painter_at(rect);
+                painter.rect_filled(rect, 0.0, Color32::from_gray(20));
+                let sample_to_y = |sample: f32| rect.center().y - sample * rect.height() / 2.0 * SCOPE_SCALE;
+                // full scale, the wave going past these clips without the limiter
+                for level in [-1.0, 1.0] {
+                    painter.hline(rect.x_range(), sample_to_y(level), Stroke::new(1.0, Color32::from_gray(70)));
+                }
+                painter.hline(rect.x_range(), rect.center().y, Stroke::new(1.0, Color32::from_gray(45)));
+                if frames.len() < SCOPE_FRAMES { return; }
+
+                let mono = |frame: &[f32; 2]| frame[0] + frame[1];
+                let last_start = frames.len() - SCOPE_FRAMES;
+                let start = (1..=last_start).rev()
+                    .find(|&i| mono(&frames[i - 1]) < 0.0 && mono(&frames[i]) >= 0.0)
+                    .unwrap_or(last_start);
+                let shown = &frames[start..start + SCOPE_FRAMES];
+                for (channel, color) in [(0, Color32::from_rgb(90, 200, 255)), (1, Color32::from_rgb(255, 140, 90))] {
+                    let points = shown.iter().enumerate()
+                        .map(|(i, frame)| egui::pos2(
+                            rect.min.x + i as f32 / (SCOPE_FRAMES - 1) as f32 * rect.width(),
+                            sample_to_y(frame[channel]).clamp(rect.min.y, rect.max.y)
+                        ))
+                        .collect();
+                    painter.add(egui::Shape::line(points, Stroke::new(1.0, color)));
+                }
+            });
+    }
+
+    /// Draws the levels of the output's frequencies, on a log scale from 20Hz to half the sample rate.
+    /// The analyzer runs while this is shown and holds still while nothing is playing or ringing out.
+    fn show_spectrum_panel(&mut self, ctx: &egui::Context) {
+        let Some(synth) = self.synth.as_ref() else { return; };
+        let sample_rate = synth.sample_rate() as f32;
+        let analyzer = self.spectrum.get_or_insert_with(|| SpectrumAnalyzer::start(synth.capture().clone(), self.spectrum_settings));
+        let paused = !self.playback.is_playing && self.meter_levels.iter().all(|level| *level <= 0.001);
+        analyzer.set_paused(paused);
+        let levels = analyzer.levels();
+
+        let settings = &mut self.spectrum_settings;
+        let old_settings = *settings;
+        egui::TopBottomPanel::bottom("spectrum")
+            .resizable(true)
+            .default_height(140.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("FFT size")
+                        .selected_text(settings.fft_size.to_string())
+                        .show_ui(ui, |ui| {
+                            for size in FFT_SIZES {
+                                ui.selectable_value(&mut settings.fft_size, size, size.to_string());
+                            }
+                        }).response.on_hover_text("Bigger sizes tell close frequencies apart better but react slower");
+                    egui::ComboBox::from_label("Window")
+                        .selected_text(settings.window.name())
+                        .show_ui(ui, |ui| {
+                            for window in FftWindow::ALL {
+                                ui.selectable_value(&mut settings.window, window, window.name());
+                            }
+                        }).response.on_hover_text("Hann suits most material. Blackman-Harris shows quiet tones next to loud ones, \
+                            rectangular gives the sharpest peaks but smears everything else");
+                });
+
+                let (rect, _) = ui.allocate_exact_size(ui.available_size(), egui::Sense::hover());
+                let painter = ui.painter_at(rect);
+                painter.rect_filled(rect, 0.0, Color32::from_gray(20));
+                let (min_freq, max_freq) = (20.0f32, sample_rate / 2.0);
+                let freq_to_x = |freq: f32| rect.min.x + (freq / min_freq).log10() / (max_freq / min_freq).log10() * rect.width();
+                let db_to_y = |db: f32| rect.min.y + db / SPECTRUM_RANGE_DB * rect.height();
+
+                let label_font = egui::FontId::proportional(10.0);
+                for (freq, label) in [(100.0, "100"), (1000.0, "1k"), (10000.0, "10k")] {
+                    let x = freq_to_x(freq);
+                    painter.vline(x, rect.y_range(), Stroke::new(1.0, Color32::from_gray(50)));
+                    painter.text(egui::pos2(x + 2.0, rect.max.y - 2.0), egui::Align2::LEFT_BOTTOM, label, label_font.clone(), Color32::from_gray(120));
+                }
+                for db in (1..(SPECTRUM_RANGE_DB / -24.0) as i32).map(|i| i as f32 * -24.0) {
+                    let y = db_to_y(db);
+                    painter.hline(rect.x_range(), y, Stroke::new(1.0, Color32::from_gray(40)));
+                    painter.text(egui::pos2(rect.min.x + 2.0, y - 1.0), egui::Align2::LEFT_BOTTOM, format!("{} dB", db), label_font.clone(), Color32::from_gray(120));
+                }
+
+                let bin_width = sample_rate / (levels.len() * 2).max(1) as f32;
+                let points = levels.iter().enumerate()
+                    .skip(1)
+                    .map(|(bin, db)| (bin as f32 * bin_width, db.max(FLOOR_DB)))
+                    .filter(|(freq, _)| *freq >= min_freq)
+                    .map(|(freq, db)| egui::pos2(freq_to_x(freq), db_to_y(db).clamp(rect.min.y, rect.max.y)))
+                    .collect::<Vec<_>>();
+                if points.len() > 1 {
+                    painter.add(egui::Shape::line(points, Stroke::new(1.0, Color32::from_rgb(120, 220, 140))));
+                }
+            });
+
+        if *settings != old_settings {
+            analyzer.set_settings(*settings);
+        }
+        if !paused {
+            self.request_animation_frame(ctx);
+        }
+    }
+
+    /// Writes the project as a MIDI file, asking where first unless it was saved before this session and `[save_as]` is false.
+    /// Returns if it was saved.
+    fn save_project(&mut self, save_as: bool) -> bool {
+        let path = match self.project_path.clone() {
+            Some(path) if !save_as => path,
+            _ => {
+                let picked = rfd::FileDialog::new()
+                    .add_filter("MIDI Files", &["mid","midi"])
+                    .set_file_name("project.mid")
+                    .save_file();
+                let Some(path) = picked else { return false; };
+                path
+            }
+        };
+
+        let mut tracks: BTreeMap<u16, Vec<Note>> = BTreeMap::new();
+        for note in self.project_note_manager.notes() {
+            tracks.entry(note.track()).or_default().push(note.to_note());
+        }
+        let saved = write_midi_file(
+            &path,
+            self.project_settings.ppq,
+            &tracks,
+            &self.playback.tempo_events,
+            self.project_settings.time_signatures.events(),
+            &self.markers.markers,
+            self.project_note_manager.controls(),
+            &ProjectData {
+                grouping: self.track_list.grouping(),
+                tracks: self.track_list.tracks.clone(),
+                pickup: self.project_settings.time_signatures.pickup()
+            }
+        );
+        match saved {
+            Ok(()) => {
+                self.set_status(&format!("Saved {}", path.file_name().unwrap_or_default().to_string_lossy()));
+                self.project_path = Some(path);
+                self.dirty = false;
+                true
+            }
+            Err(err) => {
+                self.error_message = Some(format!("Could not save {}:\n{}", path.display(), err));
+                false
+            }
+        }
+    }
+
+    /// Stops the window from closing while there are unsaved changes and asks what to do with them instead.
+    fn handle_close_request(&mut self, ctx: &egui::Context) {
+        if ctx.input(|i| i.viewport().close_requested()) && self.dirty && !self.close_confirmed {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.show_close_prompt = true;
+        }
+    }
+
+    fn show_close_prompt_window(&mut self, ctx: &egui::Context) {
+        if !self.show_close_prompt { return; }
+
+        let mut close = false;
+        egui::Window::new("Unsaved changes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, vec2(0.0, 0.0))
+            .show(ctx, |ui| {
+                ui.label("The project has changes that haven't been saved. Save them before closing?");
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        // a cancelled save dialog keeps the prompt open
+                        close = self.save_project(false);
+                    }
+                    if ui.button("Don't save").clicked() {
+                        close = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.show_close_prompt = false;
+                    }
+                });
+            });
+
+        if close {
+            self.show_close_prompt = false;
+            self.close_confirmed = true;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+    }
+
+    /// Keeps the window's size and position in the layout. While maximized or minimized the last normal geometry is kept,
+    /// so un-maximizing after a restart goes back to it.
+    fn track_window_geometry(&mut self, ctx: &egui::Context) {
+        ctx.input(|i| {
+            let viewport = i.viewport();
+            let maximized = viewport.maximized.unwrap_or(false);
+            if viewport.minimized.unwrap_or(false) {
+                return;
+            }
+            self.layout.maximized = maximized;
+            if maximized || viewport.fullscreen.unwrap_or(false) {
+                return;
+            }
+            if let Some(rect) = viewport.inner_rect {
+                self.layout.inner_size = Some(rect.size().into());
+            }
+            if let Some(rect) = viewport.outer_rect {
+                self.layout.position = Some(rect.min.into());
+            }
+        });
+    }
+
+    /// Turns the notes played on the MIDI controller into project notes while recording.
+    fn record_live_notes(&mut self) {
+        let live_notes = self.midi_input.poll();
+        if !self.playback.is_playing || !self.recorder.armed { return; }
+
+        let ppq = self.project_settings.ppq;
+        for live_note in live_notes {
+            let tick = self.playback.tick_at(ppq, live_note.received) as u32;
+            if live_note.velocity > 0 {
+                self.recorder.note_on(live_note.key, live_note.velocity, tick);
+            } else if let Some(note) = self.recorder.note_off(live_note.key, tick) {
+                self.recorded_take.push(self.project_note_manager.add_note(note));
+            }
+        }
+    }
+
+    /// Closes the notes still held when playback stops and makes the take one undo step.
+    fn finish_recording(&mut self) {
+        let tick = self.playback.get_playback_time(self.project_settings.ppq) as u32;
+        for note in self.recorder.finish(tick) {
+            self.recorded_take.push(self.project_note_manager.add_note(note));
+        }
+        let take = std::mem::take(&mut self.recorded_take);
+        self.project_note_manager.record_added(&take);
+    }
+
+    /// Starts playback from the playhead, or pauses it where it is.
+    fn toggle_playback(&mut self, ctx: &egui::Context) {
+        if self.playback.is_playing {
+            self.pause_playback(ctx);
+        } else {
+            self.start_playback(ctx);
+        }
+    }
+
+    fn start_playback(&mut self, ctx: &egui::Context) {
+        if self.playback.is_playing { return; }
+        if let Some(synth) = self.synth.as_mut() {
+            for midi_key in self.keyboard_audition.release_all() {
+                synth.note_off(0, midi_key);
+            }
+        }
+        self.playback.play();
+        if self.recorder.armed {
+            let track = self.project_note_manager.voices().map(|voice| (voice >> 8) as u16).max().map_or(0, |track| track + 1);
+            self.recorder.start(track, self.midi_input.channel());
+        }
+        self.start_synth_playback();
+        ctx.request_repaint();
+    }
+
+    /// Hands the synth the events from `playback_secs` on, leaving out the silenced and frozen entries, and starts rendering them.
+    fn start_synth_playback(&mut self) {
+        let Some(synth) = self.synth.as_mut() else { return; };
+        // frozen entries are heard from their clips instead
+        let mut silenced = self.track_list.silenced_voices();
+        silenced.extend(self.track_list.frozen_voices());
+        synth.set_clips(self.track_list.audible_clips(), self.playback.playback_secs);
+        synth.set_note_skipping(self.app_settings.lock().unwrap().audio_settings.note_skipping());
+        let ports = synth.ports();
+        synth.set_events(self.project_note_manager.get_events(self.project_settings.ppq, &self.playback, &silenced, self.playback.playback_secs, ports));
+        synth.switch_render_mode(RenderMode::Rendering);
+    }
+
+    /// Starts the synth over from where playback is, so a change to what's heard applies right away.
+    /// Playback itself and a recording in progress carry on.
+    fn restart_synth_playback(&mut self) {
+        if !self.playback.is_playing { return; }
+        // pausing and playing again moves `playback_secs` up to now
+        self.playback.pause();
+        self.playback.play();
+        if let Some(synth) = self.synth.as_mut() {
+            synth.switch_render_mode(RenderMode::Realtime);
+        }
+        self.start_synth_playback();
+    }
+
+    /// Stops playback, leaving the playhead where it stopped.
+    fn pause_playback(&mut self, ctx: &egui::Context) {
+        if !self.playback.is_playing { return; }
+        self.finish_recording();
+        let tick = self.playback.get_playback_time(self.project_settings.ppq);
+        self.playback.pause();
+        if let Some(synth) = self.synth.as_mut() {
+            synth.switch_render_mode(RenderMode::Realtime);
+        }
+        self.set_tick_pos(tick);
+        ctx.request_repaint();
+    }
+
+    /// Stops playback and returns the playhead to the start of the song.
+    fn stop_to_start(&mut self, ctx: &egui::Context) {
+        self.pause_playback(ctx);
+        self.playback.stop_to_start();
+        self.set_tick_pos(0.0);
+        ctx.request_repaint();
+    }
+
+    /// Stops playback and silences everything the synth is playing, resetting its controllers and pitch bend.
+    /// Also forgets the notes held by mouse and keyboard audition, so releasing them later doesn't matter.
+    fn panic(&mut self, ctx: &egui::Context) {
+        self.pause_playback(ctx);
+        self.keyboard_audition.release_all();
+        self.note_playing = false;
+        self.curr_pointer_key = 0;
+        if let Some(synth) = self.synth.as_mut() {
+            synth.panic();
+        }
+        self.set_status("All notes stopped and controllers reset");
+    }
+
+    /// The tick the playhead is on. That's the view's left edge, except while playing without the view following.
+    fn playhead_tick(&self) -> f32 {
+        if self.playback.is_playing && !self.follow_playback {
+            return self.playback.tick_at(self.project_settings.ppq, Instant::now()).max(0.0);
+        }
+        self.nav.as_ref().map_or(0.0, |nav| nav.lock().unwrap().tick_pos.max(0.0))
+    }
+
+    /// Moves the view to `[tick]`, keeping the renderer's note search in step.
+    fn set_tick_pos(&mut self, tick: f32) {
+        let (Some(nav), Some(renderer)) = (self.nav.as_ref(), self.renderer.as_ref()) else { return; };
+        nav.lock().unwrap().change_tick_pos(tick.max(0.0), |time| renderer.lock().unwrap().time_changed(time));
+    }
+
+    /// Moves the playhead to `[tick]` exactly, so playback starts from there. Keeps playing if it was.
+    fn set_play_position(&mut self, ctx: &egui::Context, tick: f32) {
+        let was_playing = self.playback.is_playing;
+        self.pause_playback(ctx);
+        let tick = tick.max(0.0);
+        self.playback.navigate_to(self.project_settings.ppq, tick);
+        self.set_tick_pos(tick);
+        if was_playing {
+            self.start_playback(ctx);
+        }
+    }
+
+    /// Moves the playhead to `[tick]` snapped to the seek grid, or exactly while Alt is held.
+    fn seek(&mut self, ctx: &egui::Context, tick: f32) {
+        let snap = self.app_settings.lock().unwrap().edit_settings.seek_snap;
+        let free = ctx.input(|i| i.modifiers.alt);
+        let tick = if free {
+            tick
+        } else {
+            self.project_settings.time_signatures.snap(self.project_settings.ppq, tick.max(0.0) as u64, snap) as f32
+        };
+        self.set_play_position(ctx, tick);
+    }
+
+    /// Moves the playhead to the start of the next note, or the previous one if `[forward]` isn't set, and centers the view
+    /// on its key. Only looks at the track of the selected notes if they're all on one, otherwise at every track.
+    fn jump_to_note(&mut self, ctx: &egui::Context, forward: bool) {
+        let Some(nav) = self.nav.clone() else { return; };
+        let tick = self.playhead_tick().round() as u32;
+
+        let mut selected_tracks = self.project_note_manager.selected.iter()
+            .filter_map(|id| self.project_note_manager.get(*id))
+            .map(|note| note.track());
+        let first = selected_tracks.next();
+        let track = first.filter(|first| selected_tracks.all(|track| track == *first));
+
+        let note = if forward {
+            self.project_note_manager.next_note_after(tick, track)
+        } else {
+            self.project_note_manager.previous_note_before(tick, track)
+        };
+        let Some((start, key)) = note.map(|note| (note.start, note.key)) else { return; };
+
+        self.set_play_position(ctx, start as f32);
+        let mut nav = nav.lock().unwrap();
+        nav.key_pos = (key as f32 + 0.5 - nav.zoom_keys / 2.0).clamp(0.0, (128.0 - nav.zoom_keys).max(0.0));
+    }
+
+    fn open_goto_bar(&mut self) {
+        let tick = self.playhead_tick();
+        let (bar, _, _) = self.project_settings.time_signatures.bar_beat_tick(self.project_settings.ppq, tick as u64);
+        self.goto_bar = Some(bar);
+    }
+
+    fn show_humanize_window(&mut self, ctx: &egui::Context) {
+        let mut apply = false;
+        let options = &mut self.humanize_options;
+        let has_selection = !self.project_note_manager.selected.is_empty();
+        egui::Window::new("Humanize")
+            .open(&mut self.show_humanize)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Timing");
+                    ui.add(egui::DragValue::new(&mut options.max_ticks).range(0..=self.project_settings.ppq as u32).suffix(" ticks"));
+                }).response.on_hover_text("How far each note's start can move earlier or later");
+                ui.horizontal(|ui| {
+                    ui.label("Velocity");
+                    ui.add(egui::DragValue::new(&mut options.max_velocity).range(0..=127));
+                }).response.on_hover_text("How much each note's velocity can go up or down");
+                ui.horizontal(|ui| {
+                    ui.label("Seed");
+                    ui.add(egui::DragValue::new(&mut options.seed));
+                    if ui.button("New").clicked() {
+                        options.seed = rand::random();
+                    }
+                }).response.on_hover_text("The same seed on the same notes always gives the same result");
+                apply = ui.add_enabled(has_selection, egui::Button::new("Apply to selection")).clicked();
+            });
+
+        if apply {
+            let options = self.humanize_options;
+            let ids = self.project_note_manager.selected_ids();
+            self.project_note_manager.humanize(&ids, options.max_ticks, options.max_velocity, options.seed);
+        }
+    }
+
+    fn show_quantize_window(&mut self, ctx: &egui::Context) {
+        let mut apply = false;
+        let options = &mut self.quantize_options;
+        let has_selection = !self.project_note_manager.selected.is_empty();
+        egui::Window::new("Quantize")
+            .open(&mut self.show_quantize)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Grid");
+                    egui::ComboBox::from_id_salt("quantize_grid")
+                        .selected_text(options.grid.name())
+                        .show_ui(ui, |ui| {
+                            for grid in GridResolution::CHOICES {
+                                ui.selectable_value(&mut options.grid, grid, grid.name());
+                            }
+                        });
+                }).response.on_hover_text("The note value the starts snap to");
+                ui.horizontal(|ui| {
+                    ui.label("Swing");
+                    ui.add(egui::Slider::new(&mut options.swing, 50.0..=75.0).suffix("%"));
+                }).response.on_hover_text("How late every second grid step is, as a share of two steps. \
+                    50% is straight, about 67% is a triplet shuffle");
+                apply = ui.add_enabled(has_selection, egui::Button::new("Apply to selection")).clicked();
+            });
+
+        if apply {
+            let options = self.quantize_options;
+            let ids = self.project_note_manager.selected_ids();
+            self.project_note_manager.quantize(&ids, options.grid.ticks(self.project_settings.ppq), options.swing / 100.0);
+        }
+    }
+
+    fn show_legato_window(&mut self, ctx: &egui::Context) {
+        let mut apply = false;
+        let overlap = &mut self.legato_overlap;
+        let has_selection = !self.project_note_manager.selected.is_empty();
+        let max_overlap = self.project_settings.ppq as i32;
+        egui::Window::new("Legato")
+            .open(&mut self.show_legato)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Overlap");
+                    ui.add(egui::DragValue::new(overlap).range(-max_overlap..=max_overlap).suffix(" ticks"));
+                }).response.on_hover_text("How far each note lasts past the start of the next note on its key. \
+                    0 makes them just touch, below 0 leaves a gap");
+                apply = ui.add_enabled(has_selection, egui::Button::new("Apply to selection")).clicked();
+            });
+
+        if apply {
+            let ids = self.project_note_manager.selected_ids();
+            self.project_note_manager.legato(&ids, self.legato_overlap);
+        }
+    }
+
+    fn show_scale_window(&mut self, ctx: &egui::Context) {
+        let mut apply = false;
+        let options = &mut self.scale_options;
+        let has_selection = !self.project_note_manager.selected.is_empty();
+        egui::Window::new("Scale time")
+            .open(&mut self.show_scale)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Factor");
+                    ui.add(egui::DragValue::new(&mut options.factor).range(0.01..=100.0).speed(0.01).prefix("×"));
+                    for factor in [0.5, 2.0] {
+                        if ui.button(format!("×{}", factor)).clicked() {
+                            options.factor = factor;
+                        }
+                    }
+                }).response.on_hover_text("What the starts and lengths are multiplied by. Below 1 compresses, above 1 stretches");
+                ui.horizontal(|ui| {
+                    ui.label("From");
+                    ui.radio_value(&mut options.anchor, ScaleAnchor::SelectionStart, "Selection start");
+                    ui.radio_value(&mut options.anchor, ScaleAnchor::Playhead, "Playhead");
+                }).response.on_hover_text("The point in time that stays put while the notes are stretched or compressed");
+                apply = ui.add_enabled(has_selection, egui::Button::new("Apply to selection")).clicked();
+            });
+
+        if apply {
+            let options = self.scale_options;
+            let ids = self.project_note_manager.selected_ids();
+            let anchor = match options.anchor {
+                ScaleAnchor::SelectionStart => ids.iter()
+                    .filter_map(|id| self.project_note_manager.get(*id))
+                    .map(|n| n.start)
+                    .min()
+                    .unwrap_or(0),
+                ScaleAnchor::Playhead => self.playhead_tick() as u32
+            };
+            self.project_note_manager.scale_time(&ids, options.factor, anchor);
+        }
+    }
+
+    fn show_goto_bar_window(&mut self, ctx: &egui::Context) {
+        let Some(mut bar) = self.goto_bar else { return; };
+        let mut open = true;
+        let mut go = false;
+        egui::Window::new("Go to bar")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Bar");
+                    let first_bar = self.project_settings.time_signatures.first_bar();
+                    let field = ui.add(egui::DragValue::new(&mut bar).range(first_bar..=u32::MAX as u64));
+                    if ui.memory(|m| m.focused().is_none()) {
+                        field.request_focus();
+                    }
+                    go = ui.button("Go").clicked()
+                        || (field.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)));
+                });
+            });
+
+        if go {
+            // walks the time signature map, so this stays right after signature changes
+            let tick = self.project_settings.time_signatures.bar_to_tick(self.project_settings.ppq, bar);
+            self.seek(ctx, tick as f32);
+            self.goto_bar = None;
+        } else if open && !ctx.input(|i| i.key_pressed(Key::Escape)) {
+            self.goto_bar = Some(bar);
+        } else {
+            self.goto_bar = None;
+        }
+    }
+
+    fn show_snapshot_window(&mut self, ctx: &egui::Context) {
+        let Some((mut width, mut height)) = self.snapshot_size else { return; };
+        let mut open = true;
+        let mut save = false;
+        egui::Window::new("Save snapshot")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Size");
+                    ui.add(egui::DragValue::new(&mut width).range(1..=16384).suffix(" px"));
+                    ui.label("x");
+                    ui.add(egui::DragValue::new(&mut height).range(1..=16384).suffix(" px"));
+                });
+                ui.label("The view is stretched to fit, keep the window's shape to keep notes in proportion");
+                save = ui.button("Save...").clicked();
+            });
+
+        if save {
+            self.snapshot_size = None;
+            let picked = rfd::FileDialog::new()
+                .add_filter("PNG Images", &["png"])
+                .set_file_name("piano_roll.png")
+                .save_file();
+            if let Some(path) = picked {
+                self.save_snapshot(&path, width, height);
+            }
+        } else if open && !ctx.input(|i| i.key_pressed(Key::Escape)) {
+            self.snapshot_size = Some((width, height));
+        } else {
+            self.snapshot_size = None;
+        }
+    }
+
+    /// Draws the piano roll's current view into a `[width]`x`[height]` framebuffer and saves it to `[path]` as a PNG.
+    fn save_snapshot(&mut self, path: &Path, width: u32, height: u32) {
+        let (Some(gl), Some(renderer)) = (self.gl.as_ref(), self.renderer.as_ref()) else { return; };
+        let theme = self.app_settings.lock().unwrap().theme;
+        let saved = OffscreenTarget::new(gl.clone(), width, height).and_then(|target| {
+            let pixels = target.render(renderer.lock().unwrap().deref_mut(), theme.background);
+            save_png(path, target.width(), target.height(), &pixels)
+        });
+        match saved {
+            Ok(()) => self.set_status(&format!("Saved {}", path.display())),
+            Err(err) => self.error_message = Some(format!("Could not save the snapshot:\n{}", err))
+        }
+    }
+
+    /// Asks for the size, frame rate and scrolling of an image sequence, then which folder to render it to.
+    fn show_image_sequence_window(&mut self, ctx: &egui::Context) {
+        let mut open = true;
+        let mut render = false;
+        let settings = &mut self.sequence_settings;
+        egui::Window::new("Render image sequence")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("image_sequence_grid").num_columns(2).show(ui, |ui| {
+                    ui.label("Size");
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut settings.width).range(1..=16384).suffix(" px"));
+                        ui.label("x");
+                        ui.add(egui::DragValue::new(&mut settings.height).range(1..=16384).suffix(" px"));
+                    });
+                    ui.end_row();
+
+                    ui.label("Frame rate");
+                    ui.add(egui::DragValue::new(&mut settings.fps).range(1..=240).suffix(" fps"));
+                    ui.end_row();
+
+                    ui.label("Scrolling");
+                    egui::ComboBox::from_id_salt("image_sequence_scroll")
+                        .selected_text(settings.scroll.name())
+                        .show_ui(ui, |ui| {
+                            for scroll in SequenceScroll::ALL {
+                                ui.selectable_value(&mut settings.scroll, scroll, scroll.name());
+                            }
+                        });
+                    ui.end_row();
+                });
+                ui.label("The view keeps its zoom and keys, only where it starts moves along with the song");
+                ui.separator();
+                let can_render = self.frame_sequence.is_none() && self.project_note_manager.note_count() > 0;
+                render = ui.add_enabled(can_render, egui::Button::new("Render...")).clicked();
+            });
+
+        if render && let Some(folder) = rfd::FileDialog::new().pick_folder() {
+            self.start_frame_sequence(ctx, &folder);
+        }
+        if !open || render {
+            self.show_image_sequence = false;
+        }
+    }
+
+    /// Starts rendering the song as an image sequence into `[folder]`, stopping playback first.
+    fn start_frame_sequence(&mut self, ctx: &egui::Context, folder: &Path) {
+        let Some(gl) = self.gl.clone() else { return; };
+        self.pause_playback(ctx);
+        let length_secs = self.project_note_manager.stats(self.project_settings.ppq, &self.playback, &self.project_settings.time_signatures).length_secs;
+        match FrameSequence::start(gl, folder, self.sequence_settings, length_secs) {
+            Ok(sequence) => {
+                let view_tick = self.nav.as_ref().map_or(0.0, |nav| nav.lock().unwrap().tick_pos);
+                self.frame_sequence = Some((sequence, view_tick));
+            }
+            Err(err) => self.error_message = Some(format!("Could not render the image sequence:\n{}", err))
+        }
+    }
+
+    /// Renders the next frames of the image sequence for up to `FRAME_SEQUENCE_BUDGET`, so the window stays responsive,
+    /// and shows how far it is. Puts the view back where it was once it's done or cancelled.
+    fn render_frame_sequence(&mut self, ctx: &egui::Context) {
+        let Some((sequence, _)) = self.frame_sequence.as_mut() else { return; };
+        let (Some(nav), Some(renderer)) = (self.nav.as_ref(), self.renderer.as_ref()) else { return; };
+        let ppq = self.project_settings.ppq;
+        let theme = self.app_settings.lock().unwrap().theme;
+        let settings = sequence.settings();
+        let (width, height) = (sequence.target().width(), sequence.target().height());
+
+        let started = Instant::now();
+        let mut result = Ok(());
+        while started.elapsed() < FRAME_SEQUENCE_BUDGET {
+            let Some(secs) = sequence.next_frame() else { break; };
+            let tick = self.playback.secs_to_tick(ppq, secs);
+            let mut renderer = renderer.lock().unwrap();
+            let playhead_x = {
+                let mut nav = nav.lock().unwrap();
+                let view_start = settings.scroll.view_start(tick, nav.zoom_ticks);
+                nav.change_tick_pos(view_start, |time| renderer.time_changed(time));
+                (tick - view_start) / nav.zoom_ticks * width as f32
+            };
+            let mut pixels = sequence.target().render(renderer.deref_mut(), theme.background);
+            drop(renderer);
+            draw_playhead(&mut pixels, width, height, playhead_x, theme.playhead);
+            result = sequence.write_frame(pixels);
+            if result.is_err() { break; }
+        }
+
+        let mut cancel = false;
+        if result.is_ok() && sequence.next_frame().is_some() {
+            let (rendered, frame_count, progress) = (sequence.frames_rendered(), sequence.frame_count(), sequence.progress());
+            egui::Window::new("Rendering image sequence")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, vec2(0.0, 0.0))
+                .show(ctx, |ui| {
+                    ui.label(format!("Frame {} of {}", rendered, frame_count));
+                    ui.add(egui::ProgressBar::new(progress).show_percentage().desired_width(300.0));
+                    cancel = ui.button("Cancel").clicked();
+                });
+            if !cancel {
+                ctx.request_repaint();
+                return;
+            }
+        }
+
+        let Some((mut sequence, view_tick)) = self.frame_sequence.take() else { return; };
+        self.set_tick_pos(view_tick);
+        match result.and_then(|()| sequence.finish()) {
+            Ok(()) if cancel => self.set_status("Image sequence cancelled"),
+            Ok(()) => self.set_status(&format!("Rendered {} frames", sequence.frame_count())),
+            Err(err) => self.error_message = Some(format!("Could not render the image sequence:\n{}", err))
+        }
+    }
+
+    /// Sets the tempo the song starts at, changing the first tempo event. The playhead stays on the same tick.
+    fn set_initial_tempo(&mut self, ctx: &egui::Context, bpm: f32) {
+        let bpm = bpm.clamp(10.0, 1000.0);
+        self.project_settings.initial_bpm = bpm;
+        self.playback.insert_tempo(self.project_settings.ppq, 0, bpm);
+        self.tempo_map_changed(ctx);
+    }
+
+    /// Call after editing the tempo changes. Everything timed in seconds moves, so the playhead is kept on its tick.
+    fn tempo_map_changed(&mut self, ctx: &egui::Context) {
+        if let Some(first) = self.playback.tempo_events.first().filter(|ev| ev.time == 0) {
+            self.project_settings.initial_bpm = first.tempo;
+        }
+        self.project_stats = None;
+        self.dirty = true;
+
+        // the tick the playhead is on is now a different time, so it's moved back onto it
+        if self.nav.is_none() { return; }
+        let tick = self.playhead_tick();
+        self.set_play_position(ctx, tick);
+    }
+
+    /// Draws the tempo changes in view as flags on a step line, scaled between the slowest and fastest tempo.
+    /// Clicking a flag edits it and double-clicking anywhere else adds a tempo change there, snapped to the grid unless Alt is held.
+    fn show_tempo_lane(&mut self, ctx: &egui::Context, ui: &Ui, rect: Rect, response: &egui::Response) {
+        let Some(nav) = self.nav.as_ref() else { return; };
+        let (tick_pos, zoom_ticks) = {
+            let nav = nav.lock().unwrap();
+            (nav.tick_pos, nav.zoom_ticks)
+        };
+        let tick_to_x = |tick: f32| rect.min.x + (tick - tick_pos) / zoom_ticks * rect.width();
+        let x_to_tick = |x: f32| tick_pos + (x - rect.min.x) / rect.width() * zoom_ticks;
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::from_gray(26));
+        painter.hline(rect.x_range(), rect.max.y - 0.5, Stroke::new(1.0, Color32::from_gray(60)));
+
+        let tempo_events = &self.playback.tempo_events;
+        let (slowest, fastest) = tempo_events.iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), ev| (lo.min(ev.tempo), hi.max(ev.tempo)));
+        let tempo_to_y = |tempo: f32| {
+            let t = if fastest > slowest { (tempo - slowest) / (fastest - slowest) } else { 0.5 };
+            rect.max.y - 3.0 - t * (rect.height() - 6.0)
+        };
+
+        let start = tick_pos.max(0.0) as u64;
+        let end = (tick_pos + zoom_ticks).max(0.0) as u64;
+        let line = Stroke::new(1.5, Color32::from_rgb(120, 220, 140));
+        let first = tempo_events.partition_point(|ev| ev.time <= start).saturating_sub(1);
+        let mut hovered_tick = None;
+        let pointer = response.hover_pos();
+        for (i, ev) in tempo_events.iter().enumerate().skip(first) {
+            if ev.time > end { break; }
+            let x = tick_to_x(ev.time as f32).max(rect.min.x);
+            let next_x = tempo_events.get(i + 1).map_or(rect.max.x, |next| tick_to_x(next.time as f32).min(rect.max.x));
+            let y = tempo_to_y(ev.tempo);
+            painter.hline(x..=next_x, y, line);
+            if let Some(next) = tempo_events.get(i + 1) {
+                painter.vline(next_x, y.min(tempo_to_y(next.tempo))..=y.max(tempo_to_y(next.tempo)), line);
+            }
+
+            if ev.time < start { continue; }
+            let galley = painter.layout_no_wrap(format!("{:.1}", ev.tempo), egui::FontId::proportional(11.0), Color32::BLACK);
+            let flag = Rect::from_min_size(egui::pos2(x, rect.min.y + 1.0), galley.size() + vec2(6.0, 2.0));
+            let hovered = pointer.is_some_and(|pos| flag.contains(pos));
+            if hovered {
+                hovered_tick = Some(ev.time);
+            }
+            let color = if hovered { Color32::from_rgb(170, 255, 185) } else { Color32::from_rgb(120, 220, 140) };
+            painter.rect_filled(flag, 2.0, color);
+            painter.galley(flag.min + vec2(3.0, 1.0), galley, Color32::BLACK);
+        }
+        if hovered_tick.is_some() {
+            ctx.set_cursor_icon(egui::CursorIcon::PointingHand);
+        }
+
+        if response.clicked()
+            && let Some(tick) = hovered_tick {
+            let bpm = self.playback.tempo_at(tick);
+            self.tempo_point = Some(TempoPointEdit { original_tick: Some(tick), tick, bpm });
+        } else if response.double_clicked()
+            && let Some(pos) = response.interact_pointer_pos() {
+            let tick = x_to_tick(pos.x).max(0.0) as u64;
+            let tick = if ctx.input(|i| i.modifiers.alt) {
+                tick
+            } else {
+                let grid = self.grid.ticks(self.project_settings.ppq) as u64;
+                (tick + grid / 2) / grid * grid
+            };
+            self.tempo_point = Some(TempoPointEdit { original_tick: None, tick, bpm: self.playback.tempo_at(tick) });
+        }
+        response.clone().on_hover_text_at_pointer("Double-click to add a tempo change, click one to edit it");
+    }
+
+    /// Edits the tempo change open from the tempo lane, or adds it if it's new.
+    fn show_tempo_point_window(&mut self, ctx: &egui::Context) {
+        let Some(mut edit) = self.tempo_point else { return; };
+        let mut open = true;
+        let mut apply = false;
+        let mut delete = false;
+        let ppq = self.project_settings.ppq;
+        let title = if edit.original_tick.is_some() { "Edit tempo change" } else { "Add tempo change" };
+        egui::Window::new(title)
+            .id(egui::Id::new("tempo_point"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Tick");
+                    // the change at tick 0 is what the song starts at, so it can't move
+                    ui.add_enabled(edit.original_tick != Some(0), egui::DragValue::new(&mut edit.tick).speed(ppq as f32 / 16.0));
+                    let (bar, beat, beat_tick) = self.project_settings.time_signatures.bar_beat_tick(ppq, edit.tick);
+                    ui.label(RichText::new(format!("{}:{}:{:03}", bar, beat, beat_tick)).monospace());
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Tempo");
+                    let field = ui.add(egui::DragValue::new(&mut edit.bpm).range(10.0..=1000.0).speed(0.1).max_decimals(2).suffix(" BPM"));
+                    if ui.memory(|m| m.focused().is_none()) {
+                        field.request_focus();
+                    }
+                    apply = field.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+                });
+                ui.horizontal(|ui| {
+                    apply |= ui.button(if edit.original_tick.is_some() { "Apply" } else { "Add" }).clicked();
+                    delete = ui.add_enabled(edit.can_delete(), egui::Button::new("Delete")).clicked();
+                });
+            });
+
+        if apply || delete {
+            if let Some(original) = edit.original_tick {
+                self.playback.remove_tempo(ppq, original);
+            }
+            if apply {
+                self.playback.insert_tempo(ppq, edit.tick, edit.bpm.clamp(10.0, 1000.0));
+            }
+            self.tempo_point = None;
+            self.tempo_map_changed(ctx);
+        } else if open && !ctx.input(|i| i.key_pressed(Key::Escape)) {
+            self.tempo_point = Some(edit);
+        } else {
+            self.tempo_point = None;
+        }
+    }
+
+    /// Counts a tap of the tap tempo and sets the initial tempo to what the taps so far work out to.
+    fn tap(&mut self, ctx: &egui::Context) {
+        if let Some(bpm) = self.tap_tempo.tap(Instant::now()) {
+            self.set_initial_tempo(ctx, bpm);
+        }
+    }
+
+    fn show_tempo_window(&mut self, ctx: &egui::Context) {
+        let mut open = true;
+        let mut bpm = self.project_settings.initial_bpm;
+        let mut tapped = false;
+        let tap_key = self.shortcut_text(ctx, Action::TapTempo);
+        egui::Window::new("Tempo")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Initial tempo");
+                    ui.add(egui::DragValue::new(&mut bpm).range(10.0..=1000.0).speed(0.1).max_decimals(2).suffix(" BPM"));
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    tapped = ui.add(egui::Button::new("Tap").min_size(vec2(60.0, 30.0))).clicked();
+                    match self.tap_tempo.bpm() {
+                        Some(tapped_bpm) => ui.label(format!("{:.2} BPM from {} taps", tapped_bpm, self.tap_tempo.tap_count())),
+                        None if tap_key.is_empty() => ui.label("Click on every beat"),
+                        None => ui.label(format!("Click or press {} on every beat", tap_key))
+                    };
+                });
+                if ui.button("Reset taps").clicked() {
+                    self.tap_tempo.reset();
+                }
+            });
+
+        if tapped {
+            self.tap(ctx);
+        } else if bpm != self.project_settings.initial_bpm {
+            self.set_initial_tempo(ctx, bpm);
+        }
+        if !open {
+            self.show_tempo = false;
+            self.tap_tempo.reset();
+        }
+    }
+
+    /// Puts copies of the selected notes on the clipboard, and deletes them as one undo step if `[cut]` is set.
+    fn copy_selection(&mut self, cut: bool) {
+        let ids = self.project_note_manager.selected_ids();
+        if ids.is_empty() { return; }
+        self.clipboard = self.project_note_manager.copy_notes(&ids);
+        if cut {
+            self.project_note_manager.delete_notes(&ids);
+        }
+    }
+
+    /// Pastes the clipboard at the playhead and selects the pasted notes.
+    fn paste_clipboard(&mut self) {
+        if self.clipboard.is_empty() || self.nav.is_none() { return; }
+        let tick = self.playhead_tick() as u32;
+        self.project_note_manager.paste_notes(&self.clipboard, tick);
+    }
+
+    /// Splits the selected notes that cross the playhead there, or every note that does if nothing is selected.
+    fn split_at_playhead(&mut self) {
+        if self.nav.is_none() { return; }
+        let tick = self.playhead_tick().round() as u32;
+        let notes = &mut self.project_note_manager;
+        let ids = if notes.selected.is_empty() {
+            notes.notes().filter(|n| n.start < tick && tick < n.end()).map(|n| n.id).collect()
+        } else {
+            notes.selected_ids()
+        };
+        notes.split_at(&ids, tick);
+    }
+
+    /// The menu for right-clicking the piano roll. Right-clicking a note that isn't selected selects just it first,
+    /// then everything applies to the selection.
+    fn show_note_context_menu(&mut self, rect: Rect, response: &egui::Response) {
+        if response.secondary_clicked()
+            && let Some(pos) = response.interact_pointer_pos()
+            && let Some(nav) = self.nav.as_ref() {
+            let (tick, key) = nav.lock().unwrap().pos_to_tick_key(rect, pos);
+            self.context_key = (0.0..128.0).contains(&key).then_some(key as u8);
+            if let Some(key) = self.context_key
+                && let Some(id) = self.project_note_manager.note_at(tick, key)
+                && !self.project_note_manager.selected.contains(&id) {
+                self.project_note_manager.select_note(id, false);
+            }
+        }
+
+        response.context_menu(|ui| {
+            let has_selection = !self.project_note_manager.selected.is_empty();
+            if ui.add_enabled(has_selection, egui::Button::new("Delete").shortcut_text(self.shortcut_text(&response.ctx, Action::Delete))).clicked() {
+                let ids = self.project_note_manager.selected_ids();
+                self.project_note_manager.delete_notes(&ids);
+                ui.close_menu();
+            }
+            if ui.add_enabled(has_selection, egui::Button::new("Cut")).clicked() {
+                self.copy_selection(true);
+                ui.close_menu();
+            }
+            if ui.add_enabled(has_selection, egui::Button::new("Copy")).clicked() {
+                self.copy_selection(false);
+                ui.close_menu();
+            }
+            ui.separator();
+            if ui.add_enabled(has_selection, egui::Button::new("Set velocity...")).clicked() {
+                let velocity = self.project_note_manager.selected.iter()
+                    .find_map(|id| self.project_note_manager.get(*id))
+                    .map_or(100, |note| note.velocity);
+                self.set_velocity = Some(velocity);
+                ui.close_menu();
+            }
+            if ui.add_enabled(has_selection, egui::Button::new("Velocity ramp...")).clicked() {
+                self.velocity_ramp = Some((40, 110));
+                ui.close_menu();
+            }
+            if ui.add_enabled(has_selection, egui::Button::new("Transpose...")).clicked() {
+                self.transpose_by = Some(0);
+                ui.close_menu();
+            }
+            if ui.add_enabled(has_selection, egui::Button::new("Glue")).clicked() {
+                let ids = self.project_note_manager.selected_ids();
+                self.project_note_manager.glue(&ids);
+                ui.close_menu();
+            }
+            if let Some(key) = self.context_key
+                && ui.button(format!("Select all {}", key_name(key))).on_hover_text("Select every note on this key").clicked() {
+                self.project_note_manager.select_key(key);
+                ui.close_menu();
+            }
+        });
+    }
+
+    /// Asks for the velocity to give the selected notes.
+    fn show_set_velocity_window(&mut self, ctx: &egui::Context) {
+        let Some(mut velocity) = self.set_velocity else { return; };
+        let mut open = true;
+        let mut apply = false;
+        egui::Window::new("Set velocity")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Velocity");
+                    let field = ui.add(egui::DragValue::new(&mut velocity).range(1..=127));
+                    if ui.memory(|m| m.focused().is_none()) {
+                        field.request_focus();
+                    }
+                    apply = ui.button("Apply").clicked()
+                        || (field.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)));
+                });
+            });
+
+        if apply {
+            let ids = self.project_note_manager.selected_ids();
+            self.project_note_manager.set_velocity(&ids, velocity);
+            self.last_velocity = Some(velocity);
+            self.set_velocity = None;
+        } else if open && !ctx.input(|i| i.key_pressed(Key::Escape)) {
+            self.set_velocity = Some(velocity);
+        } else {
+            self.set_velocity = None;
+        }
+    }
+
+    /// Asks for the velocities a ramp over the selected notes goes between.
+    fn show_velocity_ramp_window(&mut self, ctx: &egui::Context) {
+        let Some((mut from, mut to)) = self.velocity_ramp else { return; };
+        let mut open = true;
+        let mut apply = false;
+        egui::Window::new("Velocity ramp")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("From");
+                    let from_field = ui.add(egui::DragValue::new(&mut from).range(1..=127));
+                    if ui.memory(|m| m.focused().is_none()) {
+                        from_field.request_focus();
+                    }
+                    ui.label("to");
+                    let to_field = ui.add(egui::DragValue::new(&mut to).range(1..=127));
+                    apply = ui.button("Apply").clicked()
+                        || ((from_field.lost_focus() || to_field.lost_focus()) && ui.input(|i| i.key_pressed(Key::Enter)));
+                }).response.on_hover_text("The velocity of the earliest selected notes and of the latest, the ones between go in a straight line");
+            });
+
+        if apply {
+            let ids = self.project_note_manager.selected_ids();
+            self.project_note_manager.velocity_ramp(&ids, from, to);
+            self.last_velocity = Some(to);
+            self.velocity_ramp = None;
+        } else if open && !ctx.input(|i| i.key_pressed(Key::Escape)) {
+            self.velocity_ramp = Some((from, to));
+        } else {
+            self.velocity_ramp = None;
+        }
+    }
+
+    /// Asks how many semitones to move the selected notes by.
+    fn show_transpose_window(&mut self, ctx: &egui::Context) {
+        let Some(mut semitones) = self.transpose_by else { return; };
+        let mut open = true;
+        let mut apply = false;
+        egui::Window::new("Transpose")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Semitones");
+                    let field = ui.add(egui::DragValue::new(&mut semitones).range(-127..=127));
+                    if ui.memory(|m| m.focused().is_none()) {
+                        field.request_focus();
+                    }
+                    apply = ui.button("Apply").clicked()
+                        || (field.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)));
+                });
+            });
+
+        if apply {
+            let ids = self.project_note_manager.selected_ids();
+            self.project_note_manager.transpose(&ids, semitones);
+            self.transpose_by = None;
+        } else if open && !ctx.input(|i| i.key_pressed(Key::Escape)) {
+            self.transpose_by = Some(semitones);
+        } else {
+            self.transpose_by = None;
+        }
+    }
+
+    /// Draws the bar ruler and marker flags above the piano roll. Clicking it seeks there and dragging it scrubs.
+    fn show_ruler(&mut self, ctx: &egui::Context, ui: &Ui, rect: Rect, response: &egui::Response) {
+        let Some(nav) = self.nav.as_ref() else { return; };
+        let (tick_pos, zoom_ticks) = {
+            let nav = nav.lock().unwrap();
+            (nav.tick_pos, nav.zoom_ticks)
+        };
+        let ppq = self.project_settings.ppq;
+        let time_signatures = &self.project_settings.time_signatures;
+        let tick_to_x = |tick: f32| rect.min.x + (tick - tick_pos) / zoom_ticks * rect.width();
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::from_gray(30));
+        painter.hline(rect.x_range(), rect.max.y - 0.5, Stroke::new(1.0, Color32::from_gray(60)));
+
+        // label every bar if there's room, otherwise every 2nd, 4th, 8th... bar
+        let start = tick_pos.max(0.0) as u64;
+        let end = (tick_pos + zoom_ticks).max(0.0) as u64;
+        let (first_bar, _, _) = time_signatures.bar_beat_tick(ppq, start);
+        let bar_width = (time_signatures.bar_to_tick(ppq, first_bar + 1) - time_signatures.bar_to_tick(ppq, first_bar)) as f32
+            / zoom_ticks * rect.width();
+        let mut stride = 1;
+        while bar_width * (stride as f32) < 40.0 && stride < 1 << 20 {
+            stride *= 2;
+        }
+        // the labels stay on bar 1, 3, 5... whether or not the pickup before bar 1 is shown
+        let mut bar = if first_bar == 0 { 0 } else { first_bar - (first_bar - 1) % stride };
+        loop {
+            let tick = time_signatures.bar_to_tick(ppq, bar);
+            if tick > end { break; }
+            let x = tick_to_x(tick as f32);
+            painter.vline(x, rect.y_range(), Stroke::new(1.0, Color32::from_gray(90)));
+            painter.text(egui::pos2(x + 3.0, rect.center().y), egui::Align2::LEFT_CENTER, bar.to_string(),
+                egui::FontId::proportional(11.0), Color32::from_gray(200));
+            bar = if bar == 0 { 1 } else { bar + stride };
+        }
+
+        let flag_color = Color32::from_rgb(255, 200, 90);
+        for marker in self.markers.in_range(start, end) {
+            let x = tick_to_x(marker.tick as f32);
+            let galley = painter.layout_no_wrap(marker.name.clone(), egui::FontId::proportional(12.0), Color32::BLACK);
+            let flag = Rect::from_min_size(egui::pos2(x, rect.min.y), galley.size() + vec2(6.0, 2.0));
+            painter.rect_filled(flag, 2.0, flag_color);
+            painter.galley(flag.min + vec2(3.0, 1.0), galley, Color32::BLACK);
+        }
+
+        let pointer_tick = |pos: egui::Pos2| tick_pos + (pos.x - rect.min.x) / rect.width() * zoom_ticks;
+        if response.drag_started() {
+            self.ruler_scrub = Some(tick_pos);
+        }
+        if let Some(scrub) = self.ruler_scrub.as_mut() {
+            *scrub = (*scrub + response.drag_delta().x / rect.width() * zoom_ticks).max(0.0);
+            let scrub = *scrub;
+            if response.drag_stopped() {
+                self.ruler_scrub = None;
+                self.seek(ctx, scrub);
+            } else if !self.playback.is_playing {
+                // playing restarts the synth, so that's left until the drag ends
+                self.playback.navigate_to(ppq, scrub);
+                self.set_tick_pos(scrub);
+            }
+        } else if response.clicked()
+            && let Some(pos) = response.interact_pointer_pos() {
+            self.seek(ctx, pointer_tick(pos));
+        }
+    }
+
+    /// Shows the tick, bar:beat:tick and key under the pointer next to it while it's over the piano roll and no button is held.
+    fn show_hover_position(&self, ctx: &egui::Context, rect: Rect, response: &egui::Response) {
+        let Some(nav) = self.nav.as_ref() else { return; };
+        let Some(pos) = response.hover_pos() else { return; };
+        if ctx.input(|i| i.pointer.any_down()) { return; }
+        let (tick, key) = nav.lock().unwrap().pos_to_tick_key(rect, pos);
+        let (tick, key) = (tick.max(0.0) as u64, key.clamp(0.0, 127.0) as u8);
+        let (bar, beat, beat_tick) = self.project_settings.time_signatures.bar_beat_tick(self.project_settings.ppq, tick);
+        egui::show_tooltip_at_pointer(ctx, response.layer_id, response.id.with("hover_position"), |ui| {
+            ui.label(RichText::new(format!("{}:{}:{:03}  tick {}\n{} (key {})", bar, beat, beat_tick, tick, key_name(key), key)).monospace());
+        });
+    }
+
+    /// Draws the keys along the left of the piano roll, lined up with its rows, with every C labeled by its octave.
+    /// The keys of the highlighted scale are tinted, its root more strongly.
+    fn show_keyboard(&self, ui: &Ui, rect: Rect) {
+        let Some(nav) = self.nav.as_ref() else { return; };
+        let (key_pos, zoom_keys) = {
+            let nav = nav.lock().unwrap();
+            (nav.key_pos, nav.zoom_keys)
+        };
+        let key_height = rect.height() / zoom_keys;
+        let key_to_y = |key: f32| rect.max.y - (key - key_pos) * key_height;
+        let scale = self.project_settings.highlight_scale;
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::from_gray(30));
+        let first = key_pos.floor().max(0.0) as u8;
+        let last = (key_pos + zoom_keys).ceil().min(128.0) as u8;
+        for key in first..last {
+            let row = Rect::from_x_y_ranges(rect.x_range(), key_to_y(key as f32 + 1.0)..=key_to_y(key as f32));
+            let sharp = matches!(key % 12, 1 | 3 | 6 | 8 | 10);
+            let (key_rect, mut color) = if sharp {
+                (row.with_max_x(rect.min.x + rect.width() * 0.65), Color32::from_gray(40))
+            } else {
+                (row, Color32::from_gray(215))
+            };
+            if let Some(scale) = scale {
+                let tint = Color32::from_rgb(90, 170, 255);
+                if key % 12 == scale.root % 12 {
+                    color = color.lerp_to_gamma(tint, 0.7);
+                } else if scale.contains(key) {
+                    color = color.lerp_to_gamma(tint, 0.35);
+                }
+            }
+            if sharp {
+                // the white key behind the black one
+                painter.rect_filled(row, 0.0, Color32::from_gray(215));
+            }
+            painter.rect_filled(key_rect, 0.0, color);
+            painter.hline(rect.x_range(), row.max.y, Stroke::new(1.0, Color32::from_gray(120)));
+
+            if key % 12 == 0 && key_height >= 8.0 {
+                let font = egui::FontId::proportional(key_height.min(12.0));
+                painter.text(egui::pos2(rect.max.x - 3.0, row.center().y), egui::Align2::RIGHT_CENTER, key_name(key),
+                    font, Color32::from_gray(40));
+            }
+        }
+        painter.vline(rect.max.x - 0.5, rect.y_range(), Stroke::new(1.0, Color32::from_gray(60)));
+    }
+
+    /// Draws the whole song's note density with the visible part outlined. Dragging the outline pans the view and dragging
+    /// its edges zooms it, clicking elsewhere centers the view there. While playing the playhead only moves once the drag ends.
+    fn show_minimap(&mut self, ctx: &egui::Context, ui: &Ui, rect: Rect, response: &egui::Response) {
+        let Some(nav) = self.nav.as_ref() else { return; };
+        let (tick_pos, zoom_ticks) = {
+            let nav = nav.lock().unwrap();
+            (nav.tick_pos, nav.zoom_ticks)
+        };
+        self.minimap.update(&self.project_note_manager);
+
+        // the strip covers the song, and the view if it's scrolled past the end of it
+        let span = (self.minimap.end_tick as f32).max(tick_pos + zoom_ticks).max(1.0);
+        let tick_to_x = |tick: f32| rect.min.x + tick / span * rect.width();
+        let x_to_tick = |x: f32| (x - rect.min.x) / rect.width() * span;
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::from_gray(22));
+        painter.hline(rect.x_range(), rect.max.y - 0.5, Stroke::new(1.0, Color32::from_gray(60)));
+
+        // log scaled so a few notes still show up next to dense parts
+        let peak = (self.minimap.peak as f32).ln_1p().max(f32::EPSILON);
+        let ticks_per_bin = self.minimap.ticks_per_bin();
+        let density_color = Color32::from_rgb(110, 150, 200);
+        for (i, count) in self.minimap.bins.iter().enumerate() {
+            if *count == 0 { continue; }
+            let height = (*count as f32).ln_1p() / peak * (rect.height() - 2.0);
+            let x0 = tick_to_x(i as f32 * ticks_per_bin);
+            let x1 = tick_to_x((i + 1) as f32 * ticks_per_bin).max(x0 + 1.0);
+            let bar = Rect::from_x_y_ranges(x0..=x1, (rect.max.y - 1.0 - height)..=(rect.max.y - 1.0));
+            painter.rect_filled(bar, 0.0, density_color);
+        }
+
+        // while dragging, the view is drawn where it's being dragged to
+        let (view_start, view_end) = match self.minimap_drag {
+            Some(MinimapDrag::Pan(tick)) => (tick, tick + zoom_ticks),
+            Some(MinimapDrag::ResizeStart { start, end }) => (start, end),
+            _ => (tick_pos, tick_pos + zoom_ticks)
+        };
+        let (x0, x1) = (tick_to_x(view_start), tick_to_x(view_end));
+        let view_rect = Rect::from_x_y_ranges(x0..=x1.max(x0 + 3.0), rect.y_range());
+        painter.rect_filled(view_rect, 0.0, Color32::from_white_alpha(24));
+        painter.rect_stroke(view_rect.shrink(0.5), 0.0, Stroke::new(1.0, Color32::from_gray(200)), egui::StrokeKind::Inside);
+
+        const EDGE_GRAB: f32 = 4.0;
+        let pointer = response.interact_pointer_pos().or(response.hover_pos());
+        let near_edge = pointer.is_some_and(|pos| (pos.x - view_rect.min.x).abs() <= EDGE_GRAB || (pos.x - view_rect.max.x).abs() <= EDGE_GRAB);
+        if near_edge || matches!(self.minimap_drag, Some(MinimapDrag::ResizeStart { .. } | MinimapDrag::ResizeEnd)) {
+            ui.ctx().set_cursor_icon(egui::CursorIcon::ResizeHorizontal);
+        }
+
+        let ppq = self.project_settings.ppq;
+        if response.drag_started()
+            && let Some(pos) = response.interact_pointer_pos() {
+            self.minimap_drag = Some(if (pos.x - view_rect.max.x).abs() <= EDGE_GRAB {
+                MinimapDrag::ResizeEnd
+            } else if (pos.x - view_rect.min.x).abs() <= EDGE_GRAB {
+                MinimapDrag::ResizeStart { start: tick_pos, end: tick_pos + zoom_ticks }
+            } else if view_rect.x_range().contains(pos.x) {
+                MinimapDrag::Pan(tick_pos)
+            } else {
+                // grabbing outside the view centers it on the pointer first
+                MinimapDrag::Pan((x_to_tick(pos.x) - zoom_ticks / 2.0).max(0.0))
+            });
+        }
+
+        let Some(drag) = self.minimap_drag.as_mut() else {
+            if response.clicked()
+                && let Some(pos) = response.interact_pointer_pos() {
+                self.set_play_position(ctx, (x_to_tick(pos.x) - zoom_ticks / 2.0).max(0.0));
+            }
+            return;
+        };
+        let delta = response.drag_delta().x / rect.width() * span;
+        let new_start = match drag {
+            MinimapDrag::Pan(tick) => {
+                *tick = (*tick + delta).max(0.0);
+                Some(*tick)
+            }
+            MinimapDrag::ResizeStart { start, end } => {
+                *start = (*start + delta).clamp((*end - 384000.0).max(0.0), *end - 10.0);
+                nav.lock().unwrap().zoom_ticks = *end - *start;
+                Some(*start)
+            }
+            MinimapDrag::ResizeEnd => {
+                if let Some(pos) = response.interact_pointer_pos() {
+                    nav.lock().unwrap().zoom_ticks = (x_to_tick(pos.x) - tick_pos).clamp(10.0, 384000.0);
+                }
+                None
+            }
+        };
+
+        if response.drag_stopped() {
+            self.minimap_drag = None;
+            if let Some(tick) = new_start {
+                self.set_play_position(ctx, tick);
+            }
+        } else if let Some(tick) = new_start
+            && !self.playback.is_playing {
+            // playing restarts the synth, so that's left until the drag ends
+            self.playback.navigate_to(ppq, tick);
+            self.set_tick_pos(tick);
+        }
+    }
+
+    /// Undo, redo, playback and the shortcuts for editing the selected notes, on the keys in `KeyBindings`.
+    /// The keys are consumed, so keyboard audition doesn't play a plain letter bound here.
+    fn handle_edit_shortcuts(&mut self, ctx: &egui::Context) {
+        let bindings = self.app_settings.lock().unwrap().key_bindings.clone();
+        let pressed = |action| ctx.input_mut(|i| bindings.consume(i, action));
+        if pressed(Action::PlayPause) {
+            self.toggle_playback(ctx);
+        }
+        if pressed(Action::StopToStart) {
+            self.stop_to_start(ctx);
+        }
+        if pressed(Action::GoToBar) {
+            self.open_goto_bar();
+        }
+        if pressed(Action::Save) {
+            self.save_project(false);
+        }
+        if pressed(Action::Panic) {
+            self.panic(ctx);
+        }
+        if pressed(Action::SplitAtPlayhead) {
+            self.split_at_playhead();
+        }
+        if self.show_tempo && pressed(Action::TapTempo) {
+            self.tap(ctx);
+        }
+        if pressed(Action::PreviousNote) {
+            self.jump_to_note(ctx, false);
+        }
+        if pressed(Action::NextNote) {
+            self.jump_to_note(ctx, true);
+        }
+
+        // Escape closes the dialogs first
+        let dialog_open = self.goto_bar.is_some() || self.pending_import.is_some() || self.set_velocity.is_some() || self.transpose_by.is_some()
+            || self.velocity_ramp.is_some() || self.tempo_point.is_some();
+        let nudge_ticks = self.grid.ticks(self.project_settings.ppq) as i64;
+        let notes = &mut self.project_note_manager;
+        ctx.input_mut(|i| {
+            if bindings.consume(i, Action::Redo) {
+                notes.redo();
+            }
+            if bindings.consume(i, Action::Undo) {
+                notes.undo();
+            }
+
+            if bindings.consume(i, Action::SelectAll) {
+                notes.select_all();
+            }
+            if bindings.consume(i, Action::InvertSelection) {
+                notes.invert_selection();
+            }
+            if !dialog_open && bindings.consume(i, Action::ClearSelection) {
+                notes.clear_selection();
+            }
+            if bindings.consume(i, Action::Duplicate) {
+                let ids = notes.selected_ids();
+                notes.duplicate(&ids);
+            }
+            if bindings.consume(i, Action::Delete) {
+                let ids = notes.selected_ids();
+                notes.delete_notes(&ids);
+            }
+
+            let mut transpose_by = 0;
+            transpose_by += 12 * bindings.count_and_consume(i, Action::OctaveUp) as i32;
+            transpose_by -= 12 * bindings.count_and_consume(i, Action::OctaveDown) as i32;
+            transpose_by += bindings.count_and_consume(i, Action::TransposeUp) as i32;
+            transpose_by -= bindings.count_and_consume(i, Action::TransposeDown) as i32;
+            if transpose_by != 0 {
+                let ids = notes.selected_ids();
+                notes.transpose(&ids, transpose_by);
+            }
+
+            let mut nudge_by = 0;
+            nudge_by += 4 * bindings.count_and_consume(i, Action::NudgeRightFar) as i64;
+            nudge_by -= 4 * bindings.count_and_consume(i, Action::NudgeLeftFar) as i64;
+            nudge_by += bindings.count_and_consume(i, Action::NudgeRight) as i64;
+            nudge_by -= bindings.count_and_consume(i, Action::NudgeLeft) as i64;
+            if nudge_by != 0 {
+                let ids = notes.selected_ids();
+                notes.shift_time(&ids, nudge_by * nudge_ticks);
+            }
+        });
+    }
+
+    /// The settings tab for rebinding the shortcuts. Clicking a key waits for the next key pressed and binds it,
+    /// clicking it again stops waiting.
+    fn show_key_bindings(&mut self, ui: &mut Ui) {
+        let app_settings = self.app_settings.clone();
+        let mut app_settings = app_settings.lock().unwrap();
+        let bindings = &mut app_settings.key_bindings;
+
+        if let Some((action, slot)) = self.capturing_key {
+            let pressed = ui.input(|i| i.events.iter().find_map(|event| match event {
+                Event::Key { key, pressed: true, repeat: false, modifiers, .. } => Some(KeyboardShortcut::new(*modifiers, *key)),
+                _ => None
+            }));
+            if let Some(shortcut) = pressed {
+                bindings.set(action, slot, Some(shortcut));
+                self.capturing_key = None;
+            }
+        }
+
+        egui::ScrollArea::vertical().max_height(360.0).show(ui, |ui| {
+            egui::Grid::new("key_bindings").striped(true).show(ui, |ui| {
+                for action in Action::ALL {
+                    ui.label(action.name());
+                    for (slot, shortcut) in bindings.keys(action).into_iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            let capturing = self.capturing_key == Some((action, slot));
+                            let text = if capturing {
+                                String::from("press a key...")
+                            } else {
+                                shortcut.map_or_else(|| String::from("–"), |shortcut| ui.ctx().format_shortcut(&shortcut))
+                            };
+                            if ui.add(egui::Button::new(text).min_size(vec2(110.0, 0.0)).selected(capturing)).clicked() {
+                                self.capturing_key = (!capturing).then_some((action, slot));
+                            }
+                            if ui.add_enabled(shortcut.is_some(), egui::Button::new("×").small()).on_hover_text("Unbind").clicked() {
+                                bindings.set(action, slot, None);
+                            }
+                        });
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+
+        for (shortcut, actions) in bindings.conflicts() {
+            let names = actions.iter().map(|action| action.name()).collect::<Vec<_>>().join(", ");
+            ui.colored_label(Color32::RED, format!("{} is bound to {}, it does all of them", ui.ctx().format_shortcut(&shortcut), names));
+        }
+        for (shortcut, action) in bindings.audition_conflicts() {
+            ui.colored_label(Color32::RED, format!("{} is bound to {}, keyboard audition can't play it", ui.ctx().format_shortcut(&shortcut), action.name()));
+        }
+        if ui.button("Reset to defaults").clicked() {
+            bindings.reset();
+            self.capturing_key = None;
+        }
+    }
+
+    /// The first key bound to `[action]` as it's shown next to menu entries, empty if it has none.
+    fn shortcut_text(&self, ctx: &egui::Context, action: Action) -> String {
+        self.app_settings.lock().unwrap().key_bindings.first(action)
+            .map_or_else(String::new, |shortcut| ctx.format_shortcut(&shortcut))
+    }
+
+    /// The velocity the pencil gives the next note.
+    fn draw_velocity(&self) -> u8 {
+        match self.last_velocity {
+            Some(velocity) if self.draw_last_velocity => velocity,
+            _ => self.draw_velocity
+        }
+    }
+
+    /// Clicking empty space adds a note there `draw_length` long, starting on the grid line before the pointer
+    /// unless Alt is held. Dragging from empty space instead stretches the note to the grid line after the pointer,
+    /// or to the pointer with Alt held, but never shorter than a grid step or a tick. The note goes on the track and
+    /// channel of a selected note, and is selected instead so the next one goes there too. Clicking a note selects it.
+    fn handle_pencil_tool(&mut self, ui: &Ui, response: &egui::Response, rect: Rect, alt_down: bool) {
+        let Some(nav) = self.nav.as_ref() else { return; };
+        let nav = nav.lock().unwrap();
+        let step = self.grid.ticks(self.project_settings.ppq);
+
+        if let Some(id) = self.drawing_note {
+            let notes = &mut self.project_note_manager;
+            if let Some(pos) = ui.input(|i| i.pointer.latest_pos())
+                && let Some(start) = notes.get(id).map(|n| n.start) {
+                let tick = nav.pos_to_tick_key(rect, pos).0.max(0.0) as u32;
+                let (end, min_length) = if alt_down { (tick, 1) } else { (tick.div_ceil(step) * step, step) };
+                notes.set_drawn_length(id, end.saturating_sub(start).max(min_length));
+            }
+            if response.drag_stopped_by(PointerButton::Primary) || !ui.input(|i| i.pointer.primary_down()) {
+                notes.record_added(&[id]);
+                self.drawing_note = None;
+            }
+            return;
+        }
 
-    project_note_manager: ProjectNoteManager
-}
+        let dragged = response.drag_started_by(PointerButton::Primary);
+        if !dragged && !response.clicked_by(PointerButton::Primary) { return; }
+        let pos = if dragged { ui.input(|i| i.pointer.press_origin()) } else { response.interact_pointer_pos() };
+        let Some(pos) = pos else { return; };
+        let (tick, key) = nav.pos_to_tick_key(rect, pos);
+        if !(0.0..128.0).contains(&key) || tick < 0.0 { return; }
+        let key = key as u8;
 
-impl MainWindow {
-    fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        let mut synth = PrerenderedAudio::new();
-        synth.load_soundfonts(&[
-            "./assets/soundfonts/Sinufont.sf2".to_string()
-        ]);
-        synth.set_layer_count(2);
+        let velocity = self.draw_velocity();
+        let notes = &mut self.project_note_manager;
+        if let Some(id) = notes.note_at(tick, key) {
+            notes.select_note(id, false);
+            self.last_velocity = notes.get(id).map(|n| n.velocity);
+            return;
+        }
 
-        let mut s = Self::default();
-        let initial_tempo = s.project_settings.initial_bpm;
-        let initial_tempo_raw = (60000000.0 / initial_tempo) as u32;
+        let start = if alt_down { tick as u32 } else { tick as u32 / step * step };
+        let length = self.draw_length.map_or(step, |length| length.ticks(self.project_settings.ppq));
+        let (track, channel) = notes.selected.iter()
+            .find_map(|id| notes.get(*id))
+            .map_or((0, 0), |n| (n.track(), n.channel()));
+        let id = notes.add_note(Note { start, length, channel, track, key, velocity });
+        if dragged {
+            self.drawing_note = Some(id);
+        } else {
+            notes.record_added(&[id]);
+        }
+        notes.select_note(id, false);
+        self.last_velocity = Some(velocity);
+    }
 
-        s.playback.tempo_events.push(TempoEvent {
-            time: 0,
-            time_norm: 0.0,
-            tempo: initial_tempo
-        });
+    /// Selects notes by clicking them or by dragging a rectangle over them. `[add]` adds to the selection instead of replacing it.
+    fn handle_pointer_tool(&mut self, ui: &Ui, response: &egui::Response, rect: Rect, add: bool) {
+        let Some(nav) = self.nav.as_ref() else { return; };
+        let mut nav = nav.lock().unwrap();
+        let note_at = |notes: &ProjectNoteManager, pos| {
+            let (tick, key) = nav.pos_to_tick_key(rect, pos);
+            if !(0.0..128.0).contains(&key) { return None; }
+            notes.note_at(tick, key as u8)
+        };
 
-        s.stream = Some(synth.build_stream());
-        s.synth = Some(synth);
-        s
-    }
+        if response.clicked_by(PointerButton::Primary)
+            && let Some(pos) = response.interact_pointer_pos() {
+            match note_at(&self.project_note_manager, pos) {
+                Some(id) => {
+                    self.project_note_manager.select_note(id, add);
+                    self.last_velocity = self.project_note_manager.get(id).map(|n| n.velocity);
+                }
+                None => if !add { self.project_note_manager.clear_selection(); }
+            }
+        }
 
-    fn init_gl(&mut self) {
-        let gl = self.gl.as_ref().unwrap();
+        if response.drag_started_by(PointerButton::Primary)
+            && let Some(origin) = ui.input(|i| i.pointer.press_origin())
+            && note_at(&self.project_note_manager, origin).is_none() {
+            let start = nav.pos_to_tick_key(rect, origin);
+            self.marquee = Some((start, start));
+        }
 
-        let nav = Arc::new(Mutex::new(Navigation::new()));
-        let mut renderer = PianoRollRenderer::new(nav.clone(), gl.clone());
-        renderer.update_ppq(self.project_settings.ppq);
-        self.nav = Some(nav);
-        self.renderer = Some(Arc::new(Mutex::new(renderer)));
+        let Some((start, _)) = self.marquee else { return; };
+        if let Some(pos) = ui.input(|i| i.pointer.latest_pos()) {
+            // the view follows playback while playing along, so it's only panned otherwise
+            if !(self.playback.is_playing && self.follow_playback)
+                && let Some(tick_pos) = nav.edge_scroll(rect, pos, ui.input(|i| i.stable_dt).min(0.1))
+                && let Some(renderer) = self.renderer.as_ref() {
+                nav.change_tick_pos(tick_pos, |time| renderer.lock().unwrap().time_changed(time));
+            }
+            // keep panning while the pointer is held still at the edge
+            if !rect.shrink(EDGE_SCROLL_MARGIN).contains(pos) {
+                self.request_animation_frame(ui.ctx());
+            }
+            self.marquee = Some((start, nav.pos_to_tick_key(rect, pos)));
+        }
+
+        if (response.drag_stopped_by(PointerButton::Primary) || !ui.input(|i| i.pointer.primary_down()))
+            && let Some(((start_tick, start_key), (end_tick, end_key))) = self.marquee.take() {
+            let low_key = start_key.min(end_key).clamp(0.0, 127.0) as u8;
+            let high_key = start_key.max(end_key).clamp(0.0, 127.0) as u8;
+            self.project_note_manager.select_in_rect(start_tick.min(end_tick), start_tick.max(end_tick), low_key, high_key, add);
+        }
     }
 
     fn labeled_widget<R>(&mut self, label: &str, ui: &mut Ui, contents: impl FnOnce(&mut Ui) -> R) {
@@ -91,10 +2443,13 @@ impl MainWindow {
 
     /// Handles the zooming and panning across the piano roll / track view.
     /// `[scroll_delta]` - the amount that the user scrolled
+    /// `[rect]` - where the piano roll is drawn, zooming keeps the tick and key under the pointer in place
     /// `[is_moving]` - if wheel scroll should move the piano roll instead of zooming
     /// `[vertical_zoom]` - if the user should zoom on the keys (vertical axis) instead
-    fn handle_navigation(&mut self, ctx: &egui::Context, ui: &mut Ui, is_moving: bool, vertical_zoom: bool) {
-        let scroll_delta = ui.input(|i| i.raw_scroll_delta).y;
+    fn handle_navigation(&mut self, ui: &mut Ui, rect: Rect, is_moving: bool, vertical_zoom: bool) {
+        // some platforms already turn Shift+wheel into horizontal scrolling
+        let scroll_delta = ui.input(|i| i.raw_scroll_delta);
+        let scroll_delta = if scroll_delta.y.abs() >= scroll_delta.x.abs() { scroll_delta.y } else { scroll_delta.x };
         if (scroll_delta.abs() > 0.001) {
             let mut nav = self.nav.as_mut().unwrap();
             let mut nav = nav.lock().unwrap();
@@ -116,9 +2471,15 @@ impl MainWindow {
                 } 
             } else {
                 let zoom_factor = 1.01f32.powf(scroll_delta);
+                // keep the tick and key under the pointer where they are, or zoom from the playhead and bottom key if it's elsewhere
+                let pointer = ui.input(|i| i.pointer.hover_pos()).filter(|pos| rect.contains(*pos));
+                let (anchor_x, anchor_y) = pointer.map_or((0.0, 0.0), |pos| (
+                    (pos.x - rect.min.x) / rect.width(),
+                    1.0 - (pos.y - rect.min.y) / rect.height()
+                ));
                 // vertical zoom
                 if vertical_zoom { 
-                    let view_top = nav.key_pos + nav.zoom_keys;
+                    let anchor_key = nav.key_pos + anchor_y * nav.zoom_keys;
 
                     nav.zoom_keys *= zoom_factor;
                     if nav.zoom_keys < 12.0 {
@@ -128,14 +2489,12 @@ impl MainWindow {
                         nav.zoom_keys = 128.0;
                     }
 
-                    let view_top_new = nav.key_pos + nav.zoom_keys;
-                    let view_top_delta = view_top_new - view_top;
-                    if view_top_new > 128.0 { nav.key_pos -= view_top_delta; }
-
                     // clamp key view
-                    if nav.key_pos < 0.0 { nav.key_pos = 0.0; }
+                    nav.key_pos = (anchor_key - anchor_y * nav.zoom_keys).clamp(0.0, 128.0 - nav.zoom_keys);
                 } else { 
                     // horizontal zoom
+                    let anchor_tick = nav.tick_pos + anchor_x * nav.zoom_ticks;
+
                     nav.zoom_ticks *= zoom_factor;
                     if nav.zoom_ticks < 10.0 {
                         nav.zoom_ticks = 10.0;
@@ -143,6 +2502,13 @@ impl MainWindow {
                     if nav.zoom_ticks > 384000.0 {
                         nav.zoom_ticks = 384000.0;
                     }
+
+                    // the playhead is the left edge while playing along, so that stays put
+                    if !(self.playback.is_playing && self.follow_playback) {
+                        let new_tick_pos = (anchor_tick - anchor_x * nav.zoom_ticks).max(0.0);
+                        let rend = self.renderer.as_mut().unwrap();
+                        nav.change_tick_pos(new_tick_pos, |time| rend.lock().unwrap().time_changed(time));
+                    }
                 }
             }
         }
@@ -151,11 +2517,12 @@ impl MainWindow {
 
 impl eframe::App for MainWindow {
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        if let Some(mut synth) = self.synth.as_mut() {
-            if !self.synth_init {
-                self.stream.as_mut().unwrap().play().unwrap();
-                self.synth_init = true;
+        if !self.synth_init {
+            if let Some(stream) = self.stream.as_ref()
+                && let Err(err) = stream.play() {
+                self.audio_error = Some(AudioError::from(err).to_string());
             }
+            self.synth_init = true;
         }
 
         if self.gl.is_none() {
@@ -165,24 +2532,52 @@ impl eframe::App for MainWindow {
             }
         }
 
+        self.track_window_geometry(ctx);
+        self.handle_close_request(ctx);
+        self.handle_dropped_files(ctx);
+        self.poll_midi_loader(ctx);
+        self.poll_audio_render(ctx);
+        self.render_frame_sequence(ctx);
+        self.poll_freeze(ctx);
+        self.poll_soundfont_loader(ctx);
+        // a key pressed while rebinding is only bound
+        if !ctx.wants_keyboard_input() && self.capturing_key.is_none() {
+            self.handle_edit_shortcuts(ctx);
+        }
+        self.record_live_notes();
+        self.dirty |= std::mem::take(&mut self.project_note_manager.notes_changed);
+
         let mut hover_info = "";
 
         if self.playback.is_playing {
-            if let Some(nav) = self.nav.as_ref() {
+            if self.follow_playback && let Some(nav) = self.nav.as_ref() {
                 let mut nav = nav.lock().unwrap();
                 nav.tick_pos = self.playback.get_playback_time(self.project_settings.ppq);
-                ctx.request_repaint();
             }
+            self.request_animation_frame(ctx);
         }
 
         if self.project_note_manager.render_needs_update {
             if let Some(renderer) = self.renderer.as_mut() {
                 let notes = self.project_note_manager.get_notes();
+                self.track_list.sync(self.project_note_manager.voices());
                 {
                     //let mut renderer = renderer.lock().unwrap();
-                    renderer.lock().unwrap().update_project_notes(notes);
+                    let mut renderer = renderer.lock().unwrap();
+                    renderer.update_project_notes(notes);
+                    renderer.update_silenced_voices(self.track_list.silenced_voices());
+                    renderer.update_voice_colors(self.track_list.voice_colors());
+                    renderer.update_selection(self.project_note_manager.selected.clone());
                 }
                 self.project_note_manager.render_needs_update = false;
+                self.project_stats = None;
+
+                // a render doesn't play the notes anymore once they're edited
+                let edited = self.track_list.unfreeze_edited(self.project_note_manager.voice_revisions());
+                if let Some(group) = edited.first() {
+                    let label = self.track_list.grouping().label(*group);
+                    self.set_status(&format!("{} was edited, it's synthesized again from the next time playback starts", label));
+                }
             }
         }
 
@@ -190,8 +2585,31 @@ impl eframe::App for MainWindow {
 
             hover_info = "";
 
-            let mut sys = &mut self.sys;
-            sys.refresh_cpu_usage();
+            // sysinfo needs some time between refreshes to compute a meaningful usage anyway
+            if self.last_cpu_refresh.is_none_or(|t| t.elapsed() >= MINIMUM_CPU_UPDATE_INTERVAL) {
+                self.sys.refresh_cpu_usage();
+                self.last_cpu_refresh = Some(Instant::now());
+            }
+            if self.last_voice_count_refresh.is_none_or(|t| t.elapsed() >= VOICE_COUNT_INTERVAL)
+                && let Some(synth) = self.synth.as_ref() {
+                self.voice_count = synth.voice_count();
+                self.last_voice_count_refresh = Some(Instant::now());
+            }
+            if let Some(synth) = self.synth.as_ref() {
+                let peaks = synth.meter().take_peaks();
+                let fall = 0.05f32.powf(ctx.input(|i| i.stable_dt).min(0.1));
+                for (level, peak) in self.meter_levels.iter_mut().zip(peaks) {
+                    *level = peak.max(*level * fall);
+                }
+                self.clipped |= synth.meter().take_clipped();
+                if self.meter_levels.iter().any(|level| *level > 0.001) {
+                    self.request_animation_frame(ctx);
+                }
+            }
+            // keep the count going down while notes ring out without any input
+            if self.voice_count > 0 {
+                ctx.request_repaint_after(VOICE_COUNT_INTERVAL);
+            }
 
             egui::TopBottomPanel::top("menu_bar")
                 .show(ctx, |ui| {
@@ -200,34 +2618,146 @@ impl eframe::App for MainWindow {
                     ui.menu_button("File", |ui| {
                         if ui.button("Import MIDI file").clicked() {
                             let midi_fd = rfd::FileDialog::new()
-                                .add_filter("MIDI Files", &["mid","midi"]);
+                                .add_filter("MIDI Files", &["mid","midi","rmi"]);
                             if let Some(file) = midi_fd.pick_file() {
-                                let midi = MIDIFile::new(String::from(file.to_str().unwrap()), true)
-                                    .unwrap();
-
-                                self.project_settings.ppq = midi.ppq;
-
-                                let mut midi_evs = Vec::new();
-                                let mut notes = Vec::new();
-                                let mut tempo_evs = Vec::new();
-                                midi.get_sequences(&mut midi_evs, &mut notes, &mut tempo_evs);
-
-                                if let Some(synth) = self.synth.as_mut() {
-                                    synth.set_events(midi_evs);
-                                    // println!("{:?}", synth.events);
-                                }
-
-                                self.playback.tempo_events = tempo_evs;
-
-                                for note_key in notes {
-                                    self.project_note_manager.convert_notes(note_key);
-                                }
-                                self.project_note_manager.render_needs_update = true;
+                                self.pending_import = Some(vec![file]);
                             }
                         }
+                        if ui.button("Import and merge MIDI files").on_hover_text("Combine several MIDI files into one project. \
+                            Each file's tracks are kept separate and the first file's tempo is used").clicked() {
+                            let midi_fd = rfd::FileDialog::new()
+                                .add_filter("MIDI Files", &["mid","midi","rmi"]);
+                            if let Some(files) = midi_fd.pick_files() {
+                                self.pending_import = Some(files);
+                            }
+                        }
+                        ui.separator();
+                        if ui.button("Export audio...").on_hover_text("Render the song to WAV, as one file or one per track").clicked() {
+                            self.show_export_audio = true;
+                            ui.close_menu();
+                        }
                     });
                     ui.menu_button("Edit", |ui| {
-                        
+                        if ui.add_enabled(self.project_note_manager.can_undo(), egui::Button::new("Undo").shortcut_text(self.shortcut_text(ctx, Action::Undo))).clicked() {
+                            self.project_note_manager.undo();
+                            ui.close_menu();
+                        }
+                        if ui.add_enabled(self.project_note_manager.can_redo(), egui::Button::new("Redo").shortcut_text(self.shortcut_text(ctx, Action::Redo))).clicked() {
+                            self.project_note_manager.redo();
+                            ui.close_menu();
+                        }
+                        ui.separator();
+                        if ui.add(egui::Button::new("Select all").shortcut_text(self.shortcut_text(ctx, Action::SelectAll))).clicked() {
+                            self.project_note_manager.select_all();
+                            ui.close_menu();
+                        }
+                        if ui.add(egui::Button::new("Deselect all").shortcut_text(self.shortcut_text(ctx, Action::ClearSelection))).clicked() {
+                            self.project_note_manager.clear_selection();
+                            ui.close_menu();
+                        }
+                        if ui.add(egui::Button::new("Invert selection").shortcut_text(self.shortcut_text(ctx, Action::InvertSelection))).clicked() {
+                            self.project_note_manager.invert_selection();
+                            ui.close_menu();
+                        }
+                        ui.separator();
+                        if ui.add(egui::Button::new("Split at playhead").shortcut_text(self.shortcut_text(ctx, Action::SplitAtPlayhead)))
+                            .on_hover_text("Cut the notes crossing the playhead in two there. Only the selected ones if there's a selection").clicked() {
+                            self.split_at_playhead();
+                            ui.close_menu();
+                        }
+                        let has_selection = !self.project_note_manager.selected.is_empty();
+                        ui.add_enabled_ui(has_selection, |ui| {
+                            if ui.add(egui::Button::new("Duplicate").shortcut_text(self.shortcut_text(ctx, Action::Duplicate))).clicked() {
+                                let ids = self.project_note_manager.selected_ids();
+                                self.project_note_manager.duplicate(&ids);
+                                ui.close_menu();
+                            }
+                            if ui.button("Reverse").on_hover_text("Mirror the selected notes in time, so they play backwards").clicked() {
+                                let ids = self.project_note_manager.selected_ids();
+                                self.project_note_manager.reverse(&ids);
+                                ui.close_menu();
+                            }
+                            if ui.button("Quantize...").on_hover_text("Snap the selected notes' starts to a grid, optionally swung").clicked() {
+                                self.show_quantize = true;
+                                ui.close_menu();
+                            }
+                            if ui.button("Glue").on_hover_text("Merge selected notes on the same key that touch or overlap into one").clicked() {
+                                let ids = self.project_note_manager.selected_ids();
+                                self.project_note_manager.glue(&ids);
+                                ui.close_menu();
+                            }
+                            if ui.button("Velocity ramp...").on_hover_text("Fade the selected notes' velocities from one value to another, like a crescendo").clicked() {
+                                self.velocity_ramp = Some((40, 110));
+                                ui.close_menu();
+                            }
+                            if ui.button("Legato...").on_hover_text("Lengthen the selected notes to reach the next note on their key").clicked() {
+                                self.show_legato = true;
+                                ui.close_menu();
+                            }
+                            if ui.button("Scale time...").clicked() {
+                                self.show_scale = true;
+                                ui.close_menu();
+                            }
+                            if ui.button("Humanize...").clicked() {
+                                self.show_humanize = true;
+                                ui.close_menu();
+                            }
+                            if ui.add(egui::Button::new("Delete").shortcut_text(self.shortcut_text(ctx, Action::Delete))).clicked() {
+                                let ids = self.project_note_manager.selected_ids();
+                                self.project_note_manager.delete_notes(&ids);
+                                ui.close_menu();
+                            }
+                            ui.menu_button("Transpose", |ui| {
+                                for (name, action, semitones) in [
+                                    ("Up a semitone", Action::TransposeUp, 1),
+                                    ("Down a semitone", Action::TransposeDown, -1),
+                                    ("Up an octave", Action::OctaveUp, 12),
+                                    ("Down an octave", Action::OctaveDown, -12)
+                                ] {
+                                    if ui.add(egui::Button::new(name).shortcut_text(self.shortcut_text(ctx, action))).clicked() {
+                                        let ids = self.project_note_manager.selected_ids();
+                                        self.project_note_manager.transpose(&ids, semitones);
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                        });
+                    });
+                    ui.menu_button("View", |ui| {
+                        let mut style_changed = ui.checkbox(&mut self.note_style.borders, "Note borders")
+                            .on_hover_text("Darken the edge of every note so dense notes stay apart").changed();
+                        ui.horizontal(|ui| {
+                            ui.label("Corner radius");
+                            style_changed |= ui.add(egui::Slider::new(&mut self.note_style.corner_radius, 0.0..=8.0).suffix(" px")).changed();
+                        });
+                        if style_changed && let Some(renderer) = self.renderer.as_ref() {
+                            renderer.lock().unwrap().set_note_style(self.note_style);
+                        }
+                        if ui.checkbox(&mut self.antialiasing, "Anti-aliasing")
+                            .on_hover_text("Smooth the bar lines and note edges so they don't shimmer while scrolling. Turn it off if huge files draw slowly")
+                            .changed() && let Some(renderer) = self.renderer.as_ref() {
+                            renderer.lock().unwrap().set_antialiasing(self.antialiasing);
+                        }
+                        ui.checkbox(&mut self.show_hover_position, "Pointer position")
+                            .on_hover_text("Show the position and key under the pointer while hovering the piano roll");
+                        ui.checkbox(&mut self.follow_playback, "Follow playback")
+                            .on_hover_text("Scroll the view along while playing. Otherwise the playhead moves across the view and it can be scrolled freely");
+                        ui.menu_button("Note colors", |ui| {
+                            let modes = [
+                                (NoteColorMode::Group, "By track list entry"),
+                                (NoteColorMode::Channel, "By channel"),
+                                (NoteColorMode::Velocity, "By velocity"),
+                                (NoteColorMode::Pitch, "By pitch")
+                            ];
+                            for (mode, name) in modes {
+                                if ui.radio_value(&mut self.color_mode, mode, name).clicked() {
+                                    if let Some(renderer) = self.renderer.as_ref() {
+                                        renderer.lock().unwrap().set_color_mode(mode);
+                                    }
+                                    ui.close_menu();
+                                }
+                            }
+                        });
                     });
                     ui.menu_button("Options", |ui| {
                         if ui.button("Audio...").clicked() {
@@ -235,53 +2765,331 @@ impl eframe::App for MainWindow {
                         }
                     });
                     ui.menu_button("Project", |ui| {
+                        if ui.add(egui::Button::new("Save").shortcut_text(self.shortcut_text(ctx, Action::Save)))
+                            .on_hover_text("Save the project as a MIDI file").clicked() {
+                            self.save_project(false);
+                            ui.close_menu();
+                        }
+                        if ui.button("Save as...").clicked() {
+                            self.save_project(true);
+                            ui.close_menu();
+                        }
+                        if ui.button("Tempo...").on_hover_text("Set the tempo the song starts at, by typing it or tapping along").clicked() {
+                            self.show_tempo = true;
+                            ui.close_menu();
+                        }
+                        let time_signatures = &mut self.project_settings.time_signatures;
+                        let mut pickup = time_signatures.pickup();
+                        ui.horizontal(|ui| {
+                            ui.label("Pickup");
+                            ui.add(egui::DragValue::new(&mut pickup)
+                                .range(0..=time_signatures.first_bar_ticks(self.project_settings.ppq))
+                                .speed(self.project_settings.ppq as f32 / 8.0)
+                                .suffix(" ticks"));
+                        }).response.on_hover_text("How long the partial bar before bar 1 is, for songs starting on an upbeat. \
+                            It's numbered bar 0 and the bar lines start after it");
+                        if pickup != time_signatures.pickup() {
+                            time_signatures.set_pickup(pickup);
+                            // the length in bars counts the pickup
+                            self.project_stats = None;
+                            self.dirty = true;
+                            if let Some(renderer) = self.renderer.as_ref() {
+                                renderer.lock().unwrap().update_time_signatures(time_signatures.clone());
+                            }
+                        }
+                        ui.menu_button("Highlight scale", |ui| {
+                            let mut scale = self.project_settings.highlight_scale;
+                            ui.radio_value(&mut scale, None, "None");
+                            let mut picked = scale.unwrap_or_default();
+                            egui::ComboBox::from_label("Root")
+                                .selected_text(PITCH_NAMES[picked.root as usize])
+                                .show_ui(ui, |ui| {
+                                    for (root, name) in PITCH_NAMES.iter().enumerate() {
+                                        if ui.selectable_value(&mut picked.root, root as u8, *name).clicked() {
+                                            scale = Some(picked);
+                                        }
+                                    }
+                                });
+                            ui.separator();
+                            for kind in ScaleKind::ALL {
+                                let selected = scale.is_some_and(|scale| scale.kind == kind);
+                                if ui.radio(selected, kind.name()).clicked() {
+                                    scale = Some(MusicalScale { kind, ..picked });
+                                }
+                            }
+                            if scale != self.project_settings.highlight_scale {
+                                self.project_settings.highlight_scale = scale;
+                                if let Some(renderer) = self.renderer.as_ref() {
+                                    renderer.lock().unwrap().set_scale_mask(scale.map_or(0, |scale| scale.pitch_mask()));
+                                }
+                            }
+                        });
+                        ui.separator();
                         if ui.button("Close project").clicked() {
-                            exit(0); 
+                            // closing through the viewport rather than exiting lets `on_exit` save the layout
+                            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                         }
                     });
                     ui.menu_button("Tools", |ui| {
-                        
+                        if ui.add(egui::Button::new("Go to bar...").shortcut_text(self.shortcut_text(ctx, Action::GoToBar))).clicked() {
+                            self.open_goto_bar();
+                            ui.close_menu();
+                        }
+                        if ui.add(egui::Button::new("Panic").shortcut_text(self.shortcut_text(ctx, Action::Panic)))
+                            .on_hover_text("Stop every note that's playing and reset the controllers and pitch bend on all channels").clicked() {
+                            self.panic(ctx);
+                            ui.close_menu();
+                        }
+                        if ui.add(egui::Button::new("Next note").shortcut_text(self.shortcut_text(ctx, Action::NextNote)))
+                            .on_hover_text("Move the playhead to the next note, on the selected notes' track if they're all on one").clicked() {
+                            self.jump_to_note(ctx, true);
+                            ui.close_menu();
+                        }
+                        if ui.add(egui::Button::new("Previous note").shortcut_text(self.shortcut_text(ctx, Action::PreviousNote)))
+                            .on_hover_text("Move the playhead to the previous note, on the selected notes' track if they're all on one").clicked() {
+                            self.jump_to_note(ctx, false);
+                            ui.close_menu();
+                        }
+                        ui.checkbox(&mut self.show_track_list, "Track list");
+                        ui.checkbox(&mut self.show_markers, "Markers");
+                        ui.checkbox(&mut self.show_minimap, "Minimap");
+                        ui.checkbox(&mut self.show_tempo_lane, "Tempo lane");
+                        ui.checkbox(&mut self.show_scope, "Oscilloscope");
+                        ui.checkbox(&mut self.show_spectrum, "Spectrum");
+                        if ui.button("Statistics").clicked() {
+                            self.show_stats = true;
+                            ui.close_menu();
+                        }
+                        if ui.button("Save snapshot...")
+                            .on_hover_text("Save the piano roll as it's shown now to a PNG, at any size").clicked() {
+                            self.snapshot_size = Some(self.snapshot_size.unwrap_or((1920, 1080)));
+                            ui.close_menu();
+                        }
+                        if ui.button("Render image sequence...")
+                            .on_hover_text("Save the song scrolling past as one PNG per frame, to make a video of elsewhere").clicked() {
+                            self.show_image_sequence = true;
+                            ui.close_menu();
+                        }
                     });
                     ui.menu_button("Help", |ui| {
                         
                     });
+                    ui.separator();
+                    ui.label("Grid");
+                    let grid = self.grid;
+                    egui::ComboBox::from_id_salt("grid_resolution")
+                        .selected_text(self.grid.name())
+                        .width(60.0)
+                        .show_ui(ui, |ui| {
+                            for choice in GridResolution::CHOICES {
+                                ui.selectable_value(&mut self.grid, choice, choice.name());
+                            }
+                        })
+                        .response.on_hover_text("The step of the grid drawn in the piano roll. The arrow keys move notes by one step, or four with Shift held");
+                    if self.grid != grid
+                        && let Some(renderer) = self.renderer.as_ref() {
+                        renderer.lock().unwrap().update_grid(self.grid.ticks(self.project_settings.ppq));
+                    }
                 });
             });
 
             egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
                 ui.horizontal(|ui| {
-                    if ui.label(format!("CPU {:.1}%", sys.cpus()[0].cpu_usage())).hovered() {
-                        hover_info = "Your CPU's usage.";
+                    let cpus = self.sys.cpus();
+                    let cpu_avg = cpus.iter().map(|cpu| cpu.cpu_usage()).sum::<f32>() / cpus.len().max(1) as f32;
+                    let cpu_label = ui.label(format!("CPU {:.1}%", cpu_avg))
+                        .on_hover_ui(|ui| {
+                            for cpu in cpus {
+                                ui.add(egui::ProgressBar::new(cpu.cpu_usage() / 100.0)
+                                    .desired_width(150.0)
+                                    .text(format!("{} {:.1}%", cpu.name(), cpu.cpu_usage())));
+                            }
+                        });
+                    if cpu_label.hovered() {
+                        hover_info = "Your CPU's usage, averaged across all cores.";
+                    }
+
+                    if let Some(err) = self.audio_error.as_ref() {
+                        ui.label(RichText::new("Audio unavailable").color(Color32::YELLOW))
+                            .on_hover_text(format!("{}.\nNothing can be heard, but the project can still be edited.", err));
+                    } else if let Some(synth) = self.synth.as_ref()
+                        && ui.label(format!("Audio {:.1}%", synth.get_audio_load() * 100.0)).hovered() {
+                        hover_info = "How much of the audio thread's time budget is being used. Above 100% causes dropouts.";
+                    }
+                    if self.synth.is_some() {
+                        if level_meter(ui, self.meter_levels).hovered() {
+                            hover_info = "The output level of the left and right channels, before the limiter.";
+                        }
+                        let clip_color = if self.clipped { Color32::RED } else { Color32::from_gray(80) };
+                        let clip = ui.add(egui::Label::new(RichText::new("CLIP").color(clip_color).strong()).sense(egui::Sense::click()));
+                        if clip.clicked() {
+                            self.clipped = false;
+                        }
+                        if clip.hovered() {
+                            hover_info = "Lights up when the output went past 0 dB before the limiter. Turn the master volume down if it does. Click to reset.";
+                        }
+                    }
+                    if self.synth.is_some() && ui.label(format!("Voices {}", self.voice_count)).hovered() {
+                        hover_info = "How many voices the synth is playing at once. More voices take more CPU to render.";
+                    }
+                    if let Some(load) = self.synth.as_ref().and_then(|synth| synth.soundfont_load()) {
+                        ui.spinner();
+                        let name = Path::new(&load.path).file_name().unwrap_or_default().to_string_lossy().to_string();
+                        ui.label(format!("Loading {} {}", name, soundfont_load_progress(load)));
+                    }
+                    if self.recorder.armed && self.playback.is_playing {
+                        ui.label(RichText::new("REC").color(Color32::RED).strong());
+                    }
+                    if self.nav.is_some() {
+                        let tick = self.playhead_tick();
+                        let ppq = self.project_settings.ppq;
+                        let (bar, beat, beat_tick) = self.project_settings.time_signatures.bar_beat_tick(ppq, tick as u64);
+                        let secs = self.playback.tick_to_secs(ppq, tick);
+                        let position = ui.label(RichText::new(format!("{}:{}:{:03}  {:02}:{:06.3}",
+                            bar, beat, beat_tick, (secs / 60.0) as u32, secs % 60.0)).monospace());
+                        if position.hovered() {
+                            hover_info = "The playhead position, in bar:beat:tick and minutes:seconds.";
+                        }
                     }
                     ui.label(format!("{}", hover_info));
+
+                    if let Some((message, shown_at)) = &self.status_message {
+                        let remaining = STATUS_MESSAGE_DURATION.saturating_sub(shown_at.elapsed());
+                        if remaining.is_zero() {
+                            self.status_message = None;
+                        } else {
+                            ui.with_layout(Layout::right_to_left(egui::Align::Center), |ui| {
+                                ui.label(message);
+                            });
+                            ctx.request_repaint_after(remaining);
+                        }
+                    }
                 })
             });
 
+            if self.show_track_list {
+                self.show_track_list_panel(ctx);
+            }
+            if self.show_markers {
+                self.show_markers_panel(ctx);
+            }
+            if self.show_scope {
+                self.show_scope_panel(ctx);
+            }
+            if self.show_spectrum {
+                self.show_spectrum_panel(ctx);
+            } else {
+                self.spectrum = None;
+            }
+
             egui::SidePanel::new(egui::panel::Side::Right, "thing")
                 .resizable(false)
                 .default_width(30f32)
                 .show(ctx, |ui| {
-                    ui.button("copy");
-                    ui.button("paste");
-                    ui.button("cut");
+                    ui.selectable_value(&mut self.edit_tool, EditTool::Pointer, "select")
+                        .on_hover_text("Click notes to select them, drag on empty space to select a rectangle. Hold Shift to add to the selection");
+                    ui.selectable_value(&mut self.edit_tool, EditTool::Audition, "play")
+                        .on_hover_text("Play the key under the pointer while the mouse button is held");
+                    ui.selectable_value(&mut self.edit_tool, EditTool::Pencil, "draw")
+                        .on_hover_text("Click to add a note, or drag to draw it as long as the drag. Hold Alt to place it off the grid");
+                    if self.edit_tool == EditTool::Pencil {
+                        ui.add_enabled(!self.draw_last_velocity, egui::DragValue::new(&mut self.draw_velocity).range(1..=127))
+                            .on_hover_text("The velocity of drawn notes");
+                        ui.checkbox(&mut self.draw_last_velocity, "last")
+                            .on_hover_text("Draw with the velocity of the note last clicked, drawn or given a velocity instead");
+                        let length_name = |length: Option<GridResolution>| length.map_or("grid".to_string(), |length| length.name());
+                        egui::ComboBox::from_id_salt("draw_length")
+                            .selected_text(length_name(self.draw_length))
+                            .width(40.0)
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut self.draw_length, None, length_name(None));
+                                for choice in GridResolution::CHOICES {
+                                    ui.selectable_value(&mut self.draw_length, Some(choice), choice.name());
+                                }
+                            })
+                            .response.on_hover_text("How long a clicked note is, \"grid\" follows the grid. Dragging draws it as long as the drag");
+                    }
+                    ui.separator();
+                    let play_label = if self.playback.is_playing { "⏸" } else { "⏵" };
+                    if ui.button(play_label).on_hover_text("Play from the playhead, or pause and keep the position (Space)").clicked() {
+                        self.toggle_playback(ctx);
+                    }
+                    if ui.button("⏹").on_hover_text("Stop and go back to the start (Enter)").clicked() {
+                        self.stop_to_start(ctx);
+                    }
+                    ui.toggle_value(&mut self.recorder.armed, "rec")
+                        .on_hover_text("Record the notes played on the MIDI input while playing");
+                    ui.separator();
+                    if ui.button("copy").on_hover_text("Copy the selected notes").clicked() {
+                        self.copy_selection(false);
+                    }
+                    if ui.button("paste").on_hover_text("Paste the copied notes at the playhead").clicked() {
+                        self.paste_clipboard();
+                    }
+                    if ui.button("cut").on_hover_text("Copy the selected notes and delete them").clicked() {
+                        self.copy_selection(true);
+                    }
             });
 
             egui::CentralPanel::default()
                 .show(ctx, |ui| {
-                    let available_size = ui.available_size_before_wrap();
-                    let (rect, _response) = ui.allocate_exact_size(available_size, egui::Sense::hover());
+                    let minimap = self.show_minimap.then(|| {
+                        let minimap_size = vec2(ui.available_width(), MINIMAP_HEIGHT);
+                        let minimap = ui.allocate_exact_size(minimap_size, egui::Sense::click_and_drag());
+                        ui.add_space(-ui.spacing().item_spacing.y);
+                        minimap
+                    });
+                    // the keyboard takes the left of the piano roll, so the ruler starts after it to line up with the bars
+                    let ruler_size = vec2(ui.available_width(), RULER_HEIGHT);
+                    let (ruler_row, _) = ui.allocate_exact_size(ruler_size, egui::Sense::hover());
+                    ui.add_space(-ui.spacing().item_spacing.y);
+                    let tempo_lane = self.show_tempo_lane.then(|| {
+                        let (lane_row, _) = ui.allocate_exact_size(vec2(ruler_row.width(), TEMPO_LANE_HEIGHT), egui::Sense::hover());
+                        ui.add_space(-ui.spacing().item_spacing.y);
+                        lane_row.split_left_right_at_x(lane_row.min.x + KEYBOARD_WIDTH)
+                    });
+                    let (roll_row, _) = ui.allocate_exact_size(ui.available_size_before_wrap(), egui::Sense::hover());
+                    let (keyboard_rect, rect) = roll_row.split_left_right_at_x(roll_row.min.x + KEYBOARD_WIDTH);
+                    let (ruler_corner, ruler_rect) = ruler_row.split_left_right_at_x(ruler_row.min.x + KEYBOARD_WIDTH);
+                    let available_size = rect.size();
+                    let ruler_response = ui.interact(ruler_rect, ui.id().with("ruler"), egui::Sense::click_and_drag());
+                    let response = ui.interact(rect, ui.id().with("piano_roll"), egui::Sense::click_and_drag());
 
                     if self.gl.is_none() { return; }
                     if self.renderer.is_none() { return; }
                     if self.nav.is_none() { return; }
 
+                    if let Some((minimap_rect, minimap_response)) = minimap {
+                        self.show_minimap(ctx, ui, minimap_rect, &minimap_response);
+                    }
+                    self.show_ruler(ctx, ui, ruler_rect, &ruler_response);
+                    ui.painter().rect_filled(ruler_corner, 0.0, Color32::from_gray(30));
+                    if let Some((lane_corner, lane_rect)) = tempo_lane {
+                        ui.painter().rect_filled(lane_corner, 0.0, Color32::from_gray(26));
+                        ui.painter().text(lane_corner.center(), egui::Align2::CENTER_CENTER, "Tempo",
+                            egui::FontId::proportional(11.0), Color32::from_gray(160));
+                        let lane_response = ui.interact(lane_rect, ui.id().with("tempo_lane"), egui::Sense::click());
+                        self.show_tempo_lane(ctx, ui, lane_rect, &lane_response);
+                    }
+                    self.show_keyboard(ui, keyboard_rect);
+
                     let (alt_down, shift_down, ctrl_down)  =   ui.input(|i| (i.modifiers.alt, i.modifiers.shift, i.modifiers.ctrl) );
 
-                    self.handle_navigation(ctx, ui, ctrl_down, alt_down);
+                    let wheel_action = self.app_settings.lock().unwrap().edit_settings.wheel_action;
+                    let is_panning = shift_down || (ctrl_down != (wheel_action == WheelAction::Pan));
+                    self.handle_navigation(ui, rect, is_panning, alt_down);
+
+                    match self.edit_tool {
+                        EditTool::Pointer => self.handle_pointer_tool(ui, &response, rect, shift_down),
+                        EditTool::Pencil => self.handle_pencil_tool(ui, &response, rect, alt_down),
+                        EditTool::Audition => {}
+                    }
+                    self.show_note_context_menu(rect, &response);
 
                     if let Some(synth) = self.synth.as_mut() {
                         if !self.playback.is_playing { 
-                            if ui.input(|i| i.pointer.button_down(PointerButton::Primary)) {
+                            if self.edit_tool == EditTool::Audition && response.is_pointer_button_down_on()
+                                && ui.input(|i| i.pointer.button_down(PointerButton::Primary)) {
                                 if (self.nav.is_none()) { return; }
                                 let pos = ui.input(|i| i.pointer.interact_pos()).unwrap();
                                 let nav = self.nav.as_ref().unwrap();
@@ -289,48 +3097,58 @@ impl eframe::App for MainWindow {
                                     let nav = nav.lock().unwrap();
                                     let curr_key = ((1.0 - (pos.y - rect.y_range().min) / available_size.y) * nav.zoom_keys + nav.key_pos) as u8;
                                     if curr_key != self.curr_pointer_key || !self.note_playing {
-                                        synth.note_off(0, self.curr_pointer_key);
+                                        if !self.keyboard_audition.is_holding(self.curr_pointer_key) {
+                                            synth.note_off(0, self.curr_pointer_key);
+                                        }
                                         synth.note_on(0, curr_key, 127);
                                         self.note_playing = true;
                                     }
                                     self.curr_pointer_key = curr_key;
                                 }
                             }
-                            if ui.input(|i| i.pointer.primary_released()) {
-                                synth.note_off(0, self.curr_pointer_key);
+                            if self.note_playing && ui.input(|i| i.pointer.primary_released()) {
+                                if !self.keyboard_audition.is_holding(self.curr_pointer_key) {
+                                    synth.note_off(0, self.curr_pointer_key);
+                                }
                                 self.note_playing = false;
                             }
-                        }
-                    }
 
-                    if ui.input(|i| i.key_pressed(Key::Space)) {
-                        self.playback.play_or_stop();
-                        if let Some(nav) = self.nav.as_ref() {
-                            let mut nav = nav.lock().unwrap();
-                            if !self.playback.is_playing {
-                                //nav.tick_pos = self.last_tick;
-                                if let Some(rend) = self.renderer.as_mut() {
-                                    let mut rend = rend.lock().unwrap();
-                                    nav.change_tick_pos(self.last_tick, |time| { rend.time_changed(time) });
-                                }
-                            } else {
-                                self.last_tick = nav.tick_pos;
-                            }
+                            if !ctx.wants_keyboard_input() {
+                                self.keyboard_audition.follow_key_pos(self.nav.as_ref().unwrap().lock().unwrap().key_pos);
 
-                            if let Some(synth) = self.synth.as_mut() {
-                                if !self.playback.is_playing {
-                                    synth.switch_render_mode(RenderMode::Realtime);
-                                } else {
-                                    synth.switch_render_mode(RenderMode::Rendering);
+                                let key_events = if self.capturing_key.is_none() { ui.input(|i| i.events.clone()) } else { Vec::new() };
+                                for event in key_events {
+                                    let Event::Key { key, physical_key, pressed, repeat, modifiers } = event else { continue; };
+                                    // leave shortcuts alone
+                                    if modifiers.ctrl || modifiers.alt || modifiers.command { continue; }
+                                    // the tracker layout is positional, so prefer the physical key
+                                    let key = physical_key.unwrap_or(key);
+
+                                    if pressed {
+                                        if repeat { continue; }
+                                        match key {
+                                            Key::OpenBracket => self.keyboard_audition.shift_octave(-1),
+                                            Key::CloseBracket => self.keyboard_audition.shift_octave(1),
+                                            _ => {
+                                                if let Some(midi_key) = self.keyboard_audition.press(key) {
+                                                    synth.note_on(0, midi_key, self.keyboard_audition.velocity);
+                                                }
+                                            }
+                                        }
+                                    } else if let Some(midi_key) = self.keyboard_audition.release(key)
+                                        && !(self.note_playing && self.curr_pointer_key == midi_key)
+                                        && !self.keyboard_audition.is_holding(midi_key) {
+                                        synth.note_off(0, midi_key);
+                                    }
                                 }
                             }
-
-                            ctx.request_repaint();
                         }
                     }
-                    
+
+
                     let gl = self.gl.as_ref().unwrap();
                     let renderer = self.renderer.as_ref().unwrap();
+                    let theme = self.app_settings.lock().unwrap().theme;
 
                     let callback = egui::PaintCallback {
                         rect,
@@ -340,7 +3158,8 @@ impl eframe::App for MainWindow {
 
                             move |_info, painter| {
                                 unsafe {
-                                    gl.clear_color(0.0, 0.0, 0.0, 1.0);
+                                    let [r, g, b] = theme.background;
+                                    gl.clear_color(r, g, b, 1.0);
                                     gl.clear(glow::COLOR_BUFFER_BIT);
                                     {
                                         let mut rnd = renderer.lock().unwrap();
@@ -352,9 +3171,94 @@ impl eframe::App for MainWindow {
                         })),
                     };
                     ui.painter().add(callback);
+
+                    if self.show_hover_position {
+                        self.show_hover_position(ctx, rect, &response);
+                    }
+
+                    if let Some(((start_tick, start_key), (end_tick, end_key))) = self.marquee {
+                        let nav = self.nav.as_ref().unwrap().lock().unwrap();
+                        let marquee_rect = Rect::from_two_pos(
+                            nav.tick_key_to_pos(rect, start_tick, start_key),
+                            nav.tick_key_to_pos(rect, end_tick, end_key)
+                        ).intersect(rect);
+                        let painter = ui.painter_at(rect);
+                        let selection = theme_color(theme.selection);
+                        painter.rect_filled(marquee_rect, 0.0, selection.gamma_multiply(0.16));
+                        painter.rect_stroke(marquee_rect, 0.0, Stroke::new(1.0, selection), StrokeKind::Inside);
+                    }
+
+                    // the playhead is the view's left edge unless playback runs without the view following it
+                    let playhead_x = match self.nav.as_ref() {
+                        Some(nav) if self.playback.is_playing && !self.follow_playback =>
+                            nav.lock().unwrap().tick_key_to_pos(rect, self.playhead_tick(), 0.0).x.max(rect.left() + 1.0),
+                        _ => rect.left() + 1.0
+                    };
+                    ui.painter_at(rect).vline(playhead_x, rect.y_range(), Stroke::new(2.0, theme_color(theme.playhead)));
+
+                    if let Some(nav) = self.nav.as_ref() {
+                        let nav = nav.lock().unwrap();
+                        let start = nav.tick_pos.max(0.0) as u64;
+                        let end = (nav.tick_pos + nav.zoom_ticks).max(0.0) as u64;
+                        let painter = ui.painter_at(rect);
+                        // the flags are drawn on the ruler
+                        for marker in self.markers.in_range(start, end) {
+                            let x = nav.tick_key_to_pos(rect, marker.tick as f32, 0.0).x;
+                            painter.vline(x, rect.y_range(), Stroke::new(1.0, Color32::from_rgb(255, 200, 90)));
+                        }
+                    }
                 });
         });
 
+        if self.show_stats {
+            self.show_stats_window(ctx);
+        }
+        self.show_goto_bar_window(ctx);
+        self.show_snapshot_window(ctx);
+        if self.show_image_sequence {
+            self.show_image_sequence_window(ctx);
+        }
+        if self.show_export_audio {
+            self.show_export_audio_window(ctx);
+        }
+        if self.show_tempo {
+            self.show_tempo_window(ctx);
+        }
+        self.show_tempo_point_window(ctx);
+        self.show_set_velocity_window(ctx);
+        self.show_velocity_ramp_window(ctx);
+        self.show_transpose_window(ctx);
+        if self.show_humanize {
+            self.show_humanize_window(ctx);
+        }
+        if self.show_scale {
+            self.show_scale_window(ctx);
+        }
+        if self.show_quantize {
+            self.show_quantize_window(ctx);
+        }
+        if self.show_legato {
+            self.show_legato_window(ctx);
+        }
+        self.show_import_window(ctx);
+
+        if let Some(message) = self.error_message.clone() {
+            egui::Window::new("Error")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, vec2(0.0, 0.0))
+                .show(ctx, |ui| {
+                    ui.label(message);
+                    if ui.button("OK").clicked() {
+                        self.error_message = None;
+                    }
+                });
+        }
+        self.show_close_prompt_window(ctx);
+
+        if self.window_settings != CurrentAppSettings::Keys {
+            self.capturing_key = None;
+        }
         if self.window_settings != CurrentAppSettings::None {
             egui::Window::new("Settings")
                 .collapsible(false)
@@ -370,33 +3274,335 @@ impl eframe::App for MainWindow {
                                 if ui.selectable_label(self.window_settings == CurrentAppSettings::Audio, "Audio").clicked() {
                                     self.window_settings = CurrentAppSettings::Audio;
                                 }
+                                if ui.selectable_label(self.window_settings == CurrentAppSettings::Keys, "Keys").clicked() {
+                                    self.window_settings = CurrentAppSettings::Keys;
+                                }
                             });
                             ui.separator();
                             ui.vertical(|ui| {
                                 match self.window_settings {
                                     CurrentAppSettings::General => {
-                                        
+                                        let app_settings = self.app_settings.clone();
+                                        let mut app_settings = app_settings.lock().unwrap();
+
+                                        let seek_snap = &mut app_settings.edit_settings.seek_snap;
+                                        ui.horizontal(|ui| {
+                                            ui.label(RichText::new("Snap seeks to:").size(15.0));
+                                            ui.radio_value(seek_snap, SnapMode::Off, "Off");
+                                            ui.radio_value(seek_snap, SnapMode::Beat, "Beat");
+                                            ui.radio_value(seek_snap, SnapMode::Bar, "Bar");
+                                        }).response.on_hover_text("Round the play position to the nearest beat or bar line when seeking. Hold Alt to seek freely");
+
+                                        // a project with nothing in it yet takes the new defaults right away
+                                        let project_empty = self.project_note_manager.note_count() == 0 && self.playback.tempo_events.len() <= 1;
+                                        let edit_settings = &mut app_settings.edit_settings;
+                                        let mut defaults_changed = false;
+                                        ui.horizontal(|ui| {
+                                            ui.label(RichText::new("New projects:").size(15.0));
+                                            defaults_changed |= ui.add(egui::DragValue::new(&mut edit_settings.default_ppq).range(24..=9600).suffix(" PPQ")).changed();
+                                            defaults_changed |= ui.add(egui::DragValue::new(&mut edit_settings.default_bpm)
+                                                .range(10.0..=1000.0).speed(0.5).max_decimals(2).suffix(" BPM")).changed();
+                                        }).response.on_hover_text("The resolution and tempo a project starts with. The open project changes too while it has no notes or tempo changes");
+                                        if defaults_changed && project_empty {
+                                            let (ppq, bpm) = (edit_settings.default_ppq, edit_settings.default_bpm);
+                                            if ppq != self.project_settings.ppq {
+                                                self.project_settings.ppq = ppq;
+                                                self.playback.update_tempo_times(ppq);
+                                                if let Some(renderer) = self.renderer.as_ref() {
+                                                    let mut renderer = renderer.lock().unwrap();
+                                                    renderer.update_ppq(ppq);
+                                                    renderer.update_grid(self.grid.ticks(ppq));
+                                                }
+                                            }
+                                            if bpm != self.project_settings.initial_bpm {
+                                                self.set_initial_tempo(ctx, bpm);
+                                            }
+                                        }
+
+                                        let follow_playback = &mut app_settings.view_settings.follow_playback;
+                                        if ui.checkbox(follow_playback, "Follow playback")
+                                            .on_hover_text("If the view scrolls along while playing when the app starts. The View menu switches it for now").changed() {
+                                            self.follow_playback = *follow_playback;
+                                        }
+
+                                        let wheel_action = &mut app_settings.edit_settings.wheel_action;
+                                        ui.horizontal(|ui| {
+                                            ui.label(RichText::new("Mouse wheel:").size(15.0));
+                                            ui.radio_value(wheel_action, WheelAction::Zoom, "Zoom");
+                                            ui.radio_value(wheel_action, WheelAction::Pan, "Pan");
+                                        }).response.on_hover_text("What the wheel does over the piano roll. Ctrl+wheel does the other one, \
+                                            Shift+wheel always pans through time and holding Alt zooms or pans the keys instead");
+
+                                        let note_pairing = &mut app_settings.import_settings.note_pairing;
+                                        ui.horizontal(|ui| {
+                                            ui.label(RichText::new("Overlapping notes:").size(15.0));
+                                            ui.radio_value(note_pairing, NotePairing::Fifo, "End oldest first");
+                                            ui.radio_value(note_pairing, NotePairing::Lifo, "End newest first");
+                                        }).response.on_hover_text("When an imported MIDI starts the same key again before releasing it, which of the held notes a note off ends. \
+                                            Most players end the oldest one");
+
+                                        let max_fps = &mut app_settings.view_settings.max_fps;
+                                        ui.horizontal(|ui| {
+                                            ui.label(RichText::new("Frame rate limit:").size(15.0));
+                                            ui.add(egui::DragValue::new(max_fps)
+                                                .range(0..=480)
+                                                .custom_formatter(|fps, _| if fps == 0.0 { String::from("Off") } else { format!("{} FPS", fps) })
+                                                .custom_parser(|text| match text.trim() {
+                                                    "Off" => Some(0.0),
+                                                    text => text.trim_end_matches("FPS").trim().parse().ok()
+                                                }));
+                                        }).response.on_hover_text("The most frames per second drawn during playback and other animations, to save GPU time. \
+                                            0 draws as many as the display shows. Nothing is redrawn while idle either way");
+
+                                        let theme = &mut app_settings.theme;
+                                        let mut theme_changed = false;
+                                        ui.horizontal(|ui| {
+                                            ui.label(RichText::new("Theme:").size(15.0));
+                                            egui::ComboBox::from_id_salt("theme_preset")
+                                                .selected_text(theme.preset().map_or("Custom", |preset| preset.name()))
+                                                .show_ui(ui, |ui| {
+                                                    for preset in ThemePreset::ALL {
+                                                        if ui.selectable_label(theme.preset() == Some(preset), preset.name()).clicked() {
+                                                            *theme = preset.theme();
+                                                            theme_changed = true;
+                                                        }
+                                                    }
+                                                });
+                                        }).response.on_hover_text("The piano roll's colors. Picking a preset replaces any colors changed below");
+                                        egui::CollapsingHeader::new("Colors").show(ui, |ui| {
+                                            egui::Grid::new("theme_colors").show(ui, |ui| {
+                                                for (_, label, color) in theme.colors_mut() {
+                                                    ui.label(label);
+                                                    theme_changed |= ui.color_edit_button_rgb(color).changed();
+                                                    ui.end_row();
+                                                }
+                                            });
+                                        });
+                                        if theme_changed && let Some(renderer) = self.renderer.as_ref() {
+                                            renderer.lock().unwrap().set_theme(*theme);
+                                        }
                                     },
                                     CurrentAppSettings::Audio => {
                                         ui.vertical(|ui| {
                                             let app_settings = self.app_settings.clone();
                                             let mut app_settings = app_settings.lock().unwrap();
 
+                                            let mut picked_soundfont = None;
                                             self.labeled_widget("Soundfont", ui, |ui| {
                                                 ui.label(format!("{}", app_settings.audio_settings.soundfont_path));
                                                 if ui.button("Choose soundfont").clicked() {
                                                     let sfd = rfd::FileDialog::new()
                                                         .add_filter("Soundfont Files", &["sfz","sf2"]);
                                                     if let Some(file) = sfd.pick_file() {
-                                                        let path = file.to_string_lossy().to_string();
-                                                        app_settings.audio_settings.soundfont_path = path;
+                                                        picked_soundfont = Some(file.to_string_lossy().to_string());
                                                     }
                                                 }
                                             });
+                                            match self.synth.as_ref().and_then(|synth| synth.soundfont_info()) {
+                                                Some(info) => {
+                                                    ui.label(format!("{}: {} presets, {} samples",
+                                                        info.name.as_deref().unwrap_or("Unnamed soundfont"), info.presets.len(), info.sample_count));
+                                                    egui::CollapsingHeader::new("Presets").show(ui, |ui| {
+                                                        egui::ScrollArea::vertical().max_height(150.0).show(ui, |ui| {
+                                                            for preset in &info.presets {
+                                                                ui.label(RichText::new(format!("{:03}:{:03} {}", preset.bank, preset.preset, preset.name)).monospace());
+                                                            }
+                                                        });
+                                                    });
+                                                }
+                                                // SFZs and soundfonts that are still loading
+                                                None => {
+                                                    ui.label(RichText::new("No soundfont information").weak());
+                                                }
+                                            }
+
+                                            let audio_settings = &mut app_settings.audio_settings;
+                                            let mut options_changed = false;
+                                            ui.horizontal(|ui| {
+                                                ui.label(RichText::new("Envelope:").size(15.0));
+                                                options_changed |= envelope_curve_picker(ui, "Attack", &mut audio_settings.attack_curve);
+                                                options_changed |= envelope_curve_picker(ui, "Decay", &mut audio_settings.decay_curve);
+                                                options_changed |= envelope_curve_picker(ui, "Release", &mut audio_settings.release_curve);
+                                            }).response.on_hover_text("The shape of each volume envelope stage. Exponential release sounds more natural for pianos and plucked sounds");
+                                            options_changed |= ui.checkbox(&mut audio_settings.use_soundfont_effects, "Soundfont effects")
+                                                .on_hover_text("Apply the effects the soundfont defines for its instruments. Only does anything if the soundfont has effect definitions, \
+                                                    and currently only the low-pass filter cutoff is supported by the synth, not chorus or reverb")
+                                                .changed();
+
+                                            // the envelope and effects are set when the soundfont loads, so changing them means loading it again
+                                            let reload = picked_soundfont.or(options_changed.then(|| audio_settings.soundfont_path.clone()));
+                                            if let Some(path) = reload
+                                                && let Some(synth) = self.synth.as_mut() {
+                                                synth.reload_soundfont(path, audio_settings.soundfont_options());
+                                            }
+                                            if let Some(load) = self.synth.as_ref().and_then(|synth| synth.soundfont_load()) {
+                                                ui.horizontal(|ui| {
+                                                    ui.spinner();
+                                                    ui.label(format!("Loading soundfont... {}", soundfont_load_progress(load)));
+                                                });
+                                            }
+                                            for err in &self.soundfont_errors {
+                                                ui.colored_label(Color32::LIGHT_RED, err);
+                                            }
 
                                             self.labeled_widget("Layers", ui, |ui| {
                                                 ui.add(egui::DragValue::new(&mut app_settings.audio_settings.num_layers).range(1..=10));
                                             });
+
+                                                                            let audio_settings = &mut app_settings.audio_settings;
+                                            self.labeled_widget("Master volume", ui, |ui| {
+                                                let gain = ui.add(egui::Slider::new(&mut audio_settings.master_gain_db, -24.0..=12.0)
+                                                    .suffix(" dB")
+                                                    .fixed_decimals(1))
+                                                    .on_hover_text("Turns the output up or down before the limiter. Double-click to reset to 0 dB");
+                                                if gain.double_clicked() {
+                                                    audio_settings.master_gain_db = 0.0;
+                                                }
+                                            });
+                                            ui.horizontal(|ui| {
+                                                ui.label(RichText::new("Output:").size(15.0));
+                                                for mode in [ChannelMode::Stereo, ChannelMode::Mono] {
+                                                    ui.radio_value(&mut audio_settings.channel_mode, mode, mode.name());
+                                                }
+                                            }).response.on_hover_text("Mono sums left and right at -3 dB, for playback and exported audio. \
+                                                The synth still renders in stereo and the device gets the same channels either way");
+                                            ui.horizontal(|ui| {
+                                                ui.checkbox(&mut audio_settings.high_pass, "Remove DC offset");
+                                                ui.add_enabled(audio_settings.high_pass,
+                                                    egui::Slider::new(&mut audio_settings.high_pass_cutoff, 5.0..=80.0)
+                                                        .suffix(" Hz")
+                                                        .fixed_decimals(0)
+                                                        .text("Cutoff"));
+                                            }).response.on_hover_text("Filter out the offset some soundfonts leave and rumble below the cutoff before the limiter, \
+                                                so it doesn't waste headroom or thump. Applies to exported audio too");
+                                            ui.horizontal(|ui| {
+                                                ui.checkbox(&mut audio_settings.limiter_lookahead, "Limiter lookahead");
+                                                ui.add_enabled(audio_settings.limiter_lookahead,
+                                                    egui::Slider::new(&mut audio_settings.limiter_lookahead_ms, 0.5..=10.0)
+                                                        .suffix(" ms")
+                                                        .fixed_decimals(1));
+                                            }).response.on_hover_text("Turn the limiter down before peaks arrive instead of as they do, \
+                                                which lets fewer fast transients through and distorts dense MIDIs less. \
+                                                Everything heard is delayed by the lookahead, keep it short for playing live");
+                                            self.labeled_widget("Vibrato depth", ui, |ui| {
+                                                ui.add(egui::Slider::new(&mut audio_settings.vibrato_depth, 0.0..=2.0)
+                                                    .suffix(" semitones")
+                                                    .fixed_decimals(2))
+                                                    .on_hover_text("How far the mod wheel (CC 1) swings the pitch either way at full modulation. \
+                                                        Takes effect the next time playback starts");
+                                            });
+                                            if let Some(synth) = self.synth.as_mut() {
+                                                synth.set_master_gain(audio_settings.master_gain());
+                                                synth.set_channel_mode(audio_settings.channel_mode);
+                                                synth.set_vibrato_depth(audio_settings.vibrato_depth);
+                                                synth.set_high_pass(audio_settings.output_high_pass());
+                                                synth.set_limiter_lookahead(audio_settings.limiter_lookahead());
+                                            }
+                                            ui.horizontal(|ui| {
+                                                ui.checkbox(&mut audio_settings.enable_note_skipping, "Skip quiet notes when lagging");
+                                                ui.add_enabled(audio_settings.enable_note_skipping,
+                                                    egui::Slider::new(&mut audio_settings.note_skipping_aggressiveness, 0.1..=10.0)
+                                                        .logarithmic(true)
+                                                        .text("Aggressiveness"));
+                                            }).response.on_hover_text("When rendering can't keep up with playback, drop the quietest notes so the rest stays in time. \
+                                                Keeps black MIDIs playable, but soft notes in normal music can go missing. Higher aggressiveness drops notes sooner");
+
+                                            thread_count_picker(ui, "Channel threads", &mut audio_settings.channel_threads)
+                                                .on_hover_text("How many threads the synth's channels are rendered on");
+                                            thread_count_picker(ui, "Key threads", &mut audio_settings.key_threads)
+                                                .on_hover_text("How many threads each channel's keys are rendered on. Helps on songs with lots of notes on one channel, but adds overhead");
+                                            ui.checkbox(&mut audio_settings.fade_out_killing, "Fade out stolen voices")
+                                                .on_hover_text("Voices cut off at the layer limit fade out instead of stopping instantly, which avoids clicks in dense MIDIs");
+                                            ui.horizontal(|ui| {
+                                                ui.label(RichText::new("Channels:").size(15.0));
+                                                for ports in 1..=MAX_SYNTH_PORTS {
+                                                    ui.radio_value(&mut audio_settings.synth_ports, ports, (ports * 16).to_string());
+                                                }
+                                            }).response.on_hover_text("How many channels the synth has. Past 16, the tracks take turns at each set of 16, \
+                                                so tracks on the same MIDI channel get instruments and controllers of their own for layering. \
+                                                Channel 10 of every set is percussion");
+                                            let parallelism = audio_settings.parallelism();
+                                            let channel_init_options = audio_settings.channel_init_options();
+                                            let format = audio_settings.synth_format();
+                                            if let Some(synth) = self.synth.as_mut() {
+                                                let changed = synth.parallelism() != parallelism
+                                                    || synth.channel_init_options() != channel_init_options
+                                                    || synth.format() != format;
+                                                let restart = ui.add_enabled(changed, egui::Button::new("Restart synth"))
+                                                    .on_hover_text("Thread, voice stealing and channel changes only take effect once the synth is restarted. This stops playback");
+                                                if restart.clicked() {
+                                                    self.pause_playback(ctx);
+                                                    if let Some(synth) = self.synth.as_mut() {
+                                                        synth.rebuild_synth(parallelism, channel_init_options, format);
+                                                    }
+                                                }
+                                            }
+
+                                            ui.horizontal(|ui| {
+                                                ui.label(RichText::new("MIDI input:").size(15.0));
+                                                let selected = self.midi_input.port_name.clone();
+                                                egui::ComboBox::from_id_salt("midi_input_port")
+                                                    .selected_text(selected.as_deref().unwrap_or("None"))
+                                                    .show_ui(ui, |ui| {
+                                                        if ui.selectable_label(selected.is_none(), "None").clicked() {
+                                                            self.midi_input.disconnect();
+                                                        }
+                                                        for port in MIDIInput::port_names() {
+                                                            if ui.selectable_label(selected.as_ref() == Some(&port), &port).clicked()
+                                                                && let Some(synth) = self.synth.as_ref()
+                                                                && let Err(err) = self.midi_input.connect(&port, synth.handle()) {
+                                                                self.error_message = Some(format!("Could not open the MIDI input {}:\n{}", port, err));
+                                                            }
+                                                        }
+                                                    });
+
+                                                ui.label("Channel");
+                                                let mut channel = self.midi_input.channel() + 1;
+                                                if ui.add(egui::DragValue::new(&mut channel).range(1..=16)).changed() {
+                                                    self.midi_input.set_channel(channel - 1);
+                                                }
+                                            }).response.on_hover_text("Notes played on the MIDI controller are heard on this channel while playback is stopped");
+
+                                            if let Some(midi_output) = self.synth.as_ref().map(|synth| synth.midi_output().clone()) {
+                                                ui.horizontal(|ui| {
+                                                    ui.label(RichText::new("MIDI output:").size(15.0));
+                                                    let mut output = midi_output.lock().unwrap();
+                                                    let selected = output.port_name.clone();
+                                                    egui::ComboBox::from_id_salt("midi_output_port")
+                                                        .selected_text(selected.as_deref().unwrap_or("None"))
+                                                        .show_ui(ui, |ui| {
+                                                            if ui.selectable_label(selected.is_none(), "None").clicked() {
+                                                                output.disconnect();
+                                                            }
+                                                            for port in MIDIOutput::port_names() {
+                                                                if ui.selectable_label(selected.as_ref() == Some(&port), &port).clicked()
+                                                                    && let Err(err) = output.connect(&port) {
+                                                                    self.error_message = Some(format!("Could not open the MIDI output {}:\n{}", port, err));
+                                                                }
+                                                            }
+                                                        });
+
+                                                    let connected = output.is_connected();
+                                                    ui.add_enabled(connected, egui::Checkbox::new(&mut output.replace_synth, "Mute the built-in synth"))
+                                                        .on_hover_text("Only hear the external synth. Playback follows this from the next time it starts");
+                                                }).response.on_hover_text("Playback and the notes auditioned while editing are sent to this port too, to play them on a hardware or external synth. Everything goes out on its 16 channels");
+                                            }
+
+                                            let audition = &mut self.keyboard_audition;
+                                            ui.horizontal(|ui| {
+                                                ui.label(RichText::new("Keyboard octave:").size(15.0));
+                                                let mut octave = audition.base_octave;
+                                                if ui.add(egui::DragValue::new(&mut octave).range(0..=10)).changed() {
+                                                    audition.base_octave = octave;
+                                                    audition.follow_view = false;
+                                                }
+                                                ui.checkbox(&mut audition.follow_view, "Follow view")
+                                                    .on_hover_text("Use the lowest C in view as the octave of the Z key. [ and ] also change the octave.");
+                                            });
+                                            ui.horizontal(|ui| {
+                                                ui.label(RichText::new("Keyboard velocity:").size(15.0));
+                                                ui.add(egui::DragValue::new(&mut audition.velocity).range(1..=127));
+                                            });
                                             /*ui.vertical(|ui| {
                                                 ui.label(RichText::new("Soundfont").size(15.0));
                                                 ui.horizontal(|ui| {
@@ -412,6 +3618,9 @@ impl eframe::App for MainWindow {
                                             });*/
                                         });
                                     },
+                                    CurrentAppSettings::Keys => {
+                                        self.show_key_bindings(ui);
+                                    },
                                     CurrentAppSettings::None => {
 
                                     }
@@ -438,16 +3647,123 @@ impl eframe::App for MainWindow {
             });
         }
     }
+
+    fn on_exit(&mut self, _gl: Option<&glow::Context>) {
+        self.layout.show_track_list = self.show_track_list;
+        self.layout.show_markers = self.show_markers;
+        self.layout.show_minimap = self.show_minimap;
+        self.layout.show_tempo_lane = self.show_tempo_lane;
+        self.layout.show_scope = self.show_scope;
+        self.layout.show_spectrum = self.show_spectrum;
+        self.layout.settings_tab = self.window_settings.name().to_string();
+        if let Err(err) = self.layout.save(LAYOUT_PATH) {
+            eprintln!("Could not save the window layout: {}", err);
+        }
+        if let Err(err) = self.app_settings.lock().unwrap().save(SETTINGS_PATH) {
+            eprintln!("Could not save the settings: {}", err);
+        }
+    }
+}
+
+/// `[color]` from a theme as an egui color.
+fn theme_color(color: [f32; 3]) -> Color32 {
+    let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+    Color32::from_rgb(r, g, b)
+}
+
+/// A Linear/Exponential picker for one envelope stage, returning if it was changed.
+fn envelope_curve_picker(ui: &mut Ui, label: &str, curve: &mut EnvelopeCurveType) -> bool {
+    let name = |curve: EnvelopeCurveType| match curve {
+        EnvelopeCurveType::Linear => "Linear",
+        EnvelopeCurveType::Exponential => "Exponential"
+    };
+    let mut changed = false;
+    ui.label(label);
+    egui::ComboBox::from_id_salt(label)
+        .selected_text(name(*curve))
+        .show_ui(ui, |ui| {
+            for option in [EnvelopeCurveType::Linear, EnvelopeCurveType::Exponential] {
+                changed |= ui.selectable_value(curve, option, name(option)).changed();
+            }
+        });
+    changed
+}
+
+/// A None/Auto/Manual picker for one of xsynth's thread counts.
+fn thread_count_picker(ui: &mut Ui, label: &str, threads: &mut ThreadCount) -> egui::Response {
+    ui.horizontal(|ui| {
+        ui.label(RichText::new(label).size(15.0));
+        let name = match threads {
+            ThreadCount::None => "None",
+            ThreadCount::Auto => "Auto",
+            ThreadCount::Manual(_) => "Manual"
+        };
+        egui::ComboBox::from_id_salt(label)
+            .selected_text(name)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(threads, ThreadCount::None, "None");
+                ui.selectable_value(threads, ThreadCount::Auto, "Auto");
+                if ui.selectable_label(matches!(threads, ThreadCount::Manual(_)), "Manual").clicked()
+                    && !matches!(threads, ThreadCount::Manual(_)) {
+                    *threads = ThreadCount::Manual(2);
+                }
+            });
+        if let ThreadCount::Manual(count) = threads {
+            ui.add(egui::DragValue::new(count).range(1..=64));
+        }
+    }).response
 }
 
 fn main() -> eframe::Result {
+    // `andromeda song.mid` opens with the song imported, which is also how file associations launch it
+    let midi_file = std::env::args_os().nth(1).map(PathBuf::from);
+    let layout = WindowLayout::load(LAYOUT_PATH);
+
+    let mut viewport = egui::ViewportBuilder::default().with_maximized(layout.maximized);
+    if let Some(size) = layout.inner_size {
+        viewport = viewport.with_inner_size(size);
+    }
+    if let Some(position) = layout.position {
+        viewport = viewport.with_position(position);
+    }
     let native_options = eframe::NativeOptions {
         renderer: eframe::Renderer::Glow,
+        viewport,
         ..Default::default()
     };
 
     eframe::run_native("Andromeda", native_options, Box::new(|cc| {
         egui_extras::install_image_loaders(&cc.egui_ctx);
-        Ok(Box::new(MainWindow::new(cc)))
+        Ok(Box::new(MainWindow::new(cc, layout, midi_file)))
     }))
-}
\ No newline at end of file
+}
+/// The size of a soundfont being loaded and how long it's been loading, like `(312.4 MB, 3 s)`.
+fn soundfont_load_progress(load: &SoundfontLoad) -> String {
+    let elapsed = load.started.elapsed().as_secs();
+    match load.file_size {
+        Some(size) => format!("({:.1} MB, {} s)", size as f64 / 1_000_000.0, elapsed),
+        None => format!("({} s)", elapsed)
+    }
+}
+
+/// Draws left and right level bars from -48 dB to 0 dB.
+fn level_meter(ui: &mut Ui, levels: [f32; 2]) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(vec2(80.0, 12.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 1.0, Color32::from_gray(30));
+    for (i, level) in levels.iter().enumerate() {
+        let db = 20.0 * level.max(1e-6).log10();
+        let fill = ((db + 48.0) / 48.0).clamp(0.0, 1.0);
+        let color = if db >= 0.0 {
+            Color32::RED
+        } else if db >= -6.0 {
+            Color32::YELLOW
+        } else {
+            Color32::GREEN
+        };
+        let top = rect.min.y + 1.0 + i as f32 * 5.5;
+        let bar = Rect::from_min_size(egui::pos2(rect.min.x, top), vec2(rect.width() * fill, 4.5));
+        painter.rect_filled(bar, 0.0, color);
+    }
+    response
+}
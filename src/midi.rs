@@ -1,4 +1,6 @@
 pub mod events;
 pub mod notes;
 pub mod io;
-pub mod util;
\ No newline at end of file
+pub mod util;
+pub mod time_signatures;
+pub mod scales;
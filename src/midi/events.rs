@@ -7,10 +7,91 @@ pub struct TempoEvent {
 impl TempoEvent {
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignatureEvent {
+    pub time: u64,
+    pub numerator: u8,
+    /// the note value of a beat, 4 for quarter notes
+    pub denominator: u8,
+}
+
+/// A named position in the song, from a marker meta event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Marker {
+    pub tick: u64,
+    pub name: String,
+}
+
+/// The mod wheel, played as vibrato.
+pub const CC_MODULATION: u8 = 1;
+/// Sets the selected RPN, the coarse part. For the pitch bend range it's the semitones.
+pub const CC_DATA_ENTRY: u8 = 6;
+/// The channel's volume. The synth multiplies it with the expression, like GM does.
+pub const CC_VOLUME: u8 = 7;
+/// Where the channel sits between left and right, 64 is the center. The synth pans each channel itself,
+/// and this is the only pan there is: the track list has none of its own, so nothing is applied on top of it.
+/// The mono output is summed after panning.
+pub const CC_PAN: u8 = 10;
+/// A fraction of the channel volume, for swells within a part without touching its mix level.
+pub const CC_EXPRESSION: u8 = 11;
+/// The fine part of `CC_DATA_ENTRY`. For the pitch bend range it's the cents.
+pub const CC_DATA_ENTRY_LSB: u8 = 38;
+/// The damper pedal, values from 64 hold the released notes until it's lifted.
+pub const CC_SUSTAIN: u8 = 64;
+/// Together with `CC_RPN_MSB` selects the RPN the data entries set.
+pub const CC_RPN_LSB: u8 = 100;
+pub const CC_RPN_MSB: u8 = 101;
+/// Puts the channel's controllers back to their defaults when sent with value 0, volume and expression included.
+pub const CC_RESET_ALL_CONTROLLERS: u8 = 121;
+
+/// The RPN of the pitch bend range as its MSB and LSB, the data entry MSB sets semitones and LSB cents.
+pub const RPN_BEND_RANGE: (u8, u8) = (0, 0);
+/// No RPN selected, data entries go nowhere.
+pub const RPN_NULL: (u8, u8) = (0x7F, 0x7F);
+
+/// A control change or pitch bend on a channel. Kept from imported MIDIs so they play back and save along with the notes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControlEvent {
+    pub tick: u64,
+    pub track: u16,
+    pub channel: u8,
+    pub kind: ControlKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ControlKind {
+    Controller { number: u8, value: u8 },
+    /// from 0 to 16383, 8192 is no bend
+    PitchBend(u16),
+}
+
+impl ControlKind {
+    /// If the synth is sent this during playback. The others are only kept for saving.
+    pub fn is_played(&self) -> bool {
+        matches!(self, ControlKind::PitchBend(_) | ControlKind::Controller {
+            number: CC_MODULATION | CC_DATA_ENTRY | CC_VOLUME | CC_PAN | CC_EXPRESSION | CC_DATA_ENTRY_LSB
+                | CC_SUSTAIN | CC_RPN_LSB | CC_RPN_MSB | CC_RESET_ALL_CONTROLLERS,
+            ..
+        })
+    }
+
+    /// The event's status without the channel and its two data bytes.
+    pub fn bytes(&self) -> (u8, [u8; 2]) {
+        match *self {
+            ControlKind::Controller { number, value } => (0xB0, [number, value]),
+            ControlKind::PitchBend(value) => (0xE0, [(value & 0x7F) as u8, ((value >> 7) & 0x7F) as u8])
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum MIDIEventType {
     NoteOff,
-    NoteOn
+    NoteOn,
+    /// `data` is the channel, controller and value
+    ControlChange,
+    /// `data` is the channel then the low and high 7 bits of the bend
+    PitchBend
 }
 
 #[derive(Debug, Clone)]
@@ -18,4 +99,17 @@ pub struct MIDIEvent {
     pub time: f32,
     pub event_type: MIDIEventType,
     pub data: Vec<u8>
-}
\ No newline at end of file
+}
+
+impl MIDIEvent {
+    /// The event as a MIDI message. Synth channels past 16 go out on the MIDI channel they stand for, see `synth_channel`.
+    pub fn message(&self) -> [u8; 3] {
+        let channel = self.data[0] & 0x0F;
+        match self.event_type {
+            MIDIEventType::NoteOn => [0x90 | channel, self.data[1], self.data[2]],
+            MIDIEventType::NoteOff => [0x80 | channel, self.data[1], 0],
+            MIDIEventType::ControlChange => [0xB0 | channel, self.data[1], self.data[2]],
+            MIDIEventType::PitchBend => [0xE0 | channel, self.data[1], self.data[2]]
+        }
+    }
+}
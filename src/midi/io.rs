@@ -1,4 +1,10 @@
 pub mod buffered_byte_reader;
 pub mod byte_reader;
 pub mod midi_track_parser;
-pub mod midi_file;
\ No newline at end of file
+pub mod midi_file;
+pub mod midi_loader;
+pub mod midi_error;
+pub mod midi_input;
+pub mod midi_writer;
+pub mod midi_output;
+pub mod project_data;
@@ -2,6 +2,8 @@ use std::{fs::File, io::Read};
 use std::io::{self, Seek};
 use std::sync::{Arc, Mutex};
 
+use super::midi_error::MIDIError;
+
 pub struct BufferedByteReader {
     pub file_stream: Arc<Mutex<File>>,
     start: usize,
@@ -14,7 +16,7 @@ pub struct BufferedByteReader {
 }
 
 impl BufferedByteReader {
-    pub fn new(stream: Arc<Mutex<File>>, start: usize, len: usize, buf_size: usize) -> Result<Self, ()> {
+    pub fn new(stream: Arc<Mutex<File>>, start: usize, len: usize, buf_size: usize) -> Result<Self, MIDIError> {
         let mut buffer_length = buf_size;
         if buffer_length > len { buffer_length = len; }
         
@@ -29,27 +31,26 @@ impl BufferedByteReader {
             buf: vec![0; buffer_length]
         };
         
-        bbr.update_buffer().unwrap();
+        bbr.update_buffer()?;
 
         Ok(bbr)
     }
 
-    fn update_buffer(&mut self) -> Result<(),&str> {
+    fn update_buffer(&mut self) -> Result<(), MIDIError> {
         let mut read = self.buf_size as usize;
 
         if (self.pos + read) > (self.start + self.len) {
             read = self.start + self.len - self.pos;
         }
 
-        if read == 0 && self.buf_size != 0 {
-            panic!("outside buffer");
-        }
-
         // lol
         {
             let mut strm = self.file_stream.lock().unwrap();
-            strm.seek(io::SeekFrom::Start(self.pos as u64)).unwrap();
-            strm.read(&mut self.buf).unwrap();
+            strm.seek(io::SeekFrom::Start(self.pos as u64))?;
+            strm.read_exact(&mut self.buf[..read]).map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => MIDIError::UnexpectedEOF { pos: self.pos },
+                _ => err.into()
+            })?;
         }
 
         self.buf_start = self.pos;
@@ -58,7 +59,7 @@ impl BufferedByteReader {
         Ok(())
     }
 
-    pub fn seek(&mut self, offset: isize, origin: i32) -> Result<(), &str> {
+    pub fn seek(&mut self, offset: isize, origin: i32) -> Result<(), MIDIError> {
         let mut real_offs: isize = offset;
         if origin == 0 {
             real_offs += self.start as isize;
@@ -66,11 +67,8 @@ impl BufferedByteReader {
             real_offs += self.pos as isize;
         }
 
-        if real_offs < self.start as isize {
-            panic!("seek before start")
-        }
-        if real_offs > (self.start + self.len) as isize {
-            panic!("seek past end")
+        if real_offs < self.start as isize || real_offs > (self.start + self.len) as isize {
+            return Err(MIDIError::UnexpectedEOF { pos: self.start + self.len });
         }
 
         self.pos = real_offs as usize;
@@ -80,23 +78,26 @@ impl BufferedByteReader {
             return Ok(())
         }
 
-        self.update_buffer().unwrap();
+        self.update_buffer()?;
 
         Ok(())
     }
 
-    pub fn read(&mut self, dst: &mut [u8], size: usize) -> Result<(), &str> {
+    pub fn read(&mut self, dst: &mut [u8], size: usize) -> Result<(), MIDIError> {
         if self.pos + size > self.start + self.len {
-            panic!("read past end | requested read: {}, buff size: {}", self.pos + size, self.start + self.len);
-            //return Err(format!("read past end | requested read: {}, buff size: {}", self.pos + size, self.start + self.len))
+            return Err(MIDIError::UnexpectedEOF { pos: self.start + self.len });
         }
-        if size > self.buf_size as usize {
-            //panic!("unimplemented; read size larger than buffer size");
-            return Err("unimplemented; read size larger than buffer size")
+        if size > self.buf_size {
+            // large reads (long meta events) skip the buffer and go straight to the file
+            let mut strm = self.file_stream.lock().unwrap();
+            strm.seek(io::SeekFrom::Start(self.pos as u64))?;
+            strm.read_exact(&mut dst[..size])?;
+            drop(strm);
+            return self.seek(size as isize, 1);
         }
 
-        if self.buf_start + self.buf_pos + size > self.buf_start + self.buf_size as usize {
-            self.update_buffer().unwrap();
+        if self.buf_pos + size > self.buf_size {
+            self.update_buffer()?;
         }
 
         // skull emoji
@@ -107,14 +108,18 @@ impl BufferedByteReader {
         Ok(())
     }
 
-    pub fn read_byte(&mut self) -> Result<u8,()> {
+    /// If every byte of the chunk has been read.
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.start + self.len
+    }
+
+    pub fn read_byte(&mut self) -> Result<u8, MIDIError> {
         let mut ret: [u8; 1] = [0];
-        self.read(&mut ret, 1).unwrap();
+        self.read(&mut ret, 1)?;
         Ok(ret[0])
     }
 
-    pub fn skip_bytes(&mut self, size: usize) -> Result<(),()> {
-        self.seek(size as isize, 1).unwrap();
-        Ok(())
+    pub fn skip_bytes(&mut self, size: usize) -> Result<(), MIDIError> {
+        self.seek(size as isize, 1)
     }
 }
\ No newline at end of file
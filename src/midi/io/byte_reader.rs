@@ -1,14 +1,31 @@
-use std::{fs::File, io::Read};
+use std::{fs::File, io::{Read, Seek}};
 use std::io;
 
-pub fn read_u32(stream: &mut File) -> io::Result<u32> {
+use super::midi_error::MIDIError;
+
+fn read_exact(stream: &mut File, buf: &mut [u8]) -> Result<(), MIDIError> {
+    let pos = stream.stream_position()? as usize;
+    stream.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => MIDIError::UnexpectedEOF { pos },
+        _ => err.into()
+    })
+}
+
+pub fn read_u32(stream: &mut File) -> Result<u32, MIDIError> {
     let mut buf: [u8; 4] = [0; 4];
-    stream.read_exact(&mut buf[..])?;
+    read_exact(stream, &mut buf[..])?;
     Ok(u32::from_be_bytes(buf))
 }
 
-pub fn read_u16(stream: &mut File) -> io::Result<u16> {
+/// Reads a little endian u32, like the sizes in RIFF chunk headers.
+pub fn read_u32_le(stream: &mut File) -> Result<u32, MIDIError> {
+    let mut buf: [u8; 4] = [0; 4];
+    read_exact(stream, &mut buf[..])?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub fn read_u16(stream: &mut File) -> Result<u16, MIDIError> {
     let mut buf: [u8; 2] = [0, 0];
-    stream.read_exact(&mut buf[..])?;
+    read_exact(stream, &mut buf[..])?;
     Ok(u16::from_be_bytes(buf))
 }
\ No newline at end of file
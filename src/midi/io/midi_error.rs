@@ -0,0 +1,45 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while reading a MIDI file.
+#[derive(Debug)]
+pub enum MIDIError {
+    IO(io::Error),
+    /// the file doesn't start with `MThd`
+    BadHeaderMagic(u32),
+    /// a RIFF file that isn't an RMID, or an RMID without a `data` chunk holding the MIDI
+    BadRMID,
+    BadHeaderLength(u32),
+    UnsupportedFormat(u16),
+    /// a track chunk doesn't start with `MTrk`
+    BadTrackMagic { track: usize, magic: u32 },
+    /// the file or a track chunk ended in the middle of an event
+    UnexpectedEOF { pos: usize },
+    UnknownStatus { status: u8, pos: usize },
+    /// the worker thread loading the file stopped before finishing
+    Interrupted,
+}
+
+impl fmt::Display for MIDIError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MIDIError::IO(err) => write!(f, "Could not read the file: {}", err),
+            MIDIError::BadHeaderMagic(magic) => write!(f, "Not a MIDI file (expected an MThd header, found 0x{:08X})", magic),
+            MIDIError::BadRMID => write!(f, "Not an RMID file, or it doesn't contain any MIDI data"),
+            MIDIError::BadHeaderLength(len) => write!(f, "Invalid MIDI header length {} (expected 6)", len),
+            MIDIError::UnsupportedFormat(fmt) => write!(f, "MIDI format {} is not supported", fmt),
+            MIDIError::BadTrackMagic { track, magic } => write!(f, "Track {} has an invalid header (expected MTrk, found 0x{:08X})", track, magic),
+            MIDIError::UnexpectedEOF { pos } => write!(f, "The file ended unexpectedly at byte {}, it may be truncated", pos),
+            MIDIError::UnknownStatus { status, pos } => write!(f, "Unknown event status byte 0x{:02X} at byte {}", status, pos),
+            MIDIError::Interrupted => write!(f, "The import was interrupted")
+        }
+    }
+}
+
+impl std::error::Error for MIDIError {}
+
+impl From<io::Error> for MIDIError {
+    fn from(err: io::Error) -> Self {
+        MIDIError::IO(err)
+    }
+}
@@ -1,14 +1,66 @@
 use std::fs::File;
 use std::io::Seek;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use rayon::prelude::*;
 
 use super::byte_reader;
+use super::midi_error::MIDIError;
 use super::super::events::*;
 use super::super::util::iter_ext::{merge_midi_events, merge_notes, merge_tempo_evs};
 use crate::Note;
 
-use super::midi_track_parser::{MIDITrack};
+use super::midi_track_parser::{MIDITrack, NotePairing};
+use super::project_data::ProjectData;
+
+/// `RIFF`, `RMID` and `data`, the magics leading up to the MIDI file inside an RMID.
+const RIFF_MAGIC: u32 = 0x52494646;
+const RMID_MAGIC: u32 = 0x524D4944;
+const RMID_DATA_MAGIC: u32 = 0x64617461;
+
+/// How many track events are parsed between progress updates.
+const PROGRESS_UPDATE_INTERVAL: u64 = 4096;
+
+/// Shared progress of a `MIDIFile` load, safe to read from another thread while parsing.
+/// Both parse passes read every track once, so the load is done after reading the track data twice.
+#[derive(Default)]
+pub struct MIDIFileProgress {
+    read_bytes: AtomicUsize,
+    total_bytes: AtomicUsize,
+}
+
+impl MIDIFileProgress {
+    /// The fraction of the load that's done, from 0.0 to 1.0.
+    pub fn fraction(&self) -> f32 {
+        let total = self.total_bytes.load(Ordering::Relaxed) * 2;
+        if total == 0 { return 0.0; }
+        (self.read_bytes.load(Ordering::Relaxed) as f32 / total as f32).min(1.0)
+    }
+
+    fn add_read_bytes(&self, bytes: usize) {
+        self.read_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Runs `parse` until the track ends, reporting the bytes it read to `progress` every so often.
+fn parse_track_with_progress(
+    track: &mut MIDITrack,
+    progress: &MIDIFileProgress,
+    mut parse: impl FnMut(&mut MIDITrack) -> Result<(), MIDIError>
+) -> Result<(), MIDIError> {
+    let mut last_pos = track.rdr.pos;
+    let mut ev_count = 0u64;
+    while !track.ended {
+        parse(track)?;
+        ev_count += 1;
+        if ev_count.is_multiple_of(PROGRESS_UPDATE_INTERVAL) {
+            progress.add_read_bytes(track.rdr.pos - last_pos);
+            last_pos = track.rdr.pos;
+        }
+    }
+    progress.add_read_bytes(track.rdr.pos.saturating_sub(last_pos));
+    Ok(())
+}
 
 pub struct TrackPointer {
     pub start: u64,
@@ -23,14 +75,20 @@ pub struct MIDIFile {
     pub note_counts: Vec<u64>,
 
     pub key_range: [u8; 2],
+    /// the editor state saved with the file, read in the first pass
+    pub project_data: Option<ProjectData>,
+    pub progress: Arc<MIDIFileProgress>,
 
     tempo_evs: Vec<TempoEvent>
 }
 
 impl MIDIFile {
-    pub fn new(path: String, tick_based_parsing: bool) -> Result<Self,()> {
+    /// Opens and runs the first parse pass over the MIDI file at `[path]`.
+    /// `[progress]` is updated as the tracks are read, here and in `get_sequences`.
+    /// `[pairing]` decides which note a note off ends when the same key is held more than once.
+    pub fn new(path: String, tick_based_parsing: bool, pairing: NotePairing, progress: Arc<MIDIFileProgress>) -> Result<Self, MIDIError> {
         let file_stream = Arc::new(Mutex::new(
-            File::open(path).unwrap()
+            File::open(path)?
         ));
 
         let mut s = Self {
@@ -41,38 +99,43 @@ impl MIDIFile {
             note_counts: Vec::new(),
 
             tempo_evs: Vec::new(),
-            key_range: [0, 127]
+            key_range: [0, 127],
+            project_data: None,
+            progress
         };
 
         {
             let mut fs = file_stream.lock().unwrap();
-            s.parse_header(&mut fs).unwrap();
-            s.populate_track_locations(&mut fs).unwrap();
+            s.parse_header(&mut fs)?;
+            s.populate_track_locations(&mut fs)?;
         }
 
+        let total_bytes = s.track_locations.iter().map(|loc| loc.len as usize).sum();
+        s.progress.total_bytes.store(total_bytes, Ordering::Relaxed);
+
         let track_count = s.trk_count;
         for i in 0usize..(track_count as usize) {
-            s.tracks.push(MIDITrack::new(i, s.ppq, Arc::clone(&file_stream), &s.track_locations[i], tick_based_parsing).unwrap());
+            s.tracks.push(MIDITrack::new(i, s.ppq, Arc::clone(&file_stream), &s.track_locations[i], tick_based_parsing, pairing)?);
         }
 
         println!("----- Parse pass 1 -----");
-        let tempo_evs_seq: Vec<Vec<TempoEvent>>;
-
-        (s.note_counts, tempo_evs_seq) = s.tracks.par_iter_mut().enumerate().map(|(i, track)| {
-            while !track.ended {
-                track.parse_ev().unwrap();
-            }
+        let progress = Arc::clone(&s.progress);
+        let parsed = s.tracks.par_iter_mut().enumerate().map(|(i, track)| {
+            parse_track_with_progress(track, &progress, |track| track.parse_ev())?;
             println!("track {} of {} parsed", i, track_count);
-            track.prep_for_pass_two().unwrap();
-            (track.note_count, std::mem::take(&mut track.tempo_evs))
-        }).collect();
+            track.prep_for_pass_two()?;
+            Ok((track.note_count, std::mem::take(&mut track.tempo_evs)))
+        }).collect::<Result<Vec<_>, MIDIError>>()?;
+        let tempo_evs_seq: Vec<Vec<TempoEvent>>;
+        (s.note_counts, tempo_evs_seq) = parsed.into_iter().unzip();
 
         s.key_range = (
-            s.tracks.iter().map(|track| track.key_range[0]).min().unwrap(),
-            s.tracks.iter().map(|track| track.key_range[1]).max().unwrap()
+            s.tracks.iter().map(|track| track.key_range[0]).min().unwrap_or(0),
+            s.tracks.iter().map(|track| track.key_range[1]).max().unwrap_or(127)
         ).into();
 
         s.tempo_evs = merge_tempo_evs(tempo_evs_seq);
+        s.project_data = s.tracks.iter_mut().find_map(|track| track.project_data.take());
 
         Ok(s)
     }
@@ -81,21 +144,38 @@ impl MIDIFile {
     pub fn get_sequences(self,
         midi_evs: &mut Vec<MIDIEvent>,
         notes_out: &mut Vec<Vec<Note>>,
-        tempo_evs: &mut Vec<TempoEvent>
-        ) -> () {
+        tempo_evs: &mut Vec<TempoEvent>,
+        time_sig_evs: &mut Vec<TimeSignatureEvent>,
+        markers: &mut Vec<Marker>,
+        control_evs: &mut Vec<ControlEvent>
+        ) -> Result<(), MIDIError> {
         println!("----- Getting events (Parse pass 2) -----");
-        let (evs, (mut notes, t_evs)): (Vec<Vec<MIDIEvent>>, (Vec<Vec<Vec<Note>>>, Vec<Vec<TempoEvent>>)) = self.tracks.into_par_iter().enumerate().map(|(i, mut track)| {
-            while !track.ended {
-                track.parse_pass_two(&self.tempo_evs).unwrap();
-            }
+        // there are only ever a few of these, they're sorted where they're used
+        let time_sig_evs_out = time_sig_evs;
+        let time_sig_evs = Mutex::new(Vec::new());
+        let markers_out = markers;
+        let markers = Mutex::new(Vec::new());
+        let control_evs_out = control_evs;
+        let control_evs = Mutex::new(Vec::new());
+        let parsed = self.tracks.into_par_iter().enumerate().map(|(i, mut track)| {
+            parse_track_with_progress(&mut track, &self.progress, |track| track.parse_pass_two(&self.tempo_evs))?;
             println!("track {} of {} parsed", i, &self.trk_count);
-            (track.midi_evs,
+            time_sig_evs.lock().unwrap().append(&mut track.time_sig_evs);
+            markers.lock().unwrap().append(&mut track.markers);
+            control_evs.lock().unwrap().append(&mut track.control_evs);
+            Ok((track.midi_evs,
              (track.notes,
-              track.tempo_evs))
+              track.tempo_evs)))
 
-        }).collect();
+        }).collect::<Result<Vec<_>, MIDIError>>()?;
+        let (evs, (mut notes, t_evs)): (Vec<Vec<MIDIEvent>>, (Vec<Vec<Vec<Note>>>, Vec<Vec<TempoEvent>>)) = parsed.into_iter().unzip();
         println!("merging events...");
         (*tempo_evs) = merge_tempo_evs(t_evs);
+        (*time_sig_evs_out) = time_sig_evs.into_inner().unwrap();
+        (*markers_out) = markers.into_inner().unwrap();
+        // each track's are in order already, a stable sort keeps them that way within a tick
+        (*control_evs_out) = control_evs.into_inner().unwrap();
+        control_evs_out.sort_by_key(|ev| ev.tick);
         println!("merged tempo events");
 
         let notes_per_key: Vec<Vec<Vec<Note>>> = (0..256).map(|_| notes.iter_mut().map(|n| n.pop().unwrap()).collect::<Vec<_>>()).collect::<Vec<_>>();
@@ -114,25 +194,36 @@ impl MIDIFile {
         (*midi_evs, *notes_out) = 
             (merge_midi_events(evs),
             Arc::try_unwrap(merged_notes_at_keys).unwrap().into_inner().unwrap());
+
+        Ok(())
     }
 
-    fn parse_header(&mut self, stream: &mut File) -> Result<(),&str> {
+    fn parse_header(&mut self, stream: &mut File) -> Result<(), MIDIError> {
         // assuming header length in total is 14
         // MThd header
-        let mthd: u32 = byte_reader::read_u32(stream).unwrap();
-        assert_eq!(mthd, 0x4D546864);
+        let mut mthd: u32 = byte_reader::read_u32(stream)?;
+        if mthd == RIFF_MAGIC {
+            Self::skip_to_rmid_data(stream)?;
+            mthd = byte_reader::read_u32(stream)?;
+        }
+        if mthd != 0x4D546864 {
+            return Err(MIDIError::BadHeaderMagic(mthd));
+        }
 
         // length
-        let h_len: u32 = byte_reader::read_u32(stream).unwrap();
-        assert_eq!(h_len, 6);
+        let h_len: u32 = byte_reader::read_u32(stream)?;
+        if h_len != 6 {
+            return Err(MIDIError::BadHeaderLength(h_len));
+        }
         // format lol
-        let m_fmt: u16 = byte_reader::read_u16(stream).unwrap();
+        let m_fmt: u16 = byte_reader::read_u16(stream)?;
         if m_fmt == 2 {
-            return Err("please stop using format 2")
+            // please stop using format 2
+            return Err(MIDIError::UnsupportedFormat(m_fmt));
         }
         // track count (i think)
-        let m_trk_count: u16 = byte_reader::read_u16(stream).unwrap();
-        let m_ppq: u16 = byte_reader::read_u16(stream).unwrap();
+        let m_trk_count: u16 = byte_reader::read_u16(stream)?;
+        let m_ppq: u16 = byte_reader::read_u16(stream)?;
         
         self.trk_count = m_trk_count;
         self.ppq = m_ppq;
@@ -140,15 +231,43 @@ impl MIDIFile {
         Ok(())
     }
 
-    fn populate_track_locations(&mut self, stream: &mut File) -> Result<(), &str> {
-        for _ in 0..self.trk_count {
-            let mtrk: u32 = byte_reader::read_u32(stream).unwrap();
-            assert_eq!(mtrk, 0x4D54726B);
+    /// Moves `[stream]`, just past the `RIFF` magic of an RMID, to the start of the MIDI file in its `data` chunk.
+    /// The other chunks, like an embedded DLS or soundfont, are skipped. Track locations stay absolute file offsets.
+    fn skip_to_rmid_data(stream: &mut File) -> Result<(), MIDIError> {
+        let _riff_len = byte_reader::read_u32_le(stream)?;
+        if byte_reader::read_u32(stream)? != RMID_MAGIC {
+            return Err(MIDIError::BadRMID);
+        }
+        loop {
+            let chunk = match byte_reader::read_u32(stream) {
+                Ok(chunk) => chunk,
+                Err(MIDIError::UnexpectedEOF { .. }) => return Err(MIDIError::BadRMID),
+                Err(err) => return Err(err)
+            };
+            let len = byte_reader::read_u32_le(stream)?;
+            if chunk == RMID_DATA_MAGIC {
+                return Ok(());
+            }
+            // RIFF chunks are padded to an even length
+            stream.seek_relative(len as i64 + (len & 1) as i64)?;
+        }
+    }
+
+    fn populate_track_locations(&mut self, stream: &mut File) -> Result<(), MIDIError> {
+        let file_len = stream.metadata()?.len();
+        for track in 0..self.trk_count as usize {
+            let mtrk: u32 = byte_reader::read_u32(stream)?;
+            if mtrk != 0x4D54726B {
+                return Err(MIDIError::BadTrackMagic { track, magic: mtrk });
+            }
             
-            let t_len: u32 = byte_reader::read_u32(stream).unwrap();
-            let pos: u64 = stream.stream_position().unwrap();
+            let t_len: u32 = byte_reader::read_u32(stream)?;
+            let pos: u64 = stream.stream_position()?;
+            if pos + t_len as u64 > file_len {
+                return Err(MIDIError::UnexpectedEOF { pos: file_len as usize });
+            }
 
-            stream.seek_relative(t_len as i64).unwrap();
+            stream.seek_relative(t_len as i64)?;
 
             self.track_locations.push(TrackPointer {
                 start: pos,
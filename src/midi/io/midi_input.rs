@@ -0,0 +1,119 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::time::Instant;
+
+use midir::{Ignore, MidiInput, MidiInputConnection};
+
+use crate::audio::prerenderer::SynthHandle;
+
+const CLIENT_NAME: &str = "Andromeda";
+
+/// A note on or off received from the controller, for recording.
+pub struct LiveNote {
+    pub key: u8,
+    /// 0 for a note off
+    pub velocity: u8,
+    pub received: Instant,
+}
+
+/// Plays the notes coming in from a hardware MIDI controller.
+pub struct MIDIInput {
+    connection: Option<MidiInputConnection<()>>,
+    synth: Option<SynthHandle>,
+    received: Option<Receiver<LiveNote>>,
+    pub port_name: Option<String>,
+    /// the channel incoming notes are played on, whatever channel they were sent on
+    channel: Arc<AtomicU8>,
+}
+
+impl Default for MIDIInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MIDIInput {
+    pub fn new() -> Self {
+        Self {
+            connection: None,
+            synth: None,
+            received: None,
+            port_name: None,
+            channel: Arc::new(AtomicU8::new(0)),
+        }
+    }
+
+    /// The names of the MIDI input ports that are available right now.
+    pub fn port_names() -> Vec<String> {
+        let Ok(input) = MidiInput::new(CLIENT_NAME) else { return Vec::new(); };
+        input.ports().iter()
+            .filter_map(|port| input.port_name(port).ok())
+            .collect()
+    }
+
+    /// Closes the current port, if any, and starts playing what comes in on the port named `[port_name]`.
+    pub fn connect(&mut self, port_name: &str, synth: SynthHandle) -> Result<(), Box<dyn Error>> {
+        self.disconnect();
+
+        let mut input = MidiInput::new(CLIENT_NAME)?;
+        input.ignore(Ignore::All);
+        let port = input.ports().into_iter()
+            .find(|port| input.port_name(port).is_ok_and(|name| name == port_name))
+            .ok_or_else(|| format!("The MIDI input {} isn't available anymore", port_name))?;
+
+        let channel = Arc::clone(&self.channel);
+        self.synth = Some(synth.clone());
+        let (sender, receiver) = mpsc::channel();
+        let connection = input.connect(&port, "andromeda-in", move |_, message, _| {
+            let &[status, key, vel, ..] = message else { return; };
+            let channel = channel.load(Ordering::Relaxed) as u32;
+            let velocity = match status & 0xF0 {
+                0x90 if vel > 0 => {
+                    synth.note_on(channel, key, vel);
+                    vel
+                },
+                0x80 | 0x90 => {
+                    synth.note_off(channel, key);
+                    0
+                },
+                _ => return
+            };
+            // nothing to do if the UI has stopped listening
+            let _ = sender.send(LiveNote { key, velocity, received: Instant::now() });
+        }, ()).map_err(|err| err.to_string())?;
+
+        self.connection = Some(connection);
+        self.received = Some(receiver);
+        self.port_name = Some(port_name.to_string());
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            connection.close();
+        }
+        // anything still held on the controller would never get its note off
+        if let Some(synth) = self.synth.take() {
+            synth.all_notes_off();
+        }
+        self.received = None;
+        self.port_name = None;
+    }
+
+    /// Every note on and off received since the last call.
+    pub fn poll(&self) -> Vec<LiveNote> {
+        self.received.as_ref()
+            .map(|received| received.try_iter().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.channel.load(Ordering::Relaxed)
+    }
+
+    pub fn set_channel(&self, channel: u8) {
+        self.channel.store(channel & 0x0F, Ordering::Relaxed);
+    }
+}
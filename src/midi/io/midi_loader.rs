@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+
+use crate::midi::events::{ControlEvent, Marker, TempoEvent, TimeSignatureEvent};
+use crate::midi::notes::Note;
+
+use super::midi_error::MIDIError;
+use super::midi_file::{MIDIFile, MIDIFileProgress};
+use super::midi_track_parser::NotePairing;
+use super::project_data::ProjectData;
+
+/// Moves `[tick]` from a resolution of `[from]` ticks per quarter note to `[to]`, rounded to the nearest tick.
+/// Ends are rescaled on their own rather than as start plus length, so rounding doesn't add up along a track.
+fn rescale_tick(tick: u64, from: u16, to: u16) -> u64 {
+    let from = from.max(1) as u64;
+    (tick * to as u64 + from / 2) / from
+}
+
+/// Moves `[note]` from a resolution of `[from]` ticks per quarter note to `[to]` like `rescale_tick`.
+/// A note is never rounded down to nothing, it stays at least a tick long so it can still be seen and heard.
+fn rescale_note(note: &mut Note, from: u16, to: u16) {
+    let end = rescale_tick(note.start as u64 + note.length as u64, from, to);
+    note.start = rescale_tick(note.start as u64, from, to) as u32;
+    note.length = (end as u32).saturating_sub(note.start).max(1);
+}
+
+/// Everything the editor needs out of a parsed MIDI file.
+pub struct LoadedMIDI {
+    pub ppq: u16,
+    pub notes: Vec<Vec<Note>>,
+    pub tempo_evs: Vec<TempoEvent>,
+    pub time_sig_evs: Vec<TimeSignatureEvent>,
+    pub markers: Vec<Marker>,
+    /// every control change and pitch bend, sorted by tick
+    pub controls: Vec<ControlEvent>,
+    /// the files merged in whose tempo map didn't match the first file's, theirs were dropped
+    pub tempo_conflicts: Vec<PathBuf>,
+    /// the editor state saved with the first file, `None` if it wasn't saved by this editor
+    pub project_data: Option<ProjectData>,
+}
+
+impl LoadedMIDI {
+    /// Moves the notes of every channel used on a track onto a track of their own,
+    /// numbered in track then channel order. Control changes and track list entries follow their channel's notes.
+    fn split_by_channel(&mut self) {
+        let mut split_tracks = BTreeMap::new();
+        for note in self.notes.iter().flatten() {
+            split_tracks.entry((note.track, note.channel)).or_insert_with(Vec::new).push(*note);
+        }
+        // a channel with only control changes still gets a track, so they aren't lost on save
+        for control in &self.controls {
+            split_tracks.entry((control.track, control.channel)).or_insert_with(Vec::new);
+        }
+        let new_tracks = split_tracks.keys()
+            .enumerate()
+            .map(|(track, key)| (*key, track as u16))
+            .collect::<BTreeMap<_, _>>();
+        for control in self.controls.iter_mut() {
+            control.track = new_tracks[&(control.track, control.channel)];
+        }
+        if let Some(project_data) = self.project_data.as_mut() {
+            // an entry for a whole track goes to every track it's split into
+            let grouping = project_data.grouping;
+            let voice = |track: u16, channel: u8| ((track as u32) << 8) | channel as u32;
+            project_data.tracks = new_tracks.iter()
+                .filter_map(|(&(track, channel), &new_track)| {
+                    let state = project_data.tracks.get(&grouping.group_of(voice(track, channel)))?;
+                    Some((grouping.group_of(voice(new_track, channel)), *state))
+                })
+                .collect();
+        }
+        self.notes = split_tracks.into_iter()
+            .map(|(key, mut notes)| {
+                for note in notes.iter_mut() {
+                    note.track = new_tracks[&key];
+                }
+                notes
+            })
+            .collect();
+    }
+
+    /// Rescales every tick of the file to `[ppq]` ticks per quarter note, the tempo map and time signatures included.
+    fn resample(&mut self, ppq: u16) {
+        if ppq == self.ppq { return; }
+        let scale = |tick: u64| rescale_tick(tick, self.ppq, ppq);
+        for note in self.notes.iter_mut().flatten() {
+            rescale_note(note, self.ppq, ppq);
+        }
+        for tempo in self.tempo_evs.iter_mut() {
+            tempo.time = scale(tempo.time);
+        }
+        for time_sig in self.time_sig_evs.iter_mut() {
+            time_sig.time = scale(time_sig.time);
+        }
+        for marker in self.markers.iter_mut() {
+            marker.tick = scale(marker.tick);
+        }
+        for control in self.controls.iter_mut() {
+            control.tick = scale(control.tick);
+        }
+        if let Some(project_data) = self.project_data.as_mut() {
+            project_data.pickup = scale(project_data.pickup);
+        }
+        self.ppq = ppq;
+    }
+
+    /// Merges `[other]` into this file. Its notes and markers are rescaled to this file's PPQ and its tracks
+    /// are moved after this file's, so they stay separate. The tempo map and time signatures of this file are kept.
+    fn merge(&mut self, path: &Path, other: LoadedMIDI) {
+        let scale = |tick: u64| rescale_tick(tick, other.ppq, self.ppq);
+        let track_offset = self.notes.iter().flatten().map(|n| n.track + 1)
+            .chain(self.controls.iter().map(|c| c.track + 1))
+            .max()
+            .unwrap_or(0);
+
+        let tempo_matches = self.tempo_evs.len() == other.tempo_evs.len()
+            && self.tempo_evs.iter().zip(other.tempo_evs.iter())
+                .all(|(a, b)| a.time == scale(b.time) && (a.tempo - b.tempo).abs() < 0.01);
+        if !tempo_matches {
+            self.tempo_conflicts.push(path.to_path_buf());
+        }
+
+        for mut track in other.notes {
+            for note in track.iter_mut() {
+                rescale_note(note, other.ppq, self.ppq);
+                note.track += track_offset;
+            }
+            self.notes.push(track);
+        }
+
+        self.markers.extend(other.markers.into_iter().map(|marker| Marker { tick: scale(marker.tick), ..marker }));
+        self.markers.sort_by_key(|marker| marker.tick);
+        self.controls.extend(other.controls.into_iter().map(|control| ControlEvent {
+            tick: scale(control.tick),
+            track: control.track + track_offset,
+            ..control
+        }));
+        self.controls.sort_by_key(|control| control.tick);
+    }
+}
+
+/// Parses MIDI files on a worker thread so huge files don't freeze the UI.
+/// When given more than one file they're parsed one after another and merged into the first.
+pub struct MIDILoader {
+    pub paths: Vec<PathBuf>,
+    /// one per file, in the same order as `paths`
+    progress: Vec<Arc<MIDIFileProgress>>,
+    receiver: Receiver<Result<LoadedMIDI, (PathBuf, MIDIError)>>,
+}
+
+impl MIDILoader {
+    /// `[split_by_channel]` - if each channel of a track should get a track of its own
+    /// `[resample_ppq]` - the PPQ to rescale every file to, or `None` to keep the first file's
+    pub fn start(paths: Vec<PathBuf>, pairing: NotePairing, split_by_channel: bool, resample_ppq: Option<u16>) -> Self {
+        let progress = paths.iter().map(|_| Arc::new(MIDIFileProgress::default())).collect::<Vec<_>>();
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_paths = paths.clone();
+        let thread_progress = progress.clone();
+        std::thread::spawn(move || {
+            let load = |path: &Path, progress: Arc<MIDIFileProgress>| -> Result<LoadedMIDI, MIDIError> {
+                let mut midi = MIDIFile::new(path.to_string_lossy().to_string(), true, pairing, progress)?;
+
+                let ppq = midi.ppq;
+                // the events played back are built from the notes, these aren't needed
+                let mut midi_evs = Vec::new();
+                let mut notes = Vec::new();
+                let mut tempo_evs = Vec::new();
+                let mut time_sig_evs = Vec::new();
+                let mut markers = Vec::new();
+                let mut controls = Vec::new();
+                let project_data = midi.project_data.take();
+                midi.get_sequences(&mut midi_evs, &mut notes, &mut tempo_evs, &mut time_sig_evs, &mut markers, &mut controls)?;
+
+                Ok(LoadedMIDI {
+                    ppq,
+                    notes,
+                    tempo_evs,
+                    time_sig_evs,
+                    markers,
+                    controls,
+                    tempo_conflicts: Vec::new(),
+                    project_data
+                })
+            };
+
+            let mut merged: Option<LoadedMIDI> = None;
+            for (path, progress) in thread_paths.iter().zip(thread_progress) {
+                let mut loaded = match load(path, progress) {
+                    Ok(loaded) => loaded,
+                    Err(err) => {
+                        // the receiver is gone if the load was abandoned, nothing to do then
+                        let _ = sender.send(Err((path.clone(), err)));
+                        return;
+                    }
+                };
+                if split_by_channel {
+                    loaded.split_by_channel();
+                }
+                if let Some(ppq) = resample_ppq {
+                    loaded.resample(ppq);
+                }
+                match merged.as_mut() {
+                    Some(merged) => merged.merge(path, loaded),
+                    None => merged = Some(loaded)
+                }
+            }
+
+            if let Some(merged) = merged {
+                let _ = sender.send(Ok(merged));
+            }
+        });
+
+        Self {
+            paths,
+            progress,
+            receiver
+        }
+    }
+
+    /// The name of the file being imported, or of the first one followed by how many others there are.
+    pub fn name(&self) -> String {
+        let file_name = |path: &PathBuf| path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        match self.paths.as_slice() {
+            [] => String::new(),
+            [path] => file_name(path),
+            [path, rest @ ..] => format!("{} and {} more", file_name(path), rest.len())
+        }
+    }
+
+    /// The fraction of the files that's been parsed, from 0.0 to 1.0.
+    pub fn progress(&self) -> f32 {
+        if self.progress.is_empty() { return 0.0; }
+        self.progress.iter().map(|progress| progress.fraction()).sum::<f32>() / self.progress.len() as f32
+    }
+
+    /// Returns the parsed and merged files once the worker is done, or the file that failed to parse and why.
+    pub fn poll(&self) -> Option<Result<LoadedMIDI, (PathBuf, MIDIError)>> {
+        match self.receiver.try_recv() {
+            Ok(loaded) => Some(loaded),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err((self.paths.first().cloned().unwrap_or_default(), MIDIError::Interrupted)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rescale_note;
+    use crate::midi::notes::Note;
+
+    fn note(start: u32, length: u32) -> Note {
+        Note { start, length, channel: 0, track: 0, key: 60, velocity: 100 }
+    }
+
+    #[test]
+    fn rescaled_notes_keep_their_ends() {
+        let mut a = note(480, 240);
+        rescale_note(&mut a, 480, 960);
+        assert_eq!((a.start, a.length), (960, 480));
+    }
+
+    #[test]
+    fn grace_note_isnt_rounded_away() {
+        let mut grace = note(960, 5);
+        rescale_note(&mut grace, 960, 96);
+        assert_eq!((grace.start, grace.length), (96, 1));
+    }
+}
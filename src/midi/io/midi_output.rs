@@ -0,0 +1,158 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use midir::{MidiOutput, MidiOutputConnection};
+
+use crate::midi::events::{MIDIEvent, CC_RESET_ALL_CONTROLLERS, CC_SUSTAIN};
+
+const CLIENT_NAME: &str = "Andromeda";
+/// Turns off every note on the channel, released ones held by the pedal included once it's lifted.
+const CC_ALL_NOTES_OFF: u8 = 123;
+/// Silences the channel at once, without release.
+const CC_ALL_SOUND_OFF: u8 = 120;
+/// The longest the playback thread sleeps at once, so stopping it doesn't have to wait for the next event.
+const MAX_WAIT: Duration = Duration::from_millis(5);
+
+/// Plays the song and the auditioned notes on an external synth through a MIDI output port.
+/// Everything goes out on the port's 16 channels, whatever set of synth channels it was meant for.
+pub struct MIDIOutput {
+    /// shared with the thread streaming playback to it
+    connection: Arc<Mutex<Option<MidiOutputConnection>>>,
+    pub port_name: Option<String>,
+    /// if the built-in synth stays silent while there's a port to play to, otherwise both are heard
+    pub replace_synth: bool,
+    stop: Arc<AtomicBool>,
+    player: Option<JoinHandle<()>>,
+}
+
+impl Default for MIDIOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MIDIOutput {
+    pub fn new() -> Self {
+        Self {
+            connection: Arc::new(Mutex::new(None)),
+            port_name: None,
+            replace_synth: false,
+            stop: Arc::new(AtomicBool::new(false)),
+            player: None,
+        }
+    }
+
+    /// The names of the MIDI output ports that are available right now.
+    pub fn port_names() -> Vec<String> {
+        let Ok(output) = MidiOutput::new(CLIENT_NAME) else { return Vec::new(); };
+        output.ports().iter()
+            .filter_map(|port| output.port_name(port).ok())
+            .collect()
+    }
+
+    /// Closes the current port, if any, and plays to the port named `[port_name]` from now on.
+    pub fn connect(&mut self, port_name: &str) -> Result<(), Box<dyn Error>> {
+        self.disconnect();
+
+        let output = MidiOutput::new(CLIENT_NAME)?;
+        let port = output.ports().into_iter()
+            .find(|port| output.port_name(port).is_ok_and(|name| name == port_name))
+            .ok_or_else(|| format!("The MIDI output {} isn't available anymore", port_name))?;
+        let connection = output.connect(&port, "andromeda-out").map_err(|err| err.to_string())?;
+
+        *self.connection.lock().unwrap() = Some(connection);
+        self.port_name = Some(port_name.to_string());
+        Ok(())
+    }
+
+    pub fn disconnect(&mut self) {
+        self.stop();
+        if let Some(connection) = self.connection.lock().unwrap().take() {
+            connection.close();
+        }
+        self.port_name = None;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.port_name.is_some()
+    }
+
+    /// If the built-in synth should play as well, it always does while no port is open.
+    pub fn plays_synth(&self) -> bool {
+        !(self.is_connected() && self.replace_synth)
+    }
+
+    fn send(&self, message: &[u8]) {
+        if let Some(connection) = self.connection.lock().unwrap().as_mut() {
+            // a port that went away just isn't heard anymore
+            let _ = connection.send(message);
+        }
+    }
+
+    pub fn note_on(&self, channel: u32, key: u8, velocity: u8) {
+        self.send(&[0x90 | (channel & 0x0F) as u8, key, velocity]);
+    }
+
+    pub fn note_off(&self, channel: u32, key: u8) {
+        self.send(&[0x80 | (channel & 0x0F) as u8, key, 0]);
+    }
+
+    /// Releases every note on all 16 channels and lifts the pedal, so nothing the song left playing keeps sounding.
+    pub fn all_notes_off(&self) {
+        for channel in 0..16 {
+            self.send(&[0xB0 | channel, CC_SUSTAIN, 0]);
+            self.send(&[0xB0 | channel, CC_ALL_NOTES_OFF, 0]);
+        }
+    }
+
+    /// Silences every channel at once and resets their controllers, for when notes get stuck.
+    pub fn panic(&self) {
+        for channel in 0..16 {
+            self.send(&[0xB0 | channel, CC_ALL_SOUND_OFF, 0]);
+            self.send(&[0xB0 | channel, CC_RESET_ALL_CONTROLLERS, 0]);
+        }
+    }
+
+    /// Starts sending `[events]` on a thread of its own, each when its time in seconds from now comes.
+    /// Stops what was playing first.
+    pub fn play(&mut self, events: Vec<MIDIEvent>) {
+        self.stop();
+        if !self.is_connected() { return; }
+
+        self.stop.store(false, Ordering::SeqCst);
+        let stop = Arc::clone(&self.stop);
+        let connection = Arc::clone(&self.connection);
+        self.player = Some(std::thread::spawn(move || {
+            let start = Instant::now();
+            for e in events {
+                let due = start + Duration::from_secs_f32(e.time.max(0.0));
+                loop {
+                    if stop.load(Ordering::SeqCst) { return; }
+                    let now = Instant::now();
+                    if now >= due { break; }
+                    std::thread::sleep((due - now).min(MAX_WAIT));
+                }
+                if let Some(connection) = connection.lock().unwrap().as_mut() {
+                    let _ = connection.send(&e.message());
+                }
+            }
+        }));
+    }
+
+    /// Stops sending the playback and releases the notes it left on.
+    pub fn stop(&mut self) {
+        let Some(player) = self.player.take() else { return; };
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = player.join();
+        self.all_notes_off();
+    }
+}
+
+impl Drop for MIDIOutput {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
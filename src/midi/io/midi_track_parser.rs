@@ -6,7 +6,19 @@ use crate::midi::notes::Note;
 use crate::midi::io::{
     buffered_byte_reader::BufferedByteReader
 };
+use super::midi_error::MIDIError;
 use super::midi_file::TrackPointer;
+use super::project_data::ProjectData;
+
+/// Which note on a note off releases when the same key is held more than once on a channel.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum NotePairing {
+    /// the note that started first, what most MIDI players do
+    #[default]
+    Fifo,
+    /// the note that started last
+    Lifo
+}
 
 struct UnendedNote {
     pub id: i32,
@@ -22,6 +34,11 @@ pub struct MIDITrack {
     prev_cmd: u8,
 
     pub tempo_evs: Vec<TempoEvent>,
+    pub time_sig_evs: Vec<TimeSignatureEvent>,
+    pub markers: Vec<Marker>,
+    pub control_evs: Vec<ControlEvent>,
+    /// the editor state of a project this editor saved, see `ProjectData`
+    pub project_data: Option<ProjectData>,
     pub midi_evs: Vec<MIDIEvent>,
     // pub meta_evs: Vec<MetaEvent>,
     pub notes: Vec<Vec<Note>>,
@@ -41,13 +58,14 @@ pub struct MIDITrack {
     track_num: usize,
 
     tick_based_parsing: bool,
+    pairing: NotePairing,
     pub key_range: [u8; 2]
 }
 
 impl MIDITrack {
-    pub fn new(t_num: usize, ppq: u16, stream: Arc<Mutex<File>>, loc: &TrackPointer, tick_based_parsing: bool) -> Result<Self, ()> {
+    pub fn new(t_num: usize, ppq: u16, stream: Arc<Mutex<File>>, loc: &TrackPointer, tick_based_parsing: bool, pairing: NotePairing) -> Result<Self, MIDIError> {
         let mt = Self {
-            rdr: BufferedByteReader::new(stream, loc.start as usize, loc.len as usize, 100000).unwrap(),
+            rdr: BufferedByteReader::new(stream, loc.start as usize, loc.len as usize, 100000)?,
             ev_count: 0,
             tempo_ev_count: 0,
             note_count: 0,
@@ -55,6 +73,10 @@ impl MIDITrack {
             prev_cmd: 0x00,
 
             tempo_evs: Vec::new(),
+            time_sig_evs: Vec::new(),
+            markers: Vec::new(),
+            control_evs: Vec::new(),
+            project_data: None,
             midi_evs: Vec::new(),
             // meta_evs: Vec::new(),
             notes: Vec::new(),
@@ -74,28 +96,24 @@ impl MIDITrack {
             track_num: t_num,
 
             tick_based_parsing,
+            pairing,
             key_range: [255, 0]
         };
         Ok(mt)
     }
 
-    fn read_delta(&mut self) -> u64 {
+    fn read_delta(&mut self) -> Result<u64, MIDIError> {
         let mut n: u64 = 0;
         loop {
-            let b = self.rdr.read_byte().unwrap();
+            let b = self.rdr.read_byte()?;
             n = (n << 7) | ((b & 0x7F) as u64);
             if (b & 0x80) == 0x00 { break; }
         }
-        n
+        Ok(n)
     }
 
-    fn read_delta_time(&mut self, t_evs: &Vec<TempoEvent>) -> f64 {
-        let mut n: u64 = 0;
-        loop {
-            let b = self.rdr.read_byte().unwrap();
-            n = (n << 7) | ((b & 0x7F) as u64);
-            if (b & 0x80) == 0x00 { break; }
-        }
+    fn read_delta_time(&mut self, t_evs: &[TempoEvent]) -> Result<f64, MIDIError> {
+        let n = self.read_delta()?;
         self.track_len_p2 += n as f64;
 
         if self.tempo_id < t_evs.len() && self.track_len_p2 > t_evs[self.tempo_id].time as f64 {
@@ -108,25 +126,49 @@ impl MIDITrack {
                 self.tempo_id += 1;
             }
             v += (self.track_len_p2 - t as f64) * self.tempo_multi;
-            return v;
+            Ok(v)
         } else {
-            return (n as f64) * self.tempo_multi;
+            Ok((n as f64) * self.tempo_multi)
+        }
+
+    }
+
+    /// Reads the status byte of the next event, falling back to the previous one for running status.
+    fn read_command(&mut self) -> Result<u8, MIDIError> {
+        let mut command: u8 = self.rdr.read_byte()?;
+        if command < 0x80 {
+            if self.prev_cmd < 0x80 {
+                return Err(MIDIError::UnknownStatus { status: command, pos: self.rdr.pos - 1 });
+            }
+            self.rdr.seek(-1, 1)?;
+            command = self.prev_cmd;
         }
+        Ok(command)
+    }
 
+    /// The current position of the track in the unit notes are stored in,
+    /// ticks if `tick_based_parsing` is set and microseconds otherwise.
+    fn curr_note_time(&self) -> u32 {
+        if self.tick_based_parsing {
+            self.track_len_p2 as u32
+        } else {
+            (self.t_track_time * 1000000.0) as u32
+        }
     }
 
-    pub fn parse_ev(&mut self) -> Result<(), ()> {
+    pub fn parse_ev(&mut self) -> Result<(), MIDIError> {
         if self.ended { 
             return Ok(())
         }
-        let delta = self.read_delta();
+        // tolerate tracks missing their end of track event
+        if self.rdr.at_end() {
+            self.ended = true;
+            return Ok(())
+        }
+        let delta = self.read_delta()?;
         self.track_len += delta;
 
-        let mut command: u8 = self.rdr.read_byte().unwrap();
-        if command < 0x80 {
-            self.rdr.seek(-1, 1).unwrap();
-            command = self.prev_cmd;
-        }
+        let command = self.read_command()?;
 
         self.prev_cmd = command;
 
@@ -158,14 +200,21 @@ impl MIDITrack {
                 match command {
                     0xFF => {
                         let cmd2: u8 = self.rdr.read_byte()?;
-                        let val = self.read_delta() as usize;
+                        let val = self.read_delta()? as usize;
                         
                         match cmd2 {
                             0x00 => { self.rdr.skip_bytes(2)?; }
                             0x01..=0x07 | 0x0A => {
                                 self.rdr.skip_bytes(val)?;
                             }
-                            0x7F => { self.rdr.skip_bytes(val)?; }
+                            // sequencer specific
+                            0x7F => {
+                                let mut data: Vec<u8> = vec![0u8; val];
+                                self.rdr.read(&mut data[0..val], val)?;
+                                if let Some(project_data) = ProjectData::from_bytes(&data) {
+                                    self.project_data = Some(project_data);
+                                }
+                            }
                             0x20 => { self.rdr.skip_bytes(1)?; }
                             0x21 => { self.rdr.skip_bytes(1)?; }
                             0x2F => { self.ended = true; }
@@ -190,12 +239,12 @@ impl MIDITrack {
                             _ => {
                                 println!("unknown sys ev {}", cmd2);
                                 self.rdr.skip_bytes(val)?;
-                                self.ev_count -= 1;
+                                self.ev_count = self.ev_count.saturating_sub(1);
                             }
                         };
                     }
                     0xF0 => {
-                        let sysex_len = self.read_delta();
+                        let sysex_len = self.read_delta()?;
                         self.rdr.skip_bytes(sysex_len as usize)?;
                     }
                     0xF2 => {
@@ -205,21 +254,63 @@ impl MIDITrack {
                         self.rdr.skip_bytes(1)?;
                     },
                     0xF7 => {
-                        let sysex_len = self.read_delta();
+                        let sysex_len = self.read_delta()?;
                         self.rdr.skip_bytes(sysex_len as usize)?;
                     }
+                    0xF1 => {
+                        self.rdr.skip_bytes(1)?;
+                    }
+                    0xF4 | 0xF5 => {
+                        return Err(MIDIError::UnknownStatus { status: command, pos: self.rdr.pos - 1 });
+                    }
+                    // tune request and realtime messages carry no data
                     _ => {}
                 }
             },
-            _ => {}
+            _ => {
+                return Err(MIDIError::UnknownStatus { status: command, pos: self.rdr.pos - 1 });
+            }
         }
         self.ev_count += 1;
         Ok(())
     }
 
-    pub fn prep_for_pass_two(&mut self) -> Result<(),()> {
+    /// Ends an unended note on `[key]` and `[ch]` at the current time, picked by the track's `NotePairing`.
+    /// Returns its note on velocity, or `None` for a stray note off that doesn't release anything.
+    fn end_note(&mut self, ch: u8, key: u8) -> Option<u8> {
+        let un = &mut self.unended_notes[key as usize * 16 + ch as usize];
+        if un.is_empty() { return None; }
+
+        let n = match self.pairing {
+            NotePairing::Fifo => un.remove(0),
+            NotePairing::Lifo => un.pop()?
+        };
+        if n.id == -1 { return None; }
+
+        let end = self.curr_note_time();
+        let note = &mut self.notes[key as usize][n.id as usize];
+        note.length = end.saturating_sub(note.start);
+        note.velocity = n.vel;
+        Some(n.vel)
+    }
+
+    /// Ends every note that's still held at the current time, for when the track ends without releasing them.
+    fn end_unended_notes(&mut self) {
+        let end = self.curr_note_time();
+        for (i, unended) in self.unended_notes.iter_mut().enumerate() {
+            let key = i / 16;
+            for n in unended.drain(..) {
+                if n.id == -1 { continue; }
+                let note = &mut self.notes[key][n.id as usize];
+                note.length = end.saturating_sub(note.start);
+                note.velocity = n.vel;
+            }
+        }
+    }
+
+    pub fn prep_for_pass_two(&mut self) -> Result<(), MIDIError> {
         //reset rdr i think
-        self.rdr.seek(0, 0).unwrap();
+        self.rdr.seek(0, 0)?;
         self.prev_cmd = 0x00;
         self.ended = false;
 
@@ -230,10 +321,15 @@ impl MIDITrack {
         Ok(())
     }
 
-    pub fn parse_pass_two(&mut self, t_evs: &Vec<TempoEvent>) -> Result<(),()> {
+    pub fn parse_pass_two(&mut self, t_evs: &[TempoEvent]) -> Result<(), MIDIError> {
         if self.ended {
             return Ok(())
         }
+        if self.rdr.at_end() {
+            self.ended = true;
+            self.end_unended_notes();
+            return Ok(())
+        }
 
         if !self.unended_init {
             for _ in 0..256*16 {
@@ -242,14 +338,10 @@ impl MIDITrack {
             self.unended_init = true;
         }
 
-        let delta = self.read_delta_time(t_evs);
+        let delta = self.read_delta_time(t_evs)?;
         self.valid_delta += delta;
         self.t_track_time += delta;
-        let mut command: u8 = self.rdr.read_byte().unwrap();
-        if command < 0x80 {
-            self.rdr.seek(-1, 1).unwrap();
-            command = self.prev_cmd;
-        }
+        let command = self.read_command()?;
 
         self.prev_cmd = command;
 
@@ -261,18 +353,8 @@ impl MIDITrack {
                 let key = self.rdr.read_byte()?;
                 let mut vel = self.rdr.read_byte()?;
 
-                let un = &mut self.unended_notes[key as usize * 16 + ch as usize];
-                if un.len() != 0 {
-                    let n = un.remove(0);
-                    if n.id != -1 {
-                        self.notes[key as usize][n.id as usize].length = if self.tick_based_parsing {
-                            self.track_len_p2 as u32
-                        } else {
-                            (self.t_track_time * 1000000.0) as u32
-                        };
-                        self.notes[key as usize][n.id as usize].velocity = n.vel;
-                        vel = n.vel;
-                    }
+                if let Some(on_vel) = self.end_note(ch, key) {
+                    vel = on_vel;
                 }
 
                 self.midi_evs.push(
@@ -287,29 +369,27 @@ impl MIDITrack {
             0x90 => {
                 let key = self.rdr.read_byte()?;
                 let vel = self.rdr.read_byte()?;
-                self.midi_evs.push(
-                    MIDIEvent {
-                        time: self.t_track_time as f32,
-                        event_type: if vel > 0 { MIDIEventType::NoteOn } else { MIDIEventType::NoteOff },
-                        data: vec![ch, key, vel]
-                    }
-                );
 
+                // lots of files end notes with a velocity 0 note on (usually with running status),
+                // these are note offs and pair with the note they release just like 0x80 does
                 if vel == 0 {
-                    let un = &mut self.unended_notes[key as usize * 16 + ch as usize];
-                    if un.len() != 0 {
-                        let n = un.remove(0);
-                        if n.id != -1 {
-                            self.notes[key as usize][n.id as usize].length = if self.tick_based_parsing {
-                                self.track_len_p2 as u32
-                            } else {
-                                (self.t_track_time * 1000000.0) as u32
-                            };
-                            self.notes[key as usize][n.id as usize].velocity = n.vel;
-                            
+                    let vel = self.end_note(ch, key).unwrap_or(0);
+                    self.midi_evs.push(
+                        MIDIEvent {
+                            time: self.t_track_time as f32,
+                            event_type: MIDIEventType::NoteOff,
+                            data: vec![ch, key, vel]
                         }
-                    }
+                    );
                 } else {
+                    self.midi_evs.push(
+                        MIDIEvent {
+                            time: self.t_track_time as f32,
+                            event_type: MIDIEventType::NoteOn,
+                            data: vec![ch, key, vel]
+                        }
+                    );
+
                     self.unended_notes[key as usize * 16 + ch as usize].push(UnendedNote {
                         id: self.curr_note_idx[key as usize] as i32,
                         vel
@@ -321,16 +401,13 @@ impl MIDITrack {
                     };*/
                     //self.notes[key as usize][self.curr_note_idx[key as usize]].start = (self.t_track_time * 1000000.0) as u64;
                     //self.notes[key as usize][self.curr_note_idx[key as usize]].channel = ch;
+                    let start = self.curr_note_time();
                     self.notes[key as usize].push(Note {
                         key: key,
-                        start: if self.tick_based_parsing {
-                            self.track_len_p2 as u32
-                        } else {
-                            (self.t_track_time * 1000000.0) as u32
-                        },
+                        start,
                         length: 10000000,
                         channel: ch,
-                        // track: self.track_num,
+                        track: self.track_num as u16,
                         velocity: 0
                     });
                     self.curr_note_idx[key as usize] += 1;
@@ -339,27 +416,26 @@ impl MIDITrack {
                 self.valid_delta = 0.0;
             },
             0xB0 => {
-                let ctrl_num = self.rdr.read_byte()?;
-                let ctrl_val = self.rdr.read_byte()?;
-                /*self.midi_evs.push(MIDIEvent {
-                    time: self.t_track_time as f32,
-                    command: MIDIEventType::ControlEvent,
-                    data: vec![ch, ctrl_num, ctrl_val]
+                let number = self.rdr.read_byte()? & 0x7F;
+                let value = self.rdr.read_byte()? & 0x7F;
+                self.control_evs.push(ControlEvent {
+                    tick: self.track_len_p2 as u64,
+                    track: self.track_num as u16,
+                    channel: ch,
+                    kind: ControlKind::Controller { number, value }
                 });
-                
                 self.valid_delta = 0.0;
-                */
             },
             0xE0 => {
-                let v1 = self.rdr.read_byte()?;
-                let v2 = self.rdr.read_byte()?;
-                /*self.midi_evs.push(MIDIEvent {
-                    time: self.t_track_time as f32,
-                    command: MIDIEventType::PitchBend,
-                    data: vec![ch, v1, v2]
+                let lsb = self.rdr.read_byte()? & 0x7F;
+                let msb = self.rdr.read_byte()? & 0x7F;
+                self.control_evs.push(ControlEvent {
+                    tick: self.track_len_p2 as u64,
+                    track: self.track_num as u16,
+                    channel: ch,
+                    kind: ControlKind::PitchBend(((msb as u16) << 7) | lsb as u16)
                 });
-                
-                self.valid_delta = 0.0;*/
+                self.valid_delta = 0.0;
             },
             0xA0 => {
                self.rdr.skip_bytes(2)?;
@@ -371,19 +447,18 @@ impl MIDITrack {
                 match command {
                     0xFF => {
                         let cmd2: u8 = self.rdr.read_byte()?;
-                        let val = self.read_delta() as usize;
+                        let val = self.read_delta()? as usize;
                         
                         match cmd2 {
                             0x00 => { self.rdr.skip_bytes(2)?; }
                             // marker
                             0x06 => {
                                 let mut text_bytes: Vec<u8> = vec![0u8; val];
-                                self.rdr.read(&mut text_bytes[0..val], val).unwrap();
-                                /*self.meta_evs.push(MetaEvent {
-                                    time: self.t_track_time as f32,
-                                    meta_name: MetaEventName::Marker,
-                                    data: text_bytes
-                                });*/
+                                self.rdr.read(&mut text_bytes[0..val], val)?;
+                                self.markers.push(Marker {
+                                    tick: self.track_len_p2 as u64,
+                                    name: String::from_utf8_lossy(&text_bytes).into_owned()
+                                });
                             }
                             0x01..=0x05 | 0x07 | 0x0A => {
                                 self.rdr.skip_bytes(val)?;
@@ -391,7 +466,10 @@ impl MIDITrack {
                             0x7F => { self.rdr.skip_bytes(val)?; }
                             0x20 => { self.rdr.skip_bytes(1)?; }
                             0x21 => { self.rdr.skip_bytes(1)?; }
-                            0x2F => { self.ended = true; }
+                            0x2F => {
+                                self.ended = true;
+                                self.end_unended_notes();
+                            }
                             0x51 => {
                                 let mut tempo: u32 = 0;
                                 for _ in 0..3 {
@@ -405,7 +483,17 @@ impl MIDITrack {
                                 });
                             }
                             0x54 => { self.rdr.skip_bytes(5)?; }
-                            0x58 => { self.rdr.skip_bytes(4)?; }
+                            0x58 => {
+                                // numerator, denominator as a power of 2, then MIDI clocks per click and 32nds per quarter
+                                let numerator = self.rdr.read_byte()?;
+                                let denominator = self.rdr.read_byte()?;
+                                self.rdr.skip_bytes(val.saturating_sub(2))?;
+                                self.time_sig_evs.push(TimeSignatureEvent {
+                                    time: self.track_len_p2 as u64,
+                                    numerator,
+                                    denominator: 1u8.checked_shl(denominator as u32).unwrap_or(0)
+                                });
+                            }
                             0x59 => { self.rdr.skip_bytes(2)?; }
                             _ => {
                                 println!("unknown sys ev {}", cmd2);
@@ -414,7 +502,7 @@ impl MIDITrack {
                         };
                     }
                     0xF0 => {
-                        let sysex_len = self.read_delta();
+                        let sysex_len = self.read_delta()?;
                         self.rdr.skip_bytes(sysex_len as usize)?;
                     }
                     0xF2 => {
@@ -424,14 +512,190 @@ impl MIDITrack {
                         self.rdr.skip_bytes(1)?;
                     },
                     0xF7 => {
-                        let sysex_len = self.read_delta();
+                        let sysex_len = self.read_delta()?;
                         self.rdr.skip_bytes(sysex_len as usize)?;
                     }
+                    0xF1 => {
+                        self.rdr.skip_bytes(1)?;
+                    }
+                    0xF4 | 0xF5 => {
+                        return Err(MIDIError::UnknownStatus { status: command, pos: self.rdr.pos - 1 });
+                    }
+                    // tune request and realtime messages carry no data
                     _ => {}
                 }
             },
-            _ => {}
+            _ => {
+                return Err(MIDIError::UnknownStatus { status: command, pos: self.rdr.pos - 1 });
+            }
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::NotePairing;
+    use crate::midi::events::{MIDIEvent, MIDIEventType};
+    use crate::midi::io::midi_file::{MIDIFile, MIDIFileProgress};
+    use crate::midi::notes::{Note, ProjectNoteManager};
+
+    const PPQ: u16 = 96;
+
+    fn write_var_len(out: &mut Vec<u8>, value: u32) {
+        let mut groups = vec![(value & 0x7F) as u8];
+        let mut value = value >> 7;
+        while value > 0 {
+            groups.push((value & 0x7F) as u8 | 0x80);
+            value >>= 7;
+        }
+        out.extend(groups.iter().rev());
+    }
+
+    /// A format 0 file at `PPQ` with one track of `[events]`, each a delta time and the bytes after it.
+    /// The end of track goes right after the last event.
+    fn smf(events: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut track = Vec::new();
+        for (delta, bytes) in events {
+            write_var_len(&mut track, *delta);
+            track.extend_from_slice(bytes);
+        }
+        track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+        let mut file = b"MThd".to_vec();
+        file.extend(6u32.to_be_bytes());
+        file.extend(0u16.to_be_bytes());
+        file.extend(1u16.to_be_bytes());
+        file.extend(PPQ.to_be_bytes());
+        file.extend(b"MTrk");
+        file.extend((track.len() as u32).to_be_bytes());
+        file.extend(track);
+        file
+    }
+
+    /// Imports `[bytes]` the way the loader does. Returns the notes sorted by start then key, and the events.
+    fn parse(name: &str, bytes: &[u8], pairing: NotePairing) -> (Vec<Note>, Vec<MIDIEvent>) {
+        let path = std::env::temp_dir().join(format!("andromeda_{}_{}.mid", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let midi = MIDIFile::new(path.to_string_lossy().to_string(), true, pairing, Arc::new(MIDIFileProgress::default()));
+        let (mut midi_evs, mut notes) = (Vec::new(), Vec::new());
+        let parsed = midi.and_then(|midi| midi.get_sequences(&mut midi_evs, &mut notes,
+            &mut Vec::new(), &mut Vec::new(), &mut Vec::new(), &mut Vec::new()));
+        let _ = std::fs::remove_file(&path);
+        parsed.unwrap();
+
+        let mut notes = notes.into_iter().flatten().collect::<Vec<_>>();
+        notes.sort_by_key(|note| (note.start, note.key));
+        (notes, midi_evs)
+    }
+
+    /// Two note ons on key 60 then two note offs, the second note starting while the first is held.
+    fn overlapping_notes() -> Vec<u8> {
+        smf(&[
+            (0, &[0x90, 60, 100]),
+            (10, &[0x90, 60, 50]),
+            (10, &[0x80, 60, 0]),
+            (10, &[0x80, 60, 0])
+        ])
+    }
+
+    fn start_length_velocity(notes: &[Note]) -> Vec<(u32, u32, u8)> {
+        notes.iter().map(|note| (note.start, note.length, note.velocity)).collect()
+    }
+
+    #[test]
+    fn fifo_releases_the_oldest_note() {
+        let (notes, _) = parse("fifo", &overlapping_notes(), NotePairing::Fifo);
+        assert_eq!(start_length_velocity(&notes), vec![(0, 20, 100), (10, 20, 50)]);
+    }
+
+    #[test]
+    fn lifo_releases_the_newest_note() {
+        let (notes, _) = parse("lifo", &overlapping_notes(), NotePairing::Lifo);
+        assert_eq!(start_length_velocity(&notes), vec![(0, 30, 100), (10, 10, 50)]);
+    }
+
+    #[test]
+    fn unended_note_ends_with_the_track() {
+        let bytes = smf(&[
+            (0, &[0x90, 64, 77]),
+            (48, &[0xB0, 7, 100])
+        ]);
+        let (notes, _) = parse("unended", &bytes, NotePairing::Fifo);
+        assert_eq!(start_length_velocity(&notes), vec![(0, 48, 77)]);
+    }
+
+    #[test]
+    fn stray_note_off_is_ignored() {
+        let bytes = smf(&[
+            (0, &[0x80, 60, 64]),
+            (0, &[0x90, 60, 90]),
+            (24, &[0x80, 60, 0]),
+            (12, &[0x80, 60, 0]),
+            (12, &[0x90, 60, 80]),
+            (12, &[0x80, 60, 0])
+        ]);
+        let (notes, midi_evs) = parse("stray", &bytes, NotePairing::Fifo);
+        assert_eq!(start_length_velocity(&notes), vec![(0, 24, 90), (48, 12, 80)]);
+        let note_ons = midi_evs.iter().filter(|e| matches!(e.event_type, MIDIEventType::NoteOn)).count();
+        assert_eq!(note_ons, 2);
+    }
+
+    #[test]
+    fn zero_velocity_note_ons_with_running_status_end_notes() {
+        // the second byte pair of each note has no status, it's a velocity 0 note on by running status
+        let bytes = smf(&[
+            (0, &[0x90, 60, 100]),
+            (24, &[60, 0]),
+            (0, &[62, 90]),
+            (48, &[62, 0])
+        ]);
+        let (notes, midi_evs) = parse("running_status", &bytes, NotePairing::Fifo);
+        assert_eq!(start_length_velocity(&notes), vec![(0, 24, 100), (24, 48, 90)]);
+
+        let note_ons = midi_evs.iter().filter(|e| matches!(e.event_type, MIDIEventType::NoteOn)).count();
+        let note_offs = midi_evs.iter().filter(|e| matches!(e.event_type, MIDIEventType::NoteOff)).count();
+        assert_eq!((note_ons, note_offs), (2, 2));
+        // none of the velocity 0 note ons are left as note ons
+        assert!(midi_evs.iter().all(|e| !matches!(e.event_type, MIDIEventType::NoteOn) || e.data[2] > 0));
+    }
+
+    #[test]
+    fn imported_note_keeps_its_length() {
+        // a note starting after a bar of rest, so an end tick mistaken for a length would show
+        let bytes = smf(&[
+            (384, &[0x90, 67, 100]),
+            (72, &[0x80, 67, 0])
+        ]);
+        let (notes, _) = parse("length", &bytes, NotePairing::Fifo);
+        let mut manager = ProjectNoteManager::new();
+        manager.convert_notes(notes);
+
+        let note = manager.notes().next().unwrap();
+        assert_eq!((note.start, note.length, note.end()), (384, 72, 456));
+    }
+
+    #[test]
+    fn zero_velocity_note_ons_pair_like_note_offs() {
+        // overlapping_notes with the note offs swapped for velocity 0 note ons
+        let bytes = smf(&[
+            (0, &[0x90, 60, 100]),
+            (10, &[0x90, 60, 50]),
+            (10, &[0x90, 60, 0]),
+            (10, &[0x90, 60, 0])
+        ]);
+        let (notes, _) = parse("zero_velocity_fifo", &bytes, NotePairing::Fifo);
+        assert_eq!(start_length_velocity(&notes), vec![(0, 20, 100), (10, 20, 50)]);
+
+        let (notes, midi_evs) = parse("zero_velocity_lifo", &bytes, NotePairing::Lifo);
+        assert_eq!(start_length_velocity(&notes), vec![(0, 30, 100), (10, 10, 50)]);
+        // the note offs carry the velocity of the note they release, like a 0x80 does
+        let note_off_velocities = midi_evs.iter()
+            .filter(|e| matches!(e.event_type, MIDIEventType::NoteOff))
+            .map(|e| e.data[2])
+            .collect::<Vec<_>>();
+        assert_eq!(note_off_velocities, vec![50, 100]);
+    }
+}
+
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::midi::events::{ControlEvent, Marker, TempoEvent, TimeSignatureEvent};
+use crate::midi::notes::Note;
+
+use super::project_data::ProjectData;
+
+/// A MIDI event waiting to be written, ordered by tick. At the same tick note offs go before note ons,
+/// so a note ending where the next one on the same key starts doesn't cut it off. The note off of a note
+/// that ends where it starts goes after its note on though, otherwise the note would be left hanging.
+struct TrackEvent {
+    tick: u64,
+    /// 0 for meta events, note offs and control changes, 1 for note ons, 2 for the note offs of zero length notes
+    order: u8,
+    data: Vec<u8>,
+}
+
+/// Writes the project as a format 1 MIDI file. The first track holds the tempo map, time signatures and markers,
+/// then every project track gets a track of its own, in track order.
+/// A track with control changes but no notes is written too. `[project_data]` goes on the first track,
+/// with the tracks it refers to numbered the way they're read back.
+#[allow(clippy::too_many_arguments)]
+pub fn write_midi_file(
+    path: impl AsRef<Path>,
+    ppq: u16,
+    tracks: &BTreeMap<u16, Vec<Note>>,
+    tempo_evs: &[TempoEvent],
+    time_sig_evs: &[TimeSignatureEvent],
+    markers: &[Marker],
+    controls: &[ControlEvent],
+    project_data: &ProjectData
+) -> io::Result<()> {
+    let mut conductor = Vec::new();
+    for ev in time_sig_evs {
+        // the denominator is stored as a power of two
+        let denominator = ev.denominator.max(1).ilog2() as u8;
+        conductor.push(TrackEvent { tick: ev.time, order: 0, data: vec![0xFF, 0x58, 4, ev.numerator, denominator, 24, 8] });
+    }
+    for ev in tempo_evs {
+        let micros = (60_000_000.0 / ev.tempo.max(1.0)).round().clamp(1.0, 0xFFFFFF as f32) as u32;
+        let [_, a, b, c] = micros.to_be_bytes();
+        conductor.push(TrackEvent { tick: ev.time, order: 0, data: vec![0xFF, 0x51, 3, a, b, c] });
+    }
+    for marker in markers {
+        let mut data = vec![0xFF, 0x06];
+        write_var_len(&mut data, marker.name.len() as u32);
+        data.extend_from_slice(marker.name.as_bytes());
+        conductor.push(TrackEvent { tick: marker.tick, order: 0, data });
+    }
+
+    let mut track_events = tracks.keys()
+        .map(|track| (*track, Vec::new()))
+        .collect::<BTreeMap<u16, Vec<TrackEvent>>>();
+    for control in controls {
+        let (status, [a, b]) = control.kind.bytes();
+        track_events.entry(control.track).or_default()
+            .push(TrackEvent { tick: control.tick, order: 0, data: vec![status | (control.channel & 0x0F), a, b] });
+    }
+    for (track, notes) in tracks {
+        let events = track_events.get_mut(track).unwrap();
+        for note in notes {
+            let channel = note.channel & 0x0F;
+            events.push(TrackEvent { tick: note.start as u64, order: 1, data: vec![0x90 | channel, note.key & 0x7F, note.velocity.clamp(1, 127)] });
+            let order = if note.length == 0 { 2 } else { 0 };
+            events.push(TrackEvent { tick: note.start as u64 + note.length as u64, order, data: vec![0x80 | channel, note.key & 0x7F, 0] });
+        }
+    }
+
+    // each track is read back as the track it is in the file, the first one being track 1
+    let file_tracks = track_events.keys().copied().collect::<Vec<_>>();
+    let mut project_data = project_data.clone();
+    project_data.renumber_tracks(|track| file_tracks.binary_search(&track).map_or(track, |i| i as u16 + 1));
+    let bytes = project_data.to_bytes();
+    let mut data = vec![0xFF, 0x7F];
+    write_var_len(&mut data, bytes.len() as u32);
+    data.extend_from_slice(&bytes);
+    conductor.push(TrackEvent { tick: 0, order: 0, data });
+
+    let mut file = Vec::new();
+    file.extend_from_slice(b"MThd");
+    file.extend_from_slice(&6u32.to_be_bytes());
+    file.extend_from_slice(&1u16.to_be_bytes());
+    file.extend_from_slice(&((track_events.len() + 1).min(u16::MAX as usize) as u16).to_be_bytes());
+    file.extend_from_slice(&ppq.to_be_bytes());
+    write_track(&mut file, conductor);
+
+    for events in track_events.into_values() {
+        write_track(&mut file, events);
+    }
+
+    fs::write(path, file)
+}
+
+/// Appends an `MTrk` chunk holding `[events]` and an end of track event.
+fn write_track(file: &mut Vec<u8>, mut events: Vec<TrackEvent>) {
+    events.sort_by_key(|ev| (ev.tick, ev.order));
+
+    let mut data = Vec::new();
+    let mut last_tick = 0;
+    for ev in events {
+        write_var_len(&mut data, (ev.tick - last_tick).min(0x0FFFFFFF) as u32);
+        data.extend_from_slice(&ev.data);
+        last_tick = ev.tick;
+    }
+    data.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+    file.extend_from_slice(b"MTrk");
+    file.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    file.extend_from_slice(&data);
+}
+
+/// Appends `[value]` as a MIDI variable length quantity, 7 bits per byte with the high bit set on all but the last.
+fn write_var_len(data: &mut Vec<u8>, value: u32) {
+    let mut bytes = [0u8; 4];
+    let mut len = 0;
+    let mut value = value & 0x0FFFFFFF;
+    loop {
+        bytes[len] = (value & 0x7F) as u8;
+        len += 1;
+        value >>= 7;
+        if value == 0 { break; }
+    }
+    for (i, byte) in bytes[..len].iter().enumerate().rev() {
+        data.push(if i > 0 { byte | 0x80 } else { *byte });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use super::write_midi_file;
+    use crate::editor::track_list::{NoteGrouping, TrackState};
+    use crate::midi::io::midi_file::{MIDIFile, MIDIFileProgress};
+    use crate::midi::io::midi_track_parser::NotePairing;
+    use crate::midi::io::project_data::ProjectData;
+    use crate::midi::notes::Note;
+
+    #[test]
+    fn project_data_is_read_back_with_its_tracks() {
+        let note = |track: u16, key: u8| Note { start: 0, length: 96, channel: 0, track, key, velocity: 100 };
+        let tracks = BTreeMap::from([(3, vec![note(3, 60)]), (5, vec![note(5, 72)])]);
+        let mut project_data = ProjectData { grouping: NoteGrouping::Track, pickup: 48, ..Default::default() };
+        project_data.tracks.insert(5, TrackState { muted: true, solo: false, color: Some([0.0, 1.0, 0.0]) });
+
+        let path = std::env::temp_dir().join(format!("andromeda_project_data_{}.mid", std::process::id()));
+        write_midi_file(&path, 96, &tracks, &[], &[], &[], &[], &project_data).unwrap();
+        let midi = MIDIFile::new(path.to_string_lossy().to_string(), true, NotePairing::default(), Arc::new(MIDIFileProgress::default()));
+        let mut notes = Vec::new();
+        let parsed = midi.and_then(|mut midi| {
+            let read = midi.project_data.take();
+            midi.get_sequences(&mut Vec::new(), &mut notes, &mut Vec::new(), &mut Vec::new(), &mut Vec::new(), &mut Vec::new())?;
+            Ok(read)
+        });
+        let _ = std::fs::remove_file(&path);
+        let read = parsed.unwrap();
+
+        // the notes of track 5 are read back on track 2, so its entry has to be too
+        let muted_key = notes.into_iter().flatten().find(|note| note.track == 2).map(|note| note.key);
+        assert_eq!(muted_key, Some(72));
+        let read = read.unwrap();
+        assert_eq!(read.pickup, 48);
+        let entries = read.tracks.iter().map(|(group, state)| (*group, state.muted, state.color)).collect::<Vec<_>>();
+        assert_eq!(entries, vec![(2, true, Some([0.0, 1.0, 0.0]))]);
+    }
+
+    #[test]
+    fn zero_length_note_is_read_back_as_it_was() {
+        let note = |start: u32, length: u32, key: u8| Note { start, length, channel: 0, track: 0, key, velocity: 100 };
+        let tracks = BTreeMap::from([(0, vec![note(96, 0, 60), note(192, 96, 64)])]);
+
+        let path = std::env::temp_dir().join(format!("andromeda_zero_length_{}.mid", std::process::id()));
+        write_midi_file(&path, 96, &tracks, &[], &[], &[], &[], &ProjectData::default()).unwrap();
+        let midi = MIDIFile::new(path.to_string_lossy().to_string(), true, NotePairing::default(), Arc::new(MIDIFileProgress::default()));
+        let mut notes = Vec::new();
+        let parsed = midi.and_then(|midi| midi.get_sequences(&mut Vec::new(), &mut notes,
+            &mut Vec::new(), &mut Vec::new(), &mut Vec::new(), &mut Vec::new()));
+        let _ = std::fs::remove_file(&path);
+        parsed.unwrap();
+
+        // a note off written before its note on would leave the note held until the end of the track
+        let mut read = notes.into_iter().flatten().map(|note| (note.start, note.length, note.key)).collect::<Vec<_>>();
+        read.sort();
+        assert_eq!(read, vec![(96, 0, 60), (192, 96, 64)]);
+    }
+}
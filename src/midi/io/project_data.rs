@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+
+use crate::editor::track_list::{NoteGrouping, TrackState};
+
+/// Sequencer specific meta events (`FF 7F`) starting with these bytes hold a `ProjectData`.
+/// 0x7D is the manufacturer ID set aside for non-commercial use.
+pub const PROJECT_DATA_ID: &[u8] = b"\x7DAndromeda";
+
+/// What the editor keeps about a project that MIDI has no event for. It's saved as `key = value` lines
+/// in a sequencer specific meta event on the first track, which other programs skip.
+#[derive(Clone, Default)]
+pub struct ProjectData {
+    pub grouping: NoteGrouping,
+    /// the track list entries' flags and colors, keyed like `TrackList::tracks`
+    pub tracks: BTreeMap<u32, TrackState>,
+    /// the tick bar 1 starts on, see `TimeSignatureMap::pickup`
+    pub pickup: u64,
+}
+
+impl ProjectData {
+    /// The contents of the meta event, `PROJECT_DATA_ID` included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let groups = |flag: fn(&TrackState) -> bool| self.tracks.iter()
+            .filter(|(_, state)| flag(state))
+            .map(|(group, _)| group.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut contents = String::new();
+        contents += &format!("pickup = {}\n", self.pickup);
+        contents += &format!("grouping = {}\n", self.grouping.key());
+        contents += &format!("muted = {}\n", groups(|state| state.muted));
+        contents += &format!("solo = {}\n", groups(|state| state.solo));
+        for (group, state) in &self.tracks {
+            if let Some([r, g, b]) = state.color {
+                contents += &format!("color.{} = {}, {}, {}\n", group, r, g, b);
+            }
+        }
+
+        let mut data = PROJECT_DATA_ID.to_vec();
+        data.extend_from_slice(contents.as_bytes());
+        data
+    }
+
+    /// Reads the contents of a sequencer specific meta event, `None` if it isn't one written by `to_bytes`.
+    /// Lines that can't be read are skipped.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let contents = String::from_utf8_lossy(data.strip_prefix(PROJECT_DATA_ID)?).into_owned();
+        let mut project_data = Self::default();
+
+        let groups = |value: &str| value.split(',')
+            .filter_map(|group| group.trim().parse::<u32>().ok())
+            .collect::<Vec<_>>();
+        let color = |value: &str| -> Option<[f32; 3]> {
+            let mut parts = value.split(',').map(|part| part.trim().parse::<f32>().ok());
+            let color = [parts.next()??, parts.next()??, parts.next()??];
+            Some(color.map(|c| c.clamp(0.0, 1.0)))
+        };
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if key == "pickup" {
+                project_data.pickup = value.parse().unwrap_or_default();
+            } else if key == "grouping" {
+                project_data.grouping = NoteGrouping::ALL.into_iter().find(|grouping| grouping.key() == value).unwrap_or_default();
+            } else if key == "muted" {
+                for group in groups(value) {
+                    project_data.tracks.entry(group).or_default().muted = true;
+                }
+            } else if key == "solo" {
+                for group in groups(value) {
+                    project_data.tracks.entry(group).or_default().solo = true;
+                }
+            } else if let Some(group) = key.strip_prefix("color.").and_then(|group| group.parse::<u32>().ok())
+                && let Some(picked) = color(value) {
+                project_data.tracks.entry(group).or_default().color = Some(picked);
+            }
+        }
+        Some(project_data)
+    }
+
+    /// Moves the entries onto the tracks `[renumber]` gives for the ones they were on,
+    /// for when the tracks are numbered differently in the file than in the editor.
+    /// Entries by channel aren't on a track and stay as they are.
+    pub fn renumber_tracks(&mut self, renumber: impl Fn(u16) -> u16) {
+        let grouping = self.grouping;
+        let renumber_group = |group: u32| match grouping {
+            NoteGrouping::Track => renumber(group as u16) as u32,
+            NoteGrouping::Channel => group,
+            NoteGrouping::TrackChannel => ((renumber((group >> 8) as u16) as u32) << 8) | (group & 0xFF)
+        };
+        self.tracks = std::mem::take(&mut self.tracks).into_iter()
+            .map(|(group, state)| (renumber_group(group), state))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProjectData;
+    use crate::editor::track_list::{NoteGrouping, TrackState};
+
+    #[test]
+    fn reads_back_what_it_wrote() {
+        let mut project_data = ProjectData { grouping: NoteGrouping::TrackChannel, pickup: 240, ..Default::default() };
+        project_data.tracks.insert(0x0100, TrackState { muted: true, solo: false, color: Some([1.0, 0.5, 0.25]) });
+        project_data.tracks.insert(0x0203, TrackState { muted: false, solo: true, color: None });
+
+        let read = ProjectData::from_bytes(&project_data.to_bytes()).unwrap();
+        assert!(read.grouping == NoteGrouping::TrackChannel);
+        assert_eq!(read.pickup, 240);
+        let read = read.tracks.iter().map(|(group, state)| (*group, state.muted, state.solo, state.color)).collect::<Vec<_>>();
+        assert_eq!(read, vec![(0x0100, true, false, Some([1.0, 0.5, 0.25])), (0x0203, false, true, None)]);
+    }
+
+    #[test]
+    fn other_sequencers_data_is_skipped() {
+        assert!(ProjectData::from_bytes(&[0x00, 0x00, 0x41, 0x01]).is_none());
+    }
+
+    #[test]
+    fn renumbering_keeps_the_channel() {
+        let mut project_data = ProjectData { grouping: NoteGrouping::TrackChannel, ..Default::default() };
+        project_data.tracks.insert(0x0209, TrackState { muted: true, ..Default::default() });
+        project_data.renumber_tracks(|track| track + 1);
+        assert_eq!(project_data.tracks.keys().copied().collect::<Vec<_>>(), vec![0x0309]);
+    }
+}
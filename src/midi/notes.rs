@@ -1,31 +1,113 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
-use super::events::{MIDIEvent, MIDIEventType};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::audio::{playback::Playback, prerenderer::synth_channel};
+
+use super::events::{
+    ControlEvent, ControlKind, MIDIEvent, MIDIEventType, CC_DATA_ENTRY, CC_DATA_ENTRY_LSB, CC_RESET_ALL_CONTROLLERS, CC_RPN_LSB,
+    CC_RPN_MSB, RPN_NULL
+};
+use super::time_signatures::TimeSignatureMap;
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub struct Note {
     pub start: u32, // in ticks
-    pub length: u32, // in ticks
+    pub length: u32, // in ticks, duration rather than end tick
     pub channel: u8,
+    pub track: u16,
     pub key: u8,
     pub velocity: u8
 }
 
-#[derive(Hash, PartialEq, Eq)]
+#[derive(Hash, PartialEq, Eq, Clone, Copy)]
 pub struct ProjectNote {
+    /// identifies the note in its `ProjectNoteManager`, it's kept when the note is edited
+    pub id: u32,
     pub start: u32,
     pub length: u32,
-    pub channel_track: u32, // 00TTTTCC
+    /// the track and channel packed as `00TTTTCC`, 16 bits of track then 8 of channel.
+    /// Also what a "voice" is keyed by when the track list groups notes, see `NoteGrouping`
+    pub channel_track: u32,
     pub key: u8,
     pub velocity: u8,
 }
 
+impl ProjectNote {
+    pub fn track(&self) -> u16 {
+        ((self.channel_track >> 8) & 0xFFFF) as u16
+    }
+
+    pub fn channel(&self) -> u8 {
+        (self.channel_track & 0xFF) as u8
+    }
+
+    pub fn end(&self) -> u32 {
+        self.start + self.length
+    }
+
+    pub fn to_note(self) -> Note {
+        Note {
+            start: self.start,
+            length: self.length,
+            channel: self.channel(),
+            track: self.track(),
+            key: self.key,
+            velocity: self.velocity
+        }
+    }
+}
+
+/// How many edits can be undone before the oldest ones are forgotten.
+const UNDO_LIMIT: usize = 256;
+
+/// A change to the project's notes that can be undone. Notes keep their id when they're modified,
+/// so a modified note is in both `removed` (as it was) and `added` (as it is now).
+struct NoteEdit {
+    removed: Vec<Arc<ProjectNote>>,
+    added: Vec<Arc<ProjectNote>>,
+}
+
+#[derive(Clone, Default)]
+pub struct ProjectStats {
+    pub note_count: usize,
+    pub notes_per_track: BTreeMap<u16, usize>,
+    pub notes_per_channel: [usize; 16],
+    /// the lowest and highest key, `None` if there are no notes
+    pub key_range: Option<(u8, u8)>,
+    /// the tick the last note ends at
+    pub end_tick: u32,
+    /// how many bars long the song is up to `end_tick`, following the time signatures
+    pub length_bars: f32,
+    pub length_secs: f32,
+}
+
+/// A track's notes sorted by start. Shared with the renderer, so edits copy it if it's still being drawn.
+pub type TrackNotes = Arc<Vec<Arc<ProjectNote>>>;
+
 pub struct ProjectNoteManager {
-    pub project_notes: HashMap<u32, Arc<ProjectNote>>,
+    /// every track's notes, sorted by start
+    tracks: HashMap<u16, TrackNotes>,
+    /// the track and start tick of every note by id, for finding it in `tracks`
+    note_index: HashMap<u32, (u16, u32)>,
+    /// how many notes there are of each `channel_track`, so the voices in use are known without going over every note
+    voice_counts: BTreeMap<u32, usize>,
+    /// goes up whenever notes are added or removed, including imports, so caches of the notes know to rebuild
+    revision: u64,
+    /// the `revision` the notes of each `channel_track` last changed at, kept after a voice loses its last note
+    voice_revisions: HashMap<u32, u64>,
     pub curr_id: u32,
+    pub selected: HashSet<u32>,
+    undo_stack: Vec<NoteEdit>,
+    redo_stack: Vec<NoteEdit>,
+    /// the imported control changes and pitch bends, sorted by tick. They aren't edited, only played and saved
+    controls: Vec<ControlEvent>,
 
-    pub render_needs_update: bool
+    pub render_needs_update: bool,
+    /// set when notes are added, removed or edited, but not when they're imported or selected.
+    /// Cleared by whoever keeps track of unsaved changes
+    pub notes_changed: bool
 }
 
 impl Default for ProjectNoteManager {
@@ -37,88 +119,802 @@ impl Default for ProjectNoteManager {
 impl ProjectNoteManager {
     pub fn new() -> Self {
         Self {
-            project_notes: HashMap::new(),
+            tracks: HashMap::new(),
+            note_index: HashMap::new(),
+            voice_counts: BTreeMap::new(),
+            revision: 0,
+            voice_revisions: HashMap::new(),
             curr_id: 0,
-            render_needs_update: false
+            selected: HashSet::new(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            controls: Vec::new(),
+            render_needs_update: false,
+            notes_changed: false
         }
     }
 
-    pub fn add_note(&mut self, track: u16, note: Note) {
-        let _note = ProjectNote {
+    fn project_note(&mut self, note: Note) -> ProjectNote {
+        let id = self.curr_id;
+        self.curr_id += 1;
+        ProjectNote {
+            id,
             start: note.start,
             length: note.length,
-            channel_track: ((track as u32) << 8) | (note.channel as u32),
+            channel_track: ((note.track as u32) << 8) | (note.channel as u32),
             key: note.key,
             velocity: note.velocity
-        };
-        self.project_notes.insert(self.curr_id, Arc::new(_note));
-        self.curr_id += 1;
+        }
+    }
+
+    /// Adds a note outside of the undo history, returning its id. See `record_added`.
+    pub fn add_note(&mut self, note: Note) -> u32 {
+        let note = Arc::new(self.project_note(note));
+        let id = note.id;
+        let track = Arc::make_mut(self.tracks.entry(note.track()).or_default());
+        let pos = track.partition_point(|n| n.start <= note.start);
+        self.note_index.insert(id, (note.track(), note.start));
+        *self.voice_counts.entry(note.channel_track).or_default() += 1;
+        self.revision += 1;
+        self.voice_revisions.insert(note.channel_track, self.revision);
+        track.insert(pos, note);
         self.render_needs_update = true;
+        self.notes_changed = true;
+        id
     }
 
-    pub fn convert_notes(&mut self, notes: Vec<Note>) {
-        for n in notes {
-            let note = ProjectNote {
-                start: n.start,
-                length: n.length - n.start,
-                channel_track: n.channel as u32,
-                key: n.key,
-                velocity: n.velocity
-            };
-            self.project_notes.insert(self.curr_id, Arc::new(note));
-            self.curr_id += 1;
-        }
+    /// Records notes that were already added with `add_note` as one undo step.
+    pub fn record_added(&mut self, ids: &[u32]) {
+        let added = ids.iter()
+            .filter_map(|id| self.get(*id).cloned())
+            .collect::<Vec<_>>();
+        if added.is_empty() { return; }
+
+        self.push_undo(NoteEdit { removed: Vec::new(), added });
+    }
+
+    /// Changes the length of a note added with `add_note` that isn't recorded yet, like one the pencil is dragging out.
+    pub fn set_drawn_length(&mut self, id: u32, length: u32) {
+        let Some(&(track, start)) = self.note_index.get(&id) else { return; };
+        let Some(notes) = self.tracks.get_mut(&track) else { return; };
+        let notes = Arc::make_mut(notes);
+        let first = notes.partition_point(|n| n.start < start);
+        let Some(note) = notes[first..].iter_mut()
+            .take_while(|n| n.start == start)
+            .find(|n| n.id == id) else { return; };
+        if note.length == length { return; }
+        Arc::make_mut(note).length = length;
+        self.revision += 1;
+        self.voice_revisions.insert(note.channel_track, self.revision);
+        self.render_needs_update = true;
+    }
+
+    pub fn convert_notes(&mut self, notes: impl IntoIterator<Item = Note>) {
+        let notes = notes.into_iter()
+            .map(|n| Arc::new(self.project_note(n)))
+            .collect::<Vec<_>>();
+        self.insert_notes(notes);
+    }
+
+    /// Adds imported control changes, keeping the ones already on a tick before the new ones.
+    pub fn insert_controls(&mut self, controls: impl IntoIterator<Item = ControlEvent>) {
+        self.controls.extend(controls);
+        self.controls.sort_by_key(|control| control.tick);
+    }
+
+    pub fn controls(&self) -> &[ControlEvent] {
+        &self.controls
     }
 
     pub fn remove_last_note(&mut self) {
-        if self.project_notes.len() > 0 {
-            self.project_notes.remove(&self.curr_id);
-            self.curr_id -= 1;
+        if self.curr_id > 0 {
+            self.remove_notes(&[self.curr_id - 1]);
+            self.render_needs_update = true;
+            self.notes_changed = true;
         }
     }
 
-    pub fn get_notes(&self) -> HashMap<usize, Vec<Arc<ProjectNote>>> {
-        let mut notes = self.project_notes.values().map(|v| Arc::clone(v)).collect::<Vec<Arc<ProjectNote>>>();
-        notes.sort_by_key(|n| n.start);
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// The `revision` the notes of every `channel_track` that ever had any last changed at.
+    pub fn voice_revisions(&self) -> &HashMap<u32, u64> {
+        &self.voice_revisions
+    }
+
+    /// Every `channel_track` that has notes, in order.
+    pub fn voices(&self) -> impl Iterator<Item = u32> + '_ {
+        self.voice_counts.keys().copied()
+    }
+
+    pub fn note_count(&self) -> usize {
+        self.note_index.len()
+    }
+
+    /// Every note in the project, in no particular order.
+    pub fn notes(&self) -> impl Iterator<Item = &Arc<ProjectNote>> {
+        self.tracks.values().flat_map(|notes| notes.iter())
+    }
 
-        let mut grouped: HashMap<usize, Vec<Arc<ProjectNote>>> = HashMap::new();
+    pub fn get(&self, id: u32) -> Option<&Arc<ProjectNote>> {
+        let (track, start) = *self.note_index.get(&id)?;
+        let notes = self.tracks.get(&track)?;
+        let first = notes.partition_point(|n| n.start < start);
+        notes[first..].iter()
+            .take_while(|n| n.start == start)
+            .find(|n| n.id == id)
+    }
+
+    /// Adds notes to their tracks, sorting each track that was added to once.
+    fn insert_notes(&mut self, notes: Vec<Arc<ProjectNote>>) {
+        self.revision += 1;
+        let mut touched = HashSet::new();
         for note in notes {
-            grouped.entry(((note.channel_track >> 16) & 0xFFFF) as usize).or_default().push(note)
+            let track = note.track();
+            self.note_index.insert(note.id, (track, note.start));
+            *self.voice_counts.entry(note.channel_track).or_default() += 1;
+            self.voice_revisions.insert(note.channel_track, self.revision);
+            Arc::make_mut(self.tracks.entry(track).or_default()).push(note);
+            touched.insert(track);
+        }
+        for track in touched {
+            if let Some(notes) = self.tracks.get_mut(&track) {
+                Arc::make_mut(notes).sort_by_key(|n| n.start);
+            }
         }
+    }
+
+    /// Removes notes from their tracks, going over each track they were on once.
+    fn remove_notes(&mut self, ids: &[u32]) {
+        self.revision += 1;
+        let mut by_track: HashMap<u16, HashSet<u32>> = HashMap::new();
+        for id in ids {
+            if let Some((track, _)) = self.note_index.remove(id) {
+                by_track.entry(track).or_default().insert(*id);
+            }
+        }
+        for (track, ids) in by_track {
+            let Some(notes) = self.tracks.get_mut(&track) else { continue; };
+            let voice_counts = &mut self.voice_counts;
+            let voice_revisions = &mut self.voice_revisions;
+            let revision = self.revision;
+            Arc::make_mut(notes).retain(|n| {
+                if !ids.contains(&n.id) { return true; }
+                voice_revisions.insert(n.channel_track, revision);
+                if let Some(count) = voice_counts.get_mut(&n.channel_track) {
+                    *count -= 1;
+                    if *count == 0 {
+                        voice_counts.remove(&n.channel_track);
+                    }
+                }
+                false
+            });
+            if notes.is_empty() {
+                self.tracks.remove(&track);
+            }
+        }
+    }
+
+    /// Every track's notes, sorted by start. Only clones the `Arc`s.
+    pub fn get_notes(&self) -> HashMap<usize, TrackNotes> {
+        self.tracks.iter()
+            .map(|(track, notes)| (*track as usize, Arc::clone(notes)))
+            .collect()
+    }
+
+    /// Replaces the notes in `[edit.removed]` with the ones in `[edit.added]`.
+    fn swap_notes(&mut self, edit: &NoteEdit) {
+        let removed = edit.removed.iter().map(|n| n.id).collect::<Vec<u32>>();
+        self.remove_notes(&removed);
+        self.insert_notes(edit.added.clone());
+        self.render_needs_update = true;
+        self.notes_changed = true;
+    }
+
+    /// Applies an edit and records it for undo. Edits that don't change anything aren't recorded.
+    fn apply_edit(&mut self, removed: Vec<Arc<ProjectNote>>, added: Vec<Arc<ProjectNote>>) {
+        if removed.is_empty() && added.is_empty() { return; }
+
+        let edit = NoteEdit { removed, added };
+        self.swap_notes(&edit);
+        self.push_undo(edit);
+    }
+
+    fn push_undo(&mut self, edit: NoteEdit) {
+        self.undo_stack.push(edit);
+        if self.undo_stack.len() > UNDO_LIMIT {
+            self.undo_stack.remove(0);
+        }
+        self.redo_stack.clear();
+    }
+
+    /// Replaces each of the notes in `[ids]` with the result of `[modify]`, as one undo step.
+    /// Notes that `[modify]` leaves unchanged aren't part of the edit.
+    fn modify_notes(&mut self, ids: &[u32], mut modify: impl FnMut(&ProjectNote) -> ProjectNote) {
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        for id in ids {
+            let Some(note) = self.get(*id) else { continue; };
+            let modified = modify(note);
+            if modified != **note {
+                removed.push(Arc::clone(note));
+                added.push(Arc::new(modified));
+            }
+        }
+        self.apply_edit(removed, added);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
 
-        return grouped;
+    pub fn undo(&mut self) {
+        let Some(edit) = self.undo_stack.pop() else { return; };
+        let inverse = NoteEdit { removed: edit.added, added: edit.removed };
+        self.swap_notes(&inverse);
+        self.redo_stack.push(NoteEdit { removed: inverse.added, added: inverse.removed });
     }
 
-    pub fn get_events(&mut self) -> Vec<MIDIEvent> {
+    pub fn redo(&mut self) {
+        let Some(edit) = self.redo_stack.pop() else { return; };
+        self.swap_notes(&edit);
+        self.undo_stack.push(edit);
+    }
+
+    /// Shifts the notes in `[ids]` up or down by `[semitones]`. Notes that would go out of range stop at key 0 or 127.
+    pub fn transpose(&mut self, ids: &[u32], semitones: i32) {
+        self.modify_notes(ids, |note| ProjectNote {
+            key: (note.key as i32 + semitones).clamp(0, 127) as u8,
+            ..*note
+        });
+    }
+
+    /// Moves the notes in `[ids]` later by `[delta_ticks]`, or earlier if it's negative. Notes stop at tick 0.
+    pub fn shift_time(&mut self, ids: &[u32], delta_ticks: i64) {
+        self.modify_notes(ids, |note| ProjectNote {
+            start: (note.start as i64 + delta_ticks).clamp(0, u32::MAX as i64) as u32,
+            ..*note
+        });
+    }
+
+    /// Removes the notes in `[ids]` as one undo step. Each track they're on is only rebuilt once.
+    pub fn delete_notes(&mut self, ids: &[u32]) {
+        let removed = ids.iter()
+            .filter_map(|id| self.get(*id).cloned())
+            .collect::<Vec<_>>();
+        for note in &removed {
+            self.selected.remove(&note.id);
+        }
+        self.apply_edit(removed, Vec::new());
+    }
+
+    /// Multiplies how far the notes in `[ids]` start from `[anchor]` and how long they are by `[factor]`,
+    /// rounded to whole ticks. Notes never end up shorter than a tick or before the start of the song.
+    pub fn scale_time(&mut self, ids: &[u32], factor: f32, anchor: u32) {
+        if factor <= 0.0 { return; }
+        let factor = factor as f64;
+        self.modify_notes(ids, |note| ProjectNote {
+            start: (anchor as f64 + (note.start as f64 - anchor as f64) * factor).round().clamp(0.0, u32::MAX as f64) as u32,
+            length: (note.length as f64 * factor).round().clamp(1.0, u32::MAX as f64) as u32,
+            ..*note
+        });
+    }
+
+    /// Sets the velocity of every note in `[ids]` to `[velocity]`, at least 1 so they stay note ons.
+    pub fn set_velocity(&mut self, ids: &[u32], velocity: u8) {
+        let velocity = velocity.clamp(1, 127);
+        self.modify_notes(ids, |note| ProjectNote { velocity, ..*note });
+    }
+
+    /// Changes the velocities of the notes in `[ids]` in a straight line from `[from]` at the earliest start
+    /// to `[to]` at the latest, like a crescendo. Notes starting together get the same velocity, and none go below 1.
+    pub fn velocity_ramp(&mut self, ids: &[u32], from: u8, to: u8) {
+        let starts = ids.iter().filter_map(|id| self.get(*id)).map(|n| n.start);
+        let Some(first) = starts.clone().min() else { return; };
+        let span = (starts.max().unwrap_or(first) - first) as f32;
+        self.modify_notes(ids, |note| {
+            let t = if span > 0.0 { (note.start - first) as f32 / span } else { 0.0 };
+            ProjectNote {
+                velocity: (from as f32 + (to as f32 - from as f32) * t).round().clamp(1.0, 127.0) as u8,
+                ..*note
+            }
+        });
+    }
+
+    /// Mirrors the notes in `[ids]` in time within the span they cover together,
+    /// so the last note to end becomes the first to start. Keys and velocities stay the same.
+    pub fn reverse(&mut self, ids: &[u32]) {
+        let notes = ids.iter().filter_map(|id| self.get(*id));
+        let Some(span_start) = notes.clone().map(|n| n.start).min() else { return; };
+        let span_end = notes.map(|n| n.end()).max().unwrap_or(span_start);
+        self.modify_notes(ids, |note| ProjectNote {
+            start: span_start + (span_end - note.end()),
+            ..*note
+        });
+    }
+
+    /// Moves the starts of the notes in `[ids]` by up to `[max_ticks]` and changes their velocities by up to `[max_velocity]`,
+    /// either way at random. The notes are visited in id order, so the same `[seed]` always gives the same result.
+    pub fn humanize(&mut self, ids: &[u32], max_ticks: u32, max_velocity: u8, seed: u64) {
+        let mut ids = ids.to_vec();
+        ids.sort_unstable();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let max_ticks = max_ticks as i64;
+        let max_velocity = max_velocity as i32;
+        self.modify_notes(&ids, |note| ProjectNote {
+            start: (note.start as i64 + rng.gen_range(-max_ticks..=max_ticks)).clamp(0, u32::MAX as i64) as u32,
+            velocity: (note.velocity as i32 + rng.gen_range(-max_velocity..=max_velocity)).clamp(1, 127) as u8,
+            ..*note
+        });
+    }
+
+    /// Snaps the starts of the notes in `[ids]` to the nearest step of a grid `[step]` ticks apart, keeping their lengths.
+    /// `[swing]` is how far into each pair of steps the second one falls, 0.5 is straight and about 0.67 a triplet feel.
+    /// The pairs are counted from the start of the song.
+    pub fn quantize(&mut self, ids: &[u32], step: u32, swing: f32) {
+        if step == 0 { return; }
+        let pair = step as f64 * 2.0;
+        let off_beat = pair * swing.clamp(0.0, 1.0) as f64;
+        self.modify_notes(ids, |note| {
+            let pair_start = (note.start as f64 / pair).floor() * pair;
+            let within = note.start as f64 - pair_start;
+            let snapped = [0.0, off_beat, pair].into_iter()
+                .min_by(|a, b| (within - a).abs().total_cmp(&(within - b).abs()))
+                .unwrap_or(0.0);
+            ProjectNote {
+                start: (pair_start + snapped).round().clamp(0.0, u32::MAX as f64) as u32,
+                ..*note
+            }
+        });
+    }
+
+    /// Makes each note in `[ids]` last until the next note on its key and `channel_track` starts, plus `[overlap]` ticks,
+    /// so a negative overlap leaves a gap. The last note on a key has nothing to reach and stays as it is.
+    /// Notes never end up shorter than a tick.
+    pub fn legato(&mut self, ids: &[u32], overlap: i32) {
+        // the starts of every note on the tracks the notes are on, by voice and key
+        let tracks = ids.iter().filter_map(|id| self.get(*id)).map(|n| n.track()).collect::<HashSet<_>>();
+        let mut starts: HashMap<(u32, u8), Vec<u32>> = HashMap::new();
+        for track in tracks {
+            for note in self.tracks.get(&track).into_iter().flat_map(|notes| notes.iter()) {
+                starts.entry((note.channel_track, note.key)).or_default().push(note.start);
+            }
+        }
+        self.modify_notes(ids, |note| {
+            // the track's notes are sorted by start, so these are too
+            let key_starts = &starts[&(note.channel_track, note.key)];
+            let Some(next) = key_starts.get(key_starts.partition_point(|s| *s <= note.start)) else {
+                return *note;
+            };
+            ProjectNote {
+                length: (*next as i64 - note.start as i64 + overlap as i64).clamp(1, u32::MAX as i64) as u32,
+                ..*note
+            }
+        });
+    }
+
+    /// Merges the notes in `[ids]` that touch or overlap on the same key and `channel_track` into one note,
+    /// from the earliest start to the latest end with the velocity of the first, as one undo step.
+    /// The first note of each run keeps its id, the others are removed.
+    pub fn glue(&mut self, ids: &[u32]) {
+        let mut by_key: HashMap<(u32, u8), Vec<Arc<ProjectNote>>> = HashMap::new();
+        for note in ids.iter().filter_map(|id| self.get(*id)) {
+            by_key.entry((note.channel_track, note.key)).or_default().push(Arc::clone(note));
+        }
+
+        let mut removed = Vec::new();
+        let mut added = Vec::new();
+        for mut notes in by_key.into_values() {
+            notes.sort_by_key(|n| (n.start, n.id));
+            let mut runs: Vec<Vec<Arc<ProjectNote>>> = Vec::new();
+            let mut run_end = 0;
+            for note in notes {
+                match runs.last_mut() {
+                    Some(run) if note.start <= run_end => {
+                        run_end = run_end.max(note.end());
+                        run.push(note);
+                    }
+                    _ => {
+                        run_end = note.end();
+                        runs.push(vec![note]);
+                    }
+                }
+            }
+            for run in runs.into_iter().filter(|run| run.len() > 1) {
+                let first = *run[0];
+                let end = run.iter().map(|n| n.end()).max().unwrap_or(first.end());
+                added.push(Arc::new(ProjectNote { length: end - first.start, ..first }));
+                for note in &run[1..] {
+                    self.selected.remove(&note.id);
+                }
+                removed.extend(run);
+            }
+        }
+        self.apply_edit(removed, added);
+    }
+
+    /// Cuts each note in `[ids]` that starts before `[tick]` and ends after it in two at `[tick]`, as one undo step.
+    /// Both pieces keep the note's key and velocity and the first keeps its id. Notes that don't cross `[tick]` are skipped.
+    /// The pieces are selected instead of the current selection, unless nothing was split.
+    pub fn split_at(&mut self, ids: &[u32], tick: u32) {
+        let notes = ids.iter()
+            .filter_map(|id| self.get(*id))
+            .filter(|n| n.start < tick && tick < n.end())
+            .cloned()
+            .collect::<Vec<_>>();
+        if notes.is_empty() { return; }
+
+        let mut added = Vec::with_capacity(notes.len() * 2);
+        for note in &notes {
+            let second = self.project_note(Note { start: tick, length: note.end() - tick, ..note.to_note() });
+            added.push(Arc::new(ProjectNote { length: tick - note.start, ..**note }));
+            added.push(Arc::new(second));
+        }
+        self.selected = added.iter().map(|n| n.id).collect();
+        self.apply_edit(notes, added);
+    }
+
+    /// Copies of the notes in `[ids]` with their starts made relative to the earliest one, the way they'd go on a clipboard.
+    pub fn copy_notes(&self, ids: &[u32]) -> Vec<Note> {
+        let mut notes = ids.iter()
+            .filter_map(|id| self.get(*id))
+            .map(|n| n.to_note())
+            .collect::<Vec<_>>();
+        let first_start = notes.iter().map(|n| n.start).min().unwrap_or(0);
+        for note in notes.iter_mut() {
+            note.start -= first_start;
+        }
+        notes
+    }
+
+    /// Adds `[notes]` from `copy_notes` starting at `[tick]` as one undo step and selects them instead of the current selection.
+    pub fn paste_notes(&mut self, notes: &[Note], tick: u32) {
+        let added = notes.iter()
+            .map(|n| Arc::new(self.project_note(Note { start: n.start.saturating_add(tick), ..*n })))
+            .collect::<Vec<_>>();
+        self.selected = added.iter().map(|n| n.id).collect();
+        self.apply_edit(Vec::new(), added);
+    }
+
+    /// Copies the notes in `[ids]` to right after the last of them ends and selects the copies.
+    pub fn duplicate(&mut self, ids: &[u32]) {
+        let Some(end) = ids.iter().filter_map(|id| self.get(*id)).map(|n| n.end()).max() else { return; };
+        let notes = self.copy_notes(ids);
+        self.paste_notes(&notes, end);
+    }
+
+    pub fn selected_ids(&self) -> Vec<u32> {
+        self.selected.iter().copied().collect()
+    }
+
+    /// The topmost note at `[tick]` and `[key]`, if there is one. Later notes are drawn on top of earlier ones.
+    pub fn note_at(&self, tick: f32, key: u8) -> Option<u32> {
+        self.notes()
+            .filter(|n| n.key == key && (n.start as f32) <= tick && tick < n.end() as f32)
+            .max_by_key(|n| (n.track(), n.start))
+            .map(|n| n.id)
+    }
+
+    /// The first note starting after `[tick]` on `[track]`, or on any track if it's `None`. Binary searches each track.
+    pub fn next_note_after(&self, tick: u32, track: Option<u16>) -> Option<&Arc<ProjectNote>> {
+        self.tracks.iter()
+            .filter(|(t, _)| track.is_none_or(|track| **t == track))
+            .filter_map(|(_, notes)| notes.get(notes.partition_point(|n| n.start <= tick)))
+            .min_by_key(|n| (n.start, n.key))
+    }
+
+    /// The last note starting before `[tick]` on `[track]`, or on any track if it's `None`. Binary searches each track.
+    pub fn previous_note_before(&self, tick: u32, track: Option<u16>) -> Option<&Arc<ProjectNote>> {
+        self.tracks.iter()
+            .filter(|(t, _)| track.is_none_or(|track| **t == track))
+            .filter_map(|(_, notes)| notes.partition_point(|n| n.start < tick).checked_sub(1).map(|i| &notes[i]))
+            .max_by_key(|n| (n.start, std::cmp::Reverse(n.key)))
+    }
+
+    /// Selects every note overlapping the ticks `[start_tick]..[end_tick]` on the keys `[low_key]..=[high_key]`.
+    /// The selection is replaced unless `[add]` is set.
+    pub fn select_in_rect(&mut self, start_tick: f32, end_tick: f32, low_key: u8, high_key: u8, add: bool) {
+        if !add {
+            self.selected.clear();
+        }
+
+        let in_rect = self.tracks.values()
+            .flat_map(|notes| &notes[..notes.partition_point(|n| (n.start as f32) < end_tick)])
+            .filter(|n| n.end() as f32 > start_tick && (low_key..=high_key).contains(&n.key))
+            .map(|n| n.id);
+        self.selected.extend(in_rect);
+        self.render_needs_update = true;
+    }
+
+    /// Selects a single note, or toggles it in the selection if `[add]` is set.
+    pub fn select_note(&mut self, id: u32, add: bool) {
+        if !add {
+            self.selected.clear();
+            self.selected.insert(id);
+        } else if !self.selected.remove(&id) {
+            self.selected.insert(id);
+        }
+        self.render_needs_update = true;
+    }
+
+    /// Selects every note on `[key]` across all tracks instead of the current selection.
+    pub fn select_key(&mut self, key: u8) {
+        self.selected = self.notes()
+            .filter(|n| n.key == key)
+            .map(|n| n.id)
+            .collect();
+        self.render_needs_update = true;
+    }
+
+    /// Selects every note in the project. Only the ids are collected, the notes aren't touched.
+    pub fn select_all(&mut self) {
+        self.selected = self.note_index.keys().copied().collect();
+        self.render_needs_update = true;
+    }
+
+    /// Selects the notes that aren't selected and deselects the ones that are.
+    pub fn invert_selection(&mut self) {
+        self.selected = self.note_index.keys()
+            .filter(|id| !self.selected.contains(id))
+            .copied()
+            .collect();
+        self.render_needs_update = true;
+    }
+
+    pub fn clear_selection(&mut self) {
+        if !self.selected.is_empty() {
+            self.selected.clear();
+            self.render_needs_update = true;
+        }
+    }
+
+    /// Scans every note for the statistics panel. This walks the whole project, so cache the result.
+    pub fn stats(&self, ppq: u16, playback: &Playback, time_signatures: &TimeSignatureMap) -> ProjectStats {
+        let mut stats = ProjectStats {
+            note_count: self.note_count(),
+            ..Default::default()
+        };
+
+        for note in self.notes() {
+            *stats.notes_per_track.entry(note.track()).or_default() += 1;
+            stats.notes_per_channel[(note.channel() & 0x0F) as usize] += 1;
+            stats.key_range = Some(match stats.key_range {
+                Some((low, high)) => (low.min(note.key), high.max(note.key)),
+                None => (note.key, note.key)
+            });
+            stats.end_tick = stats.end_tick.max(note.end());
+        }
+
+        stats.length_bars = time_signatures.bars_until(ppq, stats.end_tick as u64);
+        stats.length_secs = playback.tick_to_secs(ppq, stats.end_tick as f32);
+        stats
+    }
+
+    /// Builds the note on and off and control events the synth plays, timed in seconds from `[start_secs]` into the song.
+    /// They're on the channels of a synth with `[ports]` sets of 16, see `synth_channel`.
+    /// Events of the `channel_track`s in `[silenced]` and notes starting before `[start_secs]` are left out.
+    /// The last value of each controller before `[start_secs]` is sent at the start, so e.g. a held pedal stays held.
+    pub fn get_events(&self, ppq: u16, playback: &Playback, silenced: &HashSet<u32>, start_secs: f32, ports: u32) -> Vec<MIDIEvent> {
+        // each event with where it goes among the events on its tick, see the sort below
         let mut events = Vec::new();
 
-        for note in self.project_notes.values() {
-            let ch = (note.channel_track & 0xFF) as u8;
+        let audible = self.notes()
+            .filter(|note| !silenced.contains(&note.channel_track));
+        for note in audible {
+            let ch = synth_channel(note.track(), note.channel(), ports);
 
-            events.push(
+            events.push((2,
                 MIDIEvent {
                     time: note.start as f32,
                     event_type: MIDIEventType::NoteOn,
-                    data: vec![
-                        0x90 | (ch & 0x0F),
-                        note.key,
-                        note.velocity
-                    ]
+                    data: vec![ch, note.key, note.velocity]
                 }
-            );
+            ));
 
-            events.push(
+            events.push((if note.length == 0 { 3 } else { 0 },
                 MIDIEvent {
-                    time: (note.start + note.length) as f32,
+                    time: note.end() as f32,
                     event_type: MIDIEventType::NoteOff,
-                    data: vec![
-                        0x80 | (ch & 0x0F),
-                        note.key
-                    ]
+                    data: vec![ch, note.key, note.velocity]
+                }
+            ));
+        }
+
+        let audible_controls = self.controls.iter()
+            .filter(|control| control.kind.is_played())
+            .filter(|control| !silenced.contains(&(((control.track as u32) << 8) | control.channel as u32)));
+        for control in audible_controls {
+            let ch = synth_channel(control.track, control.channel, ports);
+            let (event_type, data) = match control.kind {
+                ControlKind::Controller { number, value } => (MIDIEventType::ControlChange, vec![ch, number, value]),
+                ControlKind::PitchBend(_) => {
+                    let (_, [lsb, msb]) = control.kind.bytes();
+                    (MIDIEventType::PitchBend, vec![ch, lsb, msb])
                 }
-            );
+            };
+            events.push((1, MIDIEvent { time: control.tick as f32, event_type, data }));
+        }
+
+        // note offs go first so a note ending where the next one on the same key starts doesn't cut it off,
+        // then controls so a note starting with a pedal press is held by it. The sort is stable, so controls stay in order.
+        // A note that ends where it starts is released after its own note on, otherwise it'd be left hanging
+        events.sort_by_key(|(order, e)| (e.time as u32, *order));
+        let mut events = events.into_iter().map(|(_, e)| e).collect::<Vec<_>>();
+        playback.sorted_ticks_to_secs(ppq, events.iter_mut().map(|e| &mut e.time));
+        if start_secs > 0.0 {
+            catch_up_controls(&mut events, start_secs);
+            for e in events.iter_mut() {
+                e.time -= start_secs;
+            }
         }
-        events.sort_by_key(|e| (e.time * 1000000.0) as u32);
         events
     }
-}
\ No newline at end of file
+}
+
+/// Replaces the events of sorted `[events]` before `[start_secs]` with the last value of each controller they set,
+/// sent at `[start_secs]` in the order they were. Data entries are caught up on for each RPN they went to.
+fn catch_up_controls(events: &mut Vec<MIDIEvent>, start_secs: f32) {
+    let control = |channel: u8, number: u8, value: u8| MIDIEvent {
+        time: start_secs,
+        event_type: MIDIEventType::ControlChange,
+        data: vec![channel, number, value]
+    };
+
+    let first = events.partition_point(|e| e.time < start_secs);
+    let mut rpns = BTreeMap::new();
+    // by channel and what's set, the index of the last event setting it and the RPN of a data entry
+    let mut last_values = HashMap::new();
+    for (i, e) in events[..first].iter().enumerate() {
+        let channel = e.data[0];
+        let rpn = rpns.entry(channel).or_insert(RPN_NULL);
+        let (target, entry_rpn) = match e.event_type {
+            MIDIEventType::ControlChange => match e.data[1] {
+                CC_DATA_ENTRY | CC_DATA_ENTRY_LSB if *rpn == RPN_NULL => continue,
+                CC_DATA_ENTRY | CC_DATA_ENTRY_LSB => {
+                    // past the controller numbers and pitch bend
+                    let (msb, lsb) = *rpn;
+                    (0x10000 | (msb as u32) << 8 | (lsb as u32) << 1 | (e.data[1] == CC_DATA_ENTRY_LSB) as u32, Some(*rpn))
+                }
+                number => {
+                    match number {
+                        CC_RPN_MSB => rpn.0 = e.data[2],
+                        CC_RPN_LSB => rpn.1 = e.data[2],
+                        CC_RESET_ALL_CONTROLLERS => {
+                            *rpn = RPN_NULL;
+                            // what the controllers and bend were set to before is gone, the bend range stays like in `ChannelPitch`
+                            last_values.retain(|&(value_channel, target), _| value_channel != channel || target >= 0x10000);
+                        }
+                        _ => {}
+                    }
+                    (number as u32, None)
+                }
+            },
+            // past the controller numbers
+            MIDIEventType::PitchBend => (0x100, None),
+            _ => continue
+        };
+        last_values.insert((channel, target), (i, entry_rpn));
+    }
+
+    let mut chased = last_values.into_values().collect::<Vec<_>>();
+    chased.sort_unstable_by_key(|(i, _)| *i);
+    let mut caught_up = Vec::with_capacity(chased.len());
+    let mut reselected = HashSet::new();
+    for (i, entry_rpn) in chased {
+        let e = &events[i];
+        if let Some((msb, lsb)) = entry_rpn {
+            caught_up.push(control(e.data[0], CC_RPN_MSB, msb));
+            caught_up.push(control(e.data[0], CC_RPN_LSB, lsb));
+            reselected.insert(e.data[0]);
+        }
+        caught_up.push(MIDIEvent { time: start_secs, ..e.clone() });
+    }
+    // the data entries selected their RPNs again, leave each channel with the one it had
+    for (channel, (msb, lsb)) in rpns {
+        if reselected.contains(&channel) {
+            caught_up.push(control(channel, CC_RPN_MSB, msb));
+            caught_up.push(control(channel, CC_RPN_LSB, lsb));
+        }
+    }
+    events.splice(..first, caught_up);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{catch_up_controls, Note, ProjectNoteManager};
+    use crate::audio::playback::Playback;
+    use crate::midi::events::{MIDIEvent, MIDIEventType, TimeSignatureEvent, CC_RESET_ALL_CONTROLLERS, CC_SUSTAIN};
+    use crate::midi::time_signatures::TimeSignatureMap;
+
+    fn control(time: f32, number: u8, value: u8) -> MIDIEvent {
+        MIDIEvent { time, event_type: MIDIEventType::ControlChange, data: vec![0, number, value] }
+    }
+
+    fn note(time: f32, event_type: MIDIEventType, key: u8) -> MIDIEvent {
+        MIDIEvent { time, event_type, data: vec![0, key, 100] }
+    }
+
+    /// The time, type and data of `[events]`, for comparing them.
+    fn summary(events: &[MIDIEvent]) -> Vec<(f32, u8, Vec<u8>)> {
+        events.iter().map(|e| (e.time, e.event_type as u8, e.data.clone())).collect()
+    }
+
+    #[test]
+    fn held_pedal_is_sent_again_at_the_start() {
+        // the pedal goes down, two notes are released under it, and it's lifted after playback starts at 1.0
+        let mut events = vec![
+            control(0.25, CC_SUSTAIN, 127),
+            note(0.5, MIDIEventType::NoteOn, 60),
+            note(0.5, MIDIEventType::NoteOn, 64),
+            note(0.75, MIDIEventType::NoteOff, 60),
+            note(0.75, MIDIEventType::NoteOff, 64),
+            note(1.25, MIDIEventType::NoteOn, 67),
+            note(1.5, MIDIEventType::NoteOff, 67),
+            control(2.0, CC_SUSTAIN, 0)
+        ];
+        catch_up_controls(&mut events, 1.0);
+
+        let expected = vec![
+            control(1.0, CC_SUSTAIN, 127),
+            note(1.25, MIDIEventType::NoteOn, 67),
+            note(1.5, MIDIEventType::NoteOff, 67),
+            control(2.0, CC_SUSTAIN, 0)
+        ];
+        assert_eq!(summary(&events), summary(&expected));
+    }
+
+    #[test]
+    fn reset_all_controllers_clears_an_earlier_pedal() {
+        let mut events = vec![
+            control(0.25, CC_SUSTAIN, 127),
+            MIDIEvent { time: 0.3, event_type: MIDIEventType::PitchBend, data: vec![0, 0, 0x60] },
+            control(0.5, CC_RESET_ALL_CONTROLLERS, 0),
+            note(1.25, MIDIEventType::NoteOn, 60)
+        ];
+        catch_up_controls(&mut events, 1.0);
+
+        let expected = vec![
+            control(1.0, CC_RESET_ALL_CONTROLLERS, 0),
+            note(1.25, MIDIEventType::NoteOn, 60)
+        ];
+        assert_eq!(summary(&events), summary(&expected));
+    }
+
+    #[test]
+    fn length_in_bars_follows_the_time_signature() {
+        let mut notes = ProjectNoteManager::new();
+        // two bars of 3/4 at 96 PPQ, then half a bar of 6/8
+        notes.add_note(Note { start: 0, length: 576 + 144, channel: 0, track: 0, key: 60, velocity: 100 });
+        let time_signatures = TimeSignatureMap::new(vec![
+            TimeSignatureEvent { time: 0, numerator: 3, denominator: 4 },
+            TimeSignatureEvent { time: 576, numerator: 6, denominator: 8 }
+        ]);
+        let stats = notes.stats(96, &Playback::new(), &time_signatures);
+        assert!((stats.length_bars - 2.5).abs() < 1e-4, "{}", stats.length_bars);
+    }
+
+    #[test]
+    fn length_in_bars_counts_the_pickup_as_part_of_a_bar() {
+        let mut notes = ProjectNoteManager::new();
+        notes.add_note(Note { start: 0, length: 96 + 384, channel: 0, track: 0, key: 60, velocity: 100 });
+        // a one beat pickup in 4/4, then a whole bar
+        let mut time_signatures = TimeSignatureMap::new(Vec::new());
+        time_signatures.set_pickup(96);
+        let stats = notes.stats(96, &Playback::new(), &time_signatures);
+        assert!((stats.length_bars - 1.25).abs() < 1e-4, "{}", stats.length_bars);
+    }
+
+    #[test]
+    fn zero_length_note_is_released_after_it_starts() {
+        let mut notes = ProjectNoteManager::new();
+        notes.add_note(Note { start: 96, length: 0, channel: 0, track: 0, key: 60, velocity: 100 });
+        let events = notes.get_events(96, &Playback::new(), &Default::default(), 0.0, 1);
+        let types = events.iter().map(|e| e.event_type as u8).collect::<Vec<_>>();
+        assert_eq!(types, vec![MIDIEventType::NoteOn as u8, MIDIEventType::NoteOff as u8]);
+    }
+}
@@ -0,0 +1,88 @@
+/// The names of the pitch classes, from C.
+pub const PITCH_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// The name of MIDI key `[key]` with its octave, where 60 is middle C (C4).
+pub fn key_name(key: u8) -> String {
+    format!("{}{}", PITCH_NAMES[key as usize % 12], key as i32 / 12 - 1)
+}
+
+/// The kinds of scale the piano roll can highlight.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScaleKind {
+    #[default]
+    Major,
+    NaturalMinor,
+    HarmonicMinor,
+    MelodicMinor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues
+}
+
+impl ScaleKind {
+    pub const ALL: [ScaleKind; 12] = [
+        ScaleKind::Major, ScaleKind::NaturalMinor, ScaleKind::HarmonicMinor, ScaleKind::MelodicMinor,
+        ScaleKind::Dorian, ScaleKind::Phrygian, ScaleKind::Lydian, ScaleKind::Mixolydian, ScaleKind::Locrian,
+        ScaleKind::MajorPentatonic, ScaleKind::MinorPentatonic, ScaleKind::Blues
+    ];
+
+    /// The semitones above the root that are in the scale.
+    fn intervals(&self) -> &'static [u8] {
+        match self {
+            ScaleKind::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleKind::NaturalMinor => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleKind::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            ScaleKind::MelodicMinor => &[0, 2, 3, 5, 7, 9, 11],
+            ScaleKind::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            ScaleKind::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            ScaleKind::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            ScaleKind::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            ScaleKind::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            ScaleKind::MajorPentatonic => &[0, 2, 4, 7, 9],
+            ScaleKind::MinorPentatonic => &[0, 3, 5, 7, 10],
+            ScaleKind::Blues => &[0, 3, 5, 6, 7, 10]
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScaleKind::Major => "Major",
+            ScaleKind::NaturalMinor => "Minor",
+            ScaleKind::HarmonicMinor => "Harmonic minor",
+            ScaleKind::MelodicMinor => "Melodic minor",
+            ScaleKind::Dorian => "Dorian",
+            ScaleKind::Phrygian => "Phrygian",
+            ScaleKind::Lydian => "Lydian",
+            ScaleKind::Mixolydian => "Mixolydian",
+            ScaleKind::Locrian => "Locrian",
+            ScaleKind::MajorPentatonic => "Major pentatonic",
+            ScaleKind::MinorPentatonic => "Minor pentatonic",
+            ScaleKind::Blues => "Blues"
+        }
+    }
+}
+
+/// A scale on a root pitch class, like C major.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct MusicalScale {
+    /// the pitch class of the root, 0 for C up to 11 for B
+    pub root: u8,
+    pub kind: ScaleKind,
+}
+
+impl MusicalScale {
+    /// The pitch classes in the scale as bits, bit 0 for C up to bit 11 for B.
+    pub fn pitch_mask(&self) -> u16 {
+        self.kind.intervals().iter()
+            .fold(0, |mask, interval| mask | 1 << ((self.root % 12 + interval) % 12))
+    }
+
+    pub fn contains(&self, key: u8) -> bool {
+        self.pitch_mask() & (1 << (key % 12)) != 0
+    }
+}
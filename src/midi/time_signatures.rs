@@ -0,0 +1,194 @@
+use super::events::TimeSignatureEvent;
+
+/// What seeks snap to.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum SnapMode {
+    #[default]
+    Off,
+    Beat,
+    Bar
+}
+
+impl SnapMode {
+    pub const ALL: [SnapMode; 3] = [SnapMode::Off, SnapMode::Beat, SnapMode::Bar];
+
+    /// The name it's saved under in the settings.
+    pub fn key(&self) -> &'static str {
+        match self {
+            SnapMode::Off => "off",
+            SnapMode::Beat => "beat",
+            SnapMode::Bar => "bar"
+        }
+    }
+}
+
+/// The time signature changes of a project, used to lay ticks out in bars and beats.
+/// Always has a signature at tick 0, 4/4 if the project doesn't set one.
+/// Bar 1 starts after the pickup, which is numbered bar 0 and ends on the last beats of its bar.
+#[derive(Clone)]
+pub struct TimeSignatureMap {
+    events: Vec<TimeSignatureEvent>,
+    /// the length of the pickup in ticks, bar 1 starts on this tick
+    pickup: u64,
+}
+
+impl Default for TimeSignatureMap {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl TimeSignatureMap {
+    /// Builds the map from time signature events in any order. When several land on the same tick the last one wins.
+    pub fn new(mut events: Vec<TimeSignatureEvent>) -> Self {
+        events.sort_by_key(|ev| ev.time);
+        let mut deduped: Vec<TimeSignatureEvent> = Vec::with_capacity(events.len() + 1);
+        for ev in events {
+            if ev.numerator == 0 || ev.denominator == 0 { continue; }
+            match deduped.last_mut() {
+                Some(last) if last.time == ev.time => *last = ev,
+                _ => deduped.push(ev)
+            }
+        }
+        if deduped.first().is_none_or(|ev| ev.time > 0) {
+            deduped.insert(0, TimeSignatureEvent { time: 0, numerator: 4, denominator: 4 });
+        }
+        Self { events: deduped, pickup: 0 }
+    }
+
+    /// The signatures sorted by tick, starting with the one at tick 0.
+    pub fn events(&self) -> &[TimeSignatureEvent] {
+        &self.events
+    }
+
+    /// The tick bar 1 starts on, 0 without a pickup.
+    pub fn pickup(&self) -> u64 {
+        self.pickup
+    }
+
+    pub fn set_pickup(&mut self, pickup: u64) {
+        self.pickup = pickup;
+    }
+
+    /// The first bar there is, 0 when there's a pickup.
+    pub fn first_bar(&self) -> u64 {
+        if self.pickup > 0 { 0 } else { 1 }
+    }
+
+    /// The length of a bar of the signature at tick 0, the longest a pickup can be.
+    pub fn first_bar_ticks(&self, ppq: u16) -> u64 {
+        Self::bar_ticks(ppq, &self.events[0])
+    }
+
+    fn beat_ticks(ppq: u16, ev: &TimeSignatureEvent) -> u64 {
+        (ppq as u64 * 4 / ev.denominator as u64).max(1)
+    }
+
+    fn bar_ticks(ppq: u16, ev: &TimeSignatureEvent) -> u64 {
+        Self::beat_ticks(ppq, ev) * ev.numerator as u64
+    }
+
+    /// Walks the signatures from the end of the pickup, calling `[segment]` with each one's start tick, the bar it starts
+    /// (from 1), the signature and the next signature's start tick, until it returns true.
+    /// A signature that starts mid-bar starts a new bar there.
+    fn walk(&self, ppq: u16, mut segment: impl FnMut(u64, u64, &TimeSignatureEvent, Option<u64>) -> bool) {
+        let mut bar = 1;
+        for (i, ev) in self.events.iter().enumerate() {
+            let next = self.events.get(i + 1).map(|next| next.time);
+            // signatures that change within the pickup only decide how it's counted
+            if next.is_some_and(|next| next <= self.pickup) { continue; }
+            let start = ev.time.max(self.pickup);
+            if segment(start, bar, ev, next) { return; }
+            if let Some(next) = next {
+                bar += (next - start).div_ceil(Self::bar_ticks(ppq, ev));
+            }
+        }
+    }
+
+    /// The bar, beat (both from 1, bar 0 being the pickup) and tick into the beat at `[tick]`.
+    pub fn bar_beat_tick(&self, ppq: u16, tick: u64) -> (u64, u64, u64) {
+        if tick < self.pickup {
+            // counted back from bar 1, so a pickup of one beat in 4/4 is beat 4
+            let ev = self.signature_at(tick);
+            let bar_ticks = Self::bar_ticks(ppq, ev);
+            let beat_ticks = Self::beat_ticks(ppq, ev);
+            let into_bar = (bar_ticks - (self.pickup - tick) % bar_ticks) % bar_ticks;
+            return (0, into_bar / beat_ticks + 1, into_bar % beat_ticks);
+        }
+        let mut pos = (1, 1, 0);
+        self.walk(ppq, |start, bar, ev, next| {
+            if next.is_some_and(|next| next <= tick) { return false; }
+            let into = tick.saturating_sub(start);
+            let bar_ticks = Self::bar_ticks(ppq, ev);
+            let beat_ticks = Self::beat_ticks(ppq, ev);
+            let into_bar = into % bar_ticks;
+            pos = (bar + into / bar_ticks, into_bar / beat_ticks + 1, into_bar % beat_ticks);
+            true
+        });
+        pos
+    }
+
+    /// The tick `[bar]` (from 1) starts on. Bar 0 is the pickup and starts on tick 0.
+    pub fn bar_to_tick(&self, ppq: u16, bar: u64) -> u64 {
+        if bar == 0 { return 0; }
+        let mut tick = self.pickup;
+        self.walk(ppq, |start, first_bar, ev, next| {
+            let bar_ticks = Self::bar_ticks(ppq, ev);
+            let bars = next.map(|next| (next - start).div_ceil(bar_ticks));
+            if bars.is_some_and(|bars| first_bar + bars <= bar) { return false; }
+            tick = start + (bar - first_bar) * bar_ticks;
+            true
+        });
+        tick
+    }
+
+    /// The tick `[bar]` starts on, its length and the length of its beats, all in ticks.
+    /// A bar cut short by a signature change is only as long as it lasts. The pickup, bar 0,
+    /// is given as the whole bar it's the end of, starting on tick 0.
+    pub fn bar_span(&self, ppq: u16, bar: u64) -> (u64, u64, u64) {
+        let start = self.bar_to_tick(ppq, bar);
+        let ev = self.signature_at(start);
+        let length = if bar == 0 {
+            Self::bar_ticks(ppq, ev)
+        } else {
+            self.bar_to_tick(ppq, bar + 1) - start
+        };
+        (start, length, Self::beat_ticks(ppq, ev))
+    }
+
+    /// How many bars go by from the start of the song to `[tick]`, as long as each bar is in its signature.
+    /// The pickup counts as the part of its bar it lasts.
+    pub fn bars_until(&self, ppq: u16, tick: u64) -> f32 {
+        let first_bar_ticks = self.first_bar_ticks(ppq) as f32;
+        let (bar, _, _) = self.bar_beat_tick(ppq, tick);
+        if bar == 0 {
+            return tick as f32 / first_bar_ticks;
+        }
+        let (start, length, _) = self.bar_span(ppq, bar);
+        self.pickup as f32 / first_bar_ticks + (bar - 1) as f32 + (tick - start) as f32 / length.max(1) as f32
+    }
+
+    /// The signature in effect at `[tick]`.
+    fn signature_at(&self, tick: u64) -> &TimeSignatureEvent {
+        let index = self.events.partition_point(|ev| ev.time <= tick);
+        &self.events[index.saturating_sub(1)]
+    }
+
+    /// The bar or beat line closest to `[tick]`.
+    pub fn snap(&self, ppq: u16, tick: u64, snap: SnapMode) -> u64 {
+        let (bar, _, beat_tick) = self.bar_beat_tick(ppq, tick);
+        let bar_start = self.bar_to_tick(ppq, bar);
+        let next_bar = self.bar_to_tick(ppq, bar + 1);
+        let (before, after) = match snap {
+            SnapMode::Off => return tick,
+            SnapMode::Bar => (bar_start, next_bar),
+            SnapMode::Beat => {
+                // the pickup's first beat can start before tick 0
+                let beat_start = tick.saturating_sub(beat_tick);
+                // a bar cut short by a signature change ends before its last beat does
+                (beat_start, (beat_start + Self::beat_ticks(ppq, self.signature_at(tick))).min(next_bar))
+            }
+        };
+        if tick - before <= after - tick { before } else { after }
+    }
+}
@@ -1,3 +1,5 @@
 pub mod piano_roll;
 pub mod shaders;
-pub mod buffers;
\ No newline at end of file
+pub mod buffers;
+pub mod offscreen;
+pub mod frame_sequence;
@@ -37,6 +37,23 @@ impl Buffer {
             );
         }
     }
+
+    /// Reallocates the buffer to `[size]` bytes, leaving its contents undefined.
+    pub fn allocate(&self, size: usize, usage: u32) {
+        unsafe {
+            self.bind();
+            self.gl.buffer_data_size(self.target, size as i32, usage);
+        }
+    }
+
+    /// Overwrites the start of the buffer with `[data]` without reallocating it.
+    pub fn set_sub_data<D>(&self, data: &[D]) {
+        unsafe {
+            self.bind();
+            let (_, data_bytes, _) = data.align_to::<u8>();
+            self.gl.buffer_sub_data_u8_slice(self.target, 0, data_bytes);
+        }
+    }
 }
 
 impl Drop for Buffer {
@@ -0,0 +1,168 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::JoinHandle;
+
+use eframe::glow;
+
+use super::offscreen::{save_png, OffscreenTarget};
+
+/// How many rendered frames can wait to be written before rendering waits for the writer.
+const WRITE_QUEUE: usize = 4;
+/// How wide the playhead drawn into the frames is, in pixels.
+const PLAYHEAD_WIDTH: u32 = 2;
+
+/// Where the playhead is in the view while the song scrolls past in an image sequence.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum SequenceScroll {
+    /// the view follows the playhead at its left edge, like following playback in the editor
+    #[default]
+    LeftEdge,
+    /// the view follows the playhead in its middle, so what's about to play and what just played are both shown
+    Centered,
+    /// the view stays put while the playhead crosses it, then turns to the next page
+    Pages
+}
+
+impl SequenceScroll {
+    pub const ALL: [SequenceScroll; 3] = [SequenceScroll::LeftEdge, SequenceScroll::Centered, SequenceScroll::Pages];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SequenceScroll::LeftEdge => "Playhead at the left edge",
+            SequenceScroll::Centered => "Playhead centered",
+            SequenceScroll::Pages => "Pages"
+        }
+    }
+
+    /// The tick the view `[view_ticks]` wide starts at with the playhead on `[tick]`.
+    pub fn view_start(&self, tick: f32, view_ticks: f32) -> f32 {
+        match self {
+            SequenceScroll::LeftEdge => tick,
+            SequenceScroll::Centered => (tick - view_ticks / 2.0).max(0.0),
+            SequenceScroll::Pages => (tick / view_ticks).floor() * view_ticks
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SequenceSettings {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+    pub scroll: SequenceScroll
+}
+
+impl Default for SequenceSettings {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            fps: 60,
+            scroll: SequenceScroll::default()
+        }
+    }
+}
+
+/// Renders the song to `frame_00001.png`, `frame_00002.png`... one frame every `1 / fps` seconds, to be put together
+/// into a video elsewhere. Frames are drawn on the GL thread a few at a time with `next_frame` and `write_frame`,
+/// and written to disk on a thread of their own.
+pub struct FrameSequence {
+    target: OffscreenTarget,
+    settings: SequenceSettings,
+    folder: PathBuf,
+    frame_count: u32,
+    /// how many frames were rendered so far
+    frame: u32,
+    sender: Option<SyncSender<(PathBuf, Vec<u8>)>>,
+    writer: Option<JoinHandle<Result<(), String>>>
+}
+
+impl FrameSequence {
+    /// `[length_secs]` - how long the song is, the last frame shows its end
+    pub fn start(gl: Arc<glow::Context>, folder: &Path, settings: SequenceSettings, length_secs: f32) -> Result<Self, String> {
+        let target = OffscreenTarget::new(gl, settings.width, settings.height)?;
+        let (width, height) = (target.width(), target.height());
+        let (sender, receiver) = mpsc::sync_channel::<(PathBuf, Vec<u8>)>(WRITE_QUEUE);
+        let writer = std::thread::spawn(move || {
+            for (path, pixels) in receiver {
+                save_png(&path, width, height, &pixels)?;
+            }
+            Ok(())
+        });
+
+        Ok(Self {
+            target,
+            settings,
+            folder: folder.to_path_buf(),
+            frame_count: (length_secs.max(0.0) * settings.fps as f32).ceil() as u32 + 1,
+            frame: 0,
+            sender: Some(sender),
+            writer: Some(writer)
+        })
+    }
+
+    pub fn target(&self) -> &OffscreenTarget {
+        &self.target
+    }
+
+    pub fn settings(&self) -> SequenceSettings {
+        self.settings
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    pub fn frames_rendered(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.frame as f32 / self.frame_count as f32
+    }
+
+    /// The song position in seconds of the next frame to render, `None` once they're all rendered.
+    pub fn next_frame(&self) -> Option<f32> {
+        (self.frame < self.frame_count).then(|| self.frame as f32 / self.settings.fps as f32)
+    }
+
+    /// Queues `[pixels]` from `OffscreenTarget::render` to be written as the next frame.
+    /// Waits while `WRITE_QUEUE` frames are already waiting, fails if writing an earlier frame failed.
+    pub fn write_frame(&mut self, pixels: Vec<u8>) -> Result<(), String> {
+        self.frame += 1;
+        let path = self.folder.join(format!("frame_{:05}.png", self.frame));
+        let sent = self.sender.as_ref().is_some_and(|sender| sender.send((path, pixels)).is_ok());
+        if sent { Ok(()) } else { self.finish() }
+    }
+
+    /// Waits for the frames still queued to be written. Reports the first frame that couldn't be.
+    pub fn finish(&mut self) -> Result<(), String> {
+        self.sender = None;
+        match self.writer.take().map(|writer| writer.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err("The thread writing the frames stopped".to_string()),
+            None => Ok(())
+        }
+    }
+}
+
+impl Drop for FrameSequence {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Draws the playhead into the RGBA `[pixels]` of a `[width]`x`[height]` frame, starting `[x]` pixels from the left.
+pub fn draw_playhead(pixels: &mut [u8], width: u32, height: u32, x: f32, color: [f32; 3]) {
+    let x = x.round();
+    if x < 0.0 || x >= width as f32 { return; }
+    let [r, g, b] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+    let x = x as u32;
+    for row in 0..height {
+        for column in x..(x + PLAYHEAD_WIDTH).min(width) {
+            let i = (row * width + column) as usize * 4;
+            pixels[i..i + 4].copy_from_slice(&[r, g, b, 255]);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use eframe::egui::Vec2;
+use eframe::glow::{self, HasContext, NativeFramebuffer, NativeTexture};
+
+use super::piano_roll::Renderer;
+
+/// A framebuffer to draw the piano roll into at a size of its own instead of the window's, to save it as an image.
+/// Has to be made and used on the thread the window's GL context is current on.
+pub struct OffscreenTarget {
+    framebuffer: NativeFramebuffer,
+    texture: NativeTexture,
+    width: u32,
+    height: u32,
+    gl: Arc<glow::Context>
+}
+
+impl OffscreenTarget {
+    /// Fails if `[width]`x`[height]` is more than the GPU can draw into.
+    pub fn new(gl: Arc<glow::Context>, width: u32, height: u32) -> Result<Self, String> {
+        unsafe {
+            let max_size = gl.get_parameter_i32(glow::MAX_TEXTURE_SIZE).max(1) as u32;
+            if width == 0 || height == 0 || width > max_size || height > max_size {
+                return Err(format!("The image can be at most {}x{} pixels", max_size, max_size));
+            }
+
+            let texture = gl.create_texture()?;
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_image_2d(glow::TEXTURE_2D, 0, glow::RGBA8 as i32, width as i32, height as i32, 0,
+                glow::RGBA, glow::UNSIGNED_BYTE, glow::PixelUnpackData::Slice(None));
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
+            gl.bind_texture(glow::TEXTURE_2D, None);
+
+            let previous = gl.get_parameter_framebuffer(glow::FRAMEBUFFER_BINDING);
+            let framebuffer = match gl.create_framebuffer() {
+                Ok(framebuffer) => framebuffer,
+                Err(e) => {
+                    gl.delete_texture(texture);
+                    return Err(e);
+                }
+            };
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+            gl.framebuffer_texture_2d(glow::FRAMEBUFFER, glow::COLOR_ATTACHMENT0, glow::TEXTURE_2D, Some(texture), 0);
+            let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+            gl.bind_framebuffer(glow::FRAMEBUFFER, previous);
+
+            let target = Self {
+                framebuffer,
+                texture,
+                width,
+                height,
+                gl
+            };
+            if status != glow::FRAMEBUFFER_COMPLETE {
+                return Err(format!("Couldn't create a {}x{} framebuffer (status {:#x})", width, height, status));
+            }
+            Ok(target)
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Draws `[renderer]` over `[background]` and reads the result back as RGBA rows, top row first.
+    /// The framebuffer, viewport and scissor the window was using are put back afterwards.
+    pub fn render(&self, renderer: &mut dyn Renderer, background: [f32; 3]) -> Vec<u8> {
+        let gl = &self.gl;
+        let mut pixels = vec![0u8; self.width as usize * self.height as usize * 4];
+        unsafe {
+            let previous = gl.get_parameter_framebuffer(glow::FRAMEBUFFER_BINDING);
+            let mut viewport = [0i32; 4];
+            gl.get_parameter_i32_slice(glow::VIEWPORT, &mut viewport);
+            let scissor = gl.is_enabled(glow::SCISSOR_TEST);
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(self.framebuffer));
+            gl.viewport(0, 0, self.width as i32, self.height as i32);
+            gl.disable(glow::SCISSOR_TEST);
+            // the same blending egui sets up before it calls the piano roll's paint callback
+            gl.enable(glow::BLEND);
+            gl.blend_equation_separate(glow::FUNC_ADD, glow::FUNC_ADD);
+            gl.blend_func_separate(glow::ONE, glow::ONE_MINUS_SRC_ALPHA, glow::ONE_MINUS_DST_ALPHA, glow::ONE);
+
+            let [r, g, b] = background;
+            gl.clear_color(r, g, b, 1.0);
+            gl.clear(glow::COLOR_BUFFER_BIT);
+            renderer.window_size(Vec2::new(self.width as f32, self.height as f32));
+            renderer.draw();
+
+            gl.pixel_store_i32(glow::PACK_ALIGNMENT, 1);
+            gl.read_pixels(0, 0, self.width as i32, self.height as i32, glow::RGBA, glow::UNSIGNED_BYTE,
+                glow::PixelPackData::Slice(Some(&mut pixels)));
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, previous);
+            gl.viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            if scissor {
+                gl.enable(glow::SCISSOR_TEST);
+            }
+        }
+
+        // GL reads from the bottom row up, images go from the top down
+        let row = self.width as usize * 4;
+        let mut flipped = Vec::with_capacity(pixels.len());
+        for line in pixels.chunks_exact(row).rev() {
+            flipped.extend_from_slice(line);
+        }
+        flipped
+    }
+}
+
+impl Drop for OffscreenTarget {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.delete_framebuffer(self.framebuffer);
+            self.gl.delete_texture(self.texture);
+        }
+    }
+}
+
+/// Writes `[pixels]` from `OffscreenTarget::render` to `[path]` as a PNG, dropping the alpha since the background is opaque.
+pub fn save_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> Result<(), String> {
+    let rgb = pixels.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect::<Vec<_>>();
+    image::save_buffer_with_format(path, &rgb, width, height, image::ExtendedColorType::Rgb8, image::ImageFormat::Png)
+        .map_err(|e| format!("Couldn't save {}: {}", path.display(), e))
+}
@@ -1,14 +1,19 @@
 use std::default;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 
 use eframe::egui::Vec2;
+use eframe::egui::ecolor::rgb_from_hsv;
 use eframe::{egui, glow};
 use eframe::glow::{HasContext, Shader};
 use std::sync::{Arc, Mutex};
 
 use crate::editor::navigation::Navigation;
+use crate::editor::theme::Theme;
+use crate::editor::track_list::NoteGrouping;
 use crate::editor::project_settings::{self, ProjectSettings};
-use crate::midi::notes::ProjectNote;
+use crate::midi::notes::{ProjectNote, TrackNotes};
+use crate::midi::time_signatures::TimeSignatureMap;
 use crate::set_attribute;
 
 use super::buffers::{Buffer, VertexArray};
@@ -16,15 +21,21 @@ use super::shaders::ShaderProgram;
 
 // Note buffer settings
 const NOTE_BUFFER_SIZE: usize = 4096;
+/// how many ticks each bucket of the note index covers
+const NOTE_BUCKET_TICKS: u32 = 2048;
 
 // Piano Roll Background
 pub type BarStart = f32;
 pub type BarLength = f32;
 pub type BarNumber = u32;
+/// how many beats long the bar is, fractional when a signature change cuts it short
+pub type BarBeats = f32;
+/// how many steps of the editing grid fit in the bar, 0 to not draw the grid
+pub type BarDivisions = f32;
 
 #[repr(C, packed)]
 #[derive(Clone, Copy)]
-pub struct RenderPianoRollBar(BarStart, BarLength, BarNumber);
+pub struct RenderPianoRollBar(BarStart, BarLength, BarNumber, BarBeats, BarDivisions);
 
 // Piano Roll Notes
 pub type NoteRect = [f32; 4]; // (start, length, note bottom, note top)
@@ -51,11 +62,54 @@ const QUAD_INDICES: [u32; 6] = [
     1, 2, 3
 ];
 
+/// What the color of a note is based on.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoteColorMode {
+    /// the picked color of the note's track list entry, or the palette by entry
+    Group,
+    /// the palette by channel, unless the note's track list entry has a picked color
+    #[default]
+    Channel,
+    /// the channel color, darker for quieter notes
+    Velocity,
+    /// a hue per pitch class, so every C is the same color
+    Pitch
+}
+
+/// How the outline of each note is drawn.
+#[derive(Clone, Copy, PartialEq)]
+pub struct NoteStyle {
+    /// if notes get a darker edge, so dense notes stay apart
+    pub borders: bool,
+    /// in pixels, 0.0 for square corners
+    pub corner_radius: f32,
+}
+
+impl Default for NoteStyle {
+    fn default() -> Self {
+        Self {
+            borders: true,
+            corner_radius: 0.0
+        }
+    }
+}
+
 pub trait Renderer {
     fn draw(&mut self);
     fn window_size(&mut self, size: Vec2) {}
     fn update_ppq(&mut self, ppq: u16) {}
-    fn update_project_notes(&mut self, project_notes: HashMap<usize, Vec<Arc<ProjectNote>>>) {}
+    fn update_project_notes(&mut self, project_notes: HashMap<usize, TrackNotes>) {}
+    fn update_silenced_voices(&mut self, _silenced: HashSet<u32>) {}
+    fn update_voice_colors(&mut self, _colors: HashMap<u32, [f32; 3]>) {}
+    fn set_grouping(&mut self, _grouping: NoteGrouping) {}
+    fn set_note_style(&mut self, _style: NoteStyle) {}
+    fn set_antialiasing(&mut self, _enabled: bool) {}
+    fn set_scale_mask(&mut self, _mask: u16) {}
+    fn set_theme(&mut self, _theme: Theme) {}
+    fn set_color_mode(&mut self, _mode: NoteColorMode) {}
+    fn update_selection(&mut self, _selected: HashSet<u32>) {}
+    fn update_grid(&mut self, _grid_ticks: u32) {}
+    fn update_time_signatures(&mut self, _time_signatures: TimeSignatureMap) {}
     fn time_changed(&mut self, time: f32) {}
 }
 
@@ -79,10 +133,36 @@ pub struct PianoRollRenderer {
     gl: Arc<glow::Context>,
 
     bars_render: Vec<RenderPianoRollBar>,
-    render_notes: HashMap<usize, Vec<Arc<ProjectNote>>>,
+    render_notes: HashMap<usize, TrackNotes>,
+    /// the instances of the notes in view, rebuilt when `notes_dirty` is set or the view moves
     notes_render: Vec<RenderPianoRollNote>,
+    /// how many instances `pr_notes_ibo` has room for
+    notes_capacity: usize,
+    notes_dirty: bool,
+    last_view: Option<[f32; 4]>,
     note_colors: Vec<[f32; 3]>,
-    last_note_start: usize,
+    /// colors picked in the track list by `channel_track`, overriding `note_colors`
+    voice_colors: HashMap<u32, [f32; 3]>,
+    color_mode: NoteColorMode,
+    note_style: NoteStyle,
+    /// if the bar lines and note edges are smoothed in the shaders, at some fill rate cost
+    antialiasing: bool,
+    /// the pitch classes of the highlighted scale as bits from C, the rows of the others are shaded. 0 shades nothing
+    scale_mask: u16,
+    theme: Theme,
+    /// how the track list groups notes, which the `Group` color mode follows
+    grouping: NoteGrouping,
+    /// the `channel_track`s of muted entries, or of the ones that aren't soloed while something is. drawn dimmed
+    silenced_voices: HashSet<u32>,
+    selected_notes: HashSet<u32>,
+    /// the editing grid's step, drawn as lines between the beats
+    grid_ticks: u32,
+    /// where the bars are laid out, the pickup included
+    time_signatures: TimeSignatureMap,
+    last_note_starts: HashMap<usize, usize>,
+    /// for every `NOTE_BUCKET_TICKS` ticks of each track, the index of the first note still playing at the bucket's start.
+    /// finds where to start drawing after a seek without searching
+    note_buckets: HashMap<usize, Vec<usize>>,
     first_unhit_note: usize
 }
 
@@ -110,7 +190,9 @@ impl PianoRollRenderer {
                 RenderPianoRollBar {
                     0: 0.0,
                     1: 1.0,
-                    2: 0
+                    2: 0,
+                    3: 4.0,
+                    4: 0.0
                 }; 32
             ];
             pr_instance_buffer.set_data(pr_bars_render.as_slice(), glow::DYNAMIC_DRAW);
@@ -121,10 +203,16 @@ impl PianoRollRenderer {
             set_attribute!(glow::FLOAT, pr_vertex_array, pr_bar_length, RenderPianoRollBar::1);
             let pr_bar_number = pr_program.get_attrib_location("barNumber").unwrap();
             set_attribute!(glow::UNSIGNED_INT, pr_vertex_array, pr_bar_number, RenderPianoRollBar::2);
+            let pr_bar_beats = pr_program.get_attrib_location("barBeats").unwrap();
+            set_attribute!(glow::FLOAT, pr_vertex_array, pr_bar_beats, RenderPianoRollBar::3);
+            let pr_bar_divisions = pr_program.get_attrib_location("barDivisions").unwrap();
+            set_attribute!(glow::FLOAT, pr_vertex_array, pr_bar_divisions, RenderPianoRollBar::4);
 
             gl.vertex_attrib_divisor(1, 1);
             gl.vertex_attrib_divisor(2, 1);
             gl.vertex_attrib_divisor(3, 1);
+            gl.vertex_attrib_divisor(4, 1);
+            gl.vertex_attrib_divisor(5, 1);
 
             // -------- PIANO ROLL NOTES --------
 
@@ -173,7 +261,10 @@ impl PianoRollRenderer {
                 gl,
 
                 bars_render: pr_bars_render.to_vec(),
-                notes_render: pr_notes_render.to_vec(),
+                notes_render: Vec::new(),
+                notes_capacity: NOTE_BUFFER_SIZE,
+                notes_dirty: true,
+                last_view: None,
                 render_notes: HashMap::new(),
 
                 ppq: 1920,
@@ -188,28 +279,137 @@ impl PianoRollRenderer {
                     [0.5, 0.0, 1.0],
                     [1.0, 0.0, 1.0]
                 ],
-
-                last_note_start: 0,
+                voice_colors: HashMap::new(),
+                color_mode: NoteColorMode::default(),
+                note_style: NoteStyle::default(),
+                antialiasing: false,
+                scale_mask: 0,
+                theme: Theme::default(),
+                grouping: NoteGrouping::default(),
+                silenced_voices: HashSet::new(),
+                selected_notes: HashSet::new(),
+                grid_ticks: 0,
+                time_signatures: TimeSignatureMap::default(),
+
+                last_note_starts: HashMap::new(),
+                note_buckets: HashMap::new(),
                 first_unhit_note: 0
             }
         }
     }
 }
 
+/// Builds the `note_buckets` index of a track's notes sorted by start in one pass.
+/// A bucket's first playing note is the first note ending after its start, so each note
+/// is the answer for every bucket up to its end that an earlier note hasn't claimed, which are always the last ones.
+fn note_buckets(notes: &[Arc<ProjectNote>]) -> Vec<usize> {
+    let mut buckets = Vec::new();
+    for (i, note) in notes.iter().enumerate() {
+        let end_bucket = note.end().div_ceil(NOTE_BUCKET_TICKS) as usize;
+        while buckets.len() < end_bucket {
+            buckets.push(i);
+        }
+    }
+    buckets
+}
+
+/// The notes of a track sorted by start that are in view from `[tick_pos]` for `[zoom_ticks]` ticks.
+/// Scans on from `[last_note_start]`, the start of the range last frame, so scrolling forward only looks at the notes scrolled past.
+/// After a seek there's no previous position to scan on from, so it starts from the bucket the view starts in.
+/// No note before the bucket's first one can still be in view.
+fn visible_note_range(notes: &[Arc<ProjectNote>], buckets: &[usize], last_note_start: Option<usize>, tick_pos: f32, zoom_ticks: f32) -> Range<usize> {
+    let mut note_start = last_note_start.unwrap_or_else(|| {
+        let bucket = (tick_pos.max(0.0) as u32 / NOTE_BUCKET_TICKS) as usize;
+        buckets.get(bucket).copied().unwrap_or(notes.len())
+    });
+    for note in &notes[note_start..] {
+        if note.end() as f32 > tick_pos { break; }
+        note_start += 1;
+    }
+
+    let note_end = note_start + notes[note_start..]
+        .partition_point(|n| n.start as f32 <= tick_pos + zoom_ticks);
+    note_start..note_end
+}
+
+impl PianoRollRenderer {
+    /// Fills `notes_render` with an instance for every note in view.
+    fn build_note_instances(&mut self, nav: &Navigation) {
+        self.notes_render.clear();
+
+        // draw in track order so overlapping tracks always stack the same way
+        let mut tracks = self.render_notes.keys().copied().collect::<Vec<usize>>();
+        tracks.sort();
+
+        for track in tracks {
+            let notes = &self.render_notes[&track];
+            let buckets = self.note_buckets.get(&track).map_or(&[][..], Vec::as_slice);
+            let visible = visible_note_range(notes, buckets, self.last_note_starts.get(&track).copied(), nav.tick_pos, nav.zoom_ticks);
+            self.last_note_starts.insert(track, visible.start);
+
+            for note in &notes[visible] {
+                let note_bottom = (note.key as f32 - nav.key_pos) / (nav.zoom_keys);
+                let note_top = ((note.key as f32 + 1.0) - nav.key_pos) / (nav.zoom_keys);
+                let brightness = if self.silenced_voices.contains(&note.channel_track) { 0.25 } else { 1.0 };
+                let mut color = self.note_color(note);
+                if self.selected_notes.contains(&note.id) {
+                    color = std::array::from_fn(|i| color[i] * 0.3 + self.theme.selection[i] * 0.7);
+                }
+                let instance = RenderPianoRollNote {
+                    0: [(note.start as f32 - nav.tick_pos) / nav.zoom_ticks,
+                        (note.length as f32) / nav.zoom_ticks,
+                        (note_bottom),
+                        (note_top)],
+                    1: color.map(|c| c * brightness)
+                };
+                self.notes_render.push(instance);
+            }
+        }
+    }
+
+    /// Uploads `notes_render` to the instance buffer, only reallocating it when it has to grow.
+    fn upload_note_instances(&mut self) {
+        if self.notes_render.len() > self.notes_capacity {
+            self.notes_capacity = self.notes_render.len().next_power_of_two();
+            self.pr_notes_ibo.allocate(std::mem::size_of::<RenderPianoRollNote>() * self.notes_capacity, glow::DYNAMIC_DRAW);
+        }
+        self.pr_notes_ibo.set_sub_data(self.notes_render.as_slice());
+    }
+
+    fn note_color(&self, note: &ProjectNote) -> NoteColor {
+        // most projects have no picked colors, skip hashing every note then
+        let picked_color = if self.voice_colors.is_empty() { None } else { self.voice_colors.get(&note.channel_track).copied() };
+        let channel_color = || picked_color
+            .unwrap_or_else(|| self.note_colors[note.channel() as usize % self.note_colors.len()]);
+
+        match self.color_mode {
+            NoteColorMode::Group => picked_color.unwrap_or_else(|| {
+                let index = self.grouping.palette_index(self.grouping.group_of(note.channel_track));
+                self.note_colors[index % self.note_colors.len()]
+            }),
+            NoteColorMode::Channel => channel_color(),
+            NoteColorMode::Velocity => {
+                let scale = 0.3 + 0.7 * note.velocity as f32 / 127.0;
+                channel_color().map(|c| c * scale)
+            },
+            NoteColorMode::Pitch => rgb_from_hsv(((note.key % 12) as f32 / 12.0, 0.8, 1.0))
+        }
+    }
+}
+
 impl Renderer for PianoRollRenderer {
     fn draw(&mut self) {
         unsafe {
             // RENDER BARS
 
-            let nav = self.navigation.lock().unwrap();
+            let navigation = Arc::clone(&self.navigation);
+            let nav = navigation.lock().unwrap();
 
             {
                 self.gl.use_program(Some(self.pr_program.program));
                 //self.pr_vertex_array.bind();
 
-                let mut curr_bar_tick = 0.0;
                 let mut bar_id = 0;
-                let mut bar_num = 0;
                 {
                     let key_start = nav.key_pos;
                     let key_end = nav.key_pos + nav.zoom_keys;
@@ -218,17 +418,27 @@ impl Renderer for PianoRollRenderer {
                     self.pr_program.set_float("prBarTop", ((128.0 - key_start) / (key_end - key_start)));
                     self.pr_program.set_float("width", self.window_size.x);
                     self.pr_program.set_float("height", self.window_size.y);
-
-                    while curr_bar_tick < nav.zoom_ticks + nav.tick_pos {
-                        bar_num += 1;
-                        if (bar_num as f32) * ((self.ppq as f32) * 4.0) < nav.tick_pos {
-                            curr_bar_tick += self.ppq as f32 * 4.0;
-                            continue;
-                        }
+                    self.pr_program.set_float("antialias", if self.antialiasing { 1.0 } else { 0.0 });
+                    self.pr_program.set_float("scaleMask", self.scale_mask as f32);
+                    self.pr_program.set_vec3("keyRowColor", self.theme.key_rows);
+                    self.pr_program.set_vec3("barLineColor", self.theme.bar_line);
+                    self.pr_program.set_vec3("beatLineColor", self.theme.beat_line);
+                    self.pr_program.set_vec3("gridLineColor", self.theme.grid_line);
+
+                    // each bar is as long as its time signature makes it, starting from the one at the left edge
+                    let (mut bar_num, _, _) = self.time_signatures.bar_beat_tick(self.ppq, nav.tick_pos.max(0.0) as u64);
+                    loop {
+                        let (start, length, beat_ticks) = self.time_signatures.bar_span(self.ppq, bar_num);
+                        // the pickup is the end of a whole bar, the rest of which is before tick 0
+                        let bar_start = if bar_num == 0 { self.time_signatures.pickup() as f32 - length as f32 } else { start as f32 };
+                        if bar_start >= nav.zoom_ticks + nav.tick_pos { break; }
                         self.bars_render[bar_id] = RenderPianoRollBar {
-                            0: ((curr_bar_tick - nav.tick_pos) / nav.zoom_ticks),
-                            1: ((self.ppq as f32 * 4.0) / nav.zoom_ticks),
-                            2: bar_num as u32 - 1
+                            0: ((bar_start - nav.tick_pos) / nav.zoom_ticks),
+                            1: (length as f32 / nav.zoom_ticks),
+                            // only its parity is used, to shade every other bar
+                            2: (bar_num + 1) as u32 % 2,
+                            3: length as f32 / beat_ticks as f32,
+                            4: if self.grid_ticks > 0 { length as f32 / self.grid_ticks as f32 } else { 0.0 }
                         };
                         bar_id += 1;
                         if bar_id >= 32 {
@@ -241,7 +451,7 @@ impl Renderer for PianoRollRenderer {
                                 glow::TRIANGLES, 6, glow::UNSIGNED_INT, 0, 32);
                             bar_id = 0;
                         }
-                        curr_bar_tick += self.ppq as f32 * 4.0;
+                        bar_num += 1;
                     }
                 }
 
@@ -267,89 +477,28 @@ impl Renderer for PianoRollRenderer {
                 {
                     self.pr_notes_program.set_float("width", self.window_size.x);
                     self.pr_notes_program.set_float("height", self.window_size.y);
+                    self.pr_notes_program.set_float("noteBorders", if self.note_style.borders { 1.0 } else { 0.0 });
+                    self.pr_notes_program.set_float("cornerRadius", self.note_style.corner_radius);
+                    self.pr_notes_program.set_float("antialias", if self.antialiasing { 1.0 } else { 0.0 });
+
+                    // only rebuild and re-upload the instances when something that moves or recolors them changed
+                    let view = [nav.tick_pos, nav.key_pos, nav.zoom_ticks, nav.zoom_keys];
+                    if self.notes_dirty || self.last_view != Some(view) {
+                        self.build_note_instances(&nav);
+                        self.upload_note_instances();
+                        self.last_view = Some(view);
+                        self.notes_dirty = false;
+                    }
 
-                    let mut curr_time = 0.0;
-                    let mut curr_note = 0;
-                    let mut note_id = 0;
-                    if self.render_notes.len() > 0 {
-                        let notes = self.render_notes.get(&0).unwrap();
-
-                        let note_start = {
-                            let mut s = self.last_note_start;
-                            for i in s..notes.len() {
-                                if (notes[i].start + notes[i].length) as f32 > nav.tick_pos { break; }
-                                s += 1;
-                            }
-                            self.last_note_start = s;
-                            s
-                        };
-
-                        let note_end = {
-                            let mut e = note_start;
-                            for i in note_start..notes.len() {
-                                if notes[i].start as f32 > nav.tick_pos + nav.zoom_ticks { break; }
-                                e += 1;
-                            }
-                            e
-                        };
-
-                        for note in &notes[note_start..note_end]  {
-                            {
-                                /*if note.start + note.length < nav.tick_pos as u32 { 
-                                    curr_note += 1; 
-                                    if curr_note >= notes.len() {
-                                        break;
-                                    }
-                                    continue;
-                                }
-                                if note.start > (nav.tick_pos + nav.zoom_ticks) as u32 {
-                                    curr_note += 1; 
-                                    if curr_note >= notes.len() {
-                                        break;
-                                    }
-                                    break;
-                                }*/
-
-                                let note_bottom = (note.key as f32 - nav.key_pos) / (nav.zoom_keys);
-                                let note_top = ((note.key as f32 + 1.0) - nav.key_pos) / (nav.zoom_keys);
-                                self.notes_render[note_id] = RenderPianoRollNote {
-                                    0: [(note.start as f32 - nav.tick_pos) / nav.zoom_ticks,
-                                        (note.length as f32) / nav.zoom_ticks,
-                                        (note_bottom),
-                                        (note_top)],
-                                    1: self.note_colors[(note.channel_track & 0xFF) as usize % self.note_colors.len()]
-                                };
-                                note_id += 1;
-                                if note_id >= NOTE_BUFFER_SIZE {
-                                    self.pr_notes_vao.bind();
-                                    self.pr_notes_ibo.bind();
-                                    self.pr_notes_vbo.bind();
-                                    self.pr_notes_ebo.bind();
-                                    self.pr_notes_ibo.set_data(self.notes_render.as_slice(), glow::DYNAMIC_DRAW);
-
-                                    self.gl.use_program(Some(self.pr_notes_program.program));
-                                    self.gl.draw_elements_instanced(
-                                        glow::TRIANGLES, 6, glow::UNSIGNED_INT, 0, NOTE_BUFFER_SIZE as i32);
-                                    note_id = 0;
-                                }
-                                curr_note += 1;
-                                if curr_note >= notes.len() {
-                                    break;
-                                }
-                            }
-                        }
-
-                        if note_id != 0 {
-                            self.pr_notes_vao.bind();
-                            self.pr_notes_ibo.bind();
-                            self.pr_notes_vbo.bind();
-                            self.pr_notes_ebo.bind();
-                            self.pr_notes_ibo.set_data(self.notes_render.as_slice(), glow::DYNAMIC_DRAW);
+                    if !self.notes_render.is_empty() {
+                        self.pr_notes_vao.bind();
+                        self.pr_notes_ibo.bind();
+                        self.pr_notes_vbo.bind();
+                        self.pr_notes_ebo.bind();
 
-                            self.gl.use_program(Some(self.pr_notes_program.program));
-                            self.gl.draw_elements_instanced(
-                                glow::TRIANGLES, 6, glow::UNSIGNED_INT, 0, note_id as i32);
-                        }
+                        self.gl.use_program(Some(self.pr_notes_program.program));
+                        self.gl.draw_elements_instanced(
+                            glow::TRIANGLES, 6, glow::UNSIGNED_INT, 0, self.notes_render.len() as i32);
                     }
                 }
 
@@ -366,12 +515,134 @@ impl Renderer for PianoRollRenderer {
         self.ppq = ppq;
     }
 
-    fn update_project_notes(&mut self, project_notes: HashMap<usize, Vec<Arc<ProjectNote>>>) {
+    fn update_project_notes(&mut self, project_notes: HashMap<usize, TrackNotes>) {
+        // tracks an edit didn't touch are still the same list, their index doesn't need rebuilding
+        let mut old_buckets = std::mem::take(&mut self.note_buckets);
+        self.note_buckets = project_notes.iter()
+            .map(|(track, notes)| {
+                let unchanged = self.render_notes.get(track).is_some_and(|old| Arc::ptr_eq(old, notes));
+                let buckets = old_buckets.remove(track).filter(|_| unchanged).unwrap_or_else(|| note_buckets(notes));
+                (*track, buckets)
+            })
+            .collect();
         self.render_notes = project_notes;
+        self.last_note_starts.clear();
+        self.notes_dirty = true;
+    }
+
+    fn update_silenced_voices(&mut self, silenced: HashSet<u32>) {
+        self.silenced_voices = silenced;
+        self.notes_dirty = true;
+    }
+
+    fn update_voice_colors(&mut self, colors: HashMap<u32, [f32; 3]>) {
+        self.voice_colors = colors;
+        self.notes_dirty = true;
+    }
+
+    fn set_grouping(&mut self, grouping: NoteGrouping) {
+        self.grouping = grouping;
+        self.notes_dirty = true;
+    }
+
+    fn set_note_style(&mut self, style: NoteStyle) {
+        self.note_style = style;
+    }
+
+    fn set_antialiasing(&mut self, enabled: bool) {
+        self.antialiasing = enabled;
+    }
+
+    fn set_scale_mask(&mut self, mask: u16) {
+        self.scale_mask = mask;
+    }
+
+    fn set_theme(&mut self, theme: Theme) {
+        // the selection tint is baked into the note instances
+        self.notes_dirty |= theme.selection != self.theme.selection;
+        self.theme = theme;
+    }
+
+    fn set_color_mode(&mut self, mode: NoteColorMode) {
+        self.color_mode = mode;
+        self.notes_dirty = true;
+    }
+
+    fn update_selection(&mut self, selected: HashSet<u32>) {
+        self.selected_notes = selected;
+        self.notes_dirty = true;
+    }
+
+    fn update_grid(&mut self, grid_ticks: u32) {
+        self.grid_ticks = grid_ticks;
+    }
+
+    fn update_time_signatures(&mut self, time_signatures: TimeSignatureMap) {
+        self.time_signatures = time_signatures;
     }
 
     fn time_changed(&mut self, time: f32) {
-        self.last_note_start = 0;
+        self.last_note_starts.clear();
         self.first_unhit_note = 0;
+        self.notes_dirty = true;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{note_buckets, visible_note_range, NOTE_BUCKET_TICKS};
+    use crate::midi::notes::ProjectNote;
+
+    /// 100k notes a quarter apart at 960 PPQ, with every 1000th one held for 64 bars over the ones after it.
+    fn large_track() -> Vec<Arc<ProjectNote>> {
+        (0..100_000u32).map(|i| Arc::new(ProjectNote {
+            id: i,
+            start: i * 240,
+            length: if i % 1000 == 0 { 64 * 3840 } else { 120 },
+            channel_track: 0,
+            key: (i % 128) as u8,
+            velocity: 100
+        })).collect()
+    }
+
+    /// Checks `[range]` starts at the first note still playing at `[tick_pos]` and holds every note in view.
+    fn assert_in_view(notes: &[Arc<ProjectNote>], range: std::ops::Range<usize>, tick_pos: f32, zoom_ticks: f32) {
+        let first_playing = notes.iter().position(|n| n.end() as f32 > tick_pos).unwrap_or(notes.len());
+        assert_eq!(range.start, first_playing, "at tick {}", tick_pos);
+        for (i, note) in notes.iter().enumerate() {
+            let in_view = note.end() as f32 > tick_pos && note.start as f32 <= tick_pos + zoom_ticks;
+            assert!(!in_view || range.contains(&i), "note {} at tick {}", i, tick_pos);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn seeking_finds_the_notes_in_view() {
+        let notes = large_track();
+        let buckets = note_buckets(&notes);
+        let last_end = notes.iter().map(|n| n.end()).max().unwrap();
+        assert_eq!(buckets.len(), last_end.div_ceil(NOTE_BUCKET_TICKS) as usize);
+
+        for tick_pos in [0.0, 1000.0, 240_000.0, 240_500.0, 5_000_000.0, 23_999_900.0, 30_000_000.0] {
+            let range = visible_note_range(&notes, &buckets, None, tick_pos, 7680.0);
+            assert_in_view(&notes, range, tick_pos, 7680.0);
+        }
+    }
+
+    #[test]
+    fn scrolling_through_a_large_track_keeps_the_notes_in_view() {
+        let notes = large_track();
+        let buckets = note_buckets(&notes);
+
+        let mut last_note_start = None;
+        let mut tick_pos = 0.0;
+        while tick_pos < 24_000_000.0 {
+            let range = visible_note_range(&notes, &buckets, last_note_start, tick_pos, 7680.0);
+            if (tick_pos as u32).is_multiple_of(240_000) {
+                assert_in_view(&notes, range.clone(), tick_pos, 7680.0);
+            }
+            last_note_start = Some(range.start);
+            tick_pos += 400.0;
+        }
+    }
+}
@@ -69,4 +69,13 @@ impl ShaderProgram {
             )
         }
     }
-}
\ No newline at end of file
+
+    pub fn set_vec3(&self, name: &str, value: [f32; 3]) {
+        unsafe {
+            self.gl.uniform_3_f32(
+                self.gl.get_uniform_location(self.program, name).as_ref(),
+                value[0], value[1], value[2]
+            )
+        }
+    }
+}